@@ -1,4 +1,5 @@
 use crate::core::engine::{LLMEngine, StreamItem, GLOBAL_RT};
+use crate::core::spec_decode::SpeculativeConfig;
 use crate::core::GenerationOutput;
 use crate::server::{build_messages_and_images, run_server, ChatMessage};
 use crate::tools::Tool;
@@ -35,6 +36,9 @@ pub struct EngineBuilder {
     pd_server_prefix_cache_ratio: Option<f32>,
     pd_client_prefix_cache_ratio: Option<f32>,
     yarn_scaling_factor: Option<f64>,
+    speculative_config: Option<SpeculativeConfig>,
+    api_keys: Option<String>,
+    scheduling_policy: Option<String>,
     device_ids: Option<Vec<usize>>,
 }
 
@@ -52,6 +56,9 @@ impl EngineBuilder {
             pd_server_prefix_cache_ratio: None,
             pd_client_prefix_cache_ratio: None,
             yarn_scaling_factor: None,
+            speculative_config: None,
+            api_keys: None,
+            scheduling_policy: None,
             device_ids: None,
         }
     }
@@ -106,6 +113,24 @@ impl EngineBuilder {
         self
     }
 
+    pub fn with_speculative_config(mut self, config: SpeculativeConfig) -> Self {
+        self.speculative_config = Some(config);
+        self
+    }
+
+    /// Require the API keys listed in `path` on the server started by `start_server`.
+    pub fn with_api_keys(mut self, path: impl Into<String>) -> Self {
+        self.api_keys = Some(path.into());
+        self
+    }
+
+    /// Scheduling policy of the engine: `fcfs` (default), `priority`, `shortest-prompt`
+    /// or `fair-share`.
+    pub fn with_scheduling_policy(mut self, policy: impl Into<String>) -> Self {
+        self.scheduling_policy = Some(policy.into());
+        self
+    }
+
     pub fn with_multirank(mut self, device_ids: &str) -> Result<Self> {
         self.device_ids = Some(parse_device_ids(device_ids)?);
         Ok(self)
@@ -160,6 +185,12 @@ impl EngineBuilder {
             self.pd_client_prefix_cache_ratio,
             self.yarn_scaling_factor,
             false,
+            self.speculative_config,
+            self.api_keys,
+            self.scheduling_policy,
+            None,
+            None,
+            None,
         );
 
         let dtype = self.dtype.clone().map(dtype_to_str);
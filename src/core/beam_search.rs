@@ -0,0 +1,285 @@
+use crate::utils::config::SamplingParams;
+use crate::utils::logits_processor::{TopLogprob, MAX_TOP_LOGPROBS};
+use std::collections::HashMap;
+
+/// Largest beam width whose `num_candidates` fit in the top logprobs the sampler reports.
+pub const MAX_BEAM_WIDTH: usize = MAX_TOP_LOGPROBS / 2;
+
+/// Beam search settings resolved from the request's sampling params.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamSearchParams {
+    pub beam_width: usize,
+    pub length_penalty: f32,
+    pub early_stopping: bool,
+}
+
+impl BeamSearchParams {
+    pub fn from_sampling_params(params: &SamplingParams) -> Option<Self> {
+        params.beam_width.filter(|&w| w > 0).map(|beam_width| Self {
+            beam_width,
+            length_penalty: params.length_penalty.unwrap_or(1.0),
+            early_stopping: params.early_stopping.unwrap_or(false),
+        })
+    }
+
+    /// Candidates each beam reports per step; twice the width so that enough
+    /// non-EOS continuations survive even if every beam proposes EOS.
+    pub fn num_candidates(&self) -> usize {
+        2 * self.beam_width
+    }
+
+    /// Length-normalized score used to rank hypotheses.
+    pub fn score(&self, cum_logprob: f32, len: usize) -> f32 {
+        cum_logprob / (len.max(1) as f32).powf(self.length_penalty)
+    }
+}
+
+/// Why a beam finished.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BeamEnd {
+    Eos,
+    /// A stop token or stop sequence, with the matched stop string if any
+    Stop(Option<String>),
+    /// `max_tokens` or the batch token limit was reached
+    Length,
+}
+
+/// A finished beam.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamHypothesis {
+    pub output_ids: Vec<u32>,
+    pub score: f32,
+    pub end: BeamEnd,
+}
+
+/// A continuation chosen in a beam step: extend beam `parent_id` with `token_id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamCandidate {
+    pub parent_id: usize,
+    pub token_id: u32,
+    pub cum_logprob: f32,
+}
+
+/// Beams decoded together for one request.
+///
+/// All live beams advance in lock step: a step is taken once every live beam has
+/// reported its candidates for the current position.
+#[derive(Debug, Clone)]
+pub struct BeamGroup {
+    pub params: BeamSearchParams,
+    /// Live beams (sequence id, cumulative logprob)
+    beams: Vec<(usize, f32)>,
+    reported: HashMap<usize, Vec<TopLogprob>>,
+    hypotheses: Vec<BeamHypothesis>,
+    pub created_time: usize,
+    pub decode_start_time: Option<usize>,
+    pub prompt_len: usize,
+}
+
+impl BeamGroup {
+    pub fn new(
+        root_id: usize,
+        params: BeamSearchParams,
+        created_time: usize,
+        prompt_len: usize,
+    ) -> Self {
+        Self {
+            params,
+            beams: vec![(root_id, 0.0)],
+            reported: HashMap::new(),
+            hypotheses: Vec::new(),
+            created_time,
+            decode_start_time: None,
+            prompt_len,
+        }
+    }
+
+    pub fn beam_ids(&self) -> Vec<usize> {
+        self.beams.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Record the candidates of one beam, returns true once all live beams have reported.
+    pub fn report(&mut self, seq_id: usize, candidates: Vec<TopLogprob>) -> bool {
+        if self.beams.iter().any(|(id, _)| *id == seq_id) {
+            self.reported.insert(seq_id, candidates);
+        }
+        self.reported.len() == self.beams.len()
+    }
+
+    /// Rank the reported candidates of all beams and pick the next `beam_width` beams.
+    /// Returns the continuations and the candidates that finish a hypothesis, i.e. those
+    /// for which `ends(parent_id, token_id)` returns an end (EOS or a stop).
+    pub fn select(
+        &mut self,
+        ends: impl Fn(usize, u32) -> Option<BeamEnd>,
+    ) -> (Vec<BeamCandidate>, Vec<(BeamCandidate, BeamEnd)>) {
+        let mut candidates: Vec<BeamCandidate> = self
+            .beams
+            .iter()
+            .flat_map(|(id, cum_logprob)| {
+                self.reported
+                    .get(id)
+                    .into_iter()
+                    .flatten()
+                    .take(self.params.num_candidates())
+                    .map(move |c| BeamCandidate {
+                        parent_id: *id,
+                        token_id: c.token_id,
+                        cum_logprob: cum_logprob + c.logprob,
+                    })
+            })
+            .collect();
+        self.reported.clear();
+        candidates.sort_by(|a, b| b.cum_logprob.total_cmp(&a.cum_logprob));
+
+        let mut continuations = Vec::new();
+        let mut finished = Vec::new();
+        for (rank, candidate) in candidates.into_iter().enumerate() {
+            if continuations.len() == self.params.beam_width {
+                break;
+            }
+            if !candidate.cum_logprob.is_finite() {
+                continue;
+            }
+            if let Some(end) = ends(candidate.parent_id, candidate.token_id) {
+                // Ends ranked below the beam width cannot beat the live beams
+                if rank < self.params.beam_width {
+                    finished.push((candidate, end));
+                }
+            } else {
+                continuations.push(candidate);
+            }
+        }
+        (continuations, finished)
+    }
+
+    pub fn set_beams(&mut self, beams: Vec<(usize, f32)>) {
+        self.beams = beams;
+    }
+
+    /// Keep a finished hypothesis if it ranks among the best `beam_width`.
+    pub fn add_hypothesis(
+        &mut self,
+        output_ids: Vec<u32>,
+        cum_logprob: f32,
+        len: usize,
+        end: BeamEnd,
+    ) {
+        let score = self.params.score(cum_logprob, len);
+        self.hypotheses.push(BeamHypothesis {
+            output_ids,
+            score,
+            end,
+        });
+        self.hypotheses.sort_by(|a, b| b.score.total_cmp(&a.score));
+        self.hypotheses.truncate(self.params.beam_width);
+    }
+
+    /// Whether no live beam can improve the kept hypotheses, given the current output length.
+    pub fn is_done(&self, cur_len: usize) -> bool {
+        if self.beams.is_empty() {
+            return true;
+        }
+        if self.hypotheses.len() < self.params.beam_width {
+            return false;
+        }
+        if self.params.early_stopping {
+            return true;
+        }
+        let best_live = self
+            .beams
+            .iter()
+            .map(|(_, cum_logprob)| *cum_logprob)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let worst_kept = self
+            .hypotheses
+            .last()
+            .map_or(f32::NEG_INFINITY, |h| h.score);
+        worst_kept >= self.params.score(best_live, cur_len)
+    }
+
+    pub fn best(&self) -> Option<&BeamHypothesis> {
+        self.hypotheses.first()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(beam_width: usize, early_stopping: bool) -> BeamSearchParams {
+        BeamSearchParams {
+            beam_width,
+            length_penalty: 1.0,
+            early_stopping,
+        }
+    }
+
+    fn top(candidates: &[(u32, f32)]) -> Vec<TopLogprob> {
+        candidates
+            .iter()
+            .map(|&(token_id, logprob)| TopLogprob { token_id, logprob })
+            .collect()
+    }
+
+    #[test]
+    fn select_ranks_candidates_across_beams() {
+        let mut group = BeamGroup::new(0, params(2, false), 0, 8);
+        group.set_beams(vec![(0, -1.0), (1, -0.5)]);
+        assert!(!group.report(0, top(&[(10, -0.1), (11, -0.2)])));
+        assert!(group.report(1, top(&[(12, -1.0), (13, -2.0)])));
+
+        let (next, finished) = group.select(|_, _| None);
+        assert!(finished.is_empty());
+        let picked: Vec<(usize, u32)> = next.iter().map(|c| (c.parent_id, c.token_id)).collect();
+        assert_eq!(picked, vec![(0, 10), (0, 11)]);
+        assert!((next[0].cum_logprob + 1.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn eos_candidates_finish_hypotheses() {
+        let mut group = BeamGroup::new(0, params(2, true), 0, 8);
+        group.report(0, top(&[(2, -0.1), (10, -0.5), (11, -0.7), (2, -3.0)]));
+        let (next, finished) = group.select(|_, t| (t == 2).then_some(BeamEnd::Eos));
+        assert_eq!(finished.len(), 1);
+        assert_eq!(next.len(), 2);
+
+        let (candidate, end) = finished[0].clone();
+        group.add_hypothesis(vec![], candidate.cum_logprob, 1, end);
+        group.set_beams(next.iter().map(|c| (c.parent_id, c.cum_logprob)).collect());
+        assert!(!group.is_done(1));
+        group.add_hypothesis(vec![10], -0.9, 2, BeamEnd::Length);
+        assert!(group.is_done(2));
+        assert_eq!(group.best().unwrap().output_ids, Vec::<u32>::new());
+        assert_eq!(group.best().unwrap().end, BeamEnd::Eos);
+    }
+
+    #[test]
+    fn stops_end_only_their_own_beam() {
+        let mut group = BeamGroup::new(0, params(2, false), 0, 8);
+        group.set_beams(vec![(0, -1.0), (1, -1.0)]);
+        group.report(0, top(&[(7, -0.1), (10, -0.2)]));
+        group.report(1, top(&[(7, -0.3), (11, -0.4)]));
+
+        // Token 7 completes a stop sequence on beam 0 only
+        let (next, finished) = group.select(|parent, t| {
+            (parent == 0 && t == 7).then(|| BeamEnd::Stop(Some("stop".to_string())))
+        });
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].0.parent_id, 0);
+        assert_eq!(finished[0].1, BeamEnd::Stop(Some("stop".to_string())));
+        let picked: Vec<(usize, u32)> = next.iter().map(|c| (c.parent_id, c.token_id)).collect();
+        assert_eq!(picked, vec![(0, 10), (1, 7)]);
+    }
+
+    #[test]
+    fn is_done_waits_for_better_live_beams() {
+        let mut group = BeamGroup::new(0, params(1, false), 0, 8);
+        group.add_hypothesis(vec![1], -4.0, 2, BeamEnd::Eos);
+        group.set_beams(vec![(3, -1.0)]);
+        // -1.0 / 2 may still beat -4.0 / 2
+        assert!(!group.is_done(2));
+        group.set_beams(vec![(3, -10.0)]);
+        assert!(group.is_done(2));
+    }
+}
@@ -6,6 +6,7 @@ use crate::def_broadcast_message_to_runners;
 use crate::runner::{receive_local, send_local, MessageType};
 use crate::utils::env::{mamba_snapshot_block_stride_blocks, MAMBA_SNAPSHOT_BLOCK_STRIDE_ENV};
 use crate::utils::image::ImageData;
+use crate::utils::metrics::metrics;
 use candle_core::Result;
 use interprocess::{local_socket::Stream as LocalStream, TryClone};
 use parking_lot::RwLock;
@@ -178,10 +179,20 @@ impl BlockManager {
     }
 
     pub fn allocate(&mut self, seq: &mut Sequence) -> Result<()> {
+        self.allocate_with_prefix_limit(seq, usize::MAX)
+    }
+
+    /// Allocates `seq` reusing at most `max_cached_tokens` tokens of cached prefix, rounded
+    /// down to whole blocks.
+    pub fn allocate_with_prefix_limit(
+        &mut self,
+        seq: &mut Sequence,
+        max_cached_tokens: usize,
+    ) -> Result<()> {
         assert!(seq.block_table.is_empty());
         if self.prefix_cache.is_some() {
             let mut prefix_cache = self.prefix_cache.take().unwrap();
-            let result = self.allocate_with_prefix(seq, &mut prefix_cache);
+            let result = self.allocate_with_prefix(seq, &mut prefix_cache, max_cached_tokens);
             self.prefix_cache = Some(prefix_cache);
             result
         } else {
@@ -210,7 +221,8 @@ impl BlockManager {
 
     pub fn may_append(&mut self, seq: &mut Sequence) -> Result<()> {
         let len_mod = seq.len() % self.block_size;
-        if len_mod == 1 {
+        // Forked sequences re-decode their last prompt token, whose block already exists
+        if len_mod == 1 && seq.block_table.len() < seq.num_blocks() {
             //approaching next block
             let block_id = self
                 .free_block_ids
@@ -219,9 +231,99 @@ impl BlockManager {
             self.allocate_block(block_id);
             seq.block_table.push(block_id as u32);
         }
+        // The next token is written into the block holding position `len - 1`
+        self.copy_on_write(seq, (seq.len() - 1) / self.block_size)
+    }
+
+    /// Reserve KV slots for `num_tokens` draft tokens following the next decoded token,
+    /// keeping at least `min_free` blocks free. Returns false if nothing was reserved.
+    pub fn reserve_draft_slots(
+        &mut self,
+        seq: &mut Sequence,
+        num_tokens: usize,
+        min_free: usize,
+    ) -> Result<bool> {
+        let needed = (seq.len() + num_tokens).div_ceil(self.block_size);
+        let first = (seq.len() - 1) / self.block_size;
+        // Shared blocks in the written range need a private copy as well
+        let shared = (first..seq.block_table.len().min(needed))
+            .filter(|&i| self.blocks[seq.block_table[i] as usize].ref_count > 1)
+            .count();
+        let new_blocks = needed.saturating_sub(seq.block_table.len());
+        if self.free_block_ids.len() < new_blocks + shared + min_free {
+            return Ok(false);
+        }
+        while seq.block_table.len() < needed {
+            let block_id = self
+                .free_block_ids
+                .pop_front()
+                .ok_or_else(|| candle_core::Error::msg("No free blocks available, retry later!"))?;
+            self.allocate_block(block_id);
+            seq.block_table.push(block_id as u32);
+        }
+        for index in first..needed {
+            self.copy_on_write(seq, index)?;
+        }
+        Ok(true)
+    }
+
+    /// Roll back the slots of rejected draft tokens: free the blocks past the sequence length.
+    /// Stale KV left in the kept blocks is overwritten by the following decode steps.
+    pub fn release_draft_slots(&mut self, seq: &mut Sequence) {
+        while seq.block_table.len() > seq.num_blocks() {
+            if let Some(block_id) = seq.block_table.pop() {
+                self.decrement_block_ref(block_id as usize);
+            }
+        }
+    }
+
+    /// Share all blocks of `parent` with `child`; shared blocks are copied on first write.
+    pub fn fork(&mut self, parent: &Sequence, child: &mut Sequence) {
+        child.block_table = parent.block_table.clone();
+        for &block_id in &child.block_table {
+            self.increment_block_ref(block_id as usize);
+        }
+    }
+
+    /// Give `seq` a private copy of block `index` if other sequences still reference it.
+    fn copy_on_write(&mut self, seq: &mut Sequence, index: usize) -> Result<()> {
+        let Some(&block_id) = seq.block_table.get(index) else {
+            return Ok(());
+        };
+        let block_id = block_id as usize;
+        if self.blocks[block_id].ref_count <= 1 {
+            return Ok(());
+        }
+        let new_block_id = self
+            .free_block_ids
+            .pop_front()
+            .ok_or_else(|| candle_core::Error::msg("No free blocks available, retry later!"))?;
+        self.allocate_block(new_block_id);
+        match self.try_copy_kvcache(HashMap::from([(block_id, new_block_id)])) {
+            Ok(true) => {}
+            Ok(false) => {
+                self.decrement_block_ref(new_block_id);
+                candle_core::bail!(
+                    "Copy-on-write of block {} failed for seq {}",
+                    block_id,
+                    seq.id
+                );
+            }
+            Err(e) => {
+                self.decrement_block_ref(new_block_id);
+                return Err(e);
+            }
+        }
+        seq.block_table[index] = new_block_id as u32;
+        self.decrement_block_ref(block_id);
         Ok(())
     }
 
+    /// Whether the model keeps per-sequence recurrent (mamba) state besides the KV cache.
+    pub fn has_mamba_state(&self) -> bool {
+        self.mamba_prefix_enabled
+    }
+
     pub fn ensure_allocate(&mut self, seq: &mut Sequence) -> Result<()> {
         let mut new_blocks = Vec::new();
         for i in seq.block_table.len()..seq.num_blocks() {
@@ -317,6 +419,7 @@ impl BlockManager {
         &mut self,
         seq: &mut Sequence,
         prefix_cache: &mut PrefixCache,
+        max_cached_tokens: usize,
     ) -> Result<()> {
         let tokens = &seq.token_ids;
         let mut matched_blocks = 0usize;
@@ -327,8 +430,9 @@ impl BlockManager {
             let seed = seq.images.as_ref().map(Self::image_prefix_seed);
             let prefix_match = prefix_cache.match_prefix_with_seed(tokens, seed);
             last_hash = prefix_match.last_hash;
-            raw_matched_blocks =
-                self.adjusted_matched_blocks(tokens.len(), prefix_match.matched_blocks);
+            raw_matched_blocks = self
+                .adjusted_matched_blocks(tokens.len(), prefix_match.matched_blocks)
+                .min(max_cached_tokens / self.block_size);
             matched_blocks = self.resolve_mamba_matched_blocks(
                 prefix_cache,
                 seq.id,
@@ -363,6 +467,9 @@ impl BlockManager {
         }
 
         let cached_tokens = matched_blocks * self.block_size;
+        if prefix_cache.enabled() {
+            metrics().record_prefix_lookup(tokens.len(), cached_tokens);
+        }
         if matched_blocks > 0 {
             crate::log_info!(
                 "Prefix cache hit seq {} ({} cached tokens, {} blocks)",
@@ -731,6 +838,18 @@ impl BlockManager {
         bool
     );
 
+    // def try_check_pd_connection
+    def_broadcast_message_to_runners!(
+        pub,
+        try_check_pd_connection,
+        check_pd_connection,
+        (),
+        MessageType::CheckPdConnection,
+        (),
+        MessageType::CheckPdConnectionResponse,
+        bool
+    );
+
     // def try_swap_kvcache
     def_broadcast_message_to_runners!(
         pub,
@@ -743,6 +862,18 @@ impl BlockManager {
         bool
     );
 
+    // def try_copy_kvcache
+    def_broadcast_message_to_runners!(
+        pub,
+        try_copy_kvcache,
+        copy_kvcache,
+        (mappings: HashMap<usize, usize>),
+        MessageType::KVCacheCopy,
+        (mappings.clone()),
+        MessageType::KVCacheCopyResponse,
+        bool
+    );
+
     // def try_send_kvcache
     def_broadcast_message_to_runners!(
         pub,
@@ -1,10 +1,13 @@
 //src/core/engine.rs
-use super::runner::{ModelRunner, RunnerType, Seqs};
+use super::beam_search::{BeamEnd, BeamSearchParams, MAX_BEAM_WIDTH};
+use super::runner::{ModelRunner, RunOutput, RunnerType, Seqs};
 use super::scheduler::{Scheduler, KVCACHE_SWAP_THRESHOLD};
+use super::scheduling_policy;
 use super::sequence::Sequence;
+use super::spec_decode::{self, SpeculativeMethod};
 use crate::core::scheduler::PD_PREFILL_STATUS_CHECK_COOLING_PERIOD;
 use crate::core::sequence::{DecodeSequence, SequenceStatus};
-use crate::core::GenerationOutput;
+use crate::core::{GenerationOutput, PromptScore, ScoreCache, PREFILL_CHUNK_SIZE};
 use crate::models::layers::distributed::Comm;
 #[cfg(feature = "nccl")]
 use crate::models::layers::distributed::Id;
@@ -12,6 +15,7 @@ use crate::models::layers::VarBuilderX;
 use crate::runner::{
     receive_local, send_and_expect_ack, send_local, MessageType, RunnerInitRequest,
 };
+use crate::server::auth::KeyUsage;
 use crate::server::logger::ChatCompletionLogger;
 use crate::server::parser::{detect_prefilled_reasoning_end_marker, ToolConfig};
 use crate::server::{EmbeddingStrategy, UsageResponse};
@@ -24,8 +28,11 @@ use crate::utils::guidance::{build_llg_factory, extract_guidance_tokens, Guidanc
 use crate::utils::heartbeat::heartbeat_worker;
 use crate::utils::image::{get_image_config, ImageData, ImageProcessConfig};
 use crate::utils::kvcache_allocator::KVCacheAllocator;
+use crate::utils::logits_processor::TokenLogprobs;
+use crate::utils::metrics::{metrics, EngineGauges, FinishReason};
 use crate::utils::progress::{progress_worker, ProgressReporter};
 use crate::utils::progress::{spawn_progress_thread, ProgressLike};
+use crate::utils::special_tokens::FimTemplate;
 use crate::utils::{chat_template::ChatTemplate, prepare_engine_config};
 use crate::utils::{get_runner_path, init_config_tokenizer, spawn_runner};
 use crate::{log_info, log_warn};
@@ -41,12 +48,12 @@ use once_cell::sync::Lazy;
 use parking_lot::RwLock;
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::{BufRead, BufReader};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokenizers::Tokenizer;
 use tokio::runtime::Runtime;
 use tokio::sync::mpsc;
@@ -64,6 +71,8 @@ pub enum StreamItem {
     TokenID(u32),       //completion
     Completion((usize, usize, usize, Vec<u32>, Option<String>)), //completion
     Done((usize, usize, usize, usize, Option<String>)), //streaming end
+    Logprobs(TokenLogprobs), //logprobs of the token sent next
+    Prediction((usize, usize)), //accepted and rejected predicted output tokens, before the end
     Error(String),
 }
 
@@ -73,6 +82,22 @@ pub enum RequestType {
     Completion,
 }
 
+/// Rejection of `bad_words` requests when the llguidance tokenizer could not be built.
+pub const BAD_WORDS_UNSUPPORTED: &str =
+    "bad_words is not supported for this model: its tokenizer could not be loaded for byte-level matching";
+
+/// Window over which finished sequences' KV usage is averaged for `queue_stats`.
+const KV_RELEASE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Load of the waiting queue, used for admission control.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueueStats {
+    pub waiting_requests: usize,
+    /// Prompt tokens of the waiting sequences
+    pub waiting_tokens: usize,
+    pub estimated_wait: Option<Duration>,
+}
+
 #[allow(dead_code)]
 pub struct LLMEngine {
     pub runners: Arc<RwLock<RunnerType>>,
@@ -89,16 +114,33 @@ pub struct LLMEngine {
     decode_length: HashMap<usize, usize>,
     seq_prefilled_reasoning_end: HashMap<usize, String>,
     seq_prompt_replays: HashMap<usize, Vec<u32>>,
+    /// Sequences whose token history or `bad_words` state runner processes hold, later
+    /// decode steps only send the new token
+    runner_token_histories: HashSet<usize>,
     last_check_throughput_time: usize,
+    /// Draft tokens verified and accepted since the last throughput report
+    spec_draft_tokens: usize,
+    spec_accepted_tokens: usize,
     active_requests: HashSet<usize>,
+    /// Logprobs of recently scored inputs, reused for inputs sharing their prefix
+    score_cache: ScoreCache,
+    /// Usage counters of the API key each sequence was submitted with
+    seq_key_usage: HashMap<usize, Arc<KeyUsage>>,
+    /// KV tokens released by finished sequences, used to estimate queue wait times
+    kv_releases: VecDeque<(Instant, usize)>,
+    started_at: Instant,
     cancelled_sequences: Vec<usize>,
     stop_flag: Arc<AtomicBool>,
     has_vision: bool,
+    /// `bad_words` match on token bytes, which needs the llguidance tokenizer
+    supports_bad_words: bool,
     model_name: String,
     pub model_type: ModelType,
     pub tool_config: ToolConfig,
     pub img_cfg: Option<ImageProcessConfig>,
     pub guidance_tokens: GuidanceTokens,
+    /// Fill-in-the-middle prompt format, `None` if the tokenizer has no FIM sentinels.
+    pub fim_template: Option<FimTemplate>,
 }
 
 impl LLMEngine {
@@ -113,12 +155,14 @@ impl LLMEngine {
                 None
             }
         };
+        let supports_bad_words = llg_factory.is_some();
 
         let stop_flag = Arc::new(AtomicBool::new(false));
         let model_loaded = Arc::new(AtomicBool::new(false));
         let (mut econfig, use_runner) =
             prepare_engine_config(econfig, &config, &config_tokenizer, &mut generation_cfg);
         config.fp8_kvcache = econfig.fp8_kvcache;
+        let policy = scheduling_policy::from_name(econfig.scheduling_policy.as_deref())?;
 
         // In case config file missing bos and eos configuration
         config.apply_generation_cfg(generation_cfg.as_ref());
@@ -158,6 +202,64 @@ impl LLMEngine {
         let (model_type, default_chat_template, is_rope_i) =
             crate::utils::get_arch_rope(&tokenizer, arch.clone())?;
         log_info!("Use ROPE interleaved {is_rope_i}");
+        if econfig.speculative_config.is_some() && !spec_decode::supports_model(&model_type) {
+            crate::log_warn!(
+                "Speculative decoding is not supported for {:?} models, disabled.",
+                model_type
+            );
+            econfig.speculative_config = None;
+        }
+        if cfg!(feature = "flashinfer")
+            && econfig
+                .speculative_config
+                .as_ref()
+                .is_some_and(|spec| spec.uses_draft_model())
+        {
+            crate::log_warn!(
+                "Draft-model speculative decoding is not supported with the flashinfer backend, disabled."
+            );
+            econfig.speculative_config = None;
+        }
+        if econfig
+            .speculative_config
+            .as_ref()
+            .is_some_and(|spec| spec.method == SpeculativeMethod::Mtp)
+            && (is_gguf
+                || !spec_decode::supports_mtp(&model_type)
+                || config.num_nextn_predict_layers() == 0)
+        {
+            crate::log_warn!(
+                "No multi-token prediction layers available for {:?} models, MTP speculative decoding disabled.",
+                model_type
+            );
+            econfig.speculative_config = None;
+        }
+        // generation_config.json defaults apply to every request that leaves them unset
+        if let Some(cfg) = econfig.generation_cfg.as_ref().filter(|_| {
+            econfig
+                .speculative_config
+                .as_ref()
+                .is_some_and(|spec| spec.is_enabled())
+        }) {
+            let defaults = SamplingParams {
+                repetition_penalty: cfg.repetition_penalty,
+                no_repeat_ngram_size: cfg.no_repeat_ngram_size,
+                frequency_penalty: cfg.frequency_penalty,
+                presence_penalty: cfg.presence_penalty,
+                ..Default::default()
+            };
+            if !defaults.allows_speculation() {
+                crate::log_warn!(
+                    "generation_config sets repetition_penalty={:?}, no_repeat_ngram_size={:?}, frequency_penalty={:?}, presence_penalty={:?}: requests that do not override them are decoded without speculation.",
+                    cfg.repetition_penalty,
+                    cfg.no_repeat_ngram_size,
+                    cfg.frequency_penalty,
+                    cfg.presence_penalty
+                );
+            }
+        }
+        let draft_model = spec_decode::resolve_draft_model(&econfig, &config)?;
+        let mtp_config = spec_decode::mtp_cache_config(&econfig, &config);
 
         let is_pd_server = if let Some(p_cfg) = &econfig.pd_config {
             matches!(p_cfg.role, PdRole::Server)
@@ -230,6 +332,7 @@ impl LLMEngine {
                     reporter,
                     transfer,
                     llg_factory.clone(),
+                    draft_model.clone(),
                     None,
                 )?;
                 drop(vb);
@@ -344,6 +447,7 @@ impl LLMEngine {
                         is_gguf,
                         dtype: dtype.into(),
                         is_rope_i,
+                        draft: draft_model.clone(),
                         #[cfg(feature = "nccl")]
                         nccl_id: crate::runner::NcclId(nccl_id.clone()),
                     });
@@ -355,6 +459,17 @@ impl LLMEngine {
                         let mut econfig = econfig.clone();
                         // Use new KVCacheAllocator for multi-rank negotiation
                         let allocator = KVCacheAllocator::new(&econfig, &config, dtype);
+                        // The draft model or the MTP layer keeps a KV cache of its own
+                        let draft_config = draft_model
+                            .as_ref()
+                            .map(|draft| &draft.config)
+                            .or(mtp_config.as_ref());
+                        let allocator = match draft_config {
+                            Some(draft_config) => {
+                                allocator.with_draft_model(&econfig, draft_config, dtype)
+                            }
+                            None => allocator,
+                        };
                         let device_ids = econfig.device_ids.clone().unwrap_or(vec![0]);
                         match allocator.plan(&device_ids, &mut econfig) {
                             Ok(_) => {
@@ -405,6 +520,7 @@ impl LLMEngine {
         // Preserve model-specific tool token detection for non-guided paths.
         let mut tool_config = ToolConfig::for_model_type(&model_type);
         tool_config.validate_with_tokenizer(&tokenizer, &model_type);
+        let fim_template = FimTemplate::detect(&tokenizer, &model_type);
         let tool_call_start_ids = tool_config.tool_call_start_ids(&tokenizer);
         let tool_call_end_ids = tool_config.tool_call_end_ids(&tokenizer);
 
@@ -427,6 +543,8 @@ impl LLMEngine {
 
         // Set tokenizer for JSON tool call detection (for models like Qwen3 that output raw JSON)
         scheduler.set_tokenizer(Arc::new(tokenizer.clone()));
+        log_info!("Scheduling policy: {}", policy.name());
+        scheduler.set_policy(policy);
 
         log_warn!(
             "Maximum batched tokens {} ({} blocks x Block_Size {} for KV cache). Additional CPU KV Cache blocks {}.",
@@ -484,16 +602,25 @@ impl LLMEngine {
             decode_length: HashMap::new(),
             seq_prefilled_reasoning_end: HashMap::new(),
             seq_prompt_replays: HashMap::new(),
+            runner_token_histories: HashSet::new(),
             last_check_throughput_time: 0,
+            spec_draft_tokens: 0,
+            spec_accepted_tokens: 0,
             active_requests: HashSet::new(),
+            score_cache: ScoreCache::default(),
+            seq_key_usage: HashMap::new(),
+            kv_releases: VecDeque::new(),
+            started_at: Instant::now(),
             cancelled_sequences: Vec::new(),
             stop_flag: stop_flag.clone(),
             has_vision: config.is_multi_model.unwrap_or(false),
+            supports_bad_words,
             model_type,
             tool_config,
             img_cfg,
             model_name,
             guidance_tokens,
+            fim_template,
         }));
 
         Self::start_engine(engine.clone());
@@ -513,8 +640,31 @@ impl LLMEngine {
             .encode_fast(prompt, true)
             .expect("encode failed!");
         let token_ids: Vec<u32> = tokens.get_ids().iter().map(|&x| x).collect();
-        let length = token_ids.len();
         let raw_replay_token_ids = self.match_prompt_replay_candidate(&token_ids);
+        let (seq_id, length) =
+            self.add_token_request_(params, token_ids, request_type, images, image_idx)?;
+        if let Some(end_marker) = detect_prefilled_reasoning_end_marker(prompt) {
+            self.seq_prefilled_reasoning_end.insert(seq_id, end_marker);
+        }
+        if let Some(replay_ids) = raw_replay_token_ids {
+            self.seq_prompt_replays.insert(seq_id, replay_ids);
+        }
+        Ok((seq_id, length))
+    }
+
+    /// Queue a sequence for already tokenized prompt tokens.
+    fn add_token_request_(
+        &mut self,
+        params: &SamplingParams,
+        token_ids: Vec<u32>,
+        request_type: &RequestType,
+        images: &Option<ImageData>,
+        image_idx: i32,
+    ) -> Result<(usize, usize)> {
+        let length = token_ids.len();
+        if length == 0 {
+            candle_core::bail!("Prompt cannot be empty");
+        }
         if let Some(max_model_len) = self.econfig.max_model_len {
             if length > max_model_len - 1 {
                 candle_core::bail!(
@@ -524,6 +674,9 @@ impl LLMEngine {
                 );
             }
         }
+        if params.uses_output_history() && !self.supports_bad_words {
+            candle_core::bail!("{}", BAD_WORDS_UNSUPPORTED);
+        }
         let mut params = params.clone();
         params.max_tokens = Some(
             params
@@ -549,6 +702,13 @@ impl LLMEngine {
             params.top_p = top_p;
             params.frequency_penalty = frequency_penalty;
             params.presence_penalty = presence_penalty;
+            params.min_p = params.min_p.or(gen_cfg.min_p);
+            params.typical_p = params.typical_p.or(gen_cfg.typical_p);
+            params.epsilon_cutoff = params.epsilon_cutoff.or(gen_cfg.epsilon_cutoff);
+            params.eta_cutoff = params.eta_cutoff.or(gen_cfg.eta_cutoff);
+            params.repetition_penalty = params.repetition_penalty.or(gen_cfg.repetition_penalty);
+            params.no_repeat_ngram_size =
+                params.no_repeat_ngram_size.or(gen_cfg.no_repeat_ngram_size);
         }
 
         if let Some(stop_sequences) = &params.stop_sequences {
@@ -580,13 +740,49 @@ impl LLMEngine {
                 params.stop_sequences = Some(resolved_stop_sequences);
             }
         }
-        let seq = Sequence::new(
+        if params.dry_multiplier.is_some_and(|m| m > 0.0) {
+            let breakers = params
+                .dry_sequence_breakers
+                .clone()
+                .unwrap_or_else(|| ["\n", ":", "\"", "*"].map(String::from).to_vec());
+            let mut breaker_ids = Vec::new();
+            for breaker in &breakers {
+                match self.tokenizer.encode(breaker.as_str(), false) {
+                    Ok(encoding) => breaker_ids.extend_from_slice(encoding.get_ids()),
+                    Err(err) => {
+                        crate::log_warn!(
+                            "Failed to encode DRY sequence breaker '{}': {:?}",
+                            breaker,
+                            err
+                        );
+                    }
+                }
+            }
+            params.dry_sequence_breaker_ids = Some(breaker_ids);
+        }
+        let mut prediction_token_ids = Vec::new();
+        if let Some(prediction) = params.prediction.take().filter(|p| !p.is_empty()) {
+            if !spec_decode::supports_model(&self.model_type) || !self.scheduler.verifies_drafts() {
+                crate::log_warn!(
+                    "Predicted output ignored, draft tokens cannot be verified for this model or configuration."
+                );
+            } else {
+                match self.tokenizer.encode(prediction.as_str(), false) {
+                    Ok(encoding) => prediction_token_ids = encoding.get_ids().to_vec(),
+                    Err(err) => {
+                        crate::log_warn!("Failed to encode predicted output: {:?}", err);
+                    }
+                }
+            }
+        }
+        let mut seq = Sequence::new(
             token_ids,
             self.econfig.block_size,
             params,
             images,
             image_idx,
         );
+        seq.prediction_token_ids = prediction_token_ids;
 
         let mut required_blocks = self.scheduler.block_manager.required_blocks(&seq);
         let mut available_blocks = self.scheduler.block_manager.get_num_free_blocks();
@@ -611,12 +807,6 @@ impl LLMEngine {
             );
         }
         let seq_id = self.scheduler.add(seq);
-        if let Some(end_marker) = detect_prefilled_reasoning_end_marker(prompt) {
-            self.seq_prefilled_reasoning_end.insert(seq_id, end_marker);
-        }
-        if let Some(replay_ids) = raw_replay_token_ids {
-            self.seq_prompt_replays.insert(seq_id, replay_ids);
-        }
 
         if *request_type == RequestType::Stream {
             let tokenizer = self.tokenizer.clone();
@@ -630,9 +820,14 @@ impl LLMEngine {
             self.stream_decoders.insert(seq_id, boxed_decoder);
         }
         self.active_requests.insert(seq_id);
+        self.track_key_usage(seq_id);
         Ok((seq_id, length))
     }
 
+    pub fn supports_bad_words(&self) -> bool {
+        self.supports_bad_words
+    }
+
     pub fn add_request(
         &mut self,
         params: &SamplingParams,
@@ -643,6 +838,35 @@ impl LLMEngine {
     ) -> Result<(usize, usize, Receiver<StreamItem>)> {
         let (seq_id, prompt_length) =
             self.add_request_(params, prompt, &request_type, images, image_idx)?;
+        let rx = self.attach_receiver(seq_id, prompt_length, params, request_type);
+        Ok((seq_id, prompt_length, rx))
+    }
+
+    /// Queue a request whose prompt is given as token ids, bypassing the chat template and
+    /// the reasoning handling of chat prompts (raw completions).
+    pub fn add_token_request(
+        &mut self,
+        params: &SamplingParams,
+        token_ids: Vec<u32>,
+        request_type: RequestType,
+    ) -> Result<(usize, usize, Receiver<StreamItem>)> {
+        let vocab_size = self.tokenizer.get_vocab_size(true);
+        if let Some(&id) = token_ids.iter().find(|&&id| id as usize >= vocab_size) {
+            candle_core::bail!("Token id {} is out of the vocabulary ({})", id, vocab_size);
+        }
+        let (seq_id, prompt_length) =
+            self.add_token_request_(params, token_ids, &request_type, &None, -1)?;
+        let rx = self.attach_receiver(seq_id, prompt_length, params, request_type);
+        Ok((seq_id, prompt_length, rx))
+    }
+
+    fn attach_receiver(
+        &mut self,
+        seq_id: usize,
+        prompt_length: usize,
+        params: &SamplingParams,
+        request_type: RequestType,
+    ) -> Receiver<StreamItem> {
         let (tx, rx) = channel(1024);
         self.stream_senders.insert(seq_id, tx);
         self.request_types.insert(seq_id, request_type.clone());
@@ -655,7 +879,7 @@ impl LLMEngine {
                 params.session_id,
             );
         }
-        Ok((seq_id, prompt_length, rx))
+        rx
     }
 
     pub fn get_num_cached_tokens(&self) -> usize {
@@ -731,11 +955,35 @@ impl LLMEngine {
         self.scheduler.get_available_kv_tokens()
     }
 
+    /// Tokens appended by a speculative step that the step output does not report: all of
+    /// them for finished sequences (the finishing token is never appended), all but the
+    /// last one otherwise.
+    fn pending_accepted_tokens(
+        &self,
+        scheduled_ids: &[usize],
+        prev_output_lens: &[(usize, usize)],
+    ) -> HashMap<usize, Vec<u32>> {
+        let prev_output_lens: HashMap<usize, usize> = prev_output_lens.iter().copied().collect();
+        scheduled_ids
+            .iter()
+            .filter_map(|&idx| {
+                let s = self.scheduler.get_running(idx)?;
+                let prev = *prev_output_lens.get(&s.id)?;
+                let mut appended = s.output_ids.get(prev..)?.to_vec();
+                if !s.is_finished() || s.is_tool_call_end {
+                    appended.pop();
+                }
+                (!appended.is_empty()).then_some((s.id, appended))
+            })
+            .collect()
+    }
+
     fn take_prompt_replay_token_ids(&mut self, seq_id: usize) -> Option<Vec<u32>> {
         self.seq_prompt_replays.remove(&seq_id)
     }
 
     pub fn notify_runner_finished(&mut self, id: usize) -> Result<()> {
+        self.runner_token_histories.remove(&id);
         match &mut *self.runners.write() {
             RunnerType::Thread(model_runner) => Ok(model_runner.finished(id)),
             RunnerType::Process(ref mut runner_streams) => {
@@ -754,6 +1002,9 @@ impl LLMEngine {
     pub fn step(&mut self) -> Result<usize> {
         pub struct DecodedIds(Either<Vec<usize>, Vec<usize>>);
 
+        let mut step_logprobs: HashMap<usize, TokenLogprobs> = HashMap::new();
+        // Accepted draft tokens not covered by the per-sequence `last_token` reporting below
+        let mut spec_pending_tokens: HashMap<usize, Vec<u32>> = HashMap::new();
         // Get scheduled sequence indexes and prefill flag
         let (scheduled_ids, is_prefill) = match self.scheduler.schedule() {
             Ok((ids, prefill)) => (ids, prefill),
@@ -774,20 +1025,33 @@ impl LLMEngine {
 
             // Get immutable references to scheduled sequences for model_runner
             let seqs = self.scheduler.get_sequences(&scheduled_ids);
+            let speculative = !is_prefill && seqs.iter().any(|s| s.is_speculative());
 
-            let output_ids = match &mut *self.runners.write() {
+            let output = match &mut *self.runners.write() {
                 RunnerType::Thread(model_runner) => {
                     // Run model on the scheduled sequences in the main thread
-                    model_runner.run(Seqs::SeqRefs(&seqs), is_prefill)?
+                    if speculative {
+                        model_runner.run_speculative(&seqs)?
+                    } else {
+                        model_runner.run(Seqs::SeqRefs(&seqs), is_prefill)?
+                    }
                 }
                 RunnerType::Process(ref mut runner_streams) => {
                     let request = if is_prefill {
                         let sequences = seqs.iter().map(|s| (*s).clone()).collect::<Vec<_>>();
                         MessageType::RunPrefill((sequences, true))
+                    } else if speculative {
+                        let sequences = seqs.iter().map(|s| (*s).clone()).collect::<Vec<_>>();
+                        MessageType::RunSpeculative(sequences)
                     } else {
                         let sequences = seqs
                             .iter()
-                            .map(|s| DecodeSequence::new(s))
+                            .map(|s| {
+                                let send_history = (s.sampling_params.uses_token_history()
+                                    || s.sampling_params.uses_output_history())
+                                    && self.runner_token_histories.insert(s.id);
+                                DecodeSequence::with_token_history(s, send_history)
+                            })
                             .collect::<Vec<_>>();
                         MessageType::RunDecode((sequences, false))
                     };
@@ -797,7 +1061,7 @@ impl LLMEngine {
                         .map(|s| s.try_clone().expect("clone failed"))
                         .collect();
 
-                    let all_outputs: Result<Vec<Vec<u32>>> = cloned_streams
+                    let all_outputs: Result<Vec<RunOutput>> = cloned_streams
                         .into_par_iter()
                         .map(|mut stream| {
                             let msg = request.clone();
@@ -805,11 +1069,11 @@ impl LLMEngine {
                             let response = receive_local(&mut stream, false)?;
 
                             match response {
-                                MessageType::RunResponse(output_ids) => {
-                                    if output_ids.len() == 0 {
+                                MessageType::RunResponse(output) => {
+                                    if output.token_ids.len() == 0 {
                                         candle_core::bail!("Runner step error, no response!")
                                     } else {
-                                        Ok(output_ids)
+                                        Ok(output)
                                     }
                                 }
                                 other => {
@@ -821,14 +1085,27 @@ impl LLMEngine {
 
                     let all_outputs = all_outputs.map_err(candle_core::Error::wrap)?;
                     // Only run postprocess once after all runners finish (use first result)
-                    if let Some(output_ids) = all_outputs.first() {
-                        output_ids.clone()
+                    if let Some(output) = all_outputs.into_iter().next() {
+                        output
                         // self.scheduler.postprocess(&scheduled_ids, output_ids);
                     } else {
                         candle_core::bail!("No output ids received from model runners");
                     }
                 }
             };
+            step_logprobs = seqs
+                .iter()
+                .zip(output.logprobs)
+                .filter_map(|(s, logprobs)| logprobs.map(|l| (s.id, l)))
+                .collect();
+            let output_ids = output.token_ids;
+            let mirostat_mu = output.mirostat_mu;
+            // Output length before this step, to find the tokens accepted from drafts
+            let prev_output_lens: Vec<(usize, usize)> = if speculative {
+                seqs.iter().map(|s| (s.id, s.output_len())).collect()
+            } else {
+                Vec::new()
+            };
             // Postprocess sequences by modifying them inside the scheduler
             if is_prefill {
                 let (indices, finished_indices) =
@@ -839,17 +1116,48 @@ impl LLMEngine {
                     return Ok(0);
                 } else {
                     let output_ids: Vec<u32> = indices.iter().map(|&i| output_ids[i]).collect();
-                    self.scheduler.postprocess(&finished_indices, &output_ids);
+                    let (ids, output_ids) = self.scheduler.advance_beam_groups(
+                        &finished_indices,
+                        &output_ids,
+                        &mut step_logprobs,
+                    );
+                    self.scheduler.postprocess(&ids, &output_ids);
+                    let mirostat_mu: Vec<Option<f32>> = indices
+                        .iter()
+                        .map(|&i| mirostat_mu.get(i).copied().flatten())
+                        .collect();
+                    self.scheduler
+                        .update_mirostat_mu(&finished_indices, &mirostat_mu);
                     DecodedIds(Either::Left(finished_indices))
                 }
             } else {
-                self.scheduler.postprocess(&scheduled_ids, &output_ids);
+                let (ids, output_ids) = self.scheduler.advance_beam_groups(
+                    &scheduled_ids,
+                    &output_ids,
+                    &mut step_logprobs,
+                );
+                self.scheduler.postprocess(&ids, &output_ids);
+                if speculative {
+                    self.spec_draft_tokens += output.num_draft_tokens.iter().sum::<usize>();
+                    self.spec_accepted_tokens += output
+                        .accepted_token_ids
+                        .iter()
+                        .map(Vec::len)
+                        .sum::<usize>();
+                    self.scheduler
+                        .postprocess_accepted_drafts(&scheduled_ids, &output.accepted_token_ids);
+                    spec_pending_tokens =
+                        self.pending_accepted_tokens(&scheduled_ids, &prev_output_lens);
+                }
+                self.scheduler
+                    .update_mirostat_mu(&scheduled_ids, &mirostat_mu);
                 DecodedIds(Either::Left(scheduled_ids))
             }
         } else {
             DecodedIds(Either::Right(vec![]))
         };
 
+        let released_beams = self.finish_beam_groups();
         let (indices, is_running): (&Vec<usize>, bool) = match &decoded_ids.0 {
             Either::Left(indices) => (indices, true),
             Either::Right(indices) => (indices, false),
@@ -863,11 +1171,28 @@ impl LLMEngine {
             };
             if let Some(s) = sq {
                 let seq_id = s.id;
+                // Beam search requests are reported once their whole group finishes
+                if released_beams.contains(&seq_id) || self.scheduler.is_beam_sequence(seq_id) {
+                    continue;
+                }
                 if s.is_finished() {
                     // Normal finish handling
 
                     if let Some(sender) = self.stream_senders.get_mut(&seq_id) {
                         if let Some(request_type) = self.request_types.get(&seq_id) {
+                            for token_id in spec_pending_tokens.remove(&seq_id).unwrap_or_default()
+                            {
+                                if *request_type == RequestType::Stream {
+                                    if let Some(decoder) = self.stream_decoders.get_mut(&seq_id) {
+                                        if let Some(tok) = decoder.step(token_id)? {
+                                            let _ =
+                                                sender.try_send(StreamItem::Token(tok, token_id));
+                                        }
+                                    }
+                                } else {
+                                    let _ = sender.try_send(StreamItem::TokenID(token_id));
+                                }
+                            }
                             let prompt_start_time = s.created_time();
 
                             let decode_finish_time = SystemTime::now()
@@ -883,6 +1208,32 @@ impl LLMEngine {
                                 decode_finish_time
                             };
 
+                            let finish_reason = if s.is_tool_call_end {
+                                FinishReason::ToolCalls
+                            } else if s.stop_sequence.is_none()
+                                && s.sampling_params
+                                    .max_tokens
+                                    .is_some_and(|max| s.output_len() >= max)
+                            {
+                                FinishReason::Length
+                            } else {
+                                FinishReason::Stop
+                            };
+                            metrics().record_finished(
+                                finish_reason,
+                                s.len() - s.output_len(),
+                                s.output_len(),
+                                prompt_start_time,
+                                decode_start_time,
+                                decode_finish_time,
+                            );
+
+                            if let Some(logprobs) = step_logprobs.remove(&seq_id) {
+                                // Streams only emit the final token on tool-call end
+                                if *request_type == RequestType::Completion || s.is_tool_call_end {
+                                    let _ = sender.try_send(StreamItem::Logprobs(logprobs));
+                                }
+                            }
                             if s.is_tool_call_end {
                                 //finish early, we need to send the last token
                                 if *request_type == RequestType::Stream {
@@ -897,6 +1248,12 @@ impl LLMEngine {
                                 }
                             }
 
+                            if !s.prediction_token_ids.is_empty() {
+                                let _ = sender.try_send(StreamItem::Prediction((
+                                    s.accepted_prediction_tokens,
+                                    s.rejected_prediction_tokens,
+                                )));
+                            }
                             if *request_type == RequestType::Stream {
                                 let _ = sender.try_send(StreamItem::Done((
                                     prompt_start_time,
@@ -922,6 +1279,10 @@ impl LLMEngine {
                     if let Some(_) = self.active_requests.get(&seq_id) {
                         self.active_requests.remove(&seq_id);
                     }
+                    if let Some(usage) = self.seq_key_usage.remove(&seq_id) {
+                        usage.sequence_finished(s.len());
+                    }
+                    self.record_kv_release(s.len());
                     self.decode_start_times.remove(&seq_id);
                     self.decode_length.remove(&seq_id);
                     self.seq_prefilled_reasoning_end.remove(&seq_id);
@@ -967,16 +1328,29 @@ impl LLMEngine {
                         } else {
                             vec![s.last_token]
                         };
+                    if let Some(mut accepted) = spec_pending_tokens.remove(&seq_id) {
+                        accepted.extend(token_ids);
+                        token_ids = accepted;
+                    }
                     if let Some(mut replay_ids) = self.take_prompt_replay_token_ids(seq_id) {
                         replay_ids.extend(token_ids);
                         token_ids = replay_ids;
                     }
 
+                    // Logprobs belong to the sampled token, which is always the last one here
+                    let mut token_logprobs = step_logprobs.remove(&seq_id);
+                    let last_index = token_ids.len() - 1;
                     if let Some(sender) = self.stream_senders.get_mut(&seq_id) {
                         if let Some(request_type) = self.request_types.get(&seq_id) {
                             if *request_type == RequestType::Stream {
                                 if let Some(decoder) = self.stream_decoders.get_mut(&seq_id) {
-                                    for token_id in token_ids {
+                                    for (i, token_id) in token_ids.into_iter().enumerate() {
+                                        if i == last_index {
+                                            if let Some(logprobs) = token_logprobs.take() {
+                                                let _ =
+                                                    sender.try_send(StreamItem::Logprobs(logprobs));
+                                            }
+                                        }
                                         if let Some(tok) = decoder.step(token_id)? {
                                             let result =
                                                 sender.try_send(StreamItem::Token(tok, token_id));
@@ -992,7 +1366,12 @@ impl LLMEngine {
                                 }
                             } else {
                                 //completion request will be decoded at the final stage (at once)
-                                for token_id in token_ids {
+                                for (i, token_id) in token_ids.into_iter().enumerate() {
+                                    if i == last_index {
+                                        if let Some(logprobs) = token_logprobs.take() {
+                                            let _ = sender.try_send(StreamItem::Logprobs(logprobs));
+                                        }
+                                    }
                                     /*
                                         Check if the receiver is still active.
                                         If the client disconnected, collect_sync_results will be dropped,
@@ -1053,17 +1432,89 @@ impl LLMEngine {
             > 0
     }
 
+    /// Send the best hypothesis of each finished beam search request and release the runner
+    /// state of beams that left their group. Returns the released beam ids.
+    fn finish_beam_groups(&mut self) -> Vec<usize> {
+        let released = self.scheduler.take_released_beams();
+        for &seq_id in &released {
+            let _ = self.notify_runner_finished(seq_id);
+        }
+        for (root_id, group) in self.scheduler.take_finished_beam_groups() {
+            let decode_finish_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_millis() as usize;
+            let (output_ids, end) = group
+                .best()
+                .map(|h| (h.output_ids.clone(), h.end.clone()))
+                .unwrap_or((Vec::new(), BeamEnd::Length));
+            let (finish_reason, stop_sequence) = match end {
+                BeamEnd::Eos => (FinishReason::Stop, None),
+                BeamEnd::Stop(stop_sequence) => (FinishReason::Stop, stop_sequence),
+                BeamEnd::Length => (FinishReason::Length, None),
+            };
+            crate::log_info!(
+                "Beam search [seq_id {}] finished with {} tokens",
+                root_id,
+                output_ids.len()
+            );
+            let decode_start_time = group.decode_start_time.unwrap_or(decode_finish_time);
+            if let Some(usage) = self.seq_key_usage.remove(&root_id) {
+                usage.sequence_finished(group.prompt_len + output_ids.len());
+            }
+            self.record_kv_release(group.prompt_len + output_ids.len());
+            metrics().record_finished(
+                finish_reason,
+                group.prompt_len,
+                output_ids.len(),
+                group.created_time,
+                decode_start_time,
+                decode_finish_time,
+            );
+            if let Some(sender) = self.stream_senders.remove(&root_id) {
+                let _ = sender.try_send(StreamItem::Completion((
+                    group.created_time,
+                    decode_start_time,
+                    decode_finish_time,
+                    output_ids,
+                    stop_sequence,
+                )));
+            }
+            self.active_requests.remove(&root_id);
+            self.seq_prefilled_reasoning_end.remove(&root_id);
+            self.seq_prompt_replays.remove(&root_id);
+        }
+        released
+    }
+
     pub fn check_canceled(&mut self, reason: Option<String>) {
         if self.cancelled_sequences.is_empty() {
             return;
         }
-        for i in 0..self.cancelled_sequences.len() {
+        let mut i = 0;
+        while i < self.cancelled_sequences.len() {
             let seq_id = self.cancelled_sequences[i];
+            i += 1;
+            // Forks and beams of the request would otherwise keep decoding
+            for child_id in self.scheduler.linked_sequence_ids(seq_id) {
+                if !self.cancelled_sequences.contains(&child_id) {
+                    self.cancelled_sequences.push(child_id);
+                }
+            }
+            // Aborted sequences are charged for the prompt and tokens generated so far
+            let tokens = self.scheduler.get_seq_token_usage(seq_id).unwrap_or(0);
             self.scheduler.cancel(seq_id);
             // Ensure model-side per-sequence state (e.g., Qwen3.5 Mamba cache slot) is released.
             let _ = self.notify_runner_finished(seq_id);
-            if let Some(_) = self.active_requests.get(&seq_id) {
-                self.active_requests.remove(&seq_id);
+            if self.active_requests.remove(&seq_id) {
+                metrics().record_outcome(if reason.is_some() {
+                    FinishReason::Error
+                } else {
+                    FinishReason::Abort
+                });
+            }
+            if let Some(usage) = self.seq_key_usage.remove(&seq_id) {
+                usage.sequence_finished(tokens);
             }
             self.stream_decoders.remove(&seq_id);
             self.decode_start_times.remove(&seq_id);
@@ -1124,6 +1575,17 @@ impl LLMEngine {
             )
         }
 
+        if self.spec_draft_tokens > 0 {
+            crate::log_info!(
+                "Speculative decoding: {}/{} draft tokens accepted ({:.1}%)",
+                self.spec_accepted_tokens,
+                self.spec_draft_tokens,
+                self.spec_accepted_tokens as f64 * 100.0 / self.spec_draft_tokens as f64
+            );
+            self.spec_draft_tokens = 0;
+            self.spec_accepted_tokens = 0;
+        }
+
         if total_decoded_length % 100 > 50 {
             self.scheduler.print_free_blocks();
         }
@@ -1191,6 +1653,78 @@ impl LLMEngine {
         self.active_requests.is_empty()
     }
 
+    pub fn num_active_requests(&self) -> usize {
+        self.active_requests.len()
+    }
+
+    /// Attributes a new sequence to the API key of the request being handled, if any.
+    fn track_key_usage(&mut self, seq_id: usize) {
+        if let Some(usage) = crate::server::auth::current_key_usage() {
+            usage.sequence_started();
+            self.seq_key_usage.insert(seq_id, usage);
+        }
+    }
+
+    fn record_kv_release(&mut self, tokens: usize) {
+        let now = Instant::now();
+        self.kv_releases.push_back((now, tokens));
+        while let Some((at, _)) = self.kv_releases.front() {
+            if now.duration_since(*at) <= KV_RELEASE_WINDOW {
+                break;
+            }
+            self.kv_releases.pop_front();
+        }
+    }
+
+    /// Waiting queue size and an estimate of how long a new request would wait.
+    ///
+    /// Waiting prompts that do not fit in the free KV cache have to wait for running
+    /// sequences to finish, so the estimate divides that excess by the rate at which
+    /// finished sequences released KV tokens over the last minute. It is `None` when
+    /// nothing finished recently.
+    pub fn queue_stats(&self) -> QueueStats {
+        let (waiting_requests, waiting_tokens) = self.scheduler.waiting_load();
+        let excess = waiting_tokens.saturating_sub(self.scheduler.get_available_kv_tokens());
+        let now = Instant::now();
+        let released: usize = self
+            .kv_releases
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= KV_RELEASE_WINDOW)
+            .map(|(_, tokens)| tokens)
+            .sum();
+        let elapsed = now
+            .duration_since(self.started_at)
+            .clamp(Duration::from_secs(1), KV_RELEASE_WINDOW);
+        let estimated_wait = if excess == 0 {
+            Some(Duration::ZERO)
+        } else if released == 0 {
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                excess as f64 * elapsed.as_secs_f64() / released as f64,
+            ))
+        };
+        QueueStats {
+            waiting_requests,
+            waiting_tokens,
+            estimated_wait,
+        }
+    }
+
+    /// Samples the scheduler state exported by `/metrics`.
+    pub fn engine_gauges(&self) -> EngineGauges {
+        let (num_waiting, num_running, num_swapped) = self.scheduler.queue_depths();
+        let (cpu_swap_used_gb, cpu_swap_total_gb) = self.scheduler.get_cpu_swap_usage();
+        EngineGauges {
+            num_waiting,
+            num_running,
+            num_swapped,
+            kv_cache_usage: self.scheduler.kv_cache_usage_percent(),
+            cpu_swap_used_gb,
+            cpu_swap_total_gb,
+        }
+    }
+
     pub fn is_pd_mode(&self) -> bool {
         self.econfig.pd_config.is_some()
     }
@@ -1203,6 +1737,20 @@ impl LLMEngine {
         }
     }
 
+    /// Whether the PD peer is connected (always false outside PD mode).
+    pub fn is_pd_connected(&self) -> bool {
+        self.is_pd_mode() && self.scheduler.is_pd_connected()
+    }
+
+    /// Whether the runner subprocesses still answer heartbeats (always true when the
+    /// model runs in this process).
+    pub fn runners_alive(&self) -> bool {
+        match &*self.runners.read() {
+            RunnerType::Thread(_) => true,
+            RunnerType::Process(_) => crate::utils::heartbeat::peers_alive(),
+        }
+    }
+
     pub fn generate_sync(
         &mut self,
         params: &Vec<SamplingParams>,
@@ -1214,6 +1762,9 @@ impl LLMEngine {
         if params.len() != message_list.len() {
             candle_core::bail!("size of sampling parameters is not match with size of prompts!");
         }
+        for param in params {
+            self.check_sequence_group(param, &RequestType::Completion)?;
+        }
         let mut receivers = Vec::new();
         for (param, messages) in params.iter().zip(message_list.iter()) {
             let (prompt, image_idx) = self.apply_chat_template(param, messages, tools, false);
@@ -1224,12 +1775,113 @@ impl LLMEngine {
                 self.add_request(param, &prompt, RequestType::Completion, &images, image_idx)
             {
                 receivers.push((seq_id, prompt_length, rx));
+                let num_forks = param.n.unwrap_or(1).saturating_sub(1);
+                receivers.extend(self.add_forks(seq_id, prompt_length, num_forks));
             }
         }
 
         Ok(receivers)
     }
 
+    /// Raw completions: each prompt is already tokenized and goes to the model as is, without
+    /// a chat template. Prompts queued before a failing one are cancelled.
+    pub fn generate_completion_sync(
+        &mut self,
+        params: &SamplingParams,
+        prompts: Vec<Vec<u32>>,
+    ) -> Result<Vec<(usize, usize, mpsc::Receiver<StreamItem>)>> {
+        self.check_sequence_group(params, &RequestType::Completion)?;
+        let num_forks = params.n.unwrap_or(1).saturating_sub(1);
+        let mut receivers = Vec::new();
+        for token_ids in prompts {
+            match self.add_token_request(params, token_ids, RequestType::Completion) {
+                Ok((seq_id, prompt_length, rx)) => {
+                    receivers.push((seq_id, prompt_length, rx));
+                    receivers.extend(self.add_forks(seq_id, prompt_length, num_forks));
+                }
+                Err(e) => {
+                    for (seq_id, _, _) in &receivers {
+                        self.cancel(*seq_id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(receivers)
+    }
+
+    /// Streaming counterpart of [`Self::generate_completion_sync`] for a single prompt.
+    pub fn generate_completion_stream(
+        &mut self,
+        params: &SamplingParams,
+        token_ids: Vec<u32>,
+    ) -> Result<(usize, usize, mpsc::Receiver<StreamItem>)> {
+        self.check_sequence_group(params, &RequestType::Stream)?;
+        self.add_token_request(params, token_ids, RequestType::Stream)
+    }
+
+    /// Requests decoded as several sequences (`n` > 1 or beam search) fork KV blocks in the
+    /// scheduler, which is not available for streaming, PD disaggregation or mamba models.
+    fn check_sequence_group(
+        &self,
+        params: &SamplingParams,
+        request_type: &RequestType,
+    ) -> Result<()> {
+        let n = params.n.unwrap_or(1);
+        if n == 0 {
+            candle_core::bail!("n must be at least 1");
+        }
+        let beam_search = BeamSearchParams::from_sampling_params(params).is_some();
+        let feature = match (n > 1, beam_search) {
+            (true, true) => candle_core::bail!("n > 1 cannot be combined with beam search"),
+            (true, false) => "n > 1",
+            (false, true) => "beam search",
+            (false, false) => return Ok(()),
+        };
+        if *request_type == RequestType::Stream {
+            candle_core::bail!("{} is only supported for non-streaming requests", feature);
+        }
+        if self.is_pd_mode() {
+            candle_core::bail!("{} is not supported in PD disaggregation mode", feature);
+        }
+        if self.scheduler.block_manager.has_mamba_state() {
+            candle_core::bail!("{} is not supported for hybrid (mamba) models", feature);
+        }
+        if params.beam_width.is_some_and(|w| w > MAX_BEAM_WIDTH) {
+            candle_core::bail!("beam_width must be at most {}", MAX_BEAM_WIDTH);
+        }
+        if beam_search && (params.grammar.is_some() || params.logprobs.unwrap_or(false)) {
+            candle_core::bail!("beam search does not support logprobs or guided decoding");
+        }
+        Ok(())
+    }
+
+    /// Register `num` extra samples of `parent_id`; they are forked from the parent after
+    /// its prefill and share its prompt KV cache blocks.
+    fn add_forks(
+        &mut self,
+        parent_id: usize,
+        prompt_length: usize,
+        num: usize,
+    ) -> Vec<(usize, usize, mpsc::Receiver<StreamItem>)> {
+        let mut receivers = Vec::new();
+        for seq_id in self.scheduler.reserve_forks(parent_id, num) {
+            let (tx, rx) = channel(1024);
+            self.stream_senders.insert(seq_id, tx);
+            self.request_types.insert(seq_id, RequestType::Completion);
+            if let Some(end_marker) = self.seq_prefilled_reasoning_end.get(&parent_id).cloned() {
+                self.seq_prefilled_reasoning_end.insert(seq_id, end_marker);
+            }
+            if let Some(replay_ids) = self.seq_prompt_replays.get(&parent_id).cloned() {
+                self.seq_prompt_replays.insert(seq_id, replay_ids);
+            }
+            self.active_requests.insert(seq_id);
+            self.track_key_usage(seq_id);
+            receivers.push((seq_id, prompt_length, rx));
+        }
+        receivers
+    }
+
     pub async fn collect_sync_results(
         receivers: Vec<(usize, usize, mpsc::Receiver<StreamItem>)>,
         tokenizer: Arc<Tokenizer>,
@@ -1249,6 +1901,8 @@ impl LLMEngine {
                 async move {
                     let mut output: Option<GenerationOutput> = None;
                     let mut collected_token_ids: Vec<u32> = Vec::new();
+                    let mut collected_logprobs: Vec<TokenLogprobs> = Vec::new();
+                    let mut prediction = (0, 0);
 
                     // Initialize decoder for incremental logging if needed
                     let mut decoder = if logger.is_some() {
@@ -1287,9 +1941,18 @@ impl LLMEngine {
                                     decoded_length: decoded_len,
                                     decode_output,
                                     stop_sequence,
+                                    logprobs: std::mem::take(&mut collected_logprobs),
+                                    accepted_prediction_tokens: prediction.0,
+                                    rejected_prediction_tokens: prediction.1,
                                 });
                                 break;
                             }
+                            StreamItem::Logprobs(logprobs) => {
+                                collected_logprobs.push(logprobs);
+                            }
+                            StreamItem::Prediction(counts) => {
+                                prediction = counts;
+                            }
                             StreamItem::Token(_, _) => {
                                 decoded_tokens.fetch_add(1, Ordering::Relaxed);
 
@@ -1377,6 +2040,7 @@ impl LLMEngine {
         tools: &Vec<Tool>,
         logger: &Option<Arc<ChatCompletionLogger>>,
     ) -> Result<(usize, usize, Option<String>, mpsc::Receiver<StreamItem>)> {
+        self.check_sequence_group(params, &RequestType::Stream)?;
         let (prompt, image_idx) = self.apply_chat_template(params, messages, tools, false);
         if let Some(ref l) = logger {
             l.log_prompt(&prompt);
@@ -1483,11 +2147,10 @@ impl LLMEngine {
             let mut chunked_mean: Option<Vec<f32>> = None;
             let mut last_vec: Option<Vec<f32>> = None;
             let mut processed_tokens = 0usize;
-            let chunk_size = if cfg!(feature = "cuda") { 8192 } else { 4096 };
 
             while seq.num_cached_tokens < seq.len() {
                 let remaining = seq.len() - seq.num_cached_tokens;
-                let chunk_tokens = std::cmp::min(chunk_size, remaining);
+                let chunk_tokens = std::cmp::min(PREFILL_CHUNK_SIZE, remaining);
                 let embedding_result = match &mut *self.runners.write() {
                     RunnerType::Thread(model_runner) => model_runner.embed(&[&seq], &strategy),
                     RunnerType::Process(ref mut runner_streams) => {
@@ -1555,8 +2218,8 @@ impl LLMEngine {
 
                 seq.num_cached_tokens += chunk_tokens;
                 processed_tokens += chunk_tokens;
-                if seq.len() > chunk_size {
-                    if chunk_tokens < chunk_size {
+                if seq.len() > PREFILL_CHUNK_SIZE {
+                    if chunk_tokens < PREFILL_CHUNK_SIZE {
                         crate::log_info!(
                             "Embedding chunk prefilled finished {}/{} (Seq {})",
                             seq.num_cached_tokens,
@@ -1595,6 +2258,157 @@ impl LLMEngine {
         Ok((outputs, prompt_tokens))
     }
 
+    /// Score every prompt token of each input without generating anything.
+    /// Returns the per-input scores and the total number of prompt tokens.
+    pub fn score(&mut self, inputs: &[String], top_n: usize) -> Result<(Vec<PromptScore>, usize)> {
+        let mut token_inputs = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            let tokens = self
+                .tokenizer
+                .encode_fast(input.as_str(), true)
+                .map_err(candle_core::Error::wrap)?;
+            token_inputs.push(tokens.get_ids().to_vec());
+        }
+        self.score_tokens(token_inputs, top_n)
+    }
+
+    /// Same as [`Self::score`] for already tokenized inputs.
+    pub fn score_tokens(
+        &mut self,
+        inputs: Vec<Vec<u32>>,
+        top_n: usize,
+    ) -> Result<(Vec<PromptScore>, usize)> {
+        let mut outputs = Vec::new();
+        let mut prompt_tokens = 0;
+
+        for token_ids in inputs {
+            if token_ids.is_empty() {
+                candle_core::bail!("Scoring input cannot be empty");
+            }
+
+            if let Some(max_model_len) = self.econfig.max_model_len {
+                if token_ids.len() > max_model_len - 1 {
+                    candle_core::bail!(
+                        "Scoring input length {} exceeds max_model_len {}",
+                        token_ids.len(),
+                        max_model_len
+                    );
+                }
+            }
+
+            let mut seq = Sequence::new(
+                token_ids.clone(),
+                self.econfig.block_size,
+                SamplingParams::default(),
+                &None,
+                -1,
+            );
+            let required_blocks = self.scheduler.block_manager.required_blocks(&seq);
+            let available_blocks = self.scheduler.block_manager.get_num_free_blocks();
+            if required_blocks > available_blocks {
+                let available_tokens = available_blocks * self.econfig.block_size;
+                let required_tokens = required_blocks * self.econfig.block_size;
+                candle_core::bail!(
+                    "Remaining {} kvcache tokens, but scoring requires {} new tokens, please retry later",
+                    available_tokens,
+                    required_tokens
+                );
+            }
+            // Cached prefix blocks can only be skipped where the logprobs of their tokens are
+            // already known, which caps the reused prefix. Scoring does not restore mamba
+            // prefix snapshots, so hybrid models always recompute the whole input.
+            let known = self.score_cache.known_logprobs(&token_ids, top_n);
+            let reusable_tokens = if self.scheduler.block_manager.has_mamba_state() {
+                0
+            } else {
+                known.len()
+            };
+            if let Err(e) = self
+                .scheduler
+                .block_manager
+                .allocate_with_prefix_limit(&mut seq, reusable_tokens)
+            {
+                self.scheduler.block_manager.deallocate(&seq);
+                return Err(e);
+            }
+
+            let mut logprobs: Vec<TokenLogprobs> = Vec::with_capacity(seq.len());
+            logprobs.extend_from_slice(&known[..seq.num_cached_tokens]);
+
+            while seq.num_cached_tokens < seq.len() {
+                let remaining = seq.len() - seq.num_cached_tokens;
+                let chunk_tokens = std::cmp::min(PREFILL_CHUNK_SIZE, remaining);
+                let score_result = match &mut *self.runners.write() {
+                    RunnerType::Thread(model_runner) => model_runner.score(&[&seq], top_n),
+                    RunnerType::Process(ref mut runner_streams) => {
+                        let request = MessageType::RunScore((vec![seq.clone()], top_n));
+                        let cloned_streams: Vec<LocalStream> = runner_streams
+                            .iter_mut()
+                            .map(|s| s.try_clone().expect("clone failed"))
+                            .collect();
+
+                        let all_outputs: Result<Vec<Vec<Vec<TokenLogprobs>>>> = cloned_streams
+                            .into_par_iter()
+                            .map(|mut stream| {
+                                let msg = request.clone();
+                                send_local(&mut vec![stream.try_clone()?], &msg, false)?;
+                                let response = receive_local(&mut stream, false)?;
+
+                                match response {
+                                    MessageType::RunResponseScore(output_score) => {
+                                        if output_score.len() == 0 {
+                                            candle_core::bail!("Runner step error, no response!")
+                                        } else {
+                                            Ok(output_score)
+                                        }
+                                    }
+                                    other => {
+                                        candle_core::bail!("Unexpected response type: {:?}", other)
+                                    }
+                                }
+                            })
+                            .collect();
+                        let all_outputs = all_outputs.map_err(candle_core::Error::wrap)?;
+                        if let Some(output_score) = all_outputs.first() {
+                            Ok(output_score.clone())
+                        } else {
+                            candle_core::bail!("No prompt logprobs received from model runners");
+                        }
+                    }
+                };
+                match score_result {
+                    Ok(v) => logprobs.extend(v.into_iter().next().unwrap_or_default()),
+                    Err(e) => {
+                        self.scheduler.block_manager.deallocate(&seq);
+                        return Err(e);
+                    }
+                }
+
+                seq.num_cached_tokens += chunk_tokens;
+                if seq.len() > PREFILL_CHUNK_SIZE {
+                    crate::log_info!(
+                        "Scoring chunk prefilled {}/{} (Seq {})",
+                        seq.num_cached_tokens,
+                        seq.len(),
+                        seq.id
+                    );
+                }
+            }
+
+            self.scheduler.block_manager.cache_sequence(&seq);
+            self.scheduler.block_manager.deallocate(&seq);
+            prompt_tokens += seq.len();
+            let score = PromptScore {
+                token_ids,
+                logprobs,
+            };
+            self.score_cache.insert(top_n, score.clone());
+            outputs.push(score);
+        }
+
+        Ok((outputs, prompt_tokens))
+    }
+
     pub fn start_engine(engine: Arc<RwLock<Self>>) {
         GLOBAL_RT.spawn(async move {
             let engine = engine.clone();
@@ -1,11 +1,22 @@
+pub mod beam_search;
 pub mod block_manager;
 pub mod engine;
 pub mod prefix_cache;
 pub mod runner;
 pub mod scheduler;
+pub mod scheduling_policy;
 pub mod sequence;
+pub mod spec_decode;
+use crate::utils::logits_processor::TokenLogprobs;
 #[cfg(feature = "python")]
 use pyo3::pyclass;
+use std::collections::VecDeque;
+
+/// Maximum number of prompt tokens processed in a single prefill step.
+pub const PREFILL_CHUNK_SIZE: usize = if cfg!(feature = "cuda") { 8192 } else { 4096 };
+
+/// Scored logprobs kept for prefix reuse, counted in scored tokens.
+const SCORE_CACHE_TOKENS: usize = 1 << 20;
 
 #[cfg(feature = "python")]
 #[pyclass]
@@ -27,6 +38,13 @@ pub struct GenerationOutput {
     pub decode_output: String,
     #[pyo3(get)]
     pub stop_sequence: Option<String>,
+    /// Per-token logprobs, empty unless requested in the sampling params
+    pub logprobs: Vec<TokenLogprobs>,
+    /// Predicted output tokens accepted and rejected during decoding
+    #[pyo3(get)]
+    pub accepted_prediction_tokens: usize,
+    #[pyo3(get)]
+    pub rejected_prediction_tokens: usize,
 }
 
 #[cfg(not(feature = "python"))]
@@ -40,6 +58,89 @@ pub struct GenerationOutput {
     pub decoded_length: usize,
     pub decode_output: String,
     pub stop_sequence: Option<String>,
+    /// Per-token logprobs, empty unless requested in the sampling params
+    pub logprobs: Vec<TokenLogprobs>,
+    /// Predicted output tokens accepted and rejected during decoding
+    pub accepted_prediction_tokens: usize,
+    pub rejected_prediction_tokens: usize,
+}
+
+/// Prompt log-likelihood of a single scored input.
+#[derive(Debug, Clone, Default)]
+pub struct PromptScore {
+    pub token_ids: Vec<u32>,
+    /// `logprobs[i]` is the logprob of `token_ids[i + 1]` given the tokens before it.
+    pub logprobs: Vec<TokenLogprobs>,
+}
+
+impl PromptScore {
+    pub fn total_logprob(&self) -> f64 {
+        self.logprobs.iter().map(|lp| lp.logprob as f64).sum()
+    }
+
+    /// Perplexity over the scored tokens (`exp` of the mean negative log-likelihood).
+    pub fn perplexity(&self) -> f64 {
+        Self::corpus_perplexity(std::slice::from_ref(self))
+    }
+
+    /// Token-weighted perplexity across several scored inputs.
+    pub fn corpus_perplexity(scores: &[PromptScore]) -> f64 {
+        let num_tokens: usize = scores.iter().map(|s| s.logprobs.len()).sum();
+        if num_tokens == 0 {
+            return f64::NAN;
+        }
+        let total: f64 = scores.iter().map(|s| s.total_logprob()).sum();
+        (-total / num_tokens as f64).exp()
+    }
+}
+
+/// Recently scored prompts, so scoring an input that shares a prefix with one of them only
+/// has to compute the positions after the shared prefix.
+#[derive(Debug, Default)]
+pub struct ScoreCache {
+    entries: VecDeque<(usize, PromptScore)>,
+    num_tokens: usize,
+}
+
+impl ScoreCache {
+    /// Leading logprobs of `token_ids` already known for the same `top_n`. The logprob of a
+    /// token only depends on the tokens up to it, so any cached prompt sharing a prefix
+    /// provides the logprobs inside that prefix.
+    pub fn known_logprobs(&self, token_ids: &[u32], top_n: usize) -> &[TokenLogprobs] {
+        self.entries
+            .iter()
+            .filter(|(n, _)| *n == top_n)
+            .map(|(_, score)| {
+                let shared = score
+                    .token_ids
+                    .iter()
+                    .zip(token_ids)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                &score.logprobs[..shared.saturating_sub(1).min(score.logprobs.len())]
+            })
+            .max_by_key(|logprobs| logprobs.len())
+            .unwrap_or(&[])
+    }
+
+    pub fn insert(&mut self, top_n: usize, score: PromptScore) {
+        if let Some(pos) = self
+            .entries
+            .iter()
+            .position(|(n, s)| *n == top_n && s.token_ids == score.token_ids)
+        {
+            if let Some((_, old)) = self.entries.remove(pos) {
+                self.num_tokens -= old.logprobs.len();
+            }
+        }
+        self.num_tokens += score.logprobs.len();
+        self.entries.push_back((top_n, score));
+        while self.num_tokens > SCORE_CACHE_TOKENS && self.entries.len() > 1 {
+            if let Some((_, old)) = self.entries.pop_front() {
+                self.num_tokens -= old.logprobs.len();
+            }
+        }
+    }
 }
 
 #[macro_export]
@@ -202,3 +303,53 @@ macro_rules! graph_wrapper {
         }
     }};
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(logprobs: &[f32]) -> PromptScore {
+        PromptScore {
+            token_ids: (0..=logprobs.len() as u32).collect(),
+            logprobs: logprobs
+                .iter()
+                .enumerate()
+                .map(|(i, &logprob)| TokenLogprobs {
+                    token_id: i as u32 + 1,
+                    logprob,
+                    top_logprobs: Vec::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn perplexity_is_exp_of_mean_nll() {
+        let s = score(&[-1.0, -2.0, -3.0]);
+        assert!((s.total_logprob() + 6.0).abs() < 1e-9);
+        assert!((s.perplexity() - 2.0f64.exp()).abs() < 1e-9);
+        assert!(score(&[]).perplexity().is_nan());
+    }
+
+    #[test]
+    fn corpus_perplexity_weights_by_token_count() {
+        let scores = vec![score(&[-1.0]), score(&[-2.0, -2.0, -2.0])];
+        let expected = (7.0f64 / 4.0).exp();
+        assert!((PromptScore::corpus_perplexity(&scores) - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn score_cache_returns_logprobs_inside_the_shared_prefix() {
+        let mut cache = ScoreCache::default();
+        cache.insert(0, score(&[-1.0, -2.0, -3.0, -4.0]));
+
+        // Tokens 0..=2 are shared, so the logprobs of tokens 1 and 2 are known.
+        let known = cache.known_logprobs(&[0, 1, 2, 9, 9], 0);
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[1].logprob, -2.0);
+
+        assert_eq!(cache.known_logprobs(&[0, 1, 2, 3, 4, 5], 0).len(), 4);
+        assert!(cache.known_logprobs(&[0, 1, 2], 5).is_empty());
+        assert!(cache.known_logprobs(&[7, 1, 2], 0).is_empty());
+    }
+}
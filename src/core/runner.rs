@@ -1,8 +1,10 @@
 use crate::models::gemma3::Gemma3ForConditionalGeneration;
 use crate::models::gemma4::Gemma4ForCausalLM;
 // src/core/runner.rs
+use crate::models::layers::deltanet::MambaSnapshot;
 use crate::models::layers::distributed::Comm;
 use crate::models::layers::linear::set_linear_is_prefill;
+use crate::models::layers::mtp::MultiTokenPredictor;
 use crate::models::layers::VarBuilderX;
 use crate::server::EmbeddingStrategy;
 use crate::transfer::Transfer;
@@ -10,14 +12,19 @@ use crate::transfer::Transfer;
 use crate::utils::graph::{
     planned_graph_capture_batches, CudaGraphFn, CudaGraphWrapper, GraphCapturer, ModelFn,
 };
-use crate::utils::guidance::{GuidanceState, ParserFactory};
+use crate::utils::guidance::{BadWordsFilter, GuidanceState, ParserFactory, TokenByteIndex};
 use crate::utils::image::compute_image_slice;
-use crate::utils::logits_processor::{LogitsProcessor, Sampling};
-use crate::utils::progress::ProgressLike;
+use crate::utils::logits_processor::{
+    DryParams, LogitsProcessor, Mirostat, Sampling, TokenLogprobs, Truncation,
+};
+use crate::utils::progress::{ProgressLike, ProgressReporter};
 #[cfg(feature = "flashinfer")]
 use crate::utils::FlashInferKvParams;
 use crate::{
+    core::beam_search::BeamSearchParams,
     core::sequence::{DecodeSequence, Sequence, ToDecodeInput},
+    core::spec_decode::{accept_draft_tokens, mtp_cache_config, rejection_sample, DraftModelSpec},
+    core::PREFILL_CHUNK_SIZE,
     models::deepseek3::DeepSeekForCausalLM,
     models::glm4::GLM4ForCausalLM,
     models::glm4_moe::GLM4MoEForCausalLM,
@@ -32,7 +39,9 @@ use crate::{
     models::qwen3_5_moe::Qwen3_5MoEForCausalLM,
     models::qwen3_moe::Qwen3MoEForCausalLM,
     models::qwen3_vl::Qwen3VLForConditionalGeneration,
-    utils::config::{Config, EngineConfig, ModelType, SamplingParams},
+    utils::config::{
+        Config, EngineConfig, EosTokenId, GenerationConfig, ModelType, SamplingParams,
+    },
     utils::kvcache_allocator::KVCacheAllocator,
 };
 use attention_rs::cache;
@@ -41,17 +50,38 @@ use attention_rs::InputMetadata;
 use candle_core::{DType, Device, Result, Tensor, D};
 use interprocess::local_socket::Stream as LocalStream;
 use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::{hash_map::Entry, HashMap, HashSet};
 use std::rc::Rc;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
 use toktrie::SimpleVob;
 
-/// Cached sampling parameters computed once during prefill, reused during decode
-#[derive(Clone, Debug)]
-pub struct CachedSamplingParams {
-    pub sampling: Sampling,
-    pub frequency_penalty: Option<f32>,
-    pub presence_penalty: Option<f32>,
+/// Where the sampling strategy of a sequence comes from
+enum StrategySource<'a> {
+    /// `temperature: 0`
+    Greedy,
+    /// The request's temperature/top_k/top_p
+    User,
+    /// The model's generation_config.json
+    GenerationConfig(&'a GenerationConfig),
+    Default,
+}
+
+/// Output of a single model step, one entry per scheduled sequence
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RunOutput {
+    pub token_ids: Vec<u32>,
+    /// Sampled token logprobs, `None` for sequences that did not request them
+    pub logprobs: Vec<Option<TokenLogprobs>>,
+    /// Updated Mirostat `mu` of each row, `None` for sequences not sampled with Mirostat
+    #[serde(default)]
+    pub mirostat_mu: Vec<Option<f32>>,
+    /// Verified draft tokens following `token_ids[i]`, only filled by speculative steps
+    #[serde(default)]
+    pub accepted_token_ids: Vec<Vec<u32>>,
+    /// Number of draft tokens verified for each sequence, only filled by speculative steps
+    #[serde(default)]
+    pub num_draft_tokens: Vec<usize>,
 }
 
 pub enum Seqs<'a> {
@@ -66,6 +96,82 @@ fn sampling_params_for_batch_index<'a>(seqs: &'a Seqs<'a>, index: usize) -> &'a
     }
 }
 
+/// Prompt and output tokens of a sequence, kept in `histories` for decode rows.
+fn token_history<'a>(
+    seqs: &'a Seqs<'a>,
+    histories: &'a HashMap<usize, Vec<u32>>,
+    index: usize,
+) -> &'a [u32] {
+    match seqs {
+        Seqs::SeqRefs(refs) => &refs[index].token_ids,
+        Seqs::DecodeVec(vec) => histories.get(&vec[index].id).map_or(&[][..], Vec::as_slice),
+    }
+}
+
+fn sequence_len(seqs: &Seqs<'_>, index: usize) -> usize {
+    match seqs {
+        Seqs::SeqRefs(refs) => refs[index].len(),
+        Seqs::DecodeVec(vec) => vec[index].len,
+    }
+}
+
+/// `seq` with `token_ids` replaced, prefilling everything after `num_cached_tokens`.
+fn draft_view(seq: &Sequence, token_ids: Vec<u32>, num_cached_tokens: usize) -> Sequence {
+    let mut view = seq.clone();
+    view.token_ids = token_ids;
+    view.num_cached_tokens = num_cached_tokens;
+    view.images = None;
+    view
+}
+
+fn model_logits(model: &Model, xs: &Tensor) -> Result<Tensor> {
+    match model {
+        Model::Qwen3(model) => model.compute_logits(xs),
+        Model::Qwen3MoE(model) => model.compute_logits(xs),
+        Model::Qwen3_5(model) => model.compute_logits(xs),
+        Model::Qwen3_5MoE(model) => model.compute_logits(xs),
+        Model::LLaMa(model) => model.compute_logits(xs),
+        Model::Phi4(model) => model.compute_logits(xs),
+        Model::GLM4(model) => model.compute_logits(xs),
+        Model::GLM4MoE(model) => model.compute_logits(xs),
+        Model::DeepSeek(model) => model.compute_logits(xs),
+        Model::Gemma4(model) => model.compute_logits(xs),
+        Model::MiniMax(model) => model.compute_logits(xs),
+        _ => candle_core::bail!("Prompt scoring is not supported for this model type"),
+    }
+}
+
+fn mirostat_mu(seqs: &Seqs<'_>, index: usize) -> Option<f32> {
+    match seqs {
+        Seqs::SeqRefs(refs) => refs[index].mirostat_mu,
+        Seqs::DecodeVec(vec) => vec[index].mirostat_mu,
+    }
+}
+
+fn output_len(seqs: &Seqs<'_>, index: usize) -> usize {
+    match seqs {
+        Seqs::SeqRefs(refs) => refs[index].output_len(),
+        Seqs::DecodeVec(vec) => vec[index].output_len,
+    }
+}
+
+/// Output tokens of row `index`, `None` for decode rows that only carry `last_token`.
+fn output_history<'a>(seqs: &'a Seqs<'a>, index: usize) -> Option<&'a [u32]> {
+    match seqs {
+        Seqs::SeqRefs(refs) => Some(refs[index].output_ids.as_slice()),
+        Seqs::DecodeVec(vec) => {
+            Some(vec[index].output_ids.as_slice()).filter(|ids| !ids.is_empty())
+        }
+    }
+}
+
+fn last_token(seqs: &Seqs<'_>, index: usize) -> u32 {
+    match seqs {
+        Seqs::SeqRefs(refs) => refs[index].last_token,
+        Seqs::DecodeVec(vec) => vec[index].last_token,
+    }
+}
+
 fn collect_guided_batch_entries(seqs: &Seqs<'_>, seq_ids: &[usize]) -> Vec<(usize, usize)> {
     seq_ids
         .iter()
@@ -101,6 +207,22 @@ pub enum RunnerType {
     Process(Vec<LocalStream>),
 }
 
+/// Smaller model proposing tokens for speculative decoding. Its KV cache has one block per
+/// target block, so the target's block tables address both caches.
+struct DraftModel {
+    model: Model,
+    gpu_kv_cache: Vec<(Tensor, Tensor)>,
+    cpu_kv_cache: Vec<(Tensor, Tensor)>,
+}
+
+/// Multi-token prediction layer of the target checkpoint drafting for speculative decoding,
+/// with a single-layer KV cache addressed by the target's block tables.
+struct MtpDraft {
+    predictor: MultiTokenPredictor,
+    gpu_kv_cache: Vec<(Tensor, Tensor)>,
+    cpu_kv_cache: Vec<(Tensor, Tensor)>,
+}
+
 pub struct ModelRunner {
     model: Model,
     gpu_kv_cache: Arc<Mutex<Vec<(Tensor, Tensor)>>>,
@@ -112,18 +234,32 @@ pub struct ModelRunner {
     #[cfg(feature = "flashinfer")]
     flashinfer_kv_params: Option<FlashInferKvParams>,
     logit_processor: LogitsProcessor,
-    /// Cached sampling strategy computed once during prefill, reused during decode
-    cached_sampling: RwLock<Option<CachedSamplingParams>>,
     seq_tokens: RwLock<HashMap<usize, Vec<u32>>>,
+    /// Prompt and output tokens of decode rows whose sampling uses them, sent once and
+    /// extended with `last_token` every step
+    token_histories: RwLock<HashMap<usize, Vec<u32>>>,
     restored_prefix_sequences: RwLock<HashSet<usize>>,
     guidance_states: RwLock<HashMap<usize, GuidanceState>>,
     guidance_failed: RwLock<HashSet<usize>>,
     guidance_mismatch: RwLock<HashSet<usize>>,
     llg_factory: Option<Arc<ParserFactory>>,
+    /// Vocabulary bytes for `bad_words`, built on first use
+    token_byte_index: OnceLock<TokenByteIndex>,
+    /// `bad_words` match state of each sequence, extended with every sampled token
+    bad_words_filters: RwLock<HashMap<usize, BadWordsFilter>>,
+    /// Masked for sequences below their `min_tokens`
+    eos_token_ids: Vec<u32>,
     transfer: Option<Arc<Transfer>>,
     /// Whether this runner is on the first rank (for logging)
     is_first_rank: bool,
     model_type: ModelType,
+    draft: Option<DraftModel>,
+    /// Number of leading tokens of each sequence held in the draft KV cache
+    draft_progress: RwLock<HashMap<usize, usize>>,
+    mtp: Option<MtpDraft>,
+    /// Target hidden states not yet fed to the MTP layer: first position and one row per
+    /// position from there
+    mtp_hidden: RwLock<HashMap<usize, (usize, Tensor)>>,
 }
 
 impl ModelRunner {
@@ -329,8 +465,113 @@ impl ModelRunner {
         ))
     }
 
-    fn sample_processed_logits(&self, logits: &Tensor, sampling: &Sampling) -> Result<Vec<u32>> {
-        self.logit_processor.sample_with_strategy(logits, sampling)
+    /// Apply per-request `logit_bias`, `bad_words` and `min_tokens` on top of the grammar mask.
+    fn apply_token_constraints(
+        &self,
+        logits: &Tensor,
+        seqs: &Seqs<'_>,
+        seq_ids: &[usize],
+    ) -> Result<Tensor> {
+        let rows: Vec<usize> = (0..seq_ids.len())
+            .filter(|&i| {
+                let params = sampling_params_for_batch_index(seqs, i);
+                params.logit_bias.as_ref().is_some_and(|b| !b.is_empty())
+                    || params.uses_output_history()
+                    || params.below_min_tokens(output_len(seqs, i))
+            })
+            .collect();
+        if rows.is_empty() {
+            return Ok(logits.clone());
+        }
+
+        let vocab_size = logits.dim(1)?;
+        let mut logits_vec = logits.flatten_all()?.to_vec1::<f32>()?;
+        let mut bad_words_filters = self.bad_words_filters.write();
+        for i in rows {
+            let params = sampling_params_for_batch_index(seqs, i);
+            let row = &mut logits_vec[i * vocab_size..(i + 1) * vocab_size];
+            if let Some(bias) = &params.logit_bias {
+                for (&token, &value) in bias {
+                    if let Some(logit) = row.get_mut(token as usize) {
+                        *logit += value;
+                    }
+                }
+            }
+
+            // EOS and single-token stops stay out of reach until min_tokens are generated
+            if params.below_min_tokens(output_len(seqs, i)) {
+                let single_token_stops = params
+                    .stop_token_ids
+                    .iter()
+                    .flatten()
+                    .filter(|stop| stop.len() == 1)
+                    .map(|stop| stop[0]);
+                for token in self.eos_token_ids.iter().copied().chain(single_token_stops) {
+                    if let Some(logit) = row.get_mut(token as usize) {
+                        *logit = f32::NEG_INFINITY;
+                    }
+                }
+            }
+
+            // bad_words need the vocabulary bytes of the llguidance tokenizer
+            let (Some(words), Some(factory)) = (&params.bad_words, &self.llg_factory) else {
+                continue;
+            };
+            let index = self
+                .token_byte_index
+                .get_or_init(|| TokenByteIndex::new(factory.tok_env().tok_trie()));
+            let filter = bad_words_filters
+                .entry(seq_ids[i])
+                .or_insert_with(|| BadWordsFilter::new(words, index));
+            match output_history(seqs, i) {
+                Some(output_ids) => filter.sync(output_ids, index),
+                // Later decode steps only send the token sampled last
+                None if output_len(seqs, i) == filter.num_tokens() + 1 => {
+                    filter.push(last_token(seqs, i), index)
+                }
+                None => {}
+            }
+            for token in filter.banned_tokens(index) {
+                if let Some(logit) = row.get_mut(token as usize) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        }
+
+        Tensor::from_vec(logits_vec, logits.shape(), &self.device)
+    }
+
+    /// Sample row `i` of `logits` with `strategies[i]`, batching the rows that share a strategy.
+    /// Rows without a strategy are left at 0 for the caller to sample.
+    fn sample_processed_logits(
+        &self,
+        logits: &Tensor,
+        strategies: &[Option<Sampling>],
+    ) -> Result<Vec<u32>> {
+        if let Some(Some(first)) = strategies.first() {
+            if strategies.iter().all(|s| s.as_ref() == Some(first)) {
+                return self.logit_processor.sample_with_strategy(logits, first);
+            }
+        }
+        let mut groups: Vec<(&Sampling, Vec<u32>)> = Vec::new();
+        for (i, strategy) in strategies.iter().enumerate() {
+            let Some(strategy) = strategy else { continue };
+            match groups.iter_mut().find(|(s, _)| *s == strategy) {
+                Some((_, rows)) => rows.push(i as u32),
+                None => groups.push((strategy, vec![i as u32])),
+            }
+        }
+        let mut tokens = vec![0u32; strategies.len()];
+        for (strategy, rows) in groups {
+            let index = Tensor::new(rows.as_slice(), logits.device())?;
+            let sampled = self
+                .logit_processor
+                .sample_with_strategy(&logits.index_select(&index, 0)?, strategy)?;
+            for (row, token) in rows.into_iter().zip(sampled) {
+                tokens[row as usize] = token;
+            }
+        }
+        Ok(tokens)
     }
 
     fn commit_guided_tokens(
@@ -384,6 +625,7 @@ impl ModelRunner {
         reporter: Arc<RwLock<Box<dyn ProgressLike>>>,
         transfer: Option<Arc<Transfer>>,
         llg_factory: Option<Arc<ParserFactory>>,
+        draft: Option<DraftModelSpec>,
         stream: Option<LocalStream>,
     ) -> Result<Self> {
         let model = crate::build_model!(
@@ -439,6 +681,64 @@ impl ModelRunner {
             }
         );
 
+        // Loaded before planning so that the KV budget leaves room for the draft weights
+        let draft = match draft {
+            Some(spec) => {
+                let vb = VarBuilderX::new(&spec.model_pathes, spec.is_gguf, dtype, &device)?;
+                let reporter: Arc<RwLock<Box<dyn ProgressLike>>> =
+                    Arc::new(RwLock::new(Box::new(ProgressReporter::new(comm.rank()))));
+                let model = crate::build_model!(
+                    spec.model_type,
+                    &vb,
+                    comm,
+                    &spec.config,
+                    dtype,
+                    spec.is_rope_i,
+                    &device,
+                    reporter,
+                    {
+                        Qwen3 => Qwen3ForCausalLM,
+                        Qwen3MoE => Qwen3MoEForCausalLM,
+                        LLaMa => LLaMaForCausalLM,
+                        Phi4 => Phi4ForCausalLM,
+                        GLM4 => GLM4ForCausalLM,
+                        GLM4MoE => GLM4MoEForCausalLM,
+                        Gemma4 => Gemma4ForCausalLM,
+                        MiniMax => MiniMaxForCausalLM,
+                    }
+                )?;
+                crate::log_info!("Draft model {:?} loaded", spec.model_type);
+                Some((model, spec.config))
+            }
+            None => None,
+        };
+        let mtp_config = mtp_cache_config(econfig, config);
+        let mtp = match &mtp_config {
+            Some(_) => {
+                let predictor = match &model_type {
+                    ModelType::GLM4MoE => {
+                        GLM4MoEForCausalLM::load_mtp(vb, comm.clone(), config, dtype, is_rope_i)?
+                    }
+                    ModelType::DeepSeek => {
+                        DeepSeekForCausalLM::load_mtp(vb, comm.clone(), config, dtype, is_rope_i)?
+                    }
+                    ModelType::Qwen3_5 => {
+                        Qwen3_5ForCausalLM::load_mtp(vb, comm.clone(), config, dtype, is_rope_i)?
+                    }
+                    ModelType::Qwen3_5MoE => {
+                        Qwen3_5MoEForCausalLM::load_mtp(vb, comm.clone(), config, dtype, is_rope_i)?
+                    }
+                    _ => None,
+                };
+                let Some(predictor) = predictor else {
+                    candle_core::bail!("No multi-token prediction weights in the checkpoint");
+                };
+                crate::log_info!("Multi-token prediction layer loaded");
+                Some(predictor)
+            }
+            None => None,
+        };
+
         let allocator = if let Some(s) = stream {
             use crate::runner::{receive_local, send_local, MessageType};
             use interprocess::TryClone;
@@ -454,6 +754,14 @@ impl ModelRunner {
             KVCacheAllocator::new(econfig, config, dtype)
         } else {
             let allocator = KVCacheAllocator::new(&econfig, &config, dtype);
+            let draft_config = draft
+                .as_ref()
+                .map(|(_, draft_config)| draft_config)
+                .or(mtp_config.as_ref());
+            let allocator = match draft_config {
+                Some(draft_config) => allocator.with_draft_model(econfig, draft_config, dtype),
+                None => allocator,
+            };
             let device_ids = econfig.device_ids.clone().unwrap_or(vec![0]);
             match allocator.plan(&device_ids, econfig) {
                 Ok(_) => {
@@ -579,6 +887,40 @@ impl ModelRunner {
 
         let (gpu_kv_cache, cpu_kv_cache) =
             allocator.init_kv_cache(&allocation, dtype, &device, econfig.pd_config.as_ref())?;
+        let draft = match draft {
+            Some((model, draft_config)) => {
+                let (gpu_kv_cache, cpu_kv_cache) =
+                    KVCacheAllocator::new(econfig, &draft_config, dtype).init_kv_cache(
+                        &allocation,
+                        dtype,
+                        &device,
+                        econfig.pd_config.as_ref(),
+                    )?;
+                Some(DraftModel {
+                    model,
+                    gpu_kv_cache,
+                    cpu_kv_cache,
+                })
+            }
+            None => None,
+        };
+        let mtp = match (mtp, &mtp_config) {
+            (Some(predictor), Some(mtp_config)) => {
+                let (gpu_kv_cache, cpu_kv_cache) =
+                    KVCacheAllocator::new(econfig, mtp_config, dtype).init_kv_cache(
+                        &allocation,
+                        dtype,
+                        &device,
+                        econfig.pd_config.as_ref(),
+                    )?;
+                Some(MtpDraft {
+                    predictor,
+                    gpu_kv_cache,
+                    cpu_kv_cache,
+                })
+            }
+            _ => None,
+        };
 
         let (temperature, top_k, top_p) = if econfig.generation_cfg.is_some() {
             (
@@ -648,16 +990,27 @@ impl ModelRunner {
             #[cfg(feature = "flashinfer")]
             flashinfer_kv_params,
             logit_processor: LogitsProcessor::new(seed, temperature, top_k, top_p),
-            cached_sampling: RwLock::new(None),
             seq_tokens: RwLock::new(HashMap::new()),
+            token_histories: RwLock::new(HashMap::new()),
             restored_prefix_sequences: RwLock::new(HashSet::new()),
             guidance_states: RwLock::new(HashMap::new()),
             guidance_failed: RwLock::new(HashSet::new()),
             guidance_mismatch: RwLock::new(HashSet::new()),
             llg_factory,
+            token_byte_index: OnceLock::new(),
+            bad_words_filters: RwLock::new(HashMap::new()),
+            eos_token_ids: config
+                .eos_token_id
+                .as_ref()
+                .map(EosTokenId::to_vec)
+                .unwrap_or_default(),
             transfer,
             is_first_rank: comm.rank() == 0,
             model_type,
+            draft,
+            draft_progress: RwLock::new(HashMap::new()),
+            mtp,
+            mtp_hidden: RwLock::new(HashMap::new()),
         })
     }
 
@@ -744,7 +1097,7 @@ impl ModelRunner {
     }
 
     #[allow(unused)]
-    pub fn run(&self, seqs: Seqs, is_prefill: bool) -> Result<Vec<u32>> {
+    pub fn run(&self, seqs: Seqs, is_prefill: bool) -> Result<RunOutput> {
         #[cfg(feature = "nvtx")]
         nvtx::range_push!("{}", if is_prefill { "prefill" } else { "decoding" });
         let (input_ids, positions, mut input_metadata) = if is_prefill {
@@ -766,6 +1119,24 @@ impl ModelRunner {
         if is_prefill {
             if let Seqs::SeqRefs(seqs_ref) = &seqs {
                 self.restore_mamba_prefix_states_for_prefill(seqs_ref)?;
+                // (Re)computed blocks hold no draft KV yet
+                if self.draft.is_some() {
+                    let mut draft_progress = self.draft_progress.write();
+                    for seq in seqs_ref.iter() {
+                        draft_progress.remove(&seq.id);
+                    }
+                }
+            }
+        } else if self.mtp.is_some() {
+            // Plain decode steps leave no hidden states for the MTP layer
+            let mut mtp_hidden = self.mtp_hidden.write();
+            match &seqs {
+                Seqs::SeqRefs(seqs) => seqs.iter().for_each(|seq| {
+                    mtp_hidden.remove(&seq.id);
+                }),
+                Seqs::DecodeVec(seqs) => seqs.iter().for_each(|seq| {
+                    mtp_hidden.remove(&seq.id);
+                }),
             }
         }
 
@@ -803,8 +1174,8 @@ impl ModelRunner {
                         .capturer
                         .replay(&input_ids, &positions, &input_metadata)?,
                 };
-                let output_ids = self.sample(&logits, seqs, is_prefill)?;
-                return Ok(output_ids);
+                let output = self.sample(&logits, seqs, is_prefill)?;
+                return Ok(output);
             }
         }
 
@@ -870,6 +1241,11 @@ impl ModelRunner {
         let images = images.as_ref();
 
         let _prefill_guard = set_linear_is_prefill(is_prefill);
+        if let (Some(mtp), Seqs::SeqRefs(seqs_ref), true) = (&self.mtp, &seqs, is_prefill) {
+            let logits =
+                self.prefill_with_mtp(mtp, seqs_ref, &input_ids, &positions, &input_metadata)?;
+            return self.sample(&logits, seqs, is_prefill);
+        }
         let logits = crate::model_call!(
             &self.model,
             forward,
@@ -893,10 +1269,604 @@ impl ModelRunner {
                 MiniMax => false,
             }
         )?;
-        let output_ids = self.sample(&logits, seqs, is_prefill)?;
+        let output = self.sample(&logits, seqs, is_prefill)?;
         #[cfg(feature = "nvtx")]
         nvtx::range_pop!();
-        Ok(output_ids)
+        Ok(output)
+    }
+
+    /// Decode step verifying the draft tokens of each sequence in one forward pass.
+    /// The last token and the drafts run as a prefill chunk over the cached context, every
+    /// position is sampled and drafts are kept while they match the sampled tokens.
+    /// Drafts of the draft model or the MTP layer are verified by rejection sampling against
+    /// their draft distribution instead.
+    pub fn run_speculative(&self, seqs: &[&Sequence]) -> Result<RunOutput> {
+        let (mut drafts, mut draft_probs) = match (&self.draft, &self.mtp) {
+            (Some(draft), _) => self.propose_with_draft(draft, seqs)?,
+            (None, Some(mtp)) => self.propose_with_mtp(mtp, seqs)?,
+            (None, None) => (
+                seqs.iter().map(|seq| seq.draft_token_ids.clone()).collect(),
+                vec![None; seqs.len()],
+            ),
+        };
+        // Predicted outputs are drafted by the scheduler, whatever fills the draft slots
+        for (i, seq) in seqs.iter().enumerate() {
+            if !seq.draft_token_ids.is_empty() {
+                drafts[i] = seq.draft_token_ids.clone();
+                draft_probs[i] = None;
+            }
+        }
+        let views: Vec<Sequence> = seqs
+            .iter()
+            .zip(&drafts)
+            .map(|(seq, draft)| {
+                let mut tokens = seq.token_ids.clone();
+                tokens.extend_from_slice(draft);
+                draft_view(seq, tokens, seq.len() - 1)
+            })
+            .collect();
+        let views: Vec<&Sequence> = views.iter().collect();
+        let (input_ids, positions, input_metadata) = self.prepare_prefill(&views)?;
+        // Verification advances the mamba state over every draft
+        let drafted: Vec<usize> = (0..seqs.len()).filter(|&i| !drafts[i].is_empty()).collect();
+        let mamba_snapshot =
+            self.snapshot_mamba_states(&drafted.iter().map(|&i| seqs[i].id).collect::<Vec<_>>())?;
+
+        let _prefill_guard = set_linear_is_prefill(true);
+        let hidden = crate::model_call!(
+            &self.model,
+            forward_embedding,
+            (&input_ids, &positions, Some(&self.get_kv_cache()), &input_metadata),
+            {
+                Qwen3 => false,
+                Qwen3MoE => false,
+                Qwen3_5 => false,
+                Qwen3_5MoE => false,
+                LLaMa => false,
+                Phi4 => false,
+                GLM4 => false,
+                GLM4MoE => false,
+                DeepSeek => false,
+                Gemma4 => false,
+                MiniMax => false,
+            },
+            candle_core::bail!("Speculative decoding is not supported for this model type")
+        )?;
+        let logits = self.compute_logits(&hidden)?;
+        let num_draft_tokens: Vec<usize> = drafts.iter().map(Vec::len).collect();
+
+        // One sampling row per verified position, positioned as if decoded one by one
+        let mut rows = Vec::with_capacity(logits.dim(0)?);
+        let mut first_rows = Vec::with_capacity(seqs.len());
+        for (seq, draft) in seqs.iter().zip(&drafts) {
+            let row = DecodeSequence::new(seq);
+            first_rows.push(rows.len() as u32);
+            for j in 0..=draft.len() {
+                rows.push(DecodeSequence {
+                    len: row.len + j,
+                    output_len: row.output_len + j,
+                    ..row.clone()
+                });
+            }
+        }
+
+        // Sequences with penalties, which depend on the sampled tokens, get no drafts from
+        // the scheduler (`SamplingParams::allows_speculation`) and verify a single row
+        let output = self.sample(&logits, Seqs::DecodeVec(&rows), false)?;
+        // Target rows after the per-request constraints, for rejection sampling
+        let target_logits: Option<Vec<Vec<f32>>> = if draft_probs.iter().any(Option::is_some) {
+            let ids: Vec<usize> = rows.iter().map(|row| row.id()).collect();
+            let logits = self.apply_token_constraints(&logits, &Seqs::DecodeVec(&rows), &ids)?;
+            Some(
+                logits
+                    .to_dtype(DType::F32)?
+                    .to_device(&Device::Cpu)?
+                    .to_vec2()?,
+            )
+        } else {
+            None
+        };
+        let mut token_ids = Vec::with_capacity(seqs.len());
+        let mut accepted_token_ids = Vec::with_capacity(seqs.len());
+        for (i, (draft, &first)) in drafts.iter().zip(&first_rows).enumerate() {
+            let first = first as usize;
+            let verified = first..first + draft.len() + 1;
+            let mut accepted = match (&target_logits, &draft_probs[i]) {
+                (Some(target_logits), Some(draft_probs)) => {
+                    let sampling = self.sequence_strategy(&seqs[i].sampling_params);
+                    let target_probs: Vec<Vec<f32>> = target_logits[verified]
+                        .iter()
+                        .map(|row| sampling.probs(row))
+                        .collect();
+                    let seeded = seqs[i].sampling_params.seed.map(|seed| {
+                        self.logit_processor
+                            .with_seed(LogitsProcessor::step_seed(seed, seqs[i].len()))
+                    });
+                    let processor = seeded.as_ref().unwrap_or(&self.logit_processor);
+                    rejection_sample(
+                        draft,
+                        draft_probs,
+                        &target_probs,
+                        || processor.uniform(),
+                        |probs| processor.sample_probs(probs),
+                    )?
+                }
+                _ => accept_draft_tokens(draft, &output.token_ids[verified]),
+            };
+            token_ids.push(accepted.remove(0));
+            accepted_token_ids.push(accepted);
+        }
+        if let Some(snapshot) = &mamba_snapshot {
+            self.rewind_mamba_states(snapshot, seqs, &drafted, &drafts, &accepted_token_ids)?;
+        }
+        if self.mtp.is_some() {
+            let accepted: Vec<usize> = accepted_token_ids.iter().map(Vec::len).collect();
+            self.keep_mtp_hidden(seqs, &hidden, &first_rows, &accepted)?;
+        }
+        // Sequences without drafts have a single row carrying their logprobs and Mirostat state
+        let logprobs = first_rows
+            .iter()
+            .map(|&i| output.logprobs[i as usize].clone())
+            .collect();
+        let mirostat_mu = first_rows
+            .iter()
+            .map(|&i| output.mirostat_mu[i as usize])
+            .collect();
+        Ok(RunOutput {
+            token_ids,
+            logprobs,
+            mirostat_mu,
+            accepted_token_ids,
+            num_draft_tokens,
+        })
+    }
+
+    /// Mamba states of the given sequences before a verification pass, `None` for models
+    /// without mamba layers or if no sequence has drafts.
+    fn snapshot_mamba_states(&self, seq_ids: &[usize]) -> Result<Option<MambaSnapshot>> {
+        if seq_ids.is_empty() {
+            return Ok(None);
+        }
+        match &self.model {
+            Model::Qwen3_5(model) => model.snapshot_mamba_states(seq_ids).map(Some),
+            Model::Qwen3_5MoE(model) => model.snapshot_mamba_states(seq_ids).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Give sequences whose drafts were not all accepted the mamba state of the accepted
+    /// tokens: restore the state from before verification and run the last token and the
+    /// accepted drafts again. `drafted[row]` is the sequence of snapshot row `row`.
+    fn rewind_mamba_states(
+        &self,
+        snapshot: &MambaSnapshot,
+        seqs: &[&Sequence],
+        drafted: &[usize],
+        drafts: &[Vec<u32>],
+        accepted_token_ids: &[Vec<u32>],
+    ) -> Result<()> {
+        let (rows, views): (Vec<usize>, Vec<Sequence>) = drafted
+            .iter()
+            .enumerate()
+            .filter(|&(_, &i)| accepted_token_ids[i].len() < drafts[i].len())
+            .map(|(row, &i)| {
+                let mut tokens = seqs[i].token_ids.clone();
+                tokens.extend_from_slice(&drafts[i][..accepted_token_ids[i].len()]);
+                (row, draft_view(seqs[i], tokens, seqs[i].len() - 1))
+            })
+            .unzip();
+        if rows.is_empty() {
+            return Ok(());
+        }
+        match &self.model {
+            Model::Qwen3_5(model) => model.restore_mamba_states(snapshot, &rows)?,
+            Model::Qwen3_5MoE(model) => model.restore_mamba_states(snapshot, &rows)?,
+            _ => return Ok(()),
+        }
+        // Rewrites the KV cache of these positions with the same values
+        let views: Vec<&Sequence> = views.iter().collect();
+        let (input_ids, positions, input_metadata) = self.prepare_prefill(&views)?;
+        crate::model_call!(
+            &self.model,
+            forward_embedding,
+            (&input_ids, &positions, Some(&self.get_kv_cache()), &input_metadata),
+            {
+                Qwen3_5 => false,
+                Qwen3_5MoE => false,
+            },
+            candle_core::bail!("Model type keeps no mamba state")
+        )?;
+        Ok(())
+    }
+
+    /// Let the draft model propose `draft_slots` tokens for each sequence. Returns the drafts
+    /// and, for sequences with slots, the distribution each draft token was sampled from.
+    fn propose_with_draft(
+        &self,
+        draft: &DraftModel,
+        seqs: &[&Sequence],
+    ) -> Result<(Vec<Vec<u32>>, Vec<Option<Vec<Vec<f32>>>>)> {
+        const CATCH_UP_CHUNK: usize = 4096;
+        let mut drafts = vec![Vec::new(); seqs.len()];
+        let mut draft_probs: Vec<Option<Vec<Vec<f32>>>> = seqs
+            .iter()
+            .map(|seq| (seq.draft_slots > 0).then(Vec::new))
+            .collect();
+        let mut progress: Vec<usize> = {
+            let draft_progress = self.draft_progress.read();
+            seqs.iter()
+                .map(|seq| {
+                    let done = draft_progress.get(&seq.id).copied().unwrap_or(0);
+                    std::cmp::min(done, seq.len() - 1)
+                })
+                .collect()
+        };
+
+        // Feed the tokens the draft has not seen yet; the last chunk goes with the first step
+        loop {
+            let lagging: Vec<usize> = (0..seqs.len())
+                .filter(|&i| {
+                    seqs[i].draft_slots > 0 && seqs[i].len() - progress[i] > CATCH_UP_CHUNK
+                })
+                .collect();
+            if lagging.is_empty() {
+                break;
+            }
+            let views: Vec<Sequence> = lagging
+                .iter()
+                .map(|&i| {
+                    let end = progress[i] + CATCH_UP_CHUNK;
+                    draft_view(seqs[i], seqs[i].token_ids[..end].to_vec(), progress[i])
+                })
+                .collect();
+            self.draft_hidden(draft, &views.iter().collect::<Vec<_>>())?;
+            for &i in &lagging {
+                progress[i] += CATCH_UP_CHUNK;
+            }
+        }
+
+        let max_slots = seqs.iter().map(|seq| seq.draft_slots).max().unwrap_or(0);
+        for step in 0..max_slots {
+            let active: Vec<usize> = (0..seqs.len())
+                .filter(|&i| seqs[i].draft_slots > step)
+                .collect();
+            let views: Vec<Sequence> = active
+                .iter()
+                .map(|&i| {
+                    let mut tokens = seqs[i].token_ids.clone();
+                    tokens.extend_from_slice(&drafts[i]);
+                    let num_cached = if step == 0 {
+                        progress[i]
+                    } else {
+                        tokens.len() - 1
+                    };
+                    draft_view(seqs[i], tokens, num_cached)
+                })
+                .collect();
+            let hidden = self.draft_hidden(draft, &views.iter().collect::<Vec<_>>())?;
+            let mut last_rows = Vec::with_capacity(views.len());
+            let mut offset = 0;
+            for view in &views {
+                offset += view.len() - view.num_cached_tokens;
+                last_rows.push(offset as u32 - 1);
+            }
+            let index = Tensor::new(last_rows.as_slice(), hidden.device())?;
+            let logits: Vec<Vec<f32>> =
+                model_logits(&draft.model, &hidden.index_select(&index, 0)?)?
+                    .to_dtype(DType::F32)?
+                    .to_device(&Device::Cpu)?
+                    .to_vec2()?;
+            for (&i, row) in active.iter().zip(&logits) {
+                let probs = self.sequence_strategy(&seqs[i].sampling_params).probs(row);
+                // A stream of its own keeps seeded drafts independent of the acceptance draws
+                let seeded = seqs[i].sampling_params.seed.map(|seed| {
+                    self.logit_processor
+                        .with_seed(LogitsProcessor::step_seed(!seed, seqs[i].len() + step))
+                });
+                let processor = seeded.as_ref().unwrap_or(&self.logit_processor);
+                drafts[i].push(processor.sample_probs(&probs)?);
+                if let Some(draft_probs) = draft_probs[i].as_mut() {
+                    draft_probs.push(probs);
+                }
+            }
+        }
+
+        // Draft KV past the last token belongs to drafts that may be rejected
+        let mut draft_progress = self.draft_progress.write();
+        for seq in seqs.iter().filter(|seq| seq.draft_slots > 0) {
+            draft_progress.insert(seq.id, seq.len());
+        }
+        Ok((drafts, draft_probs))
+    }
+
+    /// Hidden states of the draft model for a prefill chunk, writing the draft KV cache.
+    fn draft_hidden(&self, draft: &DraftModel, seqs: &[&Sequence]) -> Result<Tensor> {
+        let (input_ids, positions, input_metadata) = self.prepare_prefill(seqs)?;
+        let _prefill_guard = set_linear_is_prefill(true);
+        crate::model_call!(
+            &draft.model,
+            forward_embedding,
+            (&input_ids, &positions, Some(&draft.gpu_kv_cache), &input_metadata),
+            {
+                Qwen3 => false,
+                Qwen3MoE => false,
+                LLaMa => false,
+                Phi4 => false,
+                GLM4 => false,
+                GLM4MoE => false,
+                Gemma4 => false,
+                MiniMax => false,
+            },
+            candle_core::bail!("Unsupported draft model type")
+        )
+    }
+
+    /// Prefill keeping the hidden states of every position for the MTP layer. Returns the
+    /// logits of the last position of each sequence.
+    fn prefill_with_mtp(
+        &self,
+        mtp: &MtpDraft,
+        seqs: &[&Sequence],
+        input_ids: &Tensor,
+        positions: &Tensor,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let hidden = crate::model_call!(
+            &self.model,
+            forward_embedding,
+            (input_ids, positions, Some(&self.get_kv_cache()), input_metadata),
+            {
+                Qwen3_5 => false,
+                Qwen3_5MoE => false,
+                GLM4MoE => false,
+                DeepSeek => false,
+            },
+            candle_core::bail!("Multi-token prediction is not supported for this model type")
+        )?;
+        let ends = input_metadata.seqlens.clone().unwrap_or_default();
+        let index = Tensor::new(
+            ends.iter()
+                .map(|end| end - 1)
+                .collect::<Vec<u32>>()
+                .as_slice(),
+            hidden.device(),
+        )?;
+        let logits = self.compute_logits(&hidden.index_select(&index, 0)?)?;
+
+        let mut known = Vec::with_capacity(seqs.len());
+        {
+            let mut mtp_hidden = self.mtp_hidden.write();
+            let mut start = 0;
+            for (seq, &end) in seqs.iter().zip(&ends) {
+                let rows = hidden.narrow(0, start, end as usize - start)?;
+                let chunk_start = seq.num_cached_tokens;
+                // Chunks continue the pending rows; anything else starts over at this chunk
+                let pending = match mtp_hidden.remove(&seq.id) {
+                    Some((first, prev)) if first + prev.dim(0)? == chunk_start => {
+                        (first, Tensor::cat(&[&prev, &rows], 0)?)
+                    }
+                    _ => (chunk_start, rows.copy()?),
+                };
+                mtp_hidden.insert(seq.id, pending);
+                known.push(chunk_start + end as usize - start);
+                start = end as usize;
+            }
+        }
+        self.mtp_catch_up(mtp, seqs, &known)?;
+        Ok(logits)
+    }
+
+    /// Feed the pending hidden states to the MTP layer, up to the position before the last of
+    /// the first `known[i]` tokens of each sequence (the MTP input at position `p` is token
+    /// `p + 1`). Returns the MTP output of that position, which drafts token `known[i]`.
+    fn mtp_catch_up(
+        &self,
+        mtp: &MtpDraft,
+        seqs: &[&Sequence],
+        known: &[usize],
+    ) -> Result<Vec<Option<Tensor>>> {
+        let mut outputs = vec![None; seqs.len()];
+        let mut active = Vec::new();
+        let mut views = Vec::new();
+        let mut inputs = Vec::new();
+        {
+            let mut mtp_hidden = self.mtp_hidden.write();
+            for (i, seq) in seqs.iter().enumerate() {
+                let Some((first, rows)) = mtp_hidden.get_mut(&seq.id) else {
+                    continue;
+                };
+                let end = known[i] - 1;
+                if end <= *first || end > *first + rows.dim(0)? {
+                    continue;
+                }
+                let count = end - *first;
+                inputs.push(rows.narrow(0, 0, count)?);
+                views.push(draft_view(seq, seq.token_ids[1..known[i]].to_vec(), *first));
+                *rows = rows.narrow(0, count, rows.dim(0)? - count)?.copy()?;
+                *first = end;
+                active.push(i);
+            }
+        }
+        if active.is_empty() {
+            return Ok(outputs);
+        }
+        let out = self.mtp_forward(mtp, &views.iter().collect::<Vec<_>>(), &inputs)?;
+        let mut offset = 0;
+        for (&i, input) in active.iter().zip(&inputs) {
+            offset += input.dim(0)?;
+            outputs[i] = Some(out.narrow(0, offset - 1, 1)?);
+        }
+        Ok(outputs)
+    }
+
+    /// Run the MTP layer over prefill views whose tokens are shifted one position ahead,
+    /// with one previous hidden state per input row.
+    fn mtp_forward(&self, mtp: &MtpDraft, seqs: &[&Sequence], hidden: &[Tensor]) -> Result<Tensor> {
+        let (input_ids, positions, input_metadata) = self.prepare_prefill(seqs)?;
+        let embeds = match &self.model {
+            Model::Qwen3_5(model) => model.embed_forward(&input_ids)?,
+            Model::Qwen3_5MoE(model) => model.embed_forward(&input_ids)?,
+            Model::GLM4MoE(model) => model.embed_forward(&input_ids)?,
+            Model::DeepSeek(model) => model.embed_forward(&input_ids)?,
+            _ => candle_core::bail!("Multi-token prediction is not supported for this model type"),
+        };
+        let hidden = Tensor::cat(hidden, 0)?;
+        let (k_cache, v_cache) = &mtp.gpu_kv_cache[0];
+        mtp.predictor.forward(
+            &embeds,
+            &hidden,
+            &positions,
+            (k_cache, v_cache),
+            &input_metadata,
+        )
+    }
+
+    /// Let the MTP layer propose `draft_slots` tokens for each sequence, chaining its own
+    /// output for every draft after the first. Returns the drafts and, for sequences with
+    /// slots, the distribution each draft token was sampled from.
+    fn propose_with_mtp(
+        &self,
+        mtp: &MtpDraft,
+        seqs: &[&Sequence],
+    ) -> Result<(Vec<Vec<u32>>, Vec<Option<Vec<Vec<f32>>>>)> {
+        let mut drafts = vec![Vec::new(); seqs.len()];
+        let mut draft_probs: Vec<Option<Vec<Vec<f32>>>> = seqs
+            .iter()
+            .map(|seq| (seq.draft_slots > 0).then(Vec::new))
+            .collect();
+        let known: Vec<usize> = seqs.iter().map(|seq| seq.len()).collect();
+        // Sequences without a pending state (e.g. after a plain decode step) skip this step
+        let mut current: Vec<(usize, Tensor)> = self
+            .mtp_catch_up(mtp, seqs, &known)?
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, out)| out.map(|out| (i, out)))
+            .filter(|(i, _)| seqs[*i].draft_slots > 0)
+            .collect();
+
+        let max_slots = seqs.iter().map(|seq| seq.draft_slots).max().unwrap_or(0);
+        for step in 0..max_slots {
+            current.retain(|(i, _)| seqs[*i].draft_slots > step);
+            if current.is_empty() {
+                break;
+            }
+            if step > 0 {
+                let views: Vec<Sequence> = current
+                    .iter()
+                    .map(|(i, _)| {
+                        let mut tokens = seqs[*i].token_ids[1..].to_vec();
+                        tokens.extend_from_slice(&drafts[*i]);
+                        let num_cached = tokens.len() - 1;
+                        draft_view(seqs[*i], tokens, num_cached)
+                    })
+                    .collect();
+                let hidden: Vec<Tensor> = current.iter().map(|(_, out)| out.clone()).collect();
+                let out = self.mtp_forward(mtp, &views.iter().collect::<Vec<_>>(), &hidden)?;
+                for (j, (_, prev)) in current.iter_mut().enumerate() {
+                    *prev = out.narrow(0, j, 1)?;
+                }
+            }
+            let hidden: Vec<Tensor> = current.iter().map(|(_, out)| out.clone()).collect();
+            let hidden = mtp.predictor.final_norm(&Tensor::cat(&hidden, 0)?)?;
+            let logits: Vec<Vec<f32>> = self
+                .compute_logits(&hidden)?
+                .to_dtype(DType::F32)?
+                .to_device(&Device::Cpu)?
+                .to_vec2()?;
+            for ((i, _), row) in current.iter().zip(&logits) {
+                let probs = self.sequence_strategy(&seqs[*i].sampling_params).probs(row);
+                let seeded = seqs[*i].sampling_params.seed.map(|seed| {
+                    self.logit_processor
+                        .with_seed(LogitsProcessor::step_seed(!seed, seqs[*i].len() + step))
+                });
+                let processor = seeded.as_ref().unwrap_or(&self.logit_processor);
+                drafts[*i].push(processor.sample_probs(&probs)?);
+                if let Some(draft_probs) = draft_probs[*i].as_mut() {
+                    draft_probs.push(probs);
+                }
+            }
+        }
+        Ok((drafts, draft_probs))
+    }
+
+    /// Keep the target hidden states of the verified positions (the last token and the
+    /// accepted drafts) for the MTP layer to consume in the next step.
+    fn keep_mtp_hidden(
+        &self,
+        seqs: &[&Sequence],
+        hidden: &Tensor,
+        first_rows: &[u32],
+        accepted: &[usize],
+    ) -> Result<()> {
+        let mut mtp_hidden = self.mtp_hidden.write();
+        for ((seq, &first), &accepted) in seqs.iter().zip(first_rows).zip(accepted) {
+            if seq.draft_slots == 0 {
+                mtp_hidden.remove(&seq.id);
+                continue;
+            }
+            let rows = hidden.narrow(0, first as usize, accepted + 1)?.copy()?;
+            mtp_hidden.insert(seq.id, (seq.len() - 1, rows));
+        }
+        Ok(())
+    }
+
+    fn strategy_source<'a>(&'a self, params: &SamplingParams) -> StrategySource<'a> {
+        if matches!(params.temperature, Some(t) if t == 0.0) {
+            return StrategySource::Greedy;
+        }
+        if params.temperature.is_some()
+            || matches!(params.top_k, Some(k) if k > 0)
+            || matches!(params.top_p, Some(p) if p > 0.0 && p < 1.0)
+        {
+            return StrategySource::User;
+        }
+        // generation_config only counts with a temperature AND top_k/top_p
+        match self.config.generation_cfg.as_ref() {
+            Some(cfg)
+                if cfg.temperature.is_some() && (cfg.top_k.is_some() || cfg.top_p.is_some()) =>
+            {
+                StrategySource::GenerationConfig(cfg)
+            }
+            _ => StrategySource::Default,
+        }
+    }
+
+    /// Frequency and presence penalties of a sequence, user params > generation_config.
+    fn sequence_penalties(&self, params: &SamplingParams) -> (Option<f32>, Option<f32>) {
+        let cfg = self.config.generation_cfg.as_ref();
+        (
+            params
+                .frequency_penalty
+                .or(cfg.and_then(|c| c.frequency_penalty)),
+            params
+                .presence_penalty
+                .or(cfg.and_then(|c| c.presence_penalty)),
+        )
+    }
+
+    /// Sampling strategy of a sequence before truncation: user params > generation_config
+    /// > temperature=0.7, top_k=32, top_p=0.95.
+    fn base_strategy(&self, params: &SamplingParams) -> Sampling {
+        match self.strategy_source(params) {
+            StrategySource::Greedy => Sampling::ArgMax,
+            StrategySource::User => {
+                LogitsProcessor::get_strategy(params.temperature, params.top_k, params.top_p)
+            }
+            StrategySource::GenerationConfig(cfg) => {
+                LogitsProcessor::get_strategy(cfg.temperature, cfg.top_k, cfg.top_p)
+            }
+            StrategySource::Default => Sampling::TopKThenTopP {
+                k: 32,
+                p: 0.95,
+                temperature: 0.7,
+            },
+        }
+    }
+
+    /// Sampling strategy of a sequence, narrowed by its min_p/typical/epsilon/eta truncation.
+    fn sequence_strategy(&self, params: &SamplingParams) -> Sampling {
+        self.base_strategy(params)
+            .with_truncation(Truncation::from_sampling_params(params))
     }
 
     pub fn embed(&self, seqs: &[&Sequence], strategy: &EmbeddingStrategy) -> Result<Vec<Vec<f32>>> {
@@ -953,6 +1923,85 @@ impl ModelRunner {
         Ok(outputs)
     }
 
+    /// Log-probabilities of prompt tokens for the current prefill chunk of each sequence.
+    /// Position `i` scores prompt token `i + 1`, so a chunk starting at `num_cached_tokens`
+    /// yields one entry per following prompt token (the first prompt token is never scored).
+    pub fn score(&self, seqs: &[&Sequence], top_n: usize) -> Result<Vec<Vec<TokenLogprobs>>> {
+        let (input_ids, positions, input_metadata) = self.prepare_prefill(seqs)?;
+
+        let _prefill_guard = set_linear_is_prefill(true);
+        let hidden = crate::model_call!(
+            &self.model,
+            forward_embedding,
+            (&input_ids, &positions, Some(&self.get_kv_cache()), &input_metadata),
+            {
+                Qwen3 => false,
+                Qwen3MoE => false,
+                Qwen3_5 => false,
+                Qwen3_5MoE => false,
+                LLaMa => false,
+                Phi4 => false,
+                GLM4 => false,
+                GLM4MoE => false,
+                Gemma4 => false,
+                MiniMax => false,
+            },
+            candle_core::bail!("Prompt scoring is not supported for this model type")
+        )?;
+
+        // Project to the vocabulary in small row groups to bound the logits footprint.
+        const SCORE_ROWS: usize = 256;
+        let mut start = 0;
+        let mut outputs = Vec::new();
+        for seq in seqs {
+            let len = std::cmp::min(
+                PREFILL_CHUNK_SIZE,
+                seq.len().saturating_sub(seq.num_cached_tokens),
+            );
+            let first = seq.num_cached_tokens + 1;
+            let last = std::cmp::min(seq.num_cached_tokens + len + 1, seq.len());
+            let targets: &[u32] = if first < last {
+                &seq.token_ids[first..last]
+            } else {
+                &[]
+            };
+            let mut scored = Vec::with_capacity(targets.len());
+            for (group, ids) in targets.chunks(SCORE_ROWS).enumerate() {
+                let rows = hidden.narrow(0, start + group * SCORE_ROWS, ids.len())?;
+                let logits = self.compute_logits(&rows)?;
+                if top_n == 0 {
+                    let index = Tensor::new(ids, logits.device())?.unsqueeze(1)?;
+                    let logprobs = candle_nn::ops::log_softmax(&logits, D::Minus1)?
+                        .gather(&index, 1)?
+                        .squeeze(1)?
+                        .to_vec1::<f32>()?;
+                    scored.extend(ids.iter().zip(logprobs).map(|(&token_id, logprob)| {
+                        TokenLogprobs {
+                            token_id,
+                            logprob,
+                            top_logprobs: Vec::new(),
+                        }
+                    }));
+                } else {
+                    let top = vec![Some(top_n); ids.len()];
+                    scored.extend(
+                        LogitsProcessor::compute_logprobs(&logits, ids, &top)?
+                            .into_iter()
+                            .flatten(),
+                    );
+                }
+            }
+            outputs.push(scored);
+            start += len;
+        }
+
+        Ok(outputs)
+    }
+
+    fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        model_logits(&self.model, xs)
+    }
+
     fn prepare_block_tables<'a, I, S>(&self, seqs: I) -> Result<Tensor>
     where
         I: IntoIterator<Item = &'a S>,
@@ -990,11 +2039,10 @@ impl ModelRunner {
         let mut max_seqlen_q = 0;
         let mut max_seqlen_k = 0;
         let mut slot_mapping = Vec::new();
-        let CHUNK_SIZE: usize = if cfg!(feature = "cuda") { 8192 } else { 4096 };
         let mut max_context_len = 0;
         for (seq_idx, seq) in seqs.iter().enumerate() {
             let seqlen = seq.len();
-            let num_tokens = std::cmp::min(CHUNK_SIZE, seqlen - seq.num_cached_tokens);
+            let num_tokens = std::cmp::min(PREFILL_CHUNK_SIZE, seqlen - seq.num_cached_tokens);
             input_ids
                 .extend(&seq.token_ids[seq.num_cached_tokens..seq.num_cached_tokens + num_tokens]);
             positions.extend(
@@ -1386,7 +2434,7 @@ impl ModelRunner {
         Ok((input_ids, positions, input_metadata))
     }
 
-    fn sample(&self, logits: &Tensor, seqs: Seqs, is_prefill: bool) -> Result<Vec<u32>> {
+    fn sample(&self, logits: &Tensor, seqs: Seqs, is_prefill: bool) -> Result<RunOutput> {
         let seq_ids: Vec<usize> = match &seqs {
             Seqs::SeqRefs(seqs) => seqs.iter().map(|s| s.id()).collect(),
             Seqs::DecodeVec(v) => v.iter().map(|s| s.id()).collect(),
@@ -1398,52 +2446,22 @@ impl ModelRunner {
             Seqs::DecodeVec(v) => v.len(),
         };
 
-        // Compute and cache sampling params (including penalties) during prefill, reuse during decode
-        let cached_params = match (is_prefill, &seqs) {
-            // Prefill: compute sampling strategy and penalties, cache for decode phase
-            (true, Seqs::SeqRefs(seqs)) => {
-                // Check if generation_cfg has valid sampling params (temperature AND top_k/top_p)
-                let has_valid_sampling_cfg =
-                    self.config.generation_cfg.as_ref().map_or(false, |cfg| {
-                        cfg.temperature.is_some() && (cfg.top_k.is_some() || cfg.top_p.is_some())
-                    });
-                let user_params = &seqs[0].sampling_params;
-
-                // Log thinking parameter only from first rank to avoid duplicate logs in multi-GPU
-                if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
-                    crate::log_info!(
-                        "User's thinking preference for reasoning models: {:?}",
-                        user_params.thinking
-                    );
-                }
+        // Sampling strategies and penalties are resolved per sequence, log the first prompt's
+        if let (true, Seqs::SeqRefs(seqs)) = (is_prefill, &seqs) {
+            let user_params = &seqs[0].sampling_params;
 
-                // Determine frequency/presence penalties (user params > generation_cfg)
-                let gen_cfg_freq = self
-                    .config
-                    .generation_cfg
-                    .as_ref()
-                    .and_then(|c| c.frequency_penalty);
-                let gen_cfg_pres = self
-                    .config
-                    .generation_cfg
-                    .as_ref()
-                    .and_then(|c| c.presence_penalty);
-                let frequency_penalty = user_params.frequency_penalty.or(gen_cfg_freq);
-                let presence_penalty = user_params.presence_penalty.or(gen_cfg_pres);
-
-                let user_has_temperature = user_params.temperature.is_some();
-                let user_wants_greedy = matches!(user_params.temperature, Some(t) if t == 0.0);
-                let has_user_config = user_has_temperature
-                    || matches!(user_params.top_k, Some(k) if k > 0)
-                    || matches!(user_params.top_p, Some(p) if p > 0.0 && p < 1.0);
-
-                let sampling = if user_wants_greedy {
-                    if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
+            // Log only from first rank to avoid duplicate logs in multi-GPU
+            if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
+                crate::log_info!(
+                    "User's thinking preference for reasoning models: {:?}",
+                    user_params.thinking
+                );
+                let (frequency_penalty, presence_penalty) = self.sequence_penalties(user_params);
+                match self.strategy_source(user_params) {
+                    StrategySource::Greedy => {
                         crate::log_warn!("Using greedy decoding (temperature=0.0)");
                     }
-                    Sampling::ArgMax
-                } else if has_user_config {
-                    if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
+                    StrategySource::User => {
                         crate::log_warn!(
                             "Using user's sampling params: temp={:?}, top_k={:?}, top_p={:?}, freq_penalty={:?}, pres_penalty={:?}",
                             user_params.temperature,
@@ -1453,14 +2471,7 @@ impl ModelRunner {
                             presence_penalty
                         );
                     }
-                    LogitsProcessor::get_strategy(
-                        user_params.temperature,
-                        user_params.top_k,
-                        user_params.top_p,
-                    )
-                } else if has_valid_sampling_cfg {
-                    let cfg = self.config.generation_cfg.as_ref().unwrap();
-                    if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
+                    StrategySource::GenerationConfig(cfg) => {
                         crate::log_warn!(
                             "Using sampling from generation_config: temp={:?}, top_k={:?}, top_p={:?}, freq_penalty={:?}, pres_penalty={:?}",
                             cfg.temperature,
@@ -1470,53 +2481,27 @@ impl ModelRunner {
                             presence_penalty
                         );
                     }
-                    LogitsProcessor::get_strategy(cfg.temperature, cfg.top_k, cfg.top_p)
-                } else {
-                    if self.is_first_rank && seqs[0].num_cached_tokens == 0 {
+                    StrategySource::Default => {
                         crate::log_warn!(
                             "No generation_config, using default sampling (temperature=0.7, top_k=32, top_p=0.95)"
                         );
                     }
-                    Sampling::TopKThenTopP {
-                        k: 32,
-                        p: 0.95,
-                        temperature: 0.7,
-                    }
-                };
-
-                let cached = CachedSamplingParams {
-                    sampling,
-                    frequency_penalty,
-                    presence_penalty,
-                };
-
-                // Cache for decode phase
-                *self.cached_sampling.write() = Some(cached.clone());
-                cached
-            }
-            // Decode or non-SeqRefs: use cached parameters
-            _ => self
-                .cached_sampling
-                .read()
-                .clone()
-                .unwrap_or(CachedSamplingParams {
-                    sampling: Sampling::TopKThenTopP {
-                        k: 32,
-                        p: 0.95,
-                        temperature: 0.7,
-                    },
-                    frequency_penalty: None,
-                    presence_penalty: None,
-                }),
-        };
+                }
+            }
+        }
 
         let (guided_logits, guided_seq_ids) =
             self.apply_requested_guidance(logits, &seqs, &seq_ids)?;
+        let guided_logits = self.apply_token_constraints(&guided_logits, &seqs, &seq_ids)?;
 
-        // Apply penalties using cached values (same for all sequences in batch)
+        // Apply each sequence's frequency/presence penalties
         // This is done AFTER LLG masking so penalties only affect tokens allowed by grammar
-        let has_any_penalty =
-            cached_params.frequency_penalty.is_some() || cached_params.presence_penalty.is_some();
+        let penalties: Vec<(Option<f32>, Option<f32>)> = (0..batch_size)
+            .map(|i| self.sequence_penalties(sampling_params_for_batch_index(&seqs, i)))
+            .collect();
+        let has_any_penalty = penalties
+            .iter()
+            .any(|(frequency, presence)| frequency.is_some() || presence.is_some());
 
         let logits = if !is_prefill && has_any_penalty {
             let seq_tokens = self.seq_tokens.write();
@@ -1537,15 +2522,88 @@ impl ModelRunner {
 
             self.logit_processor.apply_batch_repeat_penalty(
                 &guided_logits,
-                vec![cached_params.frequency_penalty.unwrap_or(0.0); batch_size],
-                vec![cached_params.presence_penalty.unwrap_or(0.0); batch_size],
+                penalties.iter().map(|p| p.0.unwrap_or(0.0)).collect(),
+                penalties.iter().map(|p| p.1.unwrap_or(0.0)).collect(),
                 reference_tokens,
             )?
         } else {
             guided_logits.to_owned()
         };
 
-        let tokens = self.sample_processed_logits(&logits, &cached_params.sampling)?;
+        // HF-style penalties and DRY see the prompt and output tokens of each sequence
+        let uses_history =
+            (0..batch_size).any(|i| sampling_params_for_batch_index(&seqs, i).uses_token_history());
+        let logits = if uses_history {
+            if let Seqs::DecodeVec(rows) = &seqs {
+                self.sync_token_histories(rows);
+            }
+            let histories = self.token_histories.read();
+            let params: Vec<&SamplingParams> = (0..batch_size)
+                .map(|i| sampling_params_for_batch_index(&seqs, i))
+                .collect();
+            self.logit_processor.apply_batch_history_penalties(
+                &logits,
+                params.iter().map(|p| p.repetition_penalty).collect(),
+                params.iter().map(|p| p.no_repeat_ngram_size).collect(),
+                params
+                    .iter()
+                    .map(|p| DryParams::from_sampling_params(p))
+                    .collect(),
+                (0..batch_size)
+                    .map(|i| token_history(&seqs, &histories, i))
+                    .collect(),
+            )?
+        } else {
+            logits
+        };
+
+        // Seeded sequences draw from their own RNG stream so the output does not depend on
+        // batching, Mirostat sequences sample with the `mu` carried by the sequence. Both are
+        // sampled row by row, the others in batches sharing a strategy.
+        let strategies: Vec<Option<Sampling>> = (0..batch_size)
+            .map(|i| {
+                let params = sampling_params_for_batch_index(&seqs, i);
+                let own_row =
+                    params.seed.is_some() || Mirostat::from_sampling_params(params).is_some();
+                (!own_row).then(|| self.sequence_strategy(params))
+            })
+            .collect();
+        let mut tokens = self.sample_processed_logits(&logits, &strategies)?;
+
+        let mut next_mirostat_mu = vec![None; batch_size];
+        for (i, token) in tokens.iter_mut().enumerate() {
+            if strategies[i].is_some() {
+                continue;
+            }
+            let params = sampling_params_for_batch_index(&seqs, i);
+            let seeded = params.seed.map(|seed| {
+                self.logit_processor
+                    .with_seed(LogitsProcessor::step_seed(seed, sequence_len(&seqs, i)))
+            });
+            let processor = seeded.as_ref().unwrap_or(&self.logit_processor);
+            let row = logits.narrow(0, i, 1)?;
+            if let Some(mirostat) = Mirostat::from_sampling_params(params) {
+                let mu = mirostat_mu(&seqs, i).unwrap_or(mirostat.initial_mu());
+                let temperature = params.temperature.filter(|&t| t > 0.0).unwrap_or(1.0);
+                let row = row.squeeze(0)?.to_dtype(DType::F32)?.to_vec1::<f32>()?;
+                let (next, mu) = processor.sample_mirostat(&row, temperature, &mirostat, mu)?;
+                *token = next;
+                next_mirostat_mu[i] = Some(mu);
+            } else {
+                *token = processor.sample_with_strategy(&row, &self.sequence_strategy(params))?[0];
+            }
+        }
+
+        // Beam search ranks the top candidates of each beam in the scheduler
+        let top_n: Vec<Option<usize>> = (0..batch_size)
+            .map(|i| {
+                let params = sampling_params_for_batch_index(&seqs, i);
+                BeamSearchParams::from_sampling_params(params)
+                    .map(|beam| beam.num_candidates())
+                    .or_else(|| params.requested_top_logprobs())
+            })
+            .collect();
+        let logprobs = LogitsProcessor::compute_logprobs(&logits, &tokens, &top_n)?;
 
         self.commit_guided_tokens(&seq_ids, &tokens, guided_seq_ids);
 
@@ -1565,12 +2623,35 @@ impl ModelRunner {
         }
 
         // Guided token commits are handled immediately after sampling.
-        Ok(tokens)
+        Ok(RunOutput {
+            token_ids: tokens,
+            logprobs,
+            mirostat_mu: next_mirostat_mu,
+            accepted_token_ids: Vec::new(),
+            num_draft_tokens: Vec::new(),
+        })
+    }
+
+    /// Rows carrying `token_ids` replace the kept history, the others append `last_token`.
+    fn sync_token_histories(&self, rows: &[DecodeSequence]) {
+        let mut histories = self.token_histories.write();
+        for row in rows
+            .iter()
+            .filter(|row| row.sampling_params.uses_token_history())
+        {
+            if !row.token_ids.is_empty() {
+                histories.insert(row.id, row.token_ids.clone());
+            } else if let Some(history) = histories.get_mut(&row.id) {
+                history.truncate(row.len - 1);
+                history.push(row.last_token);
+            }
+        }
     }
 
     pub fn finished(&self, id: usize) {
         let mut seq_tokens = self.seq_tokens.write();
         let _ = seq_tokens.remove(&id);
+        let _ = self.token_histories.write().remove(&id);
         let mut restored = self.restored_prefix_sequences.write();
         let _ = restored.remove(&id);
         let mut guidance_states = self.guidance_states.write();
@@ -1579,6 +2660,10 @@ impl ModelRunner {
         let _ = guidance_failed.remove(&id);
         let mut guidance_mismatch = self.guidance_mismatch.write();
         let _ = guidance_mismatch.remove(&id);
+        let mut bad_words_filters = self.bad_words_filters.write();
+        let _ = bad_words_filters.remove(&id);
+        let _ = self.draft_progress.write().remove(&id);
+        let _ = self.mtp_hidden.write().remove(&id);
         match &self.model {
             Model::Qwen3_5(model) => model.release_sequence_state(id),
             Model::Qwen3_5MoE(model) => model.release_sequence_state(id),
@@ -1659,6 +2744,12 @@ impl ModelRunner {
             }
             Ok(true)
         }
+        if let Some(draft) = &self.draft {
+            cache_swap(&draft.gpu_kv_cache, &draft.cpu_kv_cache, &mappings, swap_in)?;
+        }
+        if let Some(mtp) = &self.mtp {
+            cache_swap(&mtp.gpu_kv_cache, &mtp.cpu_kv_cache, &mappings, swap_in)?;
+        }
         cache_swap(
             &*self.get_kv_cache(),
             &*self.get_cpu_kv_cache(),
@@ -1667,6 +2758,18 @@ impl ModelRunner {
         )
     }
 
+    /// Copy KV cache blocks within the GPU cache (`src -> dst`).
+    pub fn copy_kvcache(&self, mappings: HashMap<usize, usize>) -> Result<bool> {
+        let gpu_cache = self.get_kv_cache();
+        let draft_cache = self.draft.iter().flat_map(|d| d.gpu_kv_cache.iter());
+        let mtp_cache = self.mtp.iter().flat_map(|m| m.gpu_kv_cache.iter());
+        for (k_cache, v_cache) in gpu_cache.iter().chain(draft_cache).chain(mtp_cache) {
+            cache::swap_blocks(k_cache, k_cache, &mappings)?;
+            cache::swap_blocks(v_cache, v_cache, &mappings)?;
+        }
+        Ok(true)
+    }
+
     pub fn transfer_prefill(&self, seq: &Sequence) -> Result<bool> {
         if let Some(transfer) = &self.transfer {
             if !transfer.is_client() {
@@ -1702,6 +2805,14 @@ impl ModelRunner {
         }
     }
 
+    pub fn check_pd_connection(&self) -> Result<bool> {
+        if let Some(transfer) = &self.transfer {
+            Ok(transfer.is_connected())
+        } else {
+            candle_core::bail!("KV Cache transfer engine is not initialized!");
+        }
+    }
+
     pub fn send_kvcache(&self, seq: &Sequence, first_token: u32) -> Result<bool> {
         if let Some(transfer) = &self.transfer {
             if !transfer.is_server() {
@@ -1787,6 +2898,10 @@ mod tests {
             block_table_last: 0,
             block_tables: vec![0],
             sampling_params,
+            token_ids: Vec::new(),
+            output_ids: Vec::new(),
+            output_len: 0,
+            mirostat_mu: None,
         }
     }
 
@@ -1,16 +1,22 @@
 // src/core/scheduler.rs
 use super::runner::RunnerType;
 use super::{
+    beam_search::{BeamEnd, BeamGroup, BeamSearchParams},
     block_manager::BlockManager,
     prefix_cache::PrefixCacheConfig,
+    scheduling_policy::{Fcfs, SchedulingPolicy},
     sequence::{Sequence, SequenceStatus},
+    spec_decode::{self, SpeculativeConfig},
+    PREFILL_CHUNK_SIZE,
 };
 use crate::transfer::{PdConfig, PdRole};
 use crate::utils::config::{Config, EngineConfig, EosTokenId};
+use crate::utils::logits_processor::TokenLogprobs;
+use crate::utils::metrics::metrics;
 use candle_core::Result;
 use parking_lot::RwLock;
 use regex::Regex;
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokenizers::Tokenizer;
@@ -19,6 +25,15 @@ pub struct Scheduler {
     running: Vec<Sequence>,
     cached: Vec<Sequence>,
     transferred: VecDeque<Sequence>,
+    /// Sequence ids reserved for samples forked from a parent once its prefill finishes (`n` > 1)
+    pending_forks: HashMap<usize, Vec<usize>>,
+    /// Beam search groups keyed by the id of the request's root sequence
+    beam_groups: HashMap<usize, BeamGroup>,
+    /// Live beam sequence id -> root id of its group
+    beam_members: HashMap<usize, usize>,
+    finished_beam_groups: Vec<(usize, BeamGroup)>,
+    /// Beams pruned or finished since the last `take_released_beams`
+    released_beams: Vec<usize>,
     pub block_manager: BlockManager,
     next_seq_id: usize,
     eos_token_id: Vec<u32>,
@@ -35,6 +50,12 @@ pub struct Scheduler {
     cfg: EngineConfig,
     pd_config: Option<PdConfig>,
     is_last_prefill: bool,
+    /// Draft token proposer, `None` when speculative decoding is off or unsupported
+    speculative: Option<SpeculativeConfig>,
+    /// Whether draft tokens can be verified at all, needed for predicted outputs
+    verifies_drafts: bool,
+    /// Admission order of waiting sequences and choice of sequences to swap out
+    policy: Box<dyn SchedulingPolicy>,
 }
 
 const MIN_NUM_SCHEDULED_REQS: usize = 5;
@@ -111,26 +132,64 @@ fn build_prefix_cache_config(econfig: &EngineConfig) -> PrefixCacheConfig {
     }
 }
 
+/// Why draft tokens cannot be verified with this setup, `None` if they can.
+fn draft_verification_blocker(econfig: &EngineConfig) -> Option<&'static str> {
+    if econfig.pd_config.is_some() {
+        return Some("is not supported in PD disaggregation mode");
+    }
+    // Drafts are verified as a prefill chunk on top of the cached context
+    if !econfig.prefix_cache.unwrap_or(false)
+        && !cfg!(feature = "flashattn")
+        && !cfg!(feature = "flashinfer")
+    {
+        return Some("requires prefix cache (`--prefix-cache`) or flash attention");
+    }
+    None
+}
+
+fn build_speculative_config(econfig: &EngineConfig) -> Option<SpeculativeConfig> {
+    let spec = econfig
+        .speculative_config
+        .clone()
+        .filter(SpeculativeConfig::is_enabled)?;
+    if let Some(reason) = draft_verification_blocker(econfig) {
+        crate::log_warn!("Speculative decoding {}, disabled.", reason);
+        return None;
+    }
+    crate::log_warn!(
+        "Speculative decoding enabled: {:?}, up to {} draft tokens per step.",
+        spec.method,
+        spec.num_speculative_tokens
+    );
+    Some(spec)
+}
+
 impl Scheduler {
     pub fn new(runners: Arc<RwLock<RunnerType>>, econfig: &EngineConfig, config: &Config) -> Self {
         let prefix_cache_cfg = build_prefix_cache_config(econfig);
+        let has_mamba_state = config
+            .architectures
+            .as_ref()
+            .and_then(|arches| arches.first())
+            .map(|arch| crate::utils::is_qwen3_hybrid_arch_name(arch))
+            .unwrap_or(false);
         Self {
             waiting: VecDeque::new(),
             running: Vec::new(),
             cached: Vec::new(),
             transferred: VecDeque::new(),
+            pending_forks: HashMap::new(),
+            beam_groups: HashMap::new(),
+            beam_members: HashMap::new(),
+            finished_beam_groups: Vec::new(),
+            released_beams: Vec::new(),
             block_manager: BlockManager::new(
                 runners,
                 econfig.num_blocks,
                 (econfig.cpu_mem_fold.unwrap_or(0.2f32) * econfig.num_blocks as f32) as usize,
                 econfig.block_size,
                 prefix_cache_cfg,
-                config
-                    .architectures
-                    .as_ref()
-                    .and_then(|arches| arches.first())
-                    .map(|arch| crate::utils::is_qwen3_hybrid_arch_name(arch))
-                    .unwrap_or(false),
+                has_mamba_state,
             ),
             next_seq_id: 0,
             eos_token_id: match &config.eos_token_id {
@@ -149,9 +208,17 @@ impl Scheduler {
             cfg: econfig.clone(),
             pd_config: econfig.pd_config.clone(),
             is_last_prefill: false,
+            speculative: build_speculative_config(econfig),
+            verifies_drafts: draft_verification_blocker(econfig).is_none(),
+            policy: Box::new(Fcfs),
         }
     }
 
+    /// Set the scheduling policy (called by engine, FCFS by default)
+    pub fn set_policy(&mut self, policy: Box<dyn SchedulingPolicy>) {
+        self.policy = policy;
+    }
+
     /// Set tool call end token IDs (called by engine after tokenizer is available)
     pub fn set_tool_call_end_tokens(&mut self, token_ids: Vec<u32>) {
         self.tool_call_end_token_ids = token_ids;
@@ -178,10 +245,241 @@ impl Scheduler {
         seq.id = self.next_seq_id;
         let id = seq.id;
         self.next_seq_id += 1;
+        if let Some(params) = BeamSearchParams::from_sampling_params(&seq.sampling_params) {
+            self.beam_groups.insert(
+                id,
+                BeamGroup::new(id, params, seq.created_time(), seq.len()),
+            );
+            self.beam_members.insert(id, id);
+        }
         self.waiting.push_back(seq);
         id
     }
 
+    /// Reserve `num` sequence ids that will be forked from `parent_id` after its prefill,
+    /// sharing the prompt KV blocks instead of prefilling the prompt again.
+    pub fn reserve_forks(&mut self, parent_id: usize, num: usize) -> Vec<usize> {
+        let ids: Vec<usize> = (self.next_seq_id..self.next_seq_id + num).collect();
+        self.next_seq_id += num;
+        if !ids.is_empty() {
+            self.pending_forks.insert(parent_id, ids.clone());
+        }
+        ids
+    }
+
+    /// Sequences generated on behalf of request `seq_id` besides the request's own sequence:
+    /// samples not forked yet and the live beams of its beam search group.
+    pub fn linked_sequence_ids(&self, seq_id: usize) -> Vec<usize> {
+        let mut ids = self.pending_forks.get(&seq_id).cloned().unwrap_or_default();
+        if let Some(group) = self.beam_groups.get(&seq_id) {
+            ids.extend(group.beam_ids().into_iter().filter(|&id| id != seq_id));
+        }
+        ids
+    }
+
+    pub fn is_beam_sequence(&self, seq_id: usize) -> bool {
+        self.beam_members.contains_key(&seq_id)
+    }
+
+    /// Beam sequences that left their group (pruned or finished) and need runner cleanup.
+    pub fn take_released_beams(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.released_beams)
+    }
+
+    /// Completed beam search groups, keyed by the root sequence id of the request.
+    pub fn take_finished_beam_groups(&mut self) -> Vec<(usize, BeamGroup)> {
+        std::mem::take(&mut self.finished_beam_groups)
+    }
+
+    /// Feed the candidates of scheduled beam sequences to their groups and advance every group
+    /// whose beams have all reported. Returns the ids and tokens of the remaining (non-beam)
+    /// sequences for `postprocess`.
+    pub fn advance_beam_groups(
+        &mut self,
+        ids: &[usize],
+        output_ids: &[u32],
+        logprobs: &mut HashMap<usize, TokenLogprobs>,
+    ) -> (Vec<usize>, Vec<u32>) {
+        if self.beam_groups.is_empty() {
+            return (ids.to_vec(), output_ids.to_vec());
+        }
+        let mut rest_ids = Vec::new();
+        let mut rest_tokens = Vec::new();
+        let mut ready = Vec::new();
+        for (i, &idx) in ids.iter().enumerate() {
+            let root_id = self
+                .running
+                .get(idx)
+                .and_then(|seq| self.beam_members.get(&seq.id).copied());
+            let Some(root_id) = root_id else {
+                rest_ids.push(idx);
+                rest_tokens.push(output_ids[i]);
+                continue;
+            };
+            let seq_id = self.running[idx].id;
+            let candidates = logprobs
+                .remove(&seq_id)
+                .map(|l| l.top_logprobs)
+                .unwrap_or_default();
+            if let Some(group) = self.beam_groups.get_mut(&root_id) {
+                if group.report(seq_id, candidates) && !ready.contains(&root_id) {
+                    ready.push(root_id);
+                }
+            }
+        }
+        for root_id in ready {
+            self.step_beam_group(root_id);
+        }
+        (rest_ids, rest_tokens)
+    }
+
+    fn running_index(&self, seq_id: usize) -> Option<usize> {
+        self.running.iter().position(|seq| seq.id == seq_id)
+    }
+
+    fn release_beam(&mut self, seq_id: usize) {
+        self.beam_members.remove(&seq_id);
+        if let Some(idx) = self.running_index(seq_id) {
+            let seq = &mut self.running[idx];
+            seq.status = SequenceStatus::Finished;
+            self.block_manager.deallocate(seq);
+        }
+        self.released_beams.push(seq_id);
+    }
+
+    fn step_beam_group(&mut self, root_id: usize) {
+        let Some(mut group) = self.beam_groups.remove(&root_id) else {
+            return;
+        };
+        if group.decode_start_time.is_none() {
+            group.decode_start_time = Some(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_millis() as usize,
+            );
+        }
+        let old_beams = group.beam_ids();
+        let (continuations, finished) = group.select(|seq_id, token| self.beam_end(seq_id, token));
+        for (candidate, end) in finished {
+            if let Some(idx) = self.running_index(candidate.parent_id) {
+                let seq = &self.running[idx];
+                // The ending token counts towards the hypothesis length but is not part of
+                // the output, as in `postprocess`
+                group.add_hypothesis(
+                    seq.output_ids.clone(),
+                    candidate.cum_logprob,
+                    seq.output_len() + 1,
+                    end,
+                );
+            }
+        }
+
+        // The first continuation of a beam extends it in place, further ones fork it.
+        // Forks share all KV blocks with their parent, which are copied on write.
+        let mut new_beams = Vec::new();
+        let mut appends = Vec::new();
+        for candidate in continuations {
+            let Some(idx) = self.running_index(candidate.parent_id) else {
+                continue;
+            };
+            if appends.iter().all(|(id, _)| *id != candidate.parent_id) {
+                appends.push((candidate.parent_id, candidate.token_id));
+                new_beams.push((candidate.parent_id, candidate.cum_logprob));
+            } else {
+                let mut child = self.running[idx].clone();
+                child.id = self.next_seq_id;
+                self.next_seq_id += 1;
+                child.block_table.clear();
+                self.block_manager.fork(&self.running[idx], &mut child);
+                child.append_token(candidate.token_id);
+                self.beam_members.insert(child.id, root_id);
+                new_beams.push((child.id, candidate.cum_logprob));
+                self.running.push(child);
+            }
+        }
+        for (seq_id, token) in appends {
+            if let Some(idx) = self.running_index(seq_id) {
+                self.running[idx].append_token(token);
+            }
+        }
+        for seq_id in old_beams {
+            if new_beams.iter().all(|(id, _)| *id != seq_id) {
+                self.release_beam(seq_id);
+            }
+        }
+        group.set_beams(new_beams.clone());
+
+        let (cur_len, reached_limit) = new_beams
+            .first()
+            .and_then(|(id, _)| self.running_index(*id))
+            .map(|idx| {
+                let seq = &self.running[idx];
+                (
+                    seq.output_len(),
+                    seq.output_len() >= seq.sampling_params.max_tokens.unwrap_or(16384)
+                        || seq.len() > self.cfg.max_num_batched_tokens,
+                )
+            })
+            .unwrap_or((0, true));
+        if !reached_limit && !group.is_done(cur_len) {
+            self.beam_groups.insert(root_id, group);
+            return;
+        }
+        for (seq_id, cum_logprob) in new_beams {
+            if reached_limit {
+                if let Some(idx) = self.running_index(seq_id) {
+                    let seq = &self.running[idx];
+                    group.add_hypothesis(
+                        seq.output_ids.clone(),
+                        cum_logprob,
+                        seq.output_len(),
+                        BeamEnd::Length,
+                    );
+                }
+            }
+            self.release_beam(seq_id);
+        }
+        self.finished_beam_groups.push((root_id, group));
+    }
+
+    /// Whether extending beam `seq_id` with `token` ends it, applying the same EOS, stop and
+    /// `min_tokens` rules as `postprocess`.
+    fn beam_end(&self, seq_id: usize, token: u32) -> Option<BeamEnd> {
+        let seq = &self.running[self.running_index(seq_id)?];
+        if seq.sampling_params.below_min_tokens(seq.output_len()) {
+            return None;
+        }
+        if let Some(stop_idx) = self.stop_sequence_match_index(token, seq) {
+            let stop_sequence = seq
+                .sampling_params
+                .stop_sequences
+                .as_ref()
+                .and_then(|stops| stops.get(stop_idx))
+                .cloned();
+            return Some(BeamEnd::Stop(stop_sequence));
+        }
+        self.eos_token_id.contains(&token).then_some(BeamEnd::Eos)
+    }
+
+    // Children restart from the prompt (their first token is sampled by re-decoding the last
+    // prompt token) so every sample, including the first token, is drawn independently.
+    fn fork_sequence(&mut self, idx: usize, child_ids: Vec<usize>) {
+        let parent = self.running[idx].clone();
+        for (i, child_id) in child_ids.into_iter().enumerate() {
+            let mut child = parent.clone();
+            child.id = child_id;
+            // Each sample of a seeded request gets its own reproducible stream
+            if let Some(seed) = parent.sampling_params.seed {
+                child.sampling_params.seed = Some(seed.wrapping_add(i as u64 + 1));
+            }
+            child.block_table.clear();
+            self.block_manager.fork(&parent, &mut child);
+            crate::log_info!("Seq {} forked from Seq {}", child_id, parent.id);
+            self.running.push(child);
+        }
+    }
+
     pub fn is_finished(&self) -> bool {
         self.waiting.is_empty() && self.running.is_empty()
     }
@@ -217,6 +515,8 @@ impl Scheduler {
             }
         }
 
+        self.policy.order_waiting(&mut self.waiting, &self.running);
+
         // Prefill phase: move sequences from waiting to running if possible
         while let Some(mut seq) = self.waiting.pop_front() {
             // Try to transfer prefill requests to PD server when applicable
@@ -224,6 +524,17 @@ impl Scheduler {
                 break;
             }
 
+            // Preempting shifts the indexes in `running`, so only before anything is scheduled
+            if scheduled_ids.is_empty()
+                && !(self.is_last_prefill && self.running.len() > 0)
+                && seq.block_table.is_empty()
+                && !self.block_manager.can_allocate(&seq)
+                && self.preempt_for(&seq)
+            {
+                self.waiting.push_front(seq);
+                continue;
+            }
+
             if scheduled_ids.len() >= std::cmp::max(self.cfg.max_num_seqs, MIN_NUM_SCHEDULED_REQS)
                 || num_tokens + seq.len() >= self.cfg.max_num_batched_tokens - 1
                 || (seq.block_table.is_empty() && !self.block_manager.can_allocate(&seq))
@@ -284,12 +595,7 @@ impl Scheduler {
             if evicted > 0 {
                 crate::log_warn!("Evicted {} prefix cache block(s) under pressure.", evicted);
             } else if !preempt_ids.is_empty() && self.running.len() > 1 {
-                if let Some((idx, _)) = preempt_ids
-                    .iter()
-                    .map(|&i| (i, &self.running[i]))
-                    .min_by_key(|(_, seq)| seq.id)
-                // swap-out the oldest sequence
-                {
+                if let Some(idx) = self.policy.select_victim(&self.running, &preempt_ids) {
                     crate::log_warn!("Trying to swap out preempt Seq {:?}", self.running[idx].id);
                     self.try_swap_out(idx, true);
                 }
@@ -297,6 +603,8 @@ impl Scheduler {
         }
 
         let is_pd_server = self.is_pd_server();
+        // Draft slots must not starve the next token of the other sequences
+        let min_free_blocks = self.running.len();
         for (idx, seq) in self.running.iter_mut().enumerate() {
             if decode_ids.len() >= std::cmp::max(self.cfg.max_num_seqs, MIN_NUM_SCHEDULED_REQS) {
                 break;
@@ -319,6 +627,11 @@ impl Scheduler {
                 continue;
             }
             self.block_manager.may_append(seq)?;
+            if !seq.prediction_token_ids.is_empty() {
+                Self::propose_prediction(&mut self.block_manager, seq, min_free_blocks)?;
+            } else if let Some(spec) = &self.speculative {
+                Self::propose_draft(spec, &mut self.block_manager, seq, min_free_blocks)?;
+            }
             decode_ids.push(idx);
         }
 
@@ -326,6 +639,120 @@ impl Scheduler {
         Ok((decode_ids, false))
     }
 
+    /// Swap out a running sequence that the policy lets `seq` preempt, so its prompt fits in
+    /// the KV cache. Swapped sequences are only swapped back in on CUDA.
+    fn preempt_for(&mut self, seq: &Sequence) -> bool {
+        if !cfg!(feature = "cuda") || self.is_pd_mode() {
+            return false;
+        }
+        let candidates: Vec<usize> = self
+            .running
+            .iter()
+            .enumerate()
+            .filter(|(_, running)| {
+                running.status == SequenceStatus::Running
+                    && !self.beam_members.contains_key(&running.id)
+                    && !self.pending_forks.contains_key(&running.id)
+                    && self.policy.preempts(seq, running)
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+        let Some(idx) = self.policy.select_victim(&self.running, &candidates) else {
+            return false;
+        };
+        crate::log_warn!(
+            "Swapping out Seq {} to admit Seq {} ({} policy)",
+            self.running[idx].id,
+            seq.id,
+            self.policy.name()
+        );
+        self.try_swap_out(idx, true)
+    }
+
+    /// Propose draft tokens for `seq`, or leave slots for the draft model (or MTP) to fill, and
+    /// reserve their KV slots; no drafts if blocks run short.
+    fn propose_draft(
+        spec: &SpeculativeConfig,
+        block_manager: &mut BlockManager,
+        seq: &mut Sequence,
+        min_free_blocks: usize,
+    ) -> Result<()> {
+        seq.draft_token_ids.clear();
+        seq.draft_slots = 0;
+        if !seq.sampling_params.allows_speculation() {
+            return Ok(());
+        }
+        let remaining = Self::draft_budget(seq);
+        if spec.drafts_in_runner() {
+            let num_tokens = std::cmp::min(spec.num_speculative_tokens, remaining);
+            if num_tokens > 0
+                && block_manager.reserve_draft_slots(seq, num_tokens, min_free_blocks)?
+            {
+                seq.draft_slots = num_tokens;
+            }
+            return Ok(());
+        }
+        let draft = spec.propose(&seq.token_ids, remaining);
+        if !draft.is_empty()
+            && block_manager.reserve_draft_slots(seq, draft.len(), min_free_blocks)?
+        {
+            seq.draft_token_ids = draft;
+        }
+        Ok(())
+    }
+
+    /// Propose the next tokens of the predicted output of `seq` as drafts. Requests with a
+    /// prediction draft from it alone, nothing while their output has left the prediction.
+    fn propose_prediction(
+        block_manager: &mut BlockManager,
+        seq: &mut Sequence,
+        min_free_blocks: usize,
+    ) -> Result<()> {
+        seq.draft_token_ids.clear();
+        seq.draft_slots = 0;
+        let draft = Self::prediction_draft(seq);
+        if !draft.is_empty()
+            && block_manager.reserve_draft_slots(seq, draft.len(), min_free_blocks)?
+        {
+            seq.draft_token_ids = draft;
+        }
+        Ok(())
+    }
+
+    /// Next tokens of the predicted output of `seq` that can be verified, advancing its
+    /// alignment with the prediction.
+    fn prediction_draft(seq: &mut Sequence) -> Vec<u32> {
+        if !seq.sampling_params.allows_speculation() {
+            return Vec::new();
+        }
+        let num_tokens = std::cmp::min(
+            spec_decode::PREDICTION_DRAFT_TOKENS,
+            Self::draft_budget(seq),
+        );
+        let (draft, shift) = spec_decode::propose_from_prediction(
+            &seq.prediction_token_ids,
+            &seq.output_ids,
+            seq.prediction_shift,
+            num_tokens,
+        );
+        seq.prediction_shift = shift;
+        draft
+    }
+
+    /// Drafts that fit in the remaining token budget; the verified step produces one token
+    /// besides the accepted drafts.
+    fn draft_budget(seq: &Sequence) -> usize {
+        seq.sampling_params
+            .max_tokens
+            .unwrap_or(16384)
+            .saturating_sub(seq.output_len() + 1)
+    }
+
+    /// Whether draft tokens can be verified, i.e. predicted outputs can be used.
+    pub fn verifies_drafts(&self) -> bool {
+        self.verifies_drafts
+    }
+
     /// Provide immutable access to sequences by indexes (for model inference)
     pub fn get_sequences(&self, ids: &[usize]) -> Vec<&Sequence> {
         ids.iter().map(|&i| &self.running[i]).collect()
@@ -452,6 +879,9 @@ impl Scheduler {
             }
             let seq_id = self.running[idx].id;
             let token = output_ids[i];
+            if let Some(child_ids) = self.pending_forks.remove(&seq_id) {
+                self.fork_sequence(idx, child_ids);
+            }
 
             // Since all reqeusts in PD server are prefill request, we need to finish and transfer
             // the kvcache in the first postprocess for each request.
@@ -527,21 +957,31 @@ impl Scheduler {
                     seq.is_tool_call_end = true;
                     // External tool mode: finish stream so client can handle tool calls
                     seq.status = SequenceStatus::Finished;
-                    self.block_manager
-                        .capture_mamba_prefix_state(seq, seq.len());
+                    if !seq.is_speculative() {
+                        self.block_manager
+                            .capture_mamba_prefix_state(seq, seq.len());
+                    }
                     self.block_manager.cache_sequence(seq);
                     self.block_manager.deallocate(seq);
                     continue;
                 }
             }
 
-            let matched_stop_sequence_idx =
-                self.stop_sequence_match_index(token, &self.running[idx]);
+            // Stops are ignored until the sequence has produced min_tokens
+            let below_min_tokens = {
+                let seq = &self.running[idx];
+                seq.sampling_params.below_min_tokens(seq.output_len())
+            };
+            let matched_stop_sequence_idx = if below_min_tokens {
+                None
+            } else {
+                self.stop_sequence_match_index(token, &self.running[idx])
+            };
             let hit_stop_sequence = matched_stop_sequence_idx.is_some();
             let seq = &mut self.running[idx];
 
             if hit_stop_sequence
-                || self.eos_token_id.contains(&token)
+                || (!below_min_tokens && self.eos_token_id.contains(&token))
                 || seq.output_len() >= seq.sampling_params.max_tokens.unwrap_or(16384)
                 || seq.len() > self.cfg.max_num_batched_tokens
             {
@@ -561,13 +1001,17 @@ impl Scheduler {
                     });
                 }
                 seq.status = SequenceStatus::Finished;
-                self.block_manager
-                    .capture_mamba_prefix_state(seq, seq.len());
+                // Verifying drafts advances the mamba state past the tokens appended so far
+                // in this step, so speculative steps take no snapshots
+                if !seq.is_speculative() {
+                    self.block_manager
+                        .capture_mamba_prefix_state(seq, seq.len());
+                }
                 self.block_manager.cache_sequence(seq);
                 self.block_manager.deallocate(seq);
             } else {
                 seq.append_token(token);
-                if seq.len() % self.cfg.block_size == 0 {
+                if seq.len() % self.cfg.block_size == 0 && !seq.is_speculative() {
                     self.block_manager
                         .capture_mamba_prefix_state(seq, seq.len());
                 }
@@ -575,6 +1019,63 @@ impl Scheduler {
         }
     }
 
+    /// Append the draft tokens accepted in a speculative step after `postprocess` has handled
+    /// the first token of each sequence. Tokens go through `postprocess` one at a time, so a
+    /// stop drops the tokens after it. The slots of rejected drafts are released afterwards.
+    pub fn postprocess_accepted_drafts(&mut self, ids: &[usize], accepted_token_ids: &[Vec<u32>]) {
+        for (i, &idx) in ids.iter().enumerate() {
+            let Some(seq) = self.running.get_mut(idx) else {
+                continue;
+            };
+            // Drafts of requests with a prediction all come from it
+            if !seq.prediction_token_ids.is_empty() && !seq.draft_token_ids.is_empty() {
+                let accepted = accepted_token_ids
+                    .get(i)
+                    .map_or(0, Vec::len)
+                    .min(seq.draft_token_ids.len());
+                seq.accepted_prediction_tokens += accepted;
+                seq.rejected_prediction_tokens += seq.draft_token_ids.len() - accepted;
+            }
+        }
+        let rounds = accepted_token_ids.iter().map(Vec::len).max().unwrap_or(0);
+        for round in 0..rounds {
+            let (round_ids, round_tokens): (Vec<usize>, Vec<u32>) = ids
+                .iter()
+                .zip(accepted_token_ids)
+                .filter_map(|(&idx, tokens)| {
+                    let token = *tokens.get(round)?;
+                    let seq = self.running.get(idx)?;
+                    (!seq.is_finished()).then_some((idx, token))
+                })
+                .unzip();
+            self.postprocess(&round_ids, &round_tokens);
+        }
+        for &idx in ids {
+            let Some(seq) = self.running.get_mut(idx) else {
+                continue;
+            };
+            if !seq.is_speculative() {
+                continue;
+            }
+            seq.draft_token_ids.clear();
+            seq.draft_slots = 0;
+            // Finished sequences already returned all of their blocks
+            if !seq.is_finished() {
+                self.block_manager.release_draft_slots(seq);
+            }
+        }
+    }
+
+    /// Store the Mirostat `mu` returned by the runner; call after `postprocess` so that
+    /// samples forked in this step start from the initial `mu`.
+    pub fn update_mirostat_mu(&mut self, ids: &[usize], mirostat_mu: &[Option<f32>]) {
+        for (&idx, mu) in ids.iter().zip(mirostat_mu) {
+            if let (Some(mu), Some(seq)) = (mu, self.running.get_mut(idx)) {
+                seq.mirostat_mu = Some(*mu);
+            }
+        }
+    }
+
     pub fn clear_finished(&mut self) {
         let is_pd_server = self.is_pd_server();
         for seq in &self.running {
@@ -596,6 +1097,7 @@ impl Scheduler {
             self.block_manager.deallocate(seq);
         }
         self.waiting.clear();
+        self.pending_forks.clear();
         for i in 0..self.cached.len() {
             let seq = &mut self.cached[i];
             seq.status = SequenceStatus::Finished;
@@ -643,6 +1145,9 @@ impl Scheduler {
                 crate::log_warn!("Seq {} - cancel requested (status {})", seq.id, seq.status);
             }
         }
+        self.pending_forks.remove(&seq_id);
+        self.beam_groups.remove(&seq_id);
+        self.beam_members.remove(&seq_id);
         self.release_cache(seq_id);
         self.running.retain(|seq| seq.id != seq_id);
         self.waiting.retain(|seq| seq.id != seq_id);
@@ -655,14 +1160,15 @@ impl Scheduler {
     ) -> (Vec<usize>, Vec<usize>) {
         let mut finished_seqs = Vec::new();
         let mut remove_ids = Vec::new();
-        let CHUNK_SIZE: usize = if cfg!(feature = "cuda") { 8192 } else { 4096 };
         for (i, id) in scheduled_ids.iter().enumerate() {
             if *id < self.running.len() {
                 let seq = &self.running[*id];
-                if seq.len() < CHUNK_SIZE || seq.num_cached_tokens + CHUNK_SIZE >= seq.len() {
+                if seq.len() < PREFILL_CHUNK_SIZE
+                    || seq.num_cached_tokens + PREFILL_CHUNK_SIZE >= seq.len()
+                {
                     self.block_manager
                         .capture_mamba_prefix_state(seq, seq.len());
-                    if seq.len() > CHUNK_SIZE {
+                    if seq.len() > PREFILL_CHUNK_SIZE {
                         crate::log_warn!(
                             "Seq {} - chunk prefill finished ({} tokens)",
                             seq.id,
@@ -671,12 +1177,14 @@ impl Scheduler {
                     }
                     finished_seqs.push((i, seq.id));
                 } else {
-                    self.block_manager
-                        .capture_mamba_prefix_state(seq, seq.num_cached_tokens + CHUNK_SIZE);
+                    self.block_manager.capture_mamba_prefix_state(
+                        seq,
+                        seq.num_cached_tokens + PREFILL_CHUNK_SIZE,
+                    );
                     remove_ids.push(seq.id);
                     //unfinished due to chunked_prefill, push back to waiting list
                     let mut seq = seq.clone();
-                    seq.num_cached_tokens += CHUNK_SIZE; //current prefilled CHUNK_SIZE
+                    seq.num_cached_tokens += PREFILL_CHUNK_SIZE; //current prefilled chunk
                     seq.status = SequenceStatus::Waiting;
                     crate::log_info!(
                         "Seq {} - chunk prefilled {} (remain {} tokens)",
@@ -799,6 +1307,7 @@ impl Scheduler {
                 Ok(_) => {
                     seq.status = SequenceStatus::Running;
                     crate::log_warn!("Seq {} is swapped in for execution!", seq.id);
+                    metrics().record_swap_in();
                 }
                 Err(e) => {
                     seq.status = SequenceStatus::Finished;
@@ -844,6 +1353,7 @@ impl Scheduler {
                         // no need remove it from cached list since it can be recoved
                         self.cached.remove(idx)
                     };
+                    metrics().record_swap_out(seq.status == SequenceStatus::Running);
                     if seq.status == SequenceStatus::Running {
                         seq.status = SequenceStatus::Swapped;
                     } else {
@@ -888,6 +1398,12 @@ impl Scheduler {
         }
     }
 
+    pub fn is_pd_connected(&self) -> bool {
+        self.block_manager
+            .try_check_pd_connection()
+            .unwrap_or(false)
+    }
+
     pub fn is_suitable_for_transfer(&mut self, seq: &Sequence) -> bool {
         if seq.status == SequenceStatus::Swapped // swapped out sequence
             || seq.status == SequenceStatus::FinishSwapped // swapped out and finished sequence
@@ -1125,6 +1641,22 @@ impl Scheduler {
         }
     }
 
+    /// Number of (waiting, running, swapped-out) sequences.
+    pub fn queue_depths(&self) -> (usize, usize, usize) {
+        let swapped = self
+            .cached
+            .iter()
+            .filter(|seq| seq.status == SequenceStatus::Swapped)
+            .count();
+        (self.waiting.len(), self.running.len(), swapped)
+    }
+
+    /// Number of waiting sequences and their total length in tokens.
+    pub fn waiting_load(&self) -> (usize, usize) {
+        let tokens = self.waiting.iter().map(|seq| seq.len()).sum();
+        (self.waiting.len(), tokens)
+    }
+
     pub fn kv_cache_usage_percent(&self) -> f32 {
         let total_blocks = self.block_manager.get_num_total_blocks();
         let free_blocks = self.block_manager.get_num_free_blocks();
@@ -1212,3 +1744,22 @@ impl Scheduler {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::config::SamplingParams;
+
+    #[test]
+    fn predictions_draft_with_zero_penalties() {
+        let mut params = SamplingParams::new_with_max_tokens(64);
+        params.frequency_penalty = Some(0.0);
+        params.presence_penalty = Some(0.0);
+        let mut seq = Sequence::new(vec![1, 2, 3], 16, params, &None, 0);
+        seq.prediction_token_ids = vec![10, 11, 12];
+        assert_eq!(Scheduler::prediction_draft(&mut seq), vec![10, 11, 12]);
+
+        seq.sampling_params.frequency_penalty = Some(0.5);
+        assert!(Scheduler::prediction_draft(&mut seq).is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+// src/core/scheduling_policy.rs
+//! Scheduling policies, selected with `--scheduling-policy`.
+//!
+//! A policy decides which waiting sequence is admitted next and which running sequence is
+//! swapped out when the KV cache runs short. Policies only reorder, so admission is still
+//! bounded by the KV cache and `max_num_batched_tokens`.
+use super::sequence::Sequence;
+use candle_core::Result;
+use std::collections::{HashMap, VecDeque};
+
+pub const SCHEDULING_POLICIES: [&str; 4] = ["fcfs", "priority", "shortest-prompt", "fair-share"];
+
+pub trait SchedulingPolicy: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Reorders `waiting` so the sequence to admit next is at the front.
+    fn order_waiting(&self, waiting: &mut VecDeque<Sequence>, running: &[Sequence]);
+
+    /// Picks the sequence to swap out among `candidates` (indexes into `running`).
+    fn select_victim(&self, running: &[Sequence], candidates: &[usize]) -> Option<usize>;
+
+    /// Whether `waiting` may swap out `running` when there is no KV cache left for it.
+    fn preempts(&self, _waiting: &Sequence, _running: &Sequence) -> bool {
+        false
+    }
+}
+
+/// Builds the policy named `name` (see `SCHEDULING_POLICIES`), FCFS when `None`.
+pub fn from_name(name: Option<&str>) -> Result<Box<dyn SchedulingPolicy>> {
+    let policy: Box<dyn SchedulingPolicy> = match name.unwrap_or("fcfs") {
+        "fcfs" => Box::new(Fcfs),
+        "priority" => Box::new(Priority),
+        "shortest-prompt" => Box::new(ShortestPrompt),
+        "fair-share" => Box::new(FairShare),
+        other => candle_core::bail!(
+            "Unknown scheduling policy {other:?}, expected one of: {}",
+            SCHEDULING_POLICIES.join(", ")
+        ),
+    };
+    Ok(policy)
+}
+
+/// Request priority, lower values are scheduled first.
+fn priority(seq: &Sequence) -> i32 {
+    seq.sampling_params.priority.unwrap_or(0)
+}
+
+/// First come, first served; swaps out the oldest sequence.
+pub struct Fcfs;
+
+impl SchedulingPolicy for Fcfs {
+    fn name(&self) -> &'static str {
+        "fcfs"
+    }
+
+    fn order_waiting(&self, _waiting: &mut VecDeque<Sequence>, _running: &[Sequence]) {}
+
+    fn select_victim(&self, running: &[Sequence], candidates: &[usize]) -> Option<usize> {
+        candidates.iter().copied().min_by_key(|&i| running[i].id)
+    }
+}
+
+/// Lowest `priority` value first, FCFS within a priority. A waiting sequence swaps out
+/// running sequences of a lower priority (newest first) when the KV cache is full.
+pub struct Priority;
+
+impl SchedulingPolicy for Priority {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn order_waiting(&self, waiting: &mut VecDeque<Sequence>, _running: &[Sequence]) {
+        waiting.make_contiguous().sort_by_key(priority);
+    }
+
+    fn select_victim(&self, running: &[Sequence], candidates: &[usize]) -> Option<usize> {
+        candidates
+            .iter()
+            .copied()
+            .max_by_key(|&i| (priority(&running[i]), running[i].id))
+    }
+
+    fn preempts(&self, waiting: &Sequence, running: &Sequence) -> bool {
+        priority(waiting) < priority(running)
+    }
+}
+
+/// Shortest prompt first, FCFS among equal lengths; swaps out the longest sequence,
+/// which frees the most KV cache. Long prompts can starve under sustained load.
+pub struct ShortestPrompt;
+
+impl SchedulingPolicy for ShortestPrompt {
+    fn name(&self) -> &'static str {
+        "shortest-prompt"
+    }
+
+    fn order_waiting(&self, waiting: &mut VecDeque<Sequence>, _running: &[Sequence]) {
+        waiting.make_contiguous().sort_by_key(|seq| seq.len());
+    }
+
+    fn select_victim(&self, running: &[Sequence], candidates: &[usize]) -> Option<usize> {
+        candidates
+            .iter()
+            .copied()
+            .max_by_key(|&i| (running[i].len(), running[i].id))
+    }
+}
+
+/// Round robin over sessions (`session_id`, each request without one is its own
+/// session): a session's next sequence waits behind those of sessions with fewer running
+/// and earlier waiting sequences. Swaps out from the session with the most running
+/// sequences.
+pub struct FairShare;
+
+impl FairShare {
+    fn running_per_session(running: &[Sequence]) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for session in running
+            .iter()
+            .filter_map(|seq| seq.sampling_params.session_id.as_deref())
+        {
+            *counts.entry(session).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+impl SchedulingPolicy for FairShare {
+    fn name(&self) -> &'static str {
+        "fair-share"
+    }
+
+    fn order_waiting(&self, waiting: &mut VecDeque<Sequence>, running: &[Sequence]) {
+        let mut counts: HashMap<String, usize> = Self::running_per_session(running)
+            .into_iter()
+            .map(|(session, count)| (session.to_string(), count))
+            .collect();
+        let mut ranked: Vec<(usize, Sequence)> = waiting
+            .drain(..)
+            .map(|seq| {
+                let rank = match &seq.sampling_params.session_id {
+                    Some(session) => {
+                        let count = counts.entry(session.clone()).or_insert(0);
+                        *count += 1;
+                        *count - 1
+                    }
+                    None => 0,
+                };
+                (rank, seq)
+            })
+            .collect();
+        ranked.sort_by_key(|(rank, _)| *rank);
+        waiting.extend(ranked.into_iter().map(|(_, seq)| seq));
+    }
+
+    fn select_victim(&self, running: &[Sequence], candidates: &[usize]) -> Option<usize> {
+        let counts = Self::running_per_session(running);
+        candidates.iter().copied().max_by_key(|&i| {
+            let session = running[i].sampling_params.session_id.as_deref();
+            (
+                session.and_then(|s| counts.get(s)).copied().unwrap_or(1),
+                running[i].id,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::config::SamplingParams;
+
+    fn seq(id: usize, len: usize, priority: Option<i32>, session: Option<&str>) -> Sequence {
+        let mut params = SamplingParams::default();
+        params.priority = priority;
+        params.session_id = session.map(str::to_string);
+        let mut seq = Sequence::new(vec![1; len], 64, params, &None, 0);
+        seq.id = id;
+        seq
+    }
+
+    fn ids(waiting: &VecDeque<Sequence>) -> Vec<usize> {
+        waiting.iter().map(|seq| seq.id).collect()
+    }
+
+    #[test]
+    fn policies_order_waiting_and_pick_victims() {
+        let make = || {
+            VecDeque::from(vec![
+                seq(0, 300, Some(5), Some("a")),
+                seq(1, 100, None, Some("a")),
+                seq(2, 200, Some(-1), Some("b")),
+                seq(3, 100, Some(5), None),
+            ])
+        };
+        let running = vec![
+            seq(10, 50, Some(5), Some("a")),
+            seq(11, 80, None, None),
+            seq(12, 60, None, Some("a")),
+        ];
+
+        let mut waiting = make();
+        Fcfs.order_waiting(&mut waiting, &running);
+        assert_eq!(ids(&waiting), vec![0, 1, 2, 3]);
+        assert_eq!(Fcfs.select_victim(&running, &[0, 1]), Some(0));
+
+        Priority.order_waiting(&mut waiting, &running);
+        assert_eq!(ids(&waiting), vec![2, 1, 0, 3]);
+        assert_eq!(Priority.select_victim(&running, &[0, 1]), Some(0));
+        assert!(Priority.preempts(&waiting[0], &running[1]));
+        assert!(!Priority.preempts(&waiting[3], &running[0]));
+
+        let mut waiting = make();
+        ShortestPrompt.order_waiting(&mut waiting, &running);
+        assert_eq!(ids(&waiting), vec![1, 3, 2, 0]);
+        assert_eq!(ShortestPrompt.select_victim(&running, &[0, 1]), Some(1));
+
+        // Session "a" already runs two sequences, so both of its waiting ones go last
+        let mut waiting = make();
+        FairShare.order_waiting(&mut waiting, &running);
+        assert_eq!(ids(&waiting), vec![2, 3, 0, 1]);
+        assert_eq!(FairShare.select_victim(&running, &[0, 1, 2]), Some(2));
+
+        assert_eq!(from_name(None).unwrap().name(), "fcfs");
+        for name in SCHEDULING_POLICIES {
+            assert_eq!(from_name(Some(name)).unwrap().name(), name);
+        }
+        assert!(from_name(Some("lifo")).is_err());
+    }
+}
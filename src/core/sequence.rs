@@ -47,6 +47,25 @@ pub struct Sequence {
     pub is_tool_call_end: bool,
     pub hit_stop_sequence: bool,
     pub stop_sequence: Option<String>,
+    /// Mirostat `mu`, adapted after every sampled token
+    #[serde(default)]
+    pub mirostat_mu: Option<f32>,
+    /// Draft tokens to verify in the next decode step (speculative decoding)
+    #[serde(default)]
+    pub draft_token_ids: Vec<u32>,
+    /// KV slots reserved for the draft model to propose tokens in the next decode step
+    #[serde(default)]
+    pub draft_slots: usize,
+    /// Tokenized predicted output, proposed as draft tokens while the output follows it
+    #[serde(default)]
+    pub prediction_token_ids: Vec<u32>,
+    /// Offset of the output within the prediction, moved when the output diverges and re-syncs
+    #[serde(default)]
+    pub prediction_shift: i64,
+    #[serde(default)]
+    pub accepted_prediction_tokens: usize,
+    #[serde(default)]
+    pub rejected_prediction_tokens: usize,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -58,10 +77,29 @@ pub struct DecodeSequence {
     pub block_table_last: u32,
     pub block_tables: Vec<u32>,
     pub sampling_params: SamplingParams,
+    /// Prompt and output tokens, only sent for requests whose sampling depends on them
+    /// (`repetition_penalty`, `no_repeat_ngram_size`, DRY) and only until the runner holds
+    /// them; the runner then extends its copy with `last_token`
+    #[serde(default)]
+    pub token_ids: Vec<u32>,
+    /// Output tokens, only sent for requests with `bad_words` and only until the runner
+    /// holds their match state, which it then extends with `last_token`
+    #[serde(default)]
+    pub output_ids: Vec<u32>,
+    #[serde(default)]
+    pub output_len: usize,
+    #[serde(default)]
+    pub mirostat_mu: Option<f32>,
 }
 
 impl DecodeSequence {
     pub fn new(sequence: &Sequence) -> Self {
+        Self::with_token_history(sequence, true)
+    }
+
+    /// `token_history: false` leaves `token_ids` and `output_ids` empty, for a runner that
+    /// already holds them.
+    pub fn with_token_history(sequence: &Sequence, token_history: bool) -> Self {
         let last_token = sequence.last_token;
         let len = sequence.len();
         let last_block_tokens = sequence.last_block_num_tokens();
@@ -74,6 +112,18 @@ impl DecodeSequence {
             block_table_last,
             block_tables: sequence.block_table.clone(),
             sampling_params: sequence.sampling_params.clone(),
+            token_ids: if token_history && sequence.sampling_params.uses_token_history() {
+                sequence.token_ids.clone()
+            } else {
+                Vec::new()
+            },
+            output_ids: if token_history && sequence.sampling_params.uses_output_history() {
+                sequence.output_ids.clone()
+            } else {
+                Vec::new()
+            },
+            output_len: sequence.output_len(),
+            mirostat_mu: sequence.mirostat_mu,
         }
     }
 
@@ -179,6 +229,13 @@ impl Sequence {
             is_tool_call_end: false,
             hit_stop_sequence: false,
             stop_sequence: None,
+            mirostat_mu: None,
+            draft_token_ids: Vec::new(),
+            draft_slots: 0,
+            prediction_token_ids: Vec::new(),
+            prediction_shift: 0,
+            accepted_prediction_tokens: 0,
+            rejected_prediction_tokens: 0,
         }
     }
 
@@ -194,6 +251,11 @@ impl Sequence {
         self.output_ids.len()
     }
 
+    /// Whether the next decode step verifies draft tokens
+    pub fn is_speculative(&self) -> bool {
+        !self.draft_token_ids.is_empty() || self.draft_slots > 0
+    }
+
     pub fn is_finished(&self) -> bool {
         self.status == SequenceStatus::Finished
             || self.status == SequenceStatus::Cached
@@ -0,0 +1,553 @@
+// src/core/spec_decode.rs
+use crate::utils::config::{Config, EngineConfig, ModelType};
+use crate::utils::downloader::ModelPaths;
+use candle_core::Result;
+#[cfg(feature = "python")]
+use pyo3::pyclass;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Where draft tokens come from.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SpeculativeMethod {
+    /// Prompt lookup: copy the tokens that followed an earlier occurrence of the current suffix.
+    Ngram = 1,
+    /// A smaller model sharing the tokenizer of the target proposes the drafts.
+    Draft = 2,
+    /// The multi-token prediction layers of the target checkpoint propose the drafts.
+    Mtp = 3,
+}
+
+/// Configuration for speculative decoding.
+#[cfg(not(feature = "python"))]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpeculativeConfig {
+    pub method: SpeculativeMethod,
+    /// Maximum number of draft tokens verified per decode step.
+    pub num_speculative_tokens: usize,
+    /// Longest suffix (in tokens) matched against the earlier context.
+    pub prompt_lookup_max: usize,
+    /// Shortest suffix (in tokens) that still counts as a match.
+    pub prompt_lookup_min: usize,
+    /// Model id or local path of the draft model (`SpeculativeMethod::Draft`).
+    #[serde(default)]
+    pub draft_model: Option<String>,
+}
+
+/// Configuration for speculative decoding.
+#[cfg(feature = "python")]
+#[pyclass]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SpeculativeConfig {
+    #[pyo3(get, set)]
+    pub method: SpeculativeMethod,
+    /// Maximum number of draft tokens verified per decode step.
+    #[pyo3(get, set)]
+    pub num_speculative_tokens: usize,
+    /// Longest suffix (in tokens) matched against the earlier context.
+    #[pyo3(get, set)]
+    pub prompt_lookup_max: usize,
+    /// Shortest suffix (in tokens) that still counts as a match.
+    #[pyo3(get, set)]
+    pub prompt_lookup_min: usize,
+    /// Model id or local path of the draft model (`SpeculativeMethod::Draft`).
+    #[serde(default)]
+    #[pyo3(get, set)]
+    pub draft_model: Option<String>,
+}
+
+impl SpeculativeConfig {
+    pub fn ngram(
+        num_speculative_tokens: usize,
+        prompt_lookup_max: usize,
+        prompt_lookup_min: usize,
+    ) -> Self {
+        Self {
+            method: SpeculativeMethod::Ngram,
+            num_speculative_tokens,
+            prompt_lookup_max,
+            prompt_lookup_min,
+            draft_model: None,
+        }
+    }
+
+    pub fn draft(draft_model: String, num_speculative_tokens: usize) -> Self {
+        Self {
+            method: SpeculativeMethod::Draft,
+            num_speculative_tokens,
+            prompt_lookup_max: 0,
+            prompt_lookup_min: 0,
+            draft_model: Some(draft_model),
+        }
+    }
+
+    pub fn mtp(num_speculative_tokens: usize) -> Self {
+        Self {
+            method: SpeculativeMethod::Mtp,
+            num_speculative_tokens,
+            prompt_lookup_max: 0,
+            prompt_lookup_min: 0,
+            draft_model: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.num_speculative_tokens > 0
+    }
+
+    /// Drafts are proposed by a separate draft model.
+    pub fn uses_draft_model(&self) -> bool {
+        self.method == SpeculativeMethod::Draft
+    }
+
+    /// Drafts are proposed in the runner (draft model or MTP) rather than by the scheduler.
+    pub fn drafts_in_runner(&self) -> bool {
+        matches!(
+            self.method,
+            SpeculativeMethod::Draft | SpeculativeMethod::Mtp
+        )
+    }
+
+    /// Draft tokens for a sequence whose prompt and output tokens are `token_ids`.
+    pub fn propose(&self, token_ids: &[u32], max_tokens: usize) -> Vec<u32> {
+        let num_tokens = std::cmp::min(self.num_speculative_tokens, max_tokens);
+        match self.method {
+            SpeculativeMethod::Ngram => propose_ngram(
+                token_ids,
+                self.prompt_lookup_min,
+                self.prompt_lookup_max,
+                num_tokens,
+            ),
+            SpeculativeMethod::Draft | SpeculativeMethod::Mtp => Vec::new(),
+        }
+    }
+}
+
+/// Everything a runner needs to load the draft model next to the target.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DraftModelSpec {
+    pub model_type: ModelType,
+    pub config: Config,
+    pub model_pathes: ModelPaths,
+    pub is_gguf: bool,
+    pub is_rope_i: bool,
+}
+
+/// Fetch the draft model configured in `econfig` and check that it can draft for a target
+/// with `target_config`. Returns `None` unless draft-model speculation is enabled.
+pub fn resolve_draft_model(
+    econfig: &EngineConfig,
+    target_config: &Config,
+) -> Result<Option<DraftModelSpec>> {
+    let Some(spec) = econfig
+        .speculative_config
+        .as_ref()
+        .filter(|spec| spec.is_enabled() && spec.uses_draft_model())
+        // The scheduler turns speculation off in PD disaggregation mode
+        .filter(|_| econfig.pd_config.is_none())
+    else {
+        return Ok(None);
+    };
+    let Some(draft_model) = &spec.draft_model else {
+        candle_core::bail!("Draft-model speculative decoding requires `draft_model`");
+    };
+
+    let mut draft_econfig = econfig.clone();
+    let path = Path::new(draft_model);
+    (
+        draft_econfig.model_id,
+        draft_econfig.weight_path,
+        draft_econfig.weight_file,
+    ) = if path.is_dir() {
+        (None, Some(draft_model.clone()), None)
+    } else if path.is_file() {
+        (None, None, Some(draft_model.clone()))
+    } else {
+        (Some(draft_model.clone()), None, None)
+    };
+    let (model_pathes, is_gguf, mut config, _, tokenizer, _) =
+        crate::utils::init_config_tokenizer(&draft_econfig)?;
+
+    let Some(arch) = config
+        .architectures
+        .as_ref()
+        .and_then(|a| a.first())
+        .cloned()
+    else {
+        candle_core::bail!("Draft model {} has no architecture", draft_model);
+    };
+    let (model_type, _, is_rope_i) = crate::utils::get_arch_rope(&tokenizer, arch)?;
+    if !supports_draft_model(&model_type) {
+        candle_core::bail!(
+            "{:?} models cannot be used as draft model for speculative decoding",
+            model_type
+        );
+    }
+    // Draft and target probabilities are compared token by token
+    if config.vocab_size != target_config.vocab_size {
+        candle_core::bail!(
+            "Draft model vocabulary ({}) differs from the target model vocabulary ({})",
+            config.vocab_size,
+            target_config.vocab_size
+        );
+    }
+    config.fp8_kvcache = econfig.fp8_kvcache;
+    crate::log_info!("Draft model {} ({:?}) resolved", draft_model, model_type);
+    Ok(Some(DraftModelSpec {
+        model_type,
+        config,
+        model_pathes,
+        is_gguf,
+        is_rope_i,
+    }))
+}
+
+/// Verification needs the hidden states of every position, which multimodal models do not
+/// expose. Hybrid (mamba) models rewind their recurrent state after rejected drafts.
+pub fn supports_model(model_type: &ModelType) -> bool {
+    matches!(
+        model_type,
+        ModelType::Qwen3
+            | ModelType::Qwen3MoE
+            | ModelType::Qwen3_5
+            | ModelType::Qwen3_5MoE
+            | ModelType::LLaMa
+            | ModelType::Phi4
+            | ModelType::GLM4
+            | ModelType::GLM4MoE
+            | ModelType::DeepSeek
+            | ModelType::Gemma4
+            | ModelType::MiniMax
+    )
+}
+
+/// Draft models share the block tables of the target, so their KV layout must not be MLA,
+/// and they keep no mamba state that rejected drafts would advance.
+pub fn supports_draft_model(model_type: &ModelType) -> bool {
+    supports_model(model_type)
+        && !matches!(
+            model_type,
+            ModelType::DeepSeek | ModelType::Qwen3_5 | ModelType::Qwen3_5MoE
+        )
+}
+
+/// Architectures whose loaders read the multi-token prediction layers of the checkpoint.
+pub fn supports_mtp(model_type: &ModelType) -> bool {
+    matches!(
+        model_type,
+        ModelType::GLM4MoE | ModelType::DeepSeek | ModelType::Qwen3_5 | ModelType::Qwen3_5MoE
+    )
+}
+
+/// Config of the KV cache held by the MTP layer (a single decoder layer), `None` unless
+/// MTP speculation is enabled.
+pub fn mtp_cache_config(econfig: &EngineConfig, config: &Config) -> Option<Config> {
+    econfig
+        .speculative_config
+        .as_ref()
+        .filter(|spec| spec.is_enabled() && spec.method == SpeculativeMethod::Mtp)
+        .filter(|_| econfig.pd_config.is_none())?;
+    let mut config = config.clone();
+    config.num_hidden_layers = 1;
+    // A single full-attention layer, whatever the hybrid (mamba) layout of the target
+    if config
+        .architectures
+        .as_ref()
+        .and_then(|arches| arches.first())
+        .is_some_and(|arch| crate::utils::is_qwen3_hybrid_arch_name(arch))
+    {
+        config.architectures = None;
+    }
+    Some(config)
+}
+
+/// Prompt lookup: find the most recent earlier occurrence of the last `n` tokens (longest
+/// `n` first, down to `min_ngram`) and propose up to `num_tokens` tokens that followed it.
+pub fn propose_ngram(
+    token_ids: &[u32],
+    min_ngram: usize,
+    max_ngram: usize,
+    num_tokens: usize,
+) -> Vec<u32> {
+    let len = token_ids.len();
+    if num_tokens == 0 {
+        return Vec::new();
+    }
+    for n in (min_ngram.max(1)..=max_ngram).rev() {
+        if n >= len {
+            continue;
+        }
+        let suffix = &token_ids[len - n..];
+        // The match must end before the suffix itself so at least one token follows it
+        if let Some(start) = (0..len - n).rev().find(|&s| &token_ids[s..s + n] == suffix) {
+            let begin = start + n;
+            let end = std::cmp::min(begin + num_tokens, len);
+            return token_ids[begin..end].to_vec();
+        }
+    }
+    Vec::new()
+}
+
+/// Draft tokens verified per decode step for requests with a predicted output.
+pub const PREDICTION_DRAFT_TOKENS: usize = 16;
+
+/// Longest output suffix searched for in the prediction when re-syncing after a divergence.
+const PREDICTION_RESYNC_NGRAM: usize = 8;
+
+/// Predicted outputs: propose the `num_tokens` prediction tokens that follow the output.
+///
+/// `shift` is the offset of the output within the prediction (`output_ids[i]` is expected at
+/// `prediction[i + shift]`). While the last output token matches, the prediction continues
+/// from there; otherwise the longest output suffix found in the prediction (nearest to the
+/// expected position on ties) re-syncs it, so edits that insert or drop text only cost the
+/// tokens around them. Returns the drafts and the updated shift.
+pub fn propose_from_prediction(
+    prediction: &[u32],
+    output_ids: &[u32],
+    shift: i64,
+    num_tokens: usize,
+) -> (Vec<u32>, i64) {
+    let len = output_ids.len();
+    let expected = len as i64 + shift;
+    let aligned = match output_ids.last() {
+        None => true,
+        Some(last) => {
+            expected >= 1
+                && (expected as usize) <= prediction.len()
+                && prediction[expected as usize - 1] == *last
+        }
+    };
+    let (begin, shift) = if aligned {
+        (expected.max(0) as usize, shift)
+    } else {
+        let mut found = None;
+        for n in (1..=std::cmp::min(PREDICTION_RESYNC_NGRAM, len)).rev() {
+            let suffix = &output_ids[len - n..];
+            found = (0..prediction.len().saturating_sub(n))
+                .filter(|&s| &prediction[s..s + n] == suffix)
+                .map(|s| s + n)
+                .min_by_key(|&end| (end as i64 - expected).abs());
+            if found.is_some() {
+                break;
+            }
+        }
+        match found {
+            Some(begin) => (begin, begin as i64 - len as i64),
+            None => return (Vec::new(), shift),
+        }
+    };
+    let end = std::cmp::min(begin + num_tokens, prediction.len());
+    (prediction[begin.min(end)..end].to_vec(), shift)
+}
+
+/// Tokens produced by a verification pass.
+///
+/// `sampled[j]` is the token sampled after the context extended by `draft[..j]`, so the
+/// drafts are accepted while they agree with the target model and the first disagreeing
+/// (or the bonus) sample is kept as well. Sampling each position from the target
+/// distribution and comparing it to a deterministic draft keeps the output distribution
+/// identical to decoding one token at a time.
+pub fn accept_draft_tokens(draft: &[u32], sampled: &[u32]) -> Vec<u32> {
+    let accepted = draft
+        .iter()
+        .zip(sampled)
+        .take_while(|(d, s)| d == s)
+        .count();
+    sampled[..std::cmp::min(accepted + 1, sampled.len())].to_vec()
+}
+
+/// Speculative sampling: draft `j` is kept with probability
+/// `min(1, target_probs[j][d] / draft_probs[j][d])`; the first rejected draft is replaced by a
+/// sample from the normalized `max(0, target - draft)` residual, and when every draft is kept
+/// a bonus token is sampled from `target_probs[draft.len()]`. The kept tokens are distributed
+/// exactly as if sampled from the target one at a time.
+pub fn rejection_sample(
+    draft: &[u32],
+    draft_probs: &[Vec<f32>],
+    target_probs: &[Vec<f32>],
+    mut uniform: impl FnMut() -> f32,
+    mut sample: impl FnMut(&[f32]) -> Result<u32>,
+) -> Result<Vec<u32>> {
+    let mut tokens = Vec::with_capacity(draft.len() + 1);
+    for (j, &token) in draft.iter().enumerate() {
+        let p = target_probs[j].get(token as usize).copied().unwrap_or(0.0);
+        let q = draft_probs[j].get(token as usize).copied().unwrap_or(0.0);
+        if q > 0.0 && uniform() * q < p {
+            tokens.push(token);
+            continue;
+        }
+        let residual: Vec<f32> = target_probs[j]
+            .iter()
+            .zip(&draft_probs[j])
+            .map(|(p, q)| (p - q).max(0.0))
+            .collect();
+        // Rounding can leave nothing where the two distributions (nearly) agree
+        let next = if residual.iter().any(|&r| r > 0.0) {
+            sample(&residual)?
+        } else {
+            sample(&target_probs[j])?
+        };
+        tokens.push(next);
+        return Ok(tokens);
+    }
+    tokens.push(sample(&target_probs[draft.len()])?);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ngram_proposes_continuation_of_latest_match() {
+        // suffix [7, 8] occurs twice, the most recent one is followed by 4, 5
+        let tokens = [7, 8, 1, 2, 7, 8, 4, 5, 6, 7, 8];
+        assert_eq!(propose_ngram(&tokens, 1, 3, 2), vec![4, 5]);
+        // proposals stop at the end of the context
+        assert_eq!(propose_ngram(&tokens, 1, 3, 10), vec![4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn ngram_prefers_longer_matches_and_respects_min() {
+        let tokens = [3, 9, 1, 9, 5, 3, 9];
+        // [3, 9] matches at 0 (followed by 1), a single 9 would match at 3 (followed by 5)
+        assert_eq!(propose_ngram(&tokens, 1, 2, 1), vec![1]);
+        assert_eq!(propose_ngram(&[1, 2, 3, 4], 2, 3, 4), Vec::<u32>::new());
+        assert_eq!(propose_ngram(&[1, 2, 1], 1, 3, 0), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn accept_stops_at_first_disagreement() {
+        assert_eq!(
+            accept_draft_tokens(&[4, 5, 6], &[4, 5, 9, 1]),
+            vec![4, 5, 9]
+        );
+        // all drafts accepted keeps the bonus token
+        assert_eq!(accept_draft_tokens(&[4, 5], &[4, 5, 7]), vec![4, 5, 7]);
+        assert_eq!(accept_draft_tokens(&[4], &[2, 3]), vec![2]);
+        assert_eq!(accept_draft_tokens(&[], &[2]), vec![2]);
+    }
+
+    #[test]
+    fn runner_methods_leave_drafting_to_the_runner() {
+        let tokens = [7, 8, 1, 7, 8];
+        let ngram = SpeculativeConfig::ngram(2, 2, 1);
+        assert!(!ngram.drafts_in_runner());
+        assert_eq!(ngram.propose(&tokens, 8), vec![1, 7]);
+        for spec in [
+            SpeculativeConfig::mtp(2),
+            SpeculativeConfig::draft("draft".to_string(), 2),
+        ] {
+            assert!(spec.drafts_in_runner());
+            assert!(spec.propose(&tokens, 8).is_empty());
+        }
+        assert!(!SpeculativeConfig::mtp(1).uses_draft_model());
+    }
+
+    fn argmax(probs: &[f32]) -> Result<u32> {
+        Ok(probs
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(i, _)| i as u32)
+            .unwrap())
+    }
+
+    #[test]
+    fn rejection_sampling_accepts_and_replaces() {
+        let target = vec![
+            vec![0.1, 0.9, 0.0],
+            vec![0.5, 0.0, 0.5],
+            vec![0.0, 0.0, 1.0],
+        ];
+        // the draft is never more confident than the target, so both drafts are kept
+        let draft = vec![vec![0.2, 0.8, 0.0], vec![0.6, 0.0, 0.4]];
+        let tokens = rejection_sample(&[1, 2], &draft, &target, || 0.99, argmax).unwrap();
+        assert_eq!(tokens, vec![1, 2, 2]);
+
+        // a draft token the target never samples is always rejected and resampled from
+        // the residual, which excludes it
+        let draft = vec![vec![0.0, 0.0, 1.0], vec![0.6, 0.0, 0.4]];
+        let tokens = rejection_sample(&[2, 0], &draft, &target, || 0.0, argmax).unwrap();
+        assert_eq!(tokens, vec![1]);
+
+        // over-confident drafts survive only with probability p / q
+        let draft = vec![vec![0.0, 1.0, 0.0]];
+        let kept = rejection_sample(&[1], &draft, &target[..2], || 0.85, argmax).unwrap();
+        assert_eq!(kept, vec![1, 2]);
+        let rejected = rejection_sample(&[1], &draft, &target[..2], || 0.95, argmax).unwrap();
+        assert_eq!(rejected, vec![0]);
+    }
+
+    #[test]
+    fn rejection_sampling_matches_greedy_verification() {
+        let one_hot = |i: usize| {
+            let mut v = vec![0.0; 4];
+            v[i] = 1.0;
+            v
+        };
+        let target = vec![one_hot(3), one_hot(1), one_hot(0)];
+        let draft = vec![one_hot(3), one_hot(2)];
+        let tokens = rejection_sample(&[3, 2], &draft, &target, || 0.5, argmax).unwrap();
+        assert_eq!(tokens, accept_draft_tokens(&[3, 2], &[3, 1, 0]));
+    }
+
+    #[test]
+    fn prediction_continues_while_the_output_follows_it() {
+        let prediction = [1, 2, 3, 4, 5, 6];
+        assert_eq!(
+            propose_from_prediction(&prediction, &[], 0, 3),
+            (vec![1, 2, 3], 0)
+        );
+        assert_eq!(
+            propose_from_prediction(&prediction, &[1, 2], 0, 3),
+            (vec![3, 4, 5], 0)
+        );
+        // nothing left to propose once the output reaches the end of the prediction
+        assert_eq!(
+            propose_from_prediction(&prediction, &[1, 2, 3, 4, 5, 6], 0, 3),
+            (vec![], 0)
+        );
+    }
+
+    #[test]
+    fn prediction_resyncs_after_edits() {
+        let prediction = [10, 11, 12, 13, 14, 15, 16];
+        // 20 was inserted before 12: re-sync on [11, 20, 12]'s suffix [12]
+        assert_eq!(
+            propose_from_prediction(&prediction, &[10, 11, 20, 12], 0, 2),
+            (vec![13, 14], -1)
+        );
+        // 12 and 13 were dropped
+        assert_eq!(
+            propose_from_prediction(&prediction, &[10, 11, 14], 0, 2),
+            (vec![15, 16], 2)
+        );
+        // a replaced token with no re-sync point yet proposes nothing and keeps the shift
+        assert_eq!(
+            propose_from_prediction(&prediction, &[10, 11, 30], 0, 2),
+            (vec![], 0)
+        );
+    }
+
+    #[test]
+    fn prediction_resync_prefers_the_nearest_match() {
+        // 7 occurs twice, the output is expected right before the second one
+        let prediction = [7, 1, 2, 3, 7, 4, 5];
+        assert_eq!(
+            propose_from_prediction(&prediction, &[9, 1, 9, 7], 0, 2),
+            (vec![4, 5], 1)
+        );
+    }
+
+    #[test]
+    fn hybrid_models_speculate_but_cannot_draft() {
+        for model_type in [ModelType::Qwen3_5, ModelType::Qwen3_5MoE] {
+            assert!(supports_model(&model_type));
+            assert!(supports_mtp(&model_type));
+            assert!(!supports_draft_model(&model_type));
+        }
+        assert!(supports_draft_model(&ModelType::Qwen3));
+    }
+}
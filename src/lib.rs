@@ -12,6 +12,8 @@ pub mod tools;
 pub mod transfer;
 pub mod utils;
 #[cfg(feature = "python")]
+use crate::core::spec_decode::{SpeculativeConfig, SpeculativeMethod};
+#[cfg(feature = "python")]
 use crate::core::GenerationOutput;
 #[cfg(feature = "python")]
 use crate::py::Engine;
@@ -34,5 +36,7 @@ fn vllm_rs(_py: Python<'_>, m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<PdConfig>()?;
     m.add_class::<PdMethod>()?;
     m.add_class::<PdRole>()?;
+    m.add_class::<SpeculativeConfig>()?;
+    m.add_class::<SpeculativeMethod>()?;
     Ok(())
 }
@@ -1,13 +1,15 @@
 use candle_core::Result;
 use clap::Parser;
 use colored::Colorize;
+use parking_lot::RwLock;
 use reedline::{DefaultPrompt, DefaultPromptSegment, Reedline, Signal};
 use serde_json;
 use std::sync::Arc;
 use tool_parser::ParserFactory;
 use vllm_rs::core::engine::StreamItem;
 use vllm_rs::core::engine::GLOBAL_RT;
-use vllm_rs::core::{engine::LLMEngine, GenerationOutput};
+use vllm_rs::core::spec_decode::SpeculativeConfig;
+use vllm_rs::core::{engine::LLMEngine, GenerationOutput, PromptScore};
 use vllm_rs::log_error;
 use vllm_rs::server::run_server;
 use vllm_rs::server::Args;
@@ -55,6 +57,10 @@ async fn main() -> Result<()> {
         !(interactive && (args.server || args.ui_server || args.pd_server)),
         "You selected both interactive and server mode, which is not valid!"
     );
+    assert!(
+        !(args.batch_input.is_some() && (interactive || args.server || args.pd_server)),
+        "--batch-input runs offline and cannot be combined with interactive or server mode!"
+    );
 
     let max_model_len = {
         // If not explicitly set, let the allocator auto-decide a fit based on
@@ -71,7 +77,11 @@ async fn main() -> Result<()> {
     let server = args.server
         || args.ui_server
         || args.pd_server
-        || (!interactive && args.prompts.is_none() && args.batch.is_none());
+        || (!interactive
+            && args.prompts.is_none()
+            && args.batch.is_none()
+            && args.batch_input.is_none()
+            && args.perplexity.is_none());
 
     let prompts = match (&args.prompts, interactive) {
         (Some(prompts), false) => prompts.clone(),
@@ -113,6 +123,12 @@ async fn main() -> Result<()> {
             top_k: args.top_k,
             frequency_penalty: args.frequency_penalty,
             presence_penalty: args.presence_penalty,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            repetition_penalty: None,
+            no_repeat_ngram_size: None,
             bos_token_id: None,
             eos_token_id: None,
         })
@@ -170,6 +186,20 @@ async fn main() -> Result<()> {
         None
     };
 
+    let speculative_config = match &args.draft_model {
+        Some(draft_model) => Some(SpeculativeConfig::draft(
+            draft_model.clone(),
+            args.num_speculative_tokens.unwrap_or(4),
+        )),
+        None if args.mtp => Some(SpeculativeConfig::mtp(
+            args.num_speculative_tokens.unwrap_or(1),
+        )),
+        None => args
+            .num_speculative_tokens
+            .filter(|&n| n > 0)
+            .map(|n| SpeculativeConfig::ngram(n, args.prompt_lookup_max, args.prompt_lookup_min)),
+    };
+
     let econfig = EngineConfig::new(
         args.model_id,
         args.weight_path,
@@ -202,6 +232,12 @@ async fn main() -> Result<()> {
         None, // pd_client_prefix_cache_ratio
         args.yarn_scaling_factor,
         args.disable_reasoning,
+        speculative_config,
+        args.api_keys.clone(),
+        args.scheduling_policy.clone(),
+        args.max_waiting_requests,
+        args.max_waiting_tokens,
+        args.max_queue_wait_secs,
     );
 
     let server_port = if server {
@@ -220,6 +256,14 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(path) = &args.perplexity {
+        return run_perplexity(&engine, path);
+    }
+
+    if let Some(input) = &args.batch_input {
+        return vllm_rs::server::offline::run(engine, input, args.batch_output.as_deref()).await;
+    }
+
     if !interactive && prompts.is_empty() {
         eprintln!(
             "{}",
@@ -369,6 +413,7 @@ async fn main() -> Result<()> {
                                 StreamItem::TokenID(_) | StreamItem::Completion(_) => {
                                     break;
                                 }
+                                StreamItem::Logprobs(_) | StreamItem::Prediction(_) => {}
                                 StreamItem::Done((
                                     prompt_start_time,
                                     decode_start_time,
@@ -418,6 +463,9 @@ async fn main() -> Result<()> {
                     decoded_length,
                     decode_output,
                     stop_sequence: None,
+                    logprobs: Vec::new(),
+                    accepted_prediction_tokens: 0,
+                    rejected_prediction_tokens: 0,
                 }]
             } else {
                 vllm_rs::log_warn!("Starting the inference...");
@@ -452,7 +500,7 @@ async fn main() -> Result<()> {
                 decode_finish_time,
                 decoded_length,
                 decode_output,
-                stop_sequence: _,
+                ..
             },
         ) in outputs.iter().enumerate()
         {
@@ -499,3 +547,86 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+fn load_perplexity_inputs(path: &str) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path).map_err(candle_core::Error::wrap)?;
+    if !path.ends_with(".jsonl") {
+        return Ok(vec![content]);
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let value: serde_json::Value =
+                serde_json::from_str(line).map_err(candle_core::Error::wrap)?;
+            match value {
+                serde_json::Value::String(text) => Ok(text),
+                other => other
+                    .get("text")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string())
+                    .ok_or_else(|| {
+                        candle_core::Error::msg(format!(
+                            "{path}:{}: expected a JSON string or an object with a \"text\" field",
+                            i + 1
+                        ))
+                    }),
+            }
+        })
+        .collect()
+}
+
+fn run_perplexity(engine: &Arc<RwLock<LLMEngine>>, path: &str) -> Result<()> {
+    let inputs = load_perplexity_inputs(path)?;
+    let mut engine = engine.write();
+    let window = engine.get_model_info().2.unwrap_or(32768).saturating_sub(1);
+    if window < 2 {
+        candle_core::bail!("max_model_len is too small for perplexity evaluation");
+    }
+
+    let mut scores: Vec<PromptScore> = Vec::new();
+    let start = std::time::Instant::now();
+    for (i, input) in inputs.iter().enumerate() {
+        let tokens = engine
+            .tokenizer
+            .encode_fast(input.as_str(), true)
+            .map_err(candle_core::Error::wrap)?;
+        let token_ids = tokens.get_ids();
+        if token_ids.len() < 2 {
+            tracing::warn!("Input {} has fewer than 2 tokens, skipped", i);
+            continue;
+        }
+        // Documents longer than the context are scored as consecutive, non-overlapping windows.
+        let windows: Vec<Vec<u32>> = token_ids.chunks(window).map(|c| c.to_vec()).collect();
+        let (doc_scores, _) = engine.score_tokens(windows, 0)?;
+        let doc = PromptScore {
+            token_ids: token_ids.to_vec(),
+            logprobs: doc_scores.into_iter().flat_map(|s| s.logprobs).collect(),
+        };
+        println!(
+            "[{}] tokens {}, total logprob {:.4}, perplexity {:.4}",
+            i,
+            doc.logprobs.len(),
+            doc.total_logprob(),
+            doc.perplexity()
+        );
+        scores.push(doc);
+    }
+
+    let scored_tokens: usize = scores.iter().map(|s| s.logprobs.len()).sum();
+    let elapsed = start.elapsed().as_secs_f32();
+    println!(
+        "{}",
+        format!(
+            "Perplexity over {} input(s), {} scored tokens: {:.4} ({:.2}s, {:.2} tokens/s)",
+            scores.len(),
+            scored_tokens,
+            PromptScore::corpus_perplexity(&scores),
+            elapsed,
+            scored_tokens as f32 / elapsed.max(1e-6)
+        )
+        .yellow()
+    );
+    Ok(())
+}
@@ -3,6 +3,7 @@ use crate::models::layers::mask::get_attention_causal_mask;
 use crate::models::layers::mla_attention::{MlaAttention, MlaConfig};
 use crate::models::layers::mlp::MLP;
 use crate::models::layers::moe::{FusedMoe, FusedMoeFp8, FusedMoeGGUF, FusedMoeISQ};
+use crate::models::layers::mtp::{num_mtp_layers, MtpDecoderLayer, MultiTokenPredictor};
 use crate::models::layers::others::{embedding, rms_norm, NormX};
 use crate::models::layers::rotary_emb::{ApplyRotaryEmbedding, ScalingRotaryEmbedding};
 use crate::models::layers::VarBuilderX;
@@ -239,6 +240,19 @@ impl DeepSeekDecoderLayer {
     }
 }
 
+impl MtpDecoderLayer for DeepSeekDecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        DeepSeekDecoderLayer::forward(self, xs, attention_mask, positions, cache, input_metadata)
+    }
+}
+
 pub struct DeepSeekForCausalLM {
     embed_tokens: candle_nn::Embedding,
     layers: Vec<DeepSeekDecoderLayer>,
@@ -367,13 +381,14 @@ impl DeepSeekForCausalLM {
         }
     }
 
-    pub fn forward(
+    fn forward_inner(
         &self,
         input_ids: &Tensor,
         positions: &Tensor,
         kv_caches: Option<&Vec<(Tensor, Tensor)>>,
         input_metadata: &InputMetadata,
         embeded_inputs: bool,
+        return_hidden: bool,
     ) -> Result<Tensor> {
         let seqlens = input_metadata.seqlens.clone().unwrap_or_default();
         let attention_mask = get_attention_causal_mask(
@@ -403,14 +418,23 @@ impl DeepSeekForCausalLM {
             }
         }
 
-        if !seqlens.is_empty() {
+        if !seqlens.is_empty() && !return_hidden {
             let indices: Vec<_> = seqlens.iter().map(|x| x - 1 as u32).collect();
             let batch = indices.len();
             xs = xs.index_select(&Tensor::from_vec(indices, (batch,), xs.device())?, 0)?;
         }
         let xs = self.norm.forward(&xs)?;
+        if return_hidden {
+            xs.to_dtype(DType::F32)
+        } else {
+            self.compute_logits(&xs)
+        }
+    }
+
+    /// Project final-normed hidden states onto the vocabulary (F32 logits).
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
         if self.is_qvar_builder {
-            self.lm_head.forward(&xs)
+            self.lm_head.forward(xs)
         } else {
             self.lm_head
                 .forward(&xs.to_dtype(self.dtype)?)?
@@ -418,6 +442,24 @@ impl DeepSeekForCausalLM {
         }
     }
 
+    pub fn forward(
+        &self,
+        input_ids: &Tensor,
+        positions: &Tensor,
+        kv_caches: Option<&Vec<(Tensor, Tensor)>>,
+        input_metadata: &InputMetadata,
+        embeded_inputs: bool,
+    ) -> Result<Tensor> {
+        self.forward_inner(
+            input_ids,
+            positions,
+            kv_caches,
+            input_metadata,
+            embeded_inputs,
+            false,
+        )
+    }
+
     pub fn forward_embedding(
         &self,
         input_ids: &Tensor,
@@ -426,12 +468,13 @@ impl DeepSeekForCausalLM {
         input_metadata: &InputMetadata,
         embeded_inputs: bool,
     ) -> Result<Tensor> {
-        self.forward(
+        self.forward_inner(
             input_ids,
             positions,
             kv_caches,
             input_metadata,
             embeded_inputs,
+            true,
         )
     }
 
@@ -454,6 +497,47 @@ impl DeepSeekForCausalLM {
         )
     }
 
+    /// Multi-token prediction block of the checkpoint (drafting for speculative decoding),
+    /// `None` if the checkpoint has none.
+    pub fn load_mtp(
+        vb: &VarBuilderX,
+        comm: Rc<Comm>,
+        config: &Config,
+        dtype: DType,
+        is_rope_i: bool,
+    ) -> Result<Option<MultiTokenPredictor>> {
+        if num_mtp_layers(vb, config) == 0 {
+            return Ok(None);
+        }
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(
+            if config.higher_precision_required() {
+                DType::F32
+            } else {
+                dtype
+            },
+            config,
+            &vb.device(),
+            is_rope_i,
+            config.rope_theta,
+        )?);
+        let vb = vb.pp(&format!("model.layers.{}", config.num_hidden_layers));
+        let layer = DeepSeekDecoderLayer::new(
+            vb.clone(),
+            comm,
+            rotary_emb,
+            config,
+            &MlaConfig::from_config(config),
+            dtype,
+            config.num_hidden_layers,
+        )?;
+        Ok(Some(MultiTokenPredictor::new(
+            vb,
+            config,
+            dtype,
+            Box::new(layer),
+        )?))
+    }
+
     pub fn get_vocab_size(&self) -> usize {
         self.vocab_size
     }
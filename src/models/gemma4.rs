@@ -1083,38 +1083,44 @@ impl Gemma4ForCausalLM {
         if return_hidden {
             xs.to_dtype(DType::F32)
         } else {
-            let logits = if self.is_qvar_builder {
-                self.lm_head.forward(&xs)?
-            } else {
-                self.lm_head
-                    .forward(&xs.to_dtype(self.dtype)?)?
-                    .to_dtype(DType::F32)?
-            };
+            self.compute_logits(&xs)
+        }
+    }
 
-            let final_logit_softcapping = if let Some(extra) = &self.config.extra_config_json {
-                let v: serde_json::Value =
-                    serde_json::from_str(extra).unwrap_or(serde_json::Value::Null);
-                v.get("final_logit_softcapping")
-                    .or_else(|| {
-                        v.get("text_config")
-                            .and_then(|tc| tc.get("final_logit_softcapping"))
-                    })
-                    .and_then(|v| v.as_f64())
-                    .or(self.config.final_logit_softcapping)
-            } else {
-                self.config.final_logit_softcapping
-            };
+    /// Project final-normed hidden states onto the vocabulary (F32 logits),
+    /// applying the final logit softcapping when configured.
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        let logits = if self.is_qvar_builder {
+            self.lm_head.forward(xs)?
+        } else {
+            self.lm_head
+                .forward(&xs.to_dtype(self.dtype)?)?
+                .to_dtype(DType::F32)?
+        };
 
-            let logits = if let Some(cap) = final_logit_softcapping {
-                let scaled = (logits / cap)?;
-                let tanh = scaled.tanh()?;
-                (tanh * cap)?
-            } else {
-                logits
-            };
+        let final_logit_softcapping = if let Some(extra) = &self.config.extra_config_json {
+            let v: serde_json::Value =
+                serde_json::from_str(extra).unwrap_or(serde_json::Value::Null);
+            v.get("final_logit_softcapping")
+                .or_else(|| {
+                    v.get("text_config")
+                        .and_then(|tc| tc.get("final_logit_softcapping"))
+                })
+                .and_then(|v| v.as_f64())
+                .or(self.config.final_logit_softcapping)
+        } else {
+            self.config.final_logit_softcapping
+        };
 
-            Ok(logits.to_dtype(DType::F32)?)
-        }
+        let logits = if let Some(cap) = final_logit_softcapping {
+            let scaled = (logits / cap)?;
+            let tanh = scaled.tanh()?;
+            (tanh * cap)?
+        } else {
+            logits
+        };
+
+        Ok(logits.to_dtype(DType::F32)?)
     }
 
     pub fn forward(
@@ -4,6 +4,7 @@ use crate::models::layers::distributed::{Comm, ReplicatedLinear};
 use crate::models::layers::mask::get_attention_causal_mask;
 use crate::models::layers::mlp::MLP;
 use crate::models::layers::moe::{FusedMoe, FusedMoeGGUF, FusedMoeISQ};
+use crate::models::layers::mtp::{num_mtp_layers, MtpDecoderLayer, MultiTokenPredictor};
 use crate::models::layers::others::{embedding, rms_norm, NormX};
 use crate::models::layers::rotary_emb::{ApplyRotaryEmbedding, ScalingRotaryEmbedding};
 use crate::models::layers::VarBuilderX;
@@ -223,6 +224,19 @@ impl GLM4DecoderLayer {
     }
 }
 
+impl MtpDecoderLayer for GLM4DecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        GLM4DecoderLayer::forward(self, xs, attention_mask, positions, cache, input_metadata)
+    }
+}
+
 pub struct GLM4MoEForCausalLM {
     embed_tokens: candle_nn::Embedding,
     layers: Vec<GLM4DecoderLayer>,
@@ -454,8 +468,15 @@ impl GLM4MoEForCausalLM {
         let xs = self.norm.forward(&xs)?;
         if return_hidden {
             xs.to_dtype(DType::F32)
-        } else if self.is_qvar_builder {
-            self.lm_head.forward(&xs)
+        } else {
+            self.compute_logits(&xs)
+        }
+    }
+
+    /// Project final-normed hidden states onto the vocabulary (F32 logits).
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        if self.is_qvar_builder {
+            self.lm_head.forward(xs)
         } else {
             self.lm_head
                 .forward(&xs.to_dtype(self.dtype)?)?
@@ -525,6 +546,46 @@ impl GLM4MoEForCausalLM {
         )
     }
 
+    /// Multi-token prediction block of the checkpoint (drafting for speculative decoding),
+    /// `None` if the checkpoint has none.
+    pub fn load_mtp(
+        vb: &VarBuilderX,
+        comm: Rc<Comm>,
+        config: &Config,
+        dtype: DType,
+        is_rope_i: bool,
+    ) -> Result<Option<MultiTokenPredictor>> {
+        if num_mtp_layers(vb, config) == 0 {
+            return Ok(None);
+        }
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(
+            if config.higher_precision_required() {
+                DType::F32
+            } else {
+                dtype
+            },
+            config,
+            &vb.device(),
+            is_rope_i,
+            config.rope_theta,
+        )?);
+        let vb = vb.pp(&format!("model.layers.{}", config.num_hidden_layers));
+        let layer = GLM4DecoderLayer::new(
+            vb.clone(),
+            comm,
+            rotary_emb,
+            config,
+            dtype,
+            config.num_hidden_layers,
+        )?;
+        Ok(Some(MultiTokenPredictor::new(
+            vb,
+            config,
+            dtype,
+            Box::new(layer),
+        )?))
+    }
+
     pub fn get_vocab_size(&self) -> usize {
         self.vocab_size
     }
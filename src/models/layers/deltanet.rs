@@ -16,7 +16,7 @@ use crate::utils::resolve_qwen3_hybrid_config;
 use attention_rs::gdn;
 use attention_rs::mamba_cache::MambaCache;
 use attention_rs::InputMetadata;
-use candle_core::{DType, Result, Tensor};
+use candle_core::{DType, Device, Result, Tensor};
 use candle_nn::var_builder::Shard;
 use std::rc::Rc;
 
@@ -819,3 +819,63 @@ impl GatedDeltaNet {
         self.out_proj.forward(&gated_output.to_dtype(xs.dtype())?)
     }
 }
+
+/// Copy of the conv and recurrent states of some mamba slots, taken before a forward pass
+/// that may have to be undone (e.g. verifying speculative drafts that get rejected).
+pub struct MambaSnapshot {
+    slots: Vec<usize>,
+    conv: Vec<Tensor>,
+    recurrent: Vec<Tensor>,
+}
+
+impl MambaSnapshot {
+    pub fn capture(
+        mamba_cache: &mut MambaCache,
+        num_gdn_layers: usize,
+        slots: Vec<usize>,
+        device: &Device,
+    ) -> Result<Self> {
+        let index = Tensor::from_vec(
+            slots.iter().map(|&s| s as i64).collect::<Vec<_>>(),
+            (slots.len(),),
+            device,
+        )?;
+        let mut conv = Vec::with_capacity(num_gdn_layers);
+        let mut recurrent = Vec::with_capacity(num_gdn_layers);
+        for layer in 0..num_gdn_layers {
+            conv.push(mamba_cache.get_batch_conv_state(layer, &index)?);
+            recurrent.push(
+                mamba_cache
+                    .recurrent_state_mut(layer)
+                    .index_select(&index, 0)?
+                    .copy()?,
+            );
+        }
+        Ok(Self {
+            slots,
+            conv,
+            recurrent,
+        })
+    }
+
+    /// Write back the captured states of the given rows (indices into the captured slots).
+    /// States are written in place since decode graphs hold the cache tensors.
+    pub fn restore(&self, mamba_cache: &mut MambaCache, rows: &[usize]) -> Result<()> {
+        if self.conv.is_empty() {
+            return Ok(());
+        }
+        for &row in rows {
+            let slot = self.slots[row];
+            let index = Tensor::from_vec(vec![slot as i64], (1,), self.conv[0].device())?;
+            for (layer, (conv, recurrent)) in self.conv.iter().zip(&self.recurrent).enumerate() {
+                mamba_cache.set_batch_conv_state(layer, &index, &conv.narrow(0, row, 1)?)?;
+                mamba_cache.recurrent_state_mut(layer).slice_set(
+                    &recurrent.narrow(0, row, 1)?,
+                    0,
+                    slot,
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -7,6 +7,7 @@ pub mod mask;
 pub mod mla_attention;
 pub mod mlp;
 pub mod moe;
+pub mod mtp;
 pub mod others;
 pub mod rotary_emb;
 pub mod wna16;
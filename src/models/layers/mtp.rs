@@ -0,0 +1,183 @@
+// src/models/layers/mtp.rs
+use crate::models::layers::distributed::ReplicatedLinear;
+use crate::models::layers::mask::get_attention_causal_mask;
+use crate::models::layers::others::{rms_norm, NormX};
+use crate::models::layers::VarBuilderX;
+use crate::utils::config::Config;
+use attention_rs::InputMetadata;
+use candle_core::{DType, Device, Result, Tensor};
+
+/// Decoder layer wrapped by a multi-token prediction (MTP) block.
+pub trait MtpDecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor>;
+}
+
+/// Weight names inside an MTP block, which differ between checkpoint families.
+pub struct MtpWeightNames {
+    pub embed_norm: &'static str,
+    pub hidden_norm: &'static str,
+    pub proj: &'static str,
+    pub final_norm: &'static str,
+    /// Norm weights are stored as offsets from one
+    pub norm_offset: bool,
+}
+
+/// DeepSeek-V3 / GLM-4.5 blocks, stored as `model.layers.{num_hidden_layers}`.
+pub const DEEPSEEK_MTP_WEIGHTS: MtpWeightNames = MtpWeightNames {
+    embed_norm: "enorm",
+    hidden_norm: "hnorm",
+    proj: "eh_proj",
+    final_norm: "shared_head.norm",
+    norm_offset: false,
+};
+
+/// Qwen3.5 blocks, stored as `mtp` with the decoder layer under `mtp.layers.0`.
+pub const QWEN3_5_MTP_WEIGHTS: MtpWeightNames = MtpWeightNames {
+    embed_norm: "pre_fc_norm_embedding",
+    hidden_norm: "pre_fc_norm_hidden",
+    proj: "fc",
+    final_norm: "norm",
+    norm_offset: true,
+};
+
+/// Multi-token prediction block of the checkpoint. From the embedding of token `i + 1` and
+/// the target hidden state at position `i`, it predicts token `i + 2`. Embedding and output
+/// head are shared with the target model.
+pub struct MultiTokenPredictor {
+    enorm: NormX,
+    hnorm: NormX,
+    eh_proj: ReplicatedLinear,
+    layer: Box<dyn MtpDecoderLayer>,
+    norm: NormX,
+    device: Device,
+    dtype: DType,
+    sliding_window: Option<usize>,
+}
+
+impl MultiTokenPredictor {
+    pub fn new(
+        vb: VarBuilderX,
+        config: &Config,
+        dtype: DType,
+        layer: Box<dyn MtpDecoderLayer>,
+    ) -> Result<Self> {
+        Self::with_weight_names(vb, config, dtype, layer, &DEEPSEEK_MTP_WEIGHTS)
+    }
+
+    pub fn with_weight_names(
+        vb: VarBuilderX,
+        config: &Config,
+        dtype: DType,
+        layer: Box<dyn MtpDecoderLayer>,
+        names: &MtpWeightNames,
+    ) -> Result<Self> {
+        let enorm = rms_norm(
+            config.hidden_size,
+            config.rms_norm_eps,
+            vb.pp(names.embed_norm),
+            DType::F32,
+            names.norm_offset,
+        )?;
+        let hnorm = rms_norm(
+            config.hidden_size,
+            config.rms_norm_eps,
+            vb.pp(names.hidden_norm),
+            DType::F32,
+            names.norm_offset,
+        )?;
+        let eh_proj = ReplicatedLinear::load_no_bias(
+            config.hidden_size * 2,
+            config.hidden_size,
+            vb.pp(names.proj),
+            &None,
+            &None,
+            dtype,
+        )?;
+        let norm = rms_norm(
+            config.hidden_size,
+            config.rms_norm_eps,
+            vb.pp(names.final_norm),
+            DType::F32,
+            names.norm_offset,
+        )?;
+        Ok(Self {
+            enorm,
+            hnorm,
+            eh_proj,
+            layer,
+            norm,
+            device: vb.device(),
+            dtype,
+            sliding_window: config.sliding_window,
+        })
+    }
+
+    /// Run the block over a prefill chunk. `embeds` are the embeddings of the tokens one
+    /// position ahead, `hidden` the previous hidden states of the same rows. Returns the
+    /// block output, which also serves as `hidden` for the following draft step.
+    pub fn forward(
+        &self,
+        embeds: &Tensor,
+        hidden: &Tensor,
+        positions: &Tensor,
+        cache: (&Tensor, &Tensor),
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let seqlens = input_metadata.seqlens.clone().unwrap_or_default();
+        let attention_mask = get_attention_causal_mask(
+            &self.device,
+            self.dtype,
+            positions,
+            seqlens,
+            self.sliding_window,
+            input_metadata.is_prefill,
+        );
+        let embeds = self.enorm.forward(embeds)?;
+        let hidden = self.hnorm.forward(&hidden.to_dtype(embeds.dtype())?)?;
+        let xs = Tensor::cat(&[&embeds, &hidden], 1)?;
+        let xs = self.eh_proj.forward(&xs.to_dtype(self.dtype)?)?;
+        let xs = xs.to_dtype(embeds.dtype())?;
+        self.layer.forward(
+            &xs,
+            attention_mask.as_ref(),
+            positions,
+            Some(cache),
+            input_metadata,
+        )
+    }
+
+    /// Normed block output, ready for the target's output head.
+    pub fn final_norm(&self, xs: &Tensor) -> Result<Tensor> {
+        self.norm.forward(xs)
+    }
+}
+
+/// Number of MTP blocks stored after the decoder layers of a safetensors checkpoint.
+pub fn num_mtp_layers(vb: &VarBuilderX, config: &Config) -> usize {
+    mtp_layers_with_key(
+        vb,
+        config,
+        &format!("model.layers.{}.eh_proj.weight", config.num_hidden_layers),
+    )
+}
+
+/// Number of MTP blocks stored under `mtp` in a Qwen3.5 safetensors checkpoint.
+pub fn num_qwen3_5_mtp_layers(vb: &VarBuilderX, config: &Config) -> usize {
+    mtp_layers_with_key(vb, config, "mtp.fc.weight")
+}
+
+fn mtp_layers_with_key(vb: &VarBuilderX, config: &Config, key: &str) -> usize {
+    let layers = config.num_nextn_predict_layers();
+    if layers > 0 && !vb.is_qvar_builder() && vb.has_key(key) {
+        layers
+    } else {
+        0
+    }
+}
@@ -389,8 +389,15 @@ impl MiniMaxForCausalLM {
         let xs = self.norm.forward(&xs)?;
         if return_hidden {
             xs.to_dtype(DType::F32)
-        } else if self.is_qvar_builder {
-            self.lm_head.forward(&xs)
+        } else {
+            self.compute_logits(&xs)
+        }
+    }
+
+    /// Project final-normed hidden states onto the vocabulary (F32 logits).
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        if self.is_qvar_builder {
+            self.lm_head.forward(xs)
         } else {
             self.lm_head
                 .forward(&xs.to_dtype(self.dtype)?)?
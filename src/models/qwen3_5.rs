@@ -1,10 +1,13 @@
 // src/models/qwen3_5.rs
 // Qwen3.5 dense model with hybrid attention (full attention + GatedDeltaNet layers)
 use crate::models::layers::attention::Attention;
-use crate::models::layers::deltanet::GatedDeltaNet;
+use crate::models::layers::deltanet::{GatedDeltaNet, MambaSnapshot};
 use crate::models::layers::distributed::{Comm, ReplicatedLinear};
 use crate::models::layers::mask::get_attention_causal_mask;
 use crate::models::layers::mlp::MLP;
+use crate::models::layers::mtp::{
+    num_qwen3_5_mtp_layers, MtpDecoderLayer, MultiTokenPredictor, QWEN3_5_MTP_WEIGHTS,
+};
 use crate::models::layers::others::{embedding, rms_norm, NormX};
 use crate::models::layers::rotary_emb::{ApplyRotaryEmbedding, ScalingRotaryEmbedding};
 use crate::models::layers::VarBuilderX;
@@ -177,7 +180,10 @@ impl Qwen3_5DecoderLayer {
                 }
             }
         };
+        self.forward_mlp(&attn_output, residual)
+    }
 
+    fn forward_mlp(&self, attn_output: &Tensor, residual: &Tensor) -> Result<Tensor> {
         let xs = (attn_output + residual)?;
         let residual = &xs;
         let xs = self.post_attention_layernorm.forward(&xs)?;
@@ -190,6 +196,32 @@ impl Qwen3_5DecoderLayer {
     }
 }
 
+// The MTP block of Qwen3.5 checkpoints wraps a full-attention layer, which keeps no mamba state
+impl MtpDecoderLayer for Qwen3_5DecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let Qwen3_5AttnType::FullAttention(attn) = &self.attn else {
+            candle_core::bail!("Qwen3.5 MTP layer must use full attention");
+        };
+        let rope: Arc<dyn ApplyRotaryEmbedding> = self.rotary_emb.as_ref().unwrap().clone();
+        let attn_output = attn.forward(
+            &self.input_layernorm.forward(xs)?,
+            &Some(rope),
+            attention_mask,
+            positions,
+            cache,
+            input_metadata,
+        )?;
+        self.forward_mlp(&attn_output, xs)
+    }
+}
+
 // =============================================================================
 // Qwen3.5 causal LM (dense variant)
 // =============================================================================
@@ -557,8 +589,15 @@ impl Qwen3_5ForCausalLM {
         let xs = self.norm.forward(&xs)?;
         if return_hidden {
             xs.to_dtype(DType::F32)
-        } else if self.is_qvar_builder {
-            self.lm_head.forward(&xs)
+        } else {
+            self.compute_logits(&xs)
+        }
+    }
+
+    /// Project final-normed hidden states onto the vocabulary (F32 logits).
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        if self.is_qvar_builder {
+            self.lm_head.forward(xs)
         } else {
             self.lm_head
                 .forward(&xs.to_dtype(self.dtype)?)?
@@ -683,6 +722,66 @@ impl Qwen3_5ForCausalLM {
         self.mamba_cache.write().reset_all()
     }
 
+    /// Copy the mamba states of the given sequences, to undo a forward pass over tokens that
+    /// may be rejected.
+    pub fn snapshot_mamba_states(&self, sequence_ids: &[usize]) -> Result<MambaSnapshot> {
+        let mut mamba_cache = self.mamba_cache.write();
+        let slots = mamba_cache.get_slots_for_sequences(sequence_ids)?;
+        let num_gdn_layers = self
+            .layers
+            .iter()
+            .filter(|layer| !layer.is_full_attention())
+            .count();
+        MambaSnapshot::capture(&mut mamba_cache, num_gdn_layers, slots, &self.device)
+    }
+
+    /// Restore the snapshot rows (positions in its `sequence_ids`) listed in `rows`.
+    pub fn restore_mamba_states(&self, snapshot: &MambaSnapshot, rows: &[usize]) -> Result<()> {
+        snapshot.restore(&mut self.mamba_cache.write(), rows)
+    }
+
+    /// Multi-token prediction block of the checkpoint (drafting for speculative decoding),
+    /// `None` if the checkpoint has none.
+    pub fn load_mtp(
+        vb: &VarBuilderX,
+        comm: Rc<Comm>,
+        config: &Config,
+        dtype: DType,
+        is_rope_i: bool,
+    ) -> Result<Option<MultiTokenPredictor>> {
+        if num_qwen3_5_mtp_layers(vb, config) == 0 {
+            return Ok(None);
+        }
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(
+            if config.higher_precision_required() {
+                DType::F32
+            } else {
+                dtype
+            },
+            config,
+            &vb.device(),
+            is_rope_i,
+            config.rope_theta,
+        )?);
+        let vb = vb.pp("mtp");
+        let layer = Qwen3_5DecoderLayer::new(
+            vb.pp("layers.0"),
+            comm,
+            rotary_emb,
+            config,
+            "full_attention",
+            0,
+            dtype,
+        )?;
+        Ok(Some(MultiTokenPredictor::with_weight_names(
+            vb,
+            config,
+            dtype,
+            Box::new(layer),
+            &QWEN3_5_MTP_WEIGHTS,
+        )?))
+    }
+
     pub fn dtype(&self) -> DType {
         self.dtype
     }
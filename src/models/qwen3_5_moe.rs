@@ -1,7 +1,7 @@
 // src/models/qwen3_5_moe.rs
 // Qwen3.5 MoE variant with hybrid attention (full attention + GatedDeltaNet layers)
 use crate::models::layers::attention::Attention;
-use crate::models::layers::deltanet::GatedDeltaNet;
+use crate::models::layers::deltanet::{GatedDeltaNet, MambaSnapshot};
 use crate::models::layers::distributed::{Comm, ReplicatedLinear};
 use crate::models::layers::linear::LinearX as Linear;
 use crate::models::layers::mask::get_attention_causal_mask;
@@ -9,6 +9,9 @@ use crate::models::layers::mlp::MLP;
 use crate::models::layers::moe::{
     FusedMoe, FusedMoeFp8, FusedMoeGGUF, FusedMoeISQ, FusedMoeMxfp4, FusedMoeNvfp4,
 };
+use crate::models::layers::mtp::{
+    num_qwen3_5_mtp_layers, MtpDecoderLayer, MultiTokenPredictor, QWEN3_5_MTP_WEIGHTS,
+};
 use crate::models::layers::others::{embedding, rms_norm, NormX};
 use crate::models::layers::rotary_emb::{ApplyRotaryEmbedding, ScalingRotaryEmbedding};
 use crate::models::layers::VarBuilderX;
@@ -297,6 +300,15 @@ impl Qwen3_5MoEDecoderLayer {
             }
         };
 
+        self.forward_mlp(&attn_output, residual, input_metadata.is_prefill)
+    }
+
+    fn forward_mlp(
+        &self,
+        attn_output: &Tensor,
+        residual: &Tensor,
+        is_prefill: bool,
+    ) -> Result<Tensor> {
         let xs = (attn_output + residual)?;
         let residual = &xs;
         let xs = self.post_attention_layernorm.forward(&xs)?;
@@ -311,7 +323,7 @@ impl Qwen3_5MoEDecoderLayer {
             _ => None,
         };
 
-        let mlp_output = self.mlp.forward(&xs, input_metadata.is_prefill)?;
+        let mlp_output = self.mlp.forward(&xs, is_prefill)?;
         if let Some(shared_output) = shared_output {
             residual + (mlp_output + shared_output)?
         } else {
@@ -324,6 +336,32 @@ impl Qwen3_5MoEDecoderLayer {
     }
 }
 
+// The MTP block of Qwen3.5 checkpoints wraps a full-attention layer, which keeps no mamba state
+impl MtpDecoderLayer for Qwen3_5MoEDecoderLayer {
+    fn forward(
+        &self,
+        xs: &Tensor,
+        attention_mask: Option<&Vec<Tensor>>,
+        positions: &Tensor,
+        cache: Option<(&Tensor, &Tensor)>,
+        input_metadata: &InputMetadata,
+    ) -> Result<Tensor> {
+        let Qwen3_5MoEAttnType::FullAttention(attn) = &self.attn else {
+            candle_core::bail!("Qwen3.5 MoE MTP layer must use full attention");
+        };
+        let rope: Arc<dyn ApplyRotaryEmbedding> = self.rotary_emb.as_ref().unwrap().clone();
+        let attn_output = attn.forward(
+            &self.input_layernorm.forward(xs)?,
+            &Some(rope),
+            attention_mask,
+            positions,
+            cache,
+            input_metadata,
+        )?;
+        self.forward_mlp(&attn_output, xs, input_metadata.is_prefill)
+    }
+}
+
 // =============================================================================
 // Qwen3.5 MoE Causal LM
 // =============================================================================
@@ -686,8 +724,15 @@ impl Qwen3_5MoEForCausalLM {
         let xs = self.norm.forward(&xs)?;
         if return_hidden {
             xs.to_dtype(DType::F32)
-        } else if self.is_qvar_builder {
-            self.lm_head.forward(&xs)
+        } else {
+            self.compute_logits(&xs)
+        }
+    }
+
+    /// Project final-normed hidden states onto the vocabulary (F32 logits).
+    pub fn compute_logits(&self, xs: &Tensor) -> Result<Tensor> {
+        if self.is_qvar_builder {
+            self.lm_head.forward(xs)
         } else {
             self.lm_head
                 .forward(&xs.to_dtype(self.dtype)?)?
@@ -812,6 +857,66 @@ impl Qwen3_5MoEForCausalLM {
         self.mamba_cache.write().reset_all()
     }
 
+    /// Copy the mamba states of the given sequences, to undo a forward pass over tokens that
+    /// may be rejected.
+    pub fn snapshot_mamba_states(&self, sequence_ids: &[usize]) -> Result<MambaSnapshot> {
+        let mut mamba_cache = self.mamba_cache.write();
+        let slots = mamba_cache.get_slots_for_sequences(sequence_ids)?;
+        let num_gdn_layers = self
+            .layers
+            .iter()
+            .filter(|layer| !layer.is_full_attention())
+            .count();
+        MambaSnapshot::capture(&mut mamba_cache, num_gdn_layers, slots, &self.device)
+    }
+
+    /// Restore the snapshot rows (positions in its `sequence_ids`) listed in `rows`.
+    pub fn restore_mamba_states(&self, snapshot: &MambaSnapshot, rows: &[usize]) -> Result<()> {
+        snapshot.restore(&mut self.mamba_cache.write(), rows)
+    }
+
+    /// Multi-token prediction block of the checkpoint (drafting for speculative decoding),
+    /// `None` if the checkpoint has none.
+    pub fn load_mtp(
+        vb: &VarBuilderX,
+        comm: Rc<Comm>,
+        config: &Config,
+        dtype: DType,
+        is_rope_i: bool,
+    ) -> Result<Option<MultiTokenPredictor>> {
+        if num_qwen3_5_mtp_layers(vb, config) == 0 {
+            return Ok(None);
+        }
+        let rotary_emb = Arc::new(ScalingRotaryEmbedding::new(
+            if config.higher_precision_required() {
+                DType::F32
+            } else {
+                dtype
+            },
+            config,
+            &vb.device(),
+            is_rope_i,
+            config.rope_theta,
+        )?);
+        let vb = vb.pp("mtp");
+        let layer = Qwen3_5MoEDecoderLayer::new(
+            vb.pp("layers.0"),
+            comm,
+            rotary_emb,
+            config,
+            "full_attention",
+            0,
+            dtype,
+        )?;
+        Ok(Some(MultiTokenPredictor::with_weight_names(
+            vb,
+            config,
+            dtype,
+            Box::new(layer),
+            &QWEN3_5_MTP_WEIGHTS,
+        )?))
+    }
+
     pub fn dtype(&self) -> DType {
         self.dtype
     }
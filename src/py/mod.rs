@@ -1,6 +1,7 @@
 use crate::core::engine::LLMEngine;
 use crate::core::engine::StreamItem;
 use crate::core::engine::GLOBAL_RT;
+use crate::core::spec_decode::{SpeculativeConfig, SpeculativeMethod};
 use crate::core::GenerationOutput;
 use crate::server::run_server;
 use crate::transfer::{PdConfig, PdMethod, PdRole};
@@ -156,6 +157,8 @@ impl PyStreamItem {
             StreamItem::TokenID(_) => "TOKEN_ID",
             StreamItem::Completion(_) => "COMPLETION",
             StreamItem::Done(_) => "DONE",
+            StreamItem::Logprobs(_) => "LOGPROBS",
+            StreamItem::Prediction(_) => "PREDICTION",
             StreamItem::Error(_) => "ERROR",
         }
     }
@@ -164,6 +167,8 @@ impl PyStreamItem {
     /// data depends on the `type`.
     /// - "TOKEN": str
     /// - "DONE": tuple[int, int, int, int]
+    /// - "LOGPROBS": tuple[int, float, list[tuple[int, float]]]
+    /// - "PREDICTION": tuple[int, int] (accepted and rejected predicted tokens)
     /// - "ERROR": str
     /// etc.
     #[getter]
@@ -173,6 +178,16 @@ impl PyStreamItem {
             StreamItem::TokenID(id) => id.into_py_any(py),
             StreamItem::Completion(c) => (c.0, c.1, c.2, c.3.clone()).into_py_any(py),
             StreamItem::Done(d) => (d.0, d.1, d.2, d.3).into_py_any(py),
+            StreamItem::Logprobs(l) => (
+                l.token_id,
+                l.logprob,
+                l.top_logprobs
+                    .iter()
+                    .map(|t| (t.token_id, t.logprob))
+                    .collect::<Vec<_>>(),
+            )
+                .into_py_any(py),
+            StreamItem::Prediction(p) => (p.0, p.1).into_py_any(py),
             StreamItem::Error(e) => e.into_py_any(py),
         }
     }
@@ -272,7 +287,9 @@ impl EngineConfig {
         mcp_command=None, mcp_config=None, mcp_args=None,
         tool_prompt_template=None,
         pd_server_prefix_cache_ratio=None, pd_client_prefix_cache_ratio=None, yarn_scaling_factor=None,
-        disable_reasoning=false,))]
+        disable_reasoning=false, speculative_config=None, api_keys=None,
+        scheduling_policy=None, max_waiting_requests=None, max_waiting_tokens=None,
+        max_queue_wait_secs=None,))]
     pub fn new(
         model_id: Option<String>,
         weight_path: Option<String>,
@@ -305,6 +322,12 @@ impl EngineConfig {
         pd_client_prefix_cache_ratio: Option<f32>,
         yarn_scaling_factor: Option<f64>,
         disable_reasoning: bool,
+        speculative_config: Option<SpeculativeConfig>,
+        api_keys: Option<String>,
+        scheduling_policy: Option<String>,
+        max_waiting_requests: Option<usize>,
+        max_waiting_tokens: Option<usize>,
+        max_queue_wait_secs: Option<u64>,
     ) -> Self {
         let mut device_ids = device_ids.unwrap_or_default();
         if device_ids.is_empty() {
@@ -345,11 +368,17 @@ impl EngineConfig {
             mcp_command,
             mcp_config,
             mcp_args,
+            api_keys,
+            scheduling_policy,
+            max_waiting_requests,
+            max_waiting_tokens,
+            max_queue_wait_secs,
             tool_prompt_template,
             pd_server_prefix_cache_ratio,
             pd_client_prefix_cache_ratio,
             yarn_scaling_factor,
             disable_reasoning,
+            speculative_config,
         }
     }
 }
@@ -360,7 +389,14 @@ impl SamplingParams {
     #[pyo3(signature = (temperature=None, max_tokens=None,
         ignore_eos=Some(false), top_k=None, top_p=None, session_id=None,
         frequency_penalty=None, presence_penalty=None, thinking=None,
-        grammar_json=None))]
+        grammar_json=None, logprobs=None, top_logprobs=None, n=None,
+        beam_width=None, length_penalty=None, early_stopping=None,
+        min_p=None, typical_p=None, epsilon_cutoff=None, eta_cutoff=None,
+        repetition_penalty=None, no_repeat_ngram_size=None, logit_bias=None,
+        bad_words=None, seed=None, min_tokens=None, mirostat=None, mirostat_tau=None,
+        mirostat_eta=None, dry_multiplier=None, dry_base=None, dry_allowed_length=None,
+        dry_penalty_last_n=None, dry_sequence_breakers=None, prediction=None,
+        priority=None))]
     pub fn new(
         temperature: Option<f32>,
         max_tokens: Option<usize>,
@@ -372,6 +408,32 @@ impl SamplingParams {
         presence_penalty: Option<f32>,
         thinking: Option<bool>,
         grammar_json: Option<String>,
+        logprobs: Option<bool>,
+        top_logprobs: Option<usize>,
+        n: Option<usize>,
+        beam_width: Option<usize>,
+        length_penalty: Option<f32>,
+        early_stopping: Option<bool>,
+        min_p: Option<f32>,
+        typical_p: Option<f32>,
+        epsilon_cutoff: Option<f32>,
+        eta_cutoff: Option<f32>,
+        repetition_penalty: Option<f32>,
+        no_repeat_ngram_size: Option<usize>,
+        logit_bias: Option<HashMap<u32, f32>>,
+        bad_words: Option<Vec<String>>,
+        seed: Option<u64>,
+        min_tokens: Option<usize>,
+        mirostat: Option<usize>,
+        mirostat_tau: Option<f32>,
+        mirostat_eta: Option<f32>,
+        dry_multiplier: Option<f32>,
+        dry_base: Option<f32>,
+        dry_allowed_length: Option<usize>,
+        dry_penalty_last_n: Option<usize>,
+        dry_sequence_breakers: Option<Vec<String>>,
+        prediction: Option<String>,
+        priority: Option<i32>,
     ) -> Self {
         // Convert grammar_json to TopLevelGrammar if present
         let grammar = grammar_json
@@ -394,6 +456,33 @@ impl SamplingParams {
             grammar_json,
             grammar,
             reasoning_effort: None,
+            logprobs,
+            top_logprobs,
+            n,
+            beam_width,
+            length_penalty,
+            early_stopping,
+            min_p,
+            typical_p,
+            epsilon_cutoff,
+            eta_cutoff,
+            repetition_penalty,
+            no_repeat_ngram_size,
+            logit_bias,
+            bad_words,
+            seed,
+            min_tokens,
+            mirostat,
+            mirostat_tau,
+            mirostat_eta,
+            dry_multiplier,
+            dry_base,
+            dry_allowed_length,
+            dry_penalty_last_n,
+            dry_sequence_breakers,
+            dry_sequence_breaker_ids: None,
+            prediction,
+            priority,
         }
     }
 
@@ -415,6 +504,33 @@ impl SamplingParams {
             grammar_json: None,
             grammar: None,
             reasoning_effort: None,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            beam_width: None,
+            length_penalty: None,
+            early_stopping: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            repetition_penalty: None,
+            no_repeat_ngram_size: None,
+            logit_bias: None,
+            bad_words: None,
+            seed: None,
+            min_tokens: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            dry_multiplier: None,
+            dry_base: None,
+            dry_allowed_length: None,
+            dry_penalty_last_n: None,
+            dry_sequence_breakers: None,
+            dry_sequence_breaker_ids: None,
+            prediction: None,
+            priority: None,
         }
     }
 
@@ -456,13 +572,21 @@ impl SamplingParams {
 #[pymethods]
 impl GenerationConfig {
     #[new]
-    #[pyo3(signature = (temperature=None, top_p=None, top_k=None, frequency_penalty=None, presence_penalty=None))]
+    #[pyo3(signature = (temperature=None, top_p=None, top_k=None, frequency_penalty=None, presence_penalty=None,
+        min_p=None, typical_p=None, epsilon_cutoff=None, eta_cutoff=None,
+        repetition_penalty=None, no_repeat_ngram_size=None))]
     pub fn new(
         temperature: Option<f32>,
         top_p: Option<f32>,
         top_k: Option<isize>,
         frequency_penalty: Option<f32>,
         presence_penalty: Option<f32>,
+        min_p: Option<f32>,
+        typical_p: Option<f32>,
+        epsilon_cutoff: Option<f32>,
+        eta_cutoff: Option<f32>,
+        repetition_penalty: Option<f32>,
+        no_repeat_ngram_size: Option<usize>,
     ) -> Self {
         Self {
             temperature,
@@ -470,6 +594,12 @@ impl GenerationConfig {
             top_k,
             frequency_penalty,
             presence_penalty,
+            min_p,
+            typical_p,
+            epsilon_cutoff,
+            eta_cutoff,
+            repetition_penalty,
+            no_repeat_ngram_size,
             bos_token_id: None,
             eos_token_id: None,
         }
@@ -488,3 +618,25 @@ impl PdConfig {
         Self { role, method, url }
     }
 }
+
+#[pymethods]
+impl SpeculativeConfig {
+    #[new]
+    #[pyo3(signature = (method=SpeculativeMethod::Ngram, num_speculative_tokens=4,
+        prompt_lookup_max=4, prompt_lookup_min=1, draft_model=None))]
+    pub fn new(
+        method: SpeculativeMethod,
+        num_speculative_tokens: usize,
+        prompt_lookup_max: usize,
+        prompt_lookup_min: usize,
+        draft_model: Option<String>,
+    ) -> Self {
+        Self {
+            method,
+            num_speculative_tokens,
+            prompt_lookup_max,
+            prompt_lookup_min,
+            draft_model,
+        }
+    }
+}
@@ -1,8 +1,11 @@
+use crate::core::runner::RunOutput;
 use crate::core::sequence::{DecodeSequence, Sequence};
+use crate::core::spec_decode::DraftModelSpec;
 use crate::models::layers::distributed::Id;
 use crate::server::EmbeddingStrategy;
 use crate::utils::config::{Config, EngineConfig, ModelType};
 use crate::utils::downloader::ModelPaths;
+use crate::utils::logits_processor::TokenLogprobs;
 #[cfg(feature = "nccl")]
 use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
 use candle_core::DType;
@@ -22,6 +25,7 @@ pub struct RunnerInitRequest {
     pub is_gguf: bool,
     pub dtype: SerializableDType,
     pub is_rope_i: bool,
+    pub draft: Option<DraftModelSpec>,
     #[cfg(feature = "nccl")]
     pub nccl_id: NcclId,
 }
@@ -181,8 +185,11 @@ pub enum MessageType {
     /// Sent by main process to request inference on sequences.
     RunDecode((Vec<DecodeSequence>, bool)),
 
-    /// Sent by runner in response to `Run` with generated token IDs.
-    RunResponse(Vec<u32>),
+    /// Sent by main process to verify the draft tokens of decoding sequences.
+    RunSpeculative(Vec<Sequence>),
+
+    /// Sent by runner in response to `Run` with generated token IDs (and logprobs if requested).
+    RunResponse(RunOutput),
 
     /// Sent by main process to request embedding on sequences.
     RunEmbed((Vec<Sequence>, EmbeddingStrategy)),
@@ -190,6 +197,12 @@ pub enum MessageType {
     /// Sent by runner in response to `Run` with generated embedding states
     RunResponseEmbed(Vec<Vec<f32>>),
 
+    /// Sent by main process to score prompt tokens (sequences, top logprobs per token).
+    RunScore((Vec<Sequence>, usize)),
+
+    /// Sent by runner in response to `RunScore` with per-token prompt logprobs.
+    RunResponseScore(Vec<Vec<TokenLogprobs>>),
+
     /// Sent by main process to notify the finished decoding sequences.
     FinishDecode(usize),
 
@@ -216,10 +229,19 @@ pub enum MessageType {
     CheckPrefillStatus(usize),
     CheckPrefillStatusResponse(bool),
 
+    // Readiness: whether the PD peer is connected
+    CheckPdConnection(),
+    CheckPdConnectionResponse(bool),
+
     KVCacheSwap((HashMap<usize, usize>, bool)),
 
     KVCacheSwapResponse(bool),
 
+    // copy gpu kvcache blocks (src -> dst), used for copy-on-write of forked sequences
+    KVCacheCopy(HashMap<usize, usize>),
+
+    KVCacheCopyResponse(bool),
+
     // send kvcache to client (seq_id, first_token)
     KvCacheSend((Sequence, u32)),
     KvCacheSendResponse(bool),
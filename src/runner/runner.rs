@@ -8,7 +8,7 @@ use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokenizers::Tokenizer;
-use vllm_rs::core::runner::{ModelRunner, Seqs};
+use vllm_rs::core::runner::{ModelRunner, RunOutput, Seqs};
 use vllm_rs::models::layers::distributed::Comm;
 use vllm_rs::models::layers::VarBuilderX;
 use vllm_rs::runner::{receive_local, send_local, MessageType};
@@ -196,6 +196,7 @@ fn main() -> anyhow::Result<()> {
                     progress_reporter,
                     transfer,
                     llg_factory,
+                    init_req.draft.clone(),
                     stream_kv,
                 )?;
                 drop(vb);
@@ -262,7 +263,7 @@ fn main() -> anyhow::Result<()> {
                 }
                 send_local(
                     &mut vec![stream.try_clone()?],
-                    &MessageType::RunResponse(outputs.unwrap_or(vec![])),
+                    &MessageType::RunResponse(outputs.unwrap_or(RunOutput::default())),
                     false,
                 )?;
             }
@@ -273,7 +274,20 @@ fn main() -> anyhow::Result<()> {
                 }
                 send_local(
                     &mut vec![stream.try_clone()?],
-                    &MessageType::RunResponse(outputs.unwrap_or(vec![])),
+                    &MessageType::RunResponse(outputs.unwrap_or(RunOutput::default())),
+                    false,
+                )?;
+            }
+            Ok(MessageType::RunSpeculative(sequences)) => {
+                use vllm_rs::core::sequence::Sequence;
+                let refs: Vec<&Sequence> = sequences.iter().collect();
+                let outputs = runner.run_speculative(&refs);
+                if outputs.is_err() {
+                    vllm_rs::log_error!("Runner speculative decode error: {:?}", outputs);
+                }
+                send_local(
+                    &mut vec![stream.try_clone()?],
+                    &MessageType::RunResponse(outputs.unwrap_or(RunOutput::default())),
                     false,
                 )?;
             }
@@ -291,9 +305,33 @@ fn main() -> anyhow::Result<()> {
                     false,
                 )?;
             }
+            Ok(MessageType::RunScore((sequences, top_n))) => {
+                use vllm_rs::core::sequence::Sequence;
+                let refs: Vec<&Sequence> = sequences.iter().collect();
+                let outputs = runner.score(&refs, top_n);
+                if outputs.is_err() {
+                    vllm_rs::log_error!("Runner scoring error: {:?}", outputs);
+                }
+                send_local(
+                    &mut vec![stream.try_clone()?],
+                    &MessageType::RunResponseScore(outputs.unwrap_or_default()),
+                    false,
+                )?;
+            }
             Ok(MessageType::LoadingProgress(_)) => {
                 vllm_rs::log_info!("Received loading progress message");
             }
+            Ok(MessageType::KVCacheCopy(mappings)) => {
+                let ret = runner.copy_kvcache(mappings);
+                if ret.is_err() {
+                    vllm_rs::log_error!("KvCache copy failed: {:?}", ret);
+                }
+                send_local(
+                    &mut vec![stream.try_clone()?],
+                    &MessageType::KVCacheCopyResponse(ret.is_ok()),
+                    false,
+                )?;
+            }
             Ok(MessageType::KVCacheSwap((mappings, swap_in))) => {
                 vllm_rs::log_info!(
                     "Received KVCacheSwap message: {} kv cache blocks need to {}!",
@@ -370,6 +408,14 @@ fn main() -> anyhow::Result<()> {
                     false,
                 )?;
             }
+            Ok(MessageType::CheckPdConnection()) => {
+                let connected = runner.check_pd_connection();
+                send_local(
+                    &mut vec![stream.try_clone()?],
+                    &MessageType::CheckPdConnectionResponse(connected.unwrap_or(false)),
+                    false,
+                )?;
+            }
             Ok(MessageType::KvCacheSend((sequence, token))) => {
                 let ret = runner.send_kvcache(&sequence, token);
                 if ret.is_err() {
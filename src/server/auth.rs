@@ -0,0 +1,629 @@
+// src/server/auth.rs
+//! Optional API key authentication with per-key limits, enabled by `--api-keys <file>`.
+//!
+//! The file is JSON:
+//! ```json
+//! {"keys": [{"key": "sk-team-a", "name": "team-a", "requests_per_minute": 60,
+//!            "tokens_per_minute": 200000, "max_concurrent_sequences": 8,
+//!            "max_tokens": 4096, "allowed_endpoints": ["/v1/chat/completions", "/v1/models"]}]}
+//! ```
+//! Every limit is optional. Keys are read from `Authorization: Bearer <key>` or
+//! `x-api-key: <key>`. Token and sequence counts come from the engine: sequences are
+//! attributed to the key of the request that submitted them (see `current_key_usage`),
+//! and their prompt and generated tokens are counted once they finish.
+use super::health;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use candle_core::Result;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+const MAX_BODY_BYTES: usize = 100 * 1024 * 1024;
+/// Probes must work without credentials.
+const UNAUTHENTICATED_ROUTES: &[&str] = &["/health", "/ready"];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub requests_per_minute: Option<usize>,
+    #[serde(default)]
+    pub tokens_per_minute: Option<usize>,
+    #[serde(default)]
+    pub max_concurrent_sequences: Option<usize>,
+    /// Ceiling on the output tokens of one request; also the default when a request
+    /// does not set one.
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    /// Route prefixes this key may call; all routes when absent.
+    #[serde(default)]
+    pub allowed_endpoints: Option<Vec<String>>,
+    /// Whether this key may call the `/admin` routes from other hosts than localhost.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiKeysFile {
+    keys: Vec<ApiKeyConfig>,
+}
+
+/// Sliding-window usage of one API key.
+#[derive(Debug, Default)]
+pub struct KeyUsage {
+    active_sequences: AtomicUsize,
+    requests: Mutex<VecDeque<Instant>>,
+    tokens: Mutex<VecDeque<(Instant, usize)>>,
+}
+
+impl KeyUsage {
+    pub fn sequence_started(&self) {
+        self.active_sequences.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Releases a sequence and charges its prompt and generated tokens.
+    pub fn sequence_finished(&self, tokens: usize) {
+        let _ = self
+            .active_sequences
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+        if tokens > 0 {
+            self.tokens.lock().push_back((Instant::now(), tokens));
+        }
+    }
+
+    pub fn active_sequences(&self) -> usize {
+        self.active_sequences.load(Ordering::SeqCst)
+    }
+
+    /// Tokens charged within the window, and when the oldest of them expires.
+    fn tokens_in_window(&self, now: Instant) -> (usize, Duration) {
+        let mut tokens = self.tokens.lock();
+        while tokens
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            tokens.pop_front();
+        }
+        let retry = tokens
+            .front()
+            .map_or(Duration::ZERO, |(at, _)| expires_in(*at, now));
+        (tokens.iter().map(|(_, n)| n).sum(), retry)
+    }
+}
+
+fn expires_in(at: Instant, now: Instant) -> Duration {
+    RATE_WINDOW.saturating_sub(now.duration_since(at))
+}
+
+pub struct ApiKey {
+    pub config: ApiKeyConfig,
+    pub usage: Arc<KeyUsage>,
+    /// Stable id recorded on the files, batches and responses created with this key,
+    /// so that only this key can read them and the secret itself is never persisted.
+    pub owner: String,
+}
+
+/// FNV-1a hash of the key, stable across restarts and builds.
+fn owner_id(key: &str) -> String {
+    let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("key-{hash:016x}")
+}
+
+impl ApiKey {
+    pub(super) fn allows(&self, path: &str) -> bool {
+        self.config
+            .allowed_endpoints
+            .as_ref()
+            .map_or(true, |allowed| {
+                allowed.iter().any(|prefix| {
+                    let prefix = prefix.trim_end_matches('/');
+                    path == prefix
+                        || path
+                            .strip_prefix(prefix)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })
+            })
+    }
+
+    /// Checks the rate and concurrency limits and counts the request if it is admitted.
+    fn admit(&self, now: Instant) -> std::result::Result<(), Rejection> {
+        let mut requests = self.usage.requests.lock();
+        while requests
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= RATE_WINDOW)
+        {
+            requests.pop_front();
+        }
+        if let Some(limit) = self.config.requests_per_minute {
+            if requests.len() >= limit {
+                let retry = requests
+                    .front()
+                    .map_or(RATE_WINDOW, |at| expires_in(*at, now));
+                return Err(Rejection::RequestRate(limit, retry));
+            }
+        }
+        if let Some(limit) = self.config.tokens_per_minute {
+            let (used, retry) = self.usage.tokens_in_window(now);
+            if used >= limit {
+                return Err(Rejection::TokenRate(limit, retry));
+            }
+        }
+        if let Some(limit) = self.config.max_concurrent_sequences {
+            if self.usage.active_sequences() >= limit {
+                return Err(Rejection::Concurrency(limit));
+            }
+        }
+        requests.push_back(now);
+        Ok(())
+    }
+
+    /// Counts one request against the limits of this key, for requests that do not go
+    /// through `authenticate` (batch lines). Returns false while a limit is reached.
+    pub(super) fn try_admit(&self) -> bool {
+        self.admit(Instant::now()).is_ok()
+    }
+
+    /// Applies the `max_tokens` ceiling of this key to a request body for `path`.
+    pub(super) fn limit_body(
+        &self,
+        path: &str,
+        body: &mut Value,
+    ) -> std::result::Result<(), String> {
+        let (Some(ceiling), Some((field, non_positive_is_default))) =
+            (self.config.max_tokens, max_tokens_field(path))
+        else {
+            return Ok(());
+        };
+        limit_max_tokens(body, field, non_positive_is_default, ceiling)
+            .map_err(|rejection| rejection.message())
+    }
+}
+
+pub struct ApiKeys {
+    keys: HashMap<String, Arc<ApiKey>>,
+}
+
+impl ApiKeys {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| {
+            candle_core::Error::msg(format!("Failed to read API keys file {:?}: {e}", path))
+        })?;
+        let file: ApiKeysFile = serde_json::from_str(&content).map_err(|e| {
+            candle_core::Error::msg(format!("Invalid API keys file {:?}: {e}", path))
+        })?;
+        Self::new(file.keys)
+    }
+
+    pub fn new(configs: Vec<ApiKeyConfig>) -> Result<Self> {
+        if configs.is_empty() {
+            candle_core::bail!("API keys file does not define any key");
+        }
+        let mut keys = HashMap::new();
+        for config in configs {
+            let key = config.key.trim().to_string();
+            if key.is_empty() {
+                candle_core::bail!("API keys must not be empty");
+            }
+            let api_key = Arc::new(ApiKey {
+                owner: owner_id(&key),
+                config,
+                usage: Arc::new(KeyUsage::default()),
+            });
+            if keys.insert(key, api_key).is_some() {
+                candle_core::bail!("Duplicate API key in the API keys file");
+            }
+        }
+        Ok(Self { keys })
+    }
+
+    pub fn num_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn get(&self, key: &str) -> Option<&Arc<ApiKey>> {
+        self.keys.get(key)
+    }
+}
+
+tokio::task_local! {
+    static REQUEST_KEY: Arc<ApiKey>;
+}
+
+/// The API key that sent the request being handled, if any.
+pub fn current_key() -> Option<Arc<ApiKey>> {
+    REQUEST_KEY.try_with(Arc::clone).ok()
+}
+
+/// Owner id of the API key that sent the request being handled; `None` when API keys
+/// are disabled.
+pub fn current_owner() -> Option<String> {
+    REQUEST_KEY.try_with(|key| key.owner.clone()).ok()
+}
+
+/// Whether the request being handled was sent with an admin API key.
+pub fn current_key_is_admin() -> bool {
+    REQUEST_KEY
+        .try_with(|key| key.config.admin)
+        .unwrap_or(false)
+}
+
+/// Usage counters of the API key that sent the request being handled, if any. The
+/// engine attributes the sequences it adds to this key.
+pub fn current_key_usage() -> Option<Arc<KeyUsage>> {
+    REQUEST_KEY.try_with(|key| key.usage.clone()).ok()
+}
+
+/// Runs `future` on behalf of `key`, for work that submits sequences from a spawned
+/// task (streaming handlers, batch lines).
+pub async fn with_key<F: Future>(key: Option<Arc<ApiKey>>, future: F) -> F::Output {
+    match key {
+        Some(key) => REQUEST_KEY.scope(key, future).await,
+        None => future.await,
+    }
+}
+
+#[derive(Debug)]
+enum Rejection {
+    MissingKey,
+    InvalidKey,
+    EndpointNotAllowed(String),
+    RequestRate(usize, Duration),
+    TokenRate(usize, Duration),
+    Concurrency(usize),
+    MaxTokens(String),
+}
+
+impl Rejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            Rejection::MissingKey | Rejection::InvalidKey => StatusCode::UNAUTHORIZED,
+            Rejection::EndpointNotAllowed(_) => StatusCode::FORBIDDEN,
+            Rejection::RequestRate(..) | Rejection::TokenRate(..) | Rejection::Concurrency(_) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Rejection::MaxTokens(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Rejection::MissingKey => {
+                "Missing API key. Pass it as `Authorization: Bearer <key>` or `x-api-key`."
+                    .to_string()
+            }
+            Rejection::InvalidKey => "Invalid API key.".to_string(),
+            Rejection::EndpointNotAllowed(path) => {
+                format!("This API key is not allowed to call {path}.")
+            }
+            Rejection::RequestRate(limit, _) => {
+                format!("Rate limit reached: {limit} requests per minute.")
+            }
+            Rejection::TokenRate(limit, _) => {
+                format!("Rate limit reached: {limit} tokens per minute.")
+            }
+            Rejection::Concurrency(limit) => {
+                format!("Too many concurrent sequences: this API key allows {limit}.")
+            }
+            Rejection::MaxTokens(message) => message.clone(),
+        }
+    }
+
+    /// (OpenAI error type, OpenAI error code, Anthropic error type)
+    fn kinds(&self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            Rejection::MissingKey | Rejection::InvalidKey => (
+                "invalid_request_error",
+                "invalid_api_key",
+                "authentication_error",
+            ),
+            Rejection::EndpointNotAllowed(_) => (
+                "invalid_request_error",
+                "endpoint_not_allowed",
+                "permission_error",
+            ),
+            Rejection::RequestRate(..) | Rejection::Concurrency(_) => {
+                ("requests", "rate_limit_exceeded", "rate_limit_error")
+            }
+            Rejection::TokenRate(..) => ("tokens", "rate_limit_exceeded", "rate_limit_error"),
+            Rejection::MaxTokens(_) => (
+                "invalid_request_error",
+                "max_tokens_exceeded",
+                "invalid_request_error",
+            ),
+        }
+    }
+
+    fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Rejection::RequestRate(_, retry) | Rejection::TokenRate(_, retry) => Some(*retry),
+            Rejection::Concurrency(_) => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+
+    fn into_response(self, anthropic: bool) -> Response {
+        error_response(
+            self.status(),
+            anthropic,
+            self.kinds(),
+            &self.message(),
+            self.retry_after(),
+        )
+    }
+}
+
+/// Error body in the Anthropic shape for `/v1/messages`, the OpenAI shape otherwise.
+/// `kinds` is (OpenAI error type, OpenAI error code, Anthropic error type).
+fn error_body(anthropic: bool, kinds: (&str, &str, &str), message: &str) -> Value {
+    let (openai_type, code, anthropic_type) = kinds;
+    if anthropic {
+        json!({
+            "type": "error",
+            "error": { "type": anthropic_type, "message": message },
+        })
+    } else {
+        json!({
+            "error": {
+                "message": message,
+                "type": openai_type,
+                "param": null,
+                "code": code,
+            }
+        })
+    }
+}
+
+/// Error response with a `Retry-After` header in whole seconds (at least 1) when
+/// `retry_after` is given.
+pub(super) fn error_response(
+    status: StatusCode,
+    anthropic: bool,
+    kinds: (&str, &str, &str),
+    message: &str,
+    retry_after: Option<Duration>,
+) -> Response {
+    let mut response = (status, Json(error_body(anthropic, kinds, message))).into_response();
+    if let Some(retry) = retry_after {
+        let secs = retry.as_secs() + u64::from(retry.subsec_nanos() > 0);
+        if let Ok(value) = HeaderValue::from_str(&secs.max(1).to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+    }
+    response
+}
+
+fn request_key(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.strip_prefix("Bearer ")
+                .or_else(|| v.strip_prefix("bearer "))
+        });
+    bearer
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+/// Location of the output token limit in the body of each generation route, and
+/// whether values <= 0 mean "server default" there (Ollama's `num_predict`).
+fn max_tokens_field(path: &str) -> Option<(&'static [&'static str], bool)> {
+    match path {
+        "/v1/chat/completions" | "/v1/completions" | "/v1/messages" => {
+            Some((&["max_tokens"], false))
+        }
+        "/infill" => Some((&["n_predict"], false)),
+        "/v1/responses" => Some((&["max_output_tokens"], false)),
+        "/api/chat" | "/api/generate" => Some((&["options", "num_predict"], true)),
+        _ => None,
+    }
+}
+
+/// Rejects output token limits above `ceiling` and sets the limit where it is missing.
+fn limit_max_tokens(
+    body: &mut Value,
+    field: &[&str],
+    non_positive_is_default: bool,
+    ceiling: usize,
+) -> std::result::Result<(), Rejection> {
+    let Some((name, parents)) = field.split_last() else {
+        return Ok(());
+    };
+    let mut target = body;
+    for parent in parents {
+        let Some(object) = target.as_object_mut() else {
+            return Ok(());
+        };
+        target = object
+            .entry(parent.to_string())
+            .or_insert_with(|| json!({}));
+    }
+    let Some(object) = target.as_object_mut() else {
+        return Ok(());
+    };
+    let default_below = if non_positive_is_default { 1 } else { 0 };
+    match object.get(*name).and_then(Value::as_i64) {
+        Some(n) if n > ceiling as i64 => Err(Rejection::MaxTokens(format!(
+            "`{name}` is {n}, but this API key allows at most {ceiling}."
+        ))),
+        Some(n) if n >= default_below => Ok(()),
+        _ => {
+            object.insert(name.to_string(), json!(ceiling));
+            Ok(())
+        }
+    }
+}
+
+async fn apply_max_tokens(
+    request: Request,
+    path: &str,
+    ceiling: usize,
+) -> std::result::Result<Request, Rejection> {
+    let Some((field, non_positive_is_default)) = max_tokens_field(path) else {
+        return Ok(request);
+    };
+    let (mut parts, body) = request.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_BODY_BYTES).await else {
+        return Err(Rejection::MaxTokens(
+            "Request body is too large or unreadable.".to_string(),
+        ));
+    };
+    // Malformed bodies are left for the handler to report
+    let Ok(mut value) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(Request::from_parts(parts, Body::from(bytes)));
+    };
+    limit_max_tokens(&mut value, field, non_positive_is_default, ceiling)?;
+    let bytes = serde_json::to_vec(&value).unwrap_or_else(|_| bytes.to_vec());
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+/// Middleware authenticating every route except the health probes.
+pub async fn authenticate(
+    State(keys): State<Arc<ApiKeys>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().to_string();
+    if UNAUTHENTICATED_ROUTES.contains(&path.as_str()) {
+        return next.run(request).await;
+    }
+    let anthropic = path.starts_with("/v1/messages");
+    let Some(key) = request_key(request.headers()) else {
+        return Rejection::MissingKey.into_response(anthropic);
+    };
+    let Some(key) = keys.get(key).cloned() else {
+        return Rejection::InvalidKey.into_response(anthropic);
+    };
+    if !key.allows(&path) {
+        return Rejection::EndpointNotAllowed(path).into_response(anthropic);
+    }
+    let request = match key.config.max_tokens {
+        Some(ceiling) if health::is_generation_request(request.method(), &path) => {
+            match apply_max_tokens(request, &path, ceiling).await {
+                Ok(request) => request,
+                Err(rejection) => return rejection.into_response(anthropic),
+            }
+        }
+        _ => request,
+    };
+    if let Err(rejection) = key.admit(Instant::now()) {
+        crate::log_warn!(
+            "Rejected request from API key {}: {}",
+            key.config.name.as_deref().unwrap_or("<unnamed>"),
+            rejection.message()
+        );
+        return rejection.into_response(anthropic);
+    }
+    REQUEST_KEY.scope(key, next.run(request)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(mut config: Value) -> ApiKey {
+        config["key"] = json!("sk-test");
+        ApiKey {
+            config: serde_json::from_value(config).unwrap(),
+            usage: Arc::new(KeyUsage::default()),
+            owner: owner_id("sk-test"),
+        }
+    }
+
+    #[test]
+    fn limits_requests_tokens_and_concurrency() {
+        let now = Instant::now();
+        let limited = key(json!({ "requests_per_minute": 2 }));
+        assert!(limited.admit(now).is_ok());
+        assert!(limited.admit(now).is_ok());
+        assert!(matches!(
+            limited.admit(now),
+            Err(Rejection::RequestRate(2, _))
+        ));
+        assert!(limited.admit(now + RATE_WINDOW).is_ok());
+
+        let limited = key(json!({ "tokens_per_minute": 100, "max_concurrent_sequences": 1 }));
+        limited.usage.sequence_started();
+        assert!(matches!(limited.admit(now), Err(Rejection::Concurrency(1))));
+        limited.usage.sequence_finished(120);
+        assert!(matches!(
+            limited.admit(Instant::now()),
+            Err(Rejection::TokenRate(100, _))
+        ));
+
+        let restricted =
+            key(json!({ "allowed_endpoints": ["/v1/chat/completions", "/v1/files/"] }));
+        assert!(restricted.allows("/v1/chat/completions"));
+        assert!(restricted.allows("/v1/files/file-1/content"));
+        assert!(!restricted.allows("/v1/chat/completions2"));
+        assert!(!restricted.allows("/v1/embeddings"));
+
+        assert_eq!(owner_id("sk-a"), owner_id("sk-a"));
+        assert_ne!(owner_id("sk-a"), owner_id("sk-b"));
+        assert!(!owner_id("sk-a").contains("sk-a"));
+    }
+
+    #[test]
+    fn caps_max_tokens_and_shapes_errors() {
+        let mut body = json!({ "messages": [] });
+        limit_max_tokens(&mut body, &["max_tokens"], false, 512).unwrap();
+        assert_eq!(body["max_tokens"], 512);
+        assert!(limit_max_tokens(
+            &mut json!({ "max_tokens": 600 }),
+            &["max_tokens"],
+            false,
+            512
+        )
+        .is_err());
+        let mut body = json!({ "options": { "num_predict": -1 } });
+        limit_max_tokens(&mut body, &["options", "num_predict"], true, 512).unwrap();
+        assert_eq!(body["options"]["num_predict"], 512);
+
+        let capped = key(json!({ "max_tokens": 256 }));
+        let mut body = json!({ "input": "hi" });
+        capped.limit_body("/v1/embeddings", &mut body).unwrap();
+        assert!(body.get("max_tokens").is_none());
+        let mut body = json!({ "prompt": "hi" });
+        capped.limit_body("/v1/completions", &mut body).unwrap();
+        assert_eq!(body["max_tokens"], 256);
+        assert!(capped
+            .limit_body("/v1/completions", &mut json!({ "max_tokens": 300 }))
+            .is_err());
+
+        let invalid = Rejection::InvalidKey;
+        let openai = error_body(false, invalid.kinds(), &invalid.message());
+        assert_eq!(openai["error"]["code"], "invalid_api_key");
+        let limited = Rejection::TokenRate(10, Duration::ZERO);
+        let anthropic = error_body(true, limited.kinds(), &limited.message());
+        assert_eq!(anthropic["type"], "error");
+        assert_eq!(anthropic["error"]["type"], "rate_limit_error");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", HeaderValue::from_static("sk-a"));
+        assert_eq!(request_key(&headers), Some("sk-a"));
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer sk-b"),
+        );
+        assert_eq!(request_key(&headers), Some("sk-b"));
+    }
+}
@@ -0,0 +1,168 @@
+// src/server/backpressure.rs
+//! Admission control on the engine's waiting queue.
+//!
+//! The scheduler accepts every request into its waiting queue, so under overload latency
+//! grows without bound. Setting any of `--max-waiting-requests`, `--max-waiting-tokens`
+//! (waiting prompt tokens) or `--max-queue-wait-secs` (estimated wait, see
+//! `LLMEngine::queue_stats`), or their `VLLM_RS_MAX_WAITING_REQUESTS`,
+//! `VLLM_RS_MAX_WAITING_TOKENS` and `VLLM_RS_MAX_QUEUE_WAIT_SECS` environment
+//! variables, makes the server reject new generation requests with 429 and a
+//! `Retry-After` header once a limit is reached, so a load balancer can route them to
+//! another replica. `/v1/batches` jobs are not limited: the
+//! batch worker only submits work while the engine has idle capacity.
+use super::{auth, health, ServerData};
+use crate::core::engine::QueueStats;
+use crate::utils::config::EngineConfig;
+use crate::utils::metrics::metrics;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MIN_RETRY_AFTER: Duration = Duration::from_secs(1);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QueueLimits {
+    pub max_waiting_requests: Option<usize>,
+    pub max_waiting_tokens: Option<usize>,
+    pub max_queue_wait: Option<Duration>,
+}
+
+impl QueueLimits {
+    /// Limits from the engine config, falling back to the environment for unset ones.
+    pub fn new(econfig: &EngineConfig) -> Self {
+        Self {
+            max_waiting_requests: econfig
+                .max_waiting_requests
+                .or_else(crate::utils::env::max_waiting_requests),
+            max_waiting_tokens: econfig
+                .max_waiting_tokens
+                .or_else(crate::utils::env::max_waiting_tokens),
+            max_queue_wait: econfig
+                .max_queue_wait_secs
+                .map(Duration::from_secs)
+                .or_else(crate::utils::env::max_queue_wait),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Checks the queue against the limits. On rejection returns the reason and when the
+    /// client should retry: the estimated wait, or for the wait limit the time until the
+    /// estimate falls back under it.
+    pub fn check(&self, stats: &QueueStats) -> std::result::Result<(), (String, Duration)> {
+        let clamp = |retry: Duration| retry.clamp(MIN_RETRY_AFTER, MAX_RETRY_AFTER);
+        let retry = clamp(stats.estimated_wait.unwrap_or(MIN_RETRY_AFTER));
+        if let Some(limit) = self.max_waiting_requests {
+            if stats.waiting_requests >= limit {
+                return Err((
+                    format!("Server is overloaded: {limit} requests are already waiting."),
+                    retry,
+                ));
+            }
+        }
+        if let Some(limit) = self.max_waiting_tokens {
+            if stats.waiting_tokens >= limit {
+                return Err((
+                    format!(
+                        "Server is overloaded: {} prompt tokens are already waiting (limit {limit}).",
+                        stats.waiting_tokens
+                    ),
+                    retry,
+                ));
+            }
+        }
+        if let (Some(limit), Some(wait)) = (self.max_queue_wait, stats.estimated_wait) {
+            if wait > limit {
+                return Err((
+                    format!(
+                        "Server is overloaded: estimated queue wait is {}s (limit {}s).",
+                        wait.as_secs(),
+                        limit.as_secs()
+                    ),
+                    clamp(wait - limit),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Middleware rejecting new generation requests while the waiting queue is over its limits.
+pub async fn limit_queue(
+    State((data, limits)): State<(Arc<ServerData>, QueueLimits)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let path = request.uri().path();
+    if path == "/v1/batches" || !health::is_generation_request(request.method(), path) {
+        return next.run(request).await;
+    }
+    let stats = data.engine.read().queue_stats();
+    if let Err((message, retry)) = limits.check(&stats) {
+        metrics().record_rejected();
+        return auth::error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            path.starts_with("/v1/messages"),
+            ("server_error", "server_overloaded", "overloaded_error"),
+            &message,
+            Some(retry),
+        );
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_reject_with_retry_hints() {
+        let stats = QueueStats {
+            waiting_requests: 4,
+            waiting_tokens: 8000,
+            estimated_wait: Some(Duration::from_secs(30)),
+        };
+        assert!(!QueueLimits::default().is_enabled());
+        assert!(QueueLimits::default().check(&stats).is_ok());
+
+        let by_count = QueueLimits {
+            max_waiting_requests: Some(4),
+            ..Default::default()
+        };
+        assert!(by_count.is_enabled());
+        assert_eq!(
+            by_count.check(&stats).unwrap_err().1,
+            Duration::from_secs(30)
+        );
+
+        let by_tokens = QueueLimits {
+            max_waiting_tokens: Some(10000),
+            ..Default::default()
+        };
+        assert!(by_tokens.check(&stats).is_ok());
+
+        let by_wait = QueueLimits {
+            max_queue_wait: Some(Duration::from_secs(20)),
+            ..Default::default()
+        };
+        assert_eq!(
+            by_wait.check(&stats).unwrap_err().1,
+            Duration::from_secs(10)
+        );
+        let unknown_wait = QueueStats {
+            estimated_wait: None,
+            ..stats
+        };
+        assert!(by_wait.check(&unknown_wait).is_ok());
+        assert_eq!(
+            by_count.check(&unknown_wait).unwrap_err().1,
+            MIN_RETRY_AFTER
+        );
+    }
+}
@@ -0,0 +1,1119 @@
+// src/server/batches.rs
+//! OpenAI Batch API (`/v1/files`, `/v1/batches`) backed by local storage.
+//!
+//! An uploaded JSONL file of requests becomes a batch that a background worker runs
+//! through the regular endpoint handlers. Batch requests are low priority: a new one is
+//! only started while no interactive request is running, so batches fill idle capacity,
+//! and they run with `BATCH_PRIORITY` unless the request sets `priority`, so under
+//! `--scheduling-policy priority` interactive requests arriving later go first.
+//! Results are written to output and error JSONL files that can be downloaded later.
+//! With `--api-keys`, every line runs on behalf of the key that created the batch: it
+//! is subject to that key's `max_tokens` ceiling, rate and concurrency limits, and its
+//! tokens are charged to that key. Files and batches are only visible to the key that
+//! created them; other keys get 404 as if they did not exist.
+use super::auth::{self, ApiKey};
+use super::{
+    completions, server, ChatCompletionRequest, ChatResponder, EmbeddingRequest, ServerData,
+};
+use axum::body::Bytes;
+use axum::extract::{Json, Multipart, Path, Query, State};
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
+use tokio::task::{self, JoinSet};
+use uuid::Uuid;
+
+pub const SUPPORTED_ENDPOINTS: [&str; 3] =
+    ["/v1/chat/completions", "/v1/completions", "/v1/embeddings"];
+
+/// Default `priority` of batch requests, below the default 0 of interactive requests.
+pub const BATCH_PRIORITY: i32 = 100;
+
+/// How often the worker re-checks for idle capacity and cancellation.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileObject {
+    pub id: String,
+    pub object: String,
+    pub bytes: u64,
+    pub created_at: u64,
+    pub filename: String,
+    pub purpose: String,
+    /// API key that created the file (see `auth::current_owner`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FileDeleted {
+    pub id: String,
+    pub object: &'static str,
+    pub deleted: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListResponse<T> {
+    pub object: &'static str,
+    pub data: Vec<T>,
+    pub first_id: Option<String>,
+    pub last_id: Option<String>,
+    pub has_more: bool,
+}
+
+impl<T> ListResponse<T> {
+    fn new(data: Vec<T>, has_more: bool, id: impl Fn(&T) -> &str) -> Self {
+        Self {
+            object: "list",
+            first_id: data.first().map(|item| id(item).to_string()),
+            last_id: data.last().map(|item| id(item).to_string()),
+            data,
+            has_more,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl BatchStatus {
+    pub fn is_terminal(self) -> bool {
+        matches!(
+            self,
+            BatchStatus::Failed
+                | BatchStatus::Completed
+                | BatchStatus::Expired
+                | BatchStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatchError {
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    pub param: Option<String>,
+    /// 1-based line of the input file the error refers to.
+    #[serde(default)]
+    pub line: Option<usize>,
+}
+
+impl BatchError {
+    fn new(code: &str, message: impl Into<String>, line: Option<usize>) -> Self {
+        Self {
+            code: code.to_string(),
+            message: message.into(),
+            param: None,
+            line,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchErrors {
+    pub object: String,
+    pub data: Vec<BatchError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BatchObject {
+    pub id: String,
+    pub object: String,
+    pub endpoint: String,
+    pub errors: Option<BatchErrors>,
+    pub input_file_id: String,
+    pub completion_window: String,
+    pub status: BatchStatus,
+    pub output_file_id: Option<String>,
+    pub error_file_id: Option<String>,
+    pub created_at: u64,
+    pub in_progress_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub finalizing_at: Option<u64>,
+    pub completed_at: Option<u64>,
+    pub failed_at: Option<u64>,
+    pub expired_at: Option<u64>,
+    pub cancelling_at: Option<u64>,
+    pub cancelled_at: Option<u64>,
+    pub request_counts: RequestCounts,
+    #[serde(default)]
+    pub metadata: Option<Value>,
+    /// API key that created the batch (see `auth::current_owner`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl BatchObject {
+    fn fail(&mut self, errors: Vec<BatchError>) {
+        self.status = BatchStatus::Failed;
+        self.failed_at = Some(now_secs());
+        self.errors = Some(BatchErrors {
+            object: "list".to_string(),
+            data: errors,
+        });
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateBatchRequest {
+    pub input_file_id: String,
+    pub endpoint: String,
+    #[serde(default = "default_completion_window")]
+    pub completion_window: String,
+    #[serde(default)]
+    pub metadata: Option<Value>,
+}
+
+fn default_completion_window() -> String {
+    "24h".to_string()
+}
+
+/// Parses a completion window such as `24h` into seconds.
+fn completion_window_secs(window: &str) -> Option<u64> {
+    let hours = window.trim().strip_suffix('h')?.parse::<u64>().ok()?;
+    (hours > 0).then_some(hours * 3600)
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ListQuery {
+    #[serde(default)]
+    pub after: Option<String>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub purpose: Option<String>,
+}
+
+/// One line of a batch input file.
+#[derive(Deserialize, Debug, Clone)]
+pub struct BatchInputLine {
+    pub custom_id: String,
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub url: String,
+    pub body: Value,
+}
+
+fn default_method() -> String {
+    "POST".to_string()
+}
+
+/// Parses and validates a batch input file, applying the `max_tokens` ceiling of `key`
+/// to every request. Any invalid line fails the whole batch, with one error per
+/// offending line.
+pub fn parse_batch_input(
+    content: &str,
+    endpoint: &str,
+    key: Option<&ApiKey>,
+) -> std::result::Result<Vec<BatchInputLine>, Vec<BatchError>> {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut custom_ids = HashSet::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line_no = Some(idx + 1);
+        if raw.trim().is_empty() {
+            continue;
+        }
+        let mut line = match serde_json::from_str::<BatchInputLine>(raw) {
+            Ok(line) => line,
+            Err(e) => {
+                errors.push(BatchError::new(
+                    "invalid_json_line",
+                    format!("Invalid request line: {e}"),
+                    line_no,
+                ));
+                continue;
+            }
+        };
+        if !line.method.eq_ignore_ascii_case("POST") {
+            errors.push(BatchError::new(
+                "invalid_method",
+                format!("Only POST is supported, got {}", line.method),
+                line_no,
+            ));
+        } else if line.url != endpoint {
+            errors.push(BatchError::new(
+                "mismatched_url",
+                format!(
+                    "Request url {} does not match batch endpoint {endpoint}",
+                    line.url
+                ),
+                line_no,
+            ));
+        } else if !custom_ids.insert(line.custom_id.clone()) {
+            errors.push(BatchError::new(
+                "duplicate_custom_id",
+                format!("Duplicate custom_id {}", line.custom_id),
+                line_no,
+            ));
+        } else if let Some(Err(message)) = key.map(|key| key.limit_body(endpoint, &mut line.body)) {
+            errors.push(BatchError::new("max_tokens_exceeded", message, line_no));
+        } else {
+            lines.push(line);
+        }
+    }
+    if lines.is_empty() && errors.is_empty() {
+        errors.push(BatchError::new(
+            "empty_file",
+            "The input file has no requests",
+            None,
+        ));
+    }
+    if errors.is_empty() {
+        Ok(lines)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Uploaded files and batches, persisted under one directory:
+/// `files/<id>` holds file contents, `files/<id>.json` and `batches/<id>.json` metadata.
+pub struct BatchStore {
+    dir: PathBuf,
+    files: Mutex<Vec<FileObject>>,
+    batches: Mutex<Vec<BatchObject>>,
+    /// Queued batch ids with the API key that submitted them.
+    queue: Mutex<VecDeque<(String, Option<Arc<ApiKey>>)>>,
+    wake: Notify,
+}
+
+impl BatchStore {
+    /// Opens the store, reloading earlier files and batches. Batches that were still
+    /// running when the server stopped are marked failed.
+    pub fn open(dir: PathBuf) -> Self {
+        for sub in ["files", "batches"] {
+            if let Err(e) = fs::create_dir_all(dir.join(sub)) {
+                crate::log_warn!(
+                    "Unable to create batch directory {:?}: {}",
+                    dir.join(sub),
+                    e
+                );
+            }
+        }
+        let mut files: Vec<FileObject> = load_json_dir(&dir.join("files"));
+        files.sort_by_key(|file| file.created_at);
+        let mut batches: Vec<BatchObject> = load_json_dir(&dir.join("batches"));
+        batches.sort_by_key(|batch| batch.created_at);
+        let store = Self {
+            dir,
+            files: Mutex::new(files),
+            batches: Mutex::new(Vec::new()),
+            queue: Mutex::new(VecDeque::new()),
+            wake: Notify::new(),
+        };
+        for mut batch in batches {
+            if !batch.status.is_terminal() {
+                batch.fail(vec![BatchError::new(
+                    "server_restarted",
+                    "The server restarted before the batch finished",
+                    None,
+                )]);
+                store.save_batch(&batch);
+            }
+            store.batches.lock().push(batch);
+        }
+        store
+    }
+
+    /// A store that is never persisted or served, for running the endpoint handlers
+    /// outside the HTTP server.
+    pub fn detached() -> Self {
+        Self {
+            dir: PathBuf::new(),
+            files: Mutex::new(Vec::new()),
+            batches: Mutex::new(Vec::new()),
+            queue: Mutex::new(VecDeque::new()),
+            wake: Notify::new(),
+        }
+    }
+
+    fn file_path(&self, id: &str) -> PathBuf {
+        self.dir.join("files").join(id)
+    }
+
+    fn save_json(path: PathBuf, value: &impl Serialize) {
+        let written = serde_json::to_vec_pretty(value)
+            .map_err(std::io::Error::other)
+            .and_then(|bytes| fs::write(&path, bytes));
+        if let Err(e) = written {
+            crate::log_error!("Failed to write {:?}: {}", path, e);
+        }
+    }
+
+    fn save_batch(&self, batch: &BatchObject) {
+        let path = self.dir.join("batches").join(format!("{}.json", batch.id));
+        Self::save_json(path, batch);
+    }
+
+    fn register_file(&self, file: FileObject) -> FileObject {
+        Self::save_json(self.file_path(&format!("{}.json", file.id)), &file);
+        self.files.lock().push(file.clone());
+        file
+    }
+
+    pub fn create_file(
+        &self,
+        filename: &str,
+        purpose: &str,
+        content: &[u8],
+        owner: Option<String>,
+    ) -> std::io::Result<FileObject> {
+        let id = format!("file-{}", Uuid::new_v4().simple());
+        fs::write(self.file_path(&id), content)?;
+        Ok(self.register_file(FileObject {
+            id,
+            object: "file".to_string(),
+            bytes: content.len() as u64,
+            created_at: now_secs(),
+            filename: filename.to_string(),
+            purpose: purpose.to_string(),
+            owner,
+        }))
+    }
+
+    pub fn file(&self, id: &str) -> Option<FileObject> {
+        self.files.lock().iter().find(|file| file.id == id).cloned()
+    }
+
+    pub fn file_content(&self, id: &str) -> Option<Vec<u8>> {
+        self.file(id)?;
+        fs::read(self.file_path(id)).ok()
+    }
+
+    pub fn delete_file(&self, id: &str) -> bool {
+        let mut files = self.files.lock();
+        let Some(idx) = files.iter().position(|file| file.id == id) else {
+            return false;
+        };
+        files.remove(idx);
+        let _ = fs::remove_file(self.file_path(id));
+        let _ = fs::remove_file(self.file_path(&format!("{id}.json")));
+        true
+    }
+
+    pub fn batch(&self, id: &str) -> Option<BatchObject> {
+        self.batches
+            .lock()
+            .iter()
+            .find(|batch| batch.id == id)
+            .cloned()
+    }
+
+    /// Applies `update` to a batch and persists it.
+    pub fn update_batch(
+        &self,
+        id: &str,
+        update: impl FnOnce(&mut BatchObject),
+    ) -> Option<BatchObject> {
+        let batch = {
+            let mut batches = self.batches.lock();
+            let batch = batches.iter_mut().find(|batch| batch.id == id)?;
+            update(batch);
+            batch.clone()
+        };
+        self.save_batch(&batch);
+        Some(batch)
+    }
+
+    /// Stores a new batch and queues it for the worker to run on behalf of `key`.
+    pub fn submit(&self, batch: BatchObject, key: Option<Arc<ApiKey>>) -> BatchObject {
+        self.save_batch(&batch);
+        self.batches.lock().push(batch.clone());
+        self.queue.lock().push_back((batch.id.clone(), key));
+        self.wake.notify_one();
+        batch
+    }
+
+    /// Newest first, after the `after` cursor.
+    fn list<T: Clone>(
+        items: &[T],
+        query: &ListQuery,
+        id: impl Fn(&T) -> &str,
+        keep: impl Fn(&T) -> bool,
+    ) -> ListResponse<T> {
+        let limit = query.limit.unwrap_or(20).clamp(1, 10_000);
+        let mut newest_first = items.iter().rev().filter(|item| keep(item));
+        if let Some(after) = &query.after {
+            newest_first
+                .by_ref()
+                .find(|item| id(item) == after.as_str());
+        }
+        let mut data: Vec<T> = newest_first.by_ref().take(limit + 1).cloned().collect();
+        let has_more = data.len() > limit;
+        data.truncate(limit);
+        ListResponse::new(data, has_more, id)
+    }
+}
+
+fn load_json_dir<T: serde::de::DeserializeOwned>(dir: &std::path::Path) -> Vec<T> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| {
+            let parsed = fs::read(&path)
+                .ok()
+                .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+            if parsed.is_none() {
+                crate::log_warn!("Skipping unreadable batch metadata {:?}", path);
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Starts the worker that runs queued batches one after another.
+pub fn spawn_worker(data: Arc<ServerData>) {
+    task::spawn(async move {
+        loop {
+            let next = data.batch_store.queue.lock().pop_front();
+            match next {
+                Some((batch_id, key)) => run_batch(&data, &batch_id, key).await,
+                None => data.batch_store.wake.notified().await,
+            }
+        }
+    });
+}
+
+/// Batch requests start only while no interactive request is running, the
+/// scheduler has free sequence slots and the server is not draining.
+fn has_idle_capacity(data: &ServerData, batch_inflight: usize) -> bool {
+    if data.drain.is_draining() {
+        return false;
+    }
+    let active = data.engine.read().num_active_requests();
+    active <= batch_inflight && active < data.econfig.max_num_seqs
+}
+
+/// Runs one request through the handler of its endpoint, returning the HTTP status and body.
+pub(crate) async fn execute(data: Arc<ServerData>, endpoint: &str, body: Value) -> (u16, Value) {
+    let invalid =
+        |e: serde_json::Error| ChatResponder::ValidationError(format!("Invalid body: {e}"));
+    let responder = match endpoint {
+        "/v1/chat/completions" => match serde_json::from_value::<ChatCompletionRequest>(body) {
+            Ok(mut request) => {
+                request.stream = Some(false);
+                request.priority = request.priority.or(Some(BATCH_PRIORITY));
+                server::chat_completion(State(data), Json(request)).await
+            }
+            Err(e) => invalid(e),
+        },
+        "/v1/completions" => match serde_json::from_value::<completions::CompletionRequest>(body) {
+            Ok(mut request) => {
+                request.stream = Some(false);
+                request.priority = request.priority.or(Some(BATCH_PRIORITY));
+                completions::completions(State(data), Json(request)).await
+            }
+            Err(e) => invalid(e),
+        },
+        "/v1/embeddings" => match serde_json::from_value::<EmbeddingRequest>(body) {
+            Ok(request) => server::create_embeddings(State(data), Json(request)).await,
+            Err(e) => invalid(e),
+        },
+        other => ChatResponder::ValidationError(format!("Unsupported endpoint {other}")),
+    };
+    let response = responder.into_response();
+    let status = response.status().as_u16();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| json!({ "error": { "message": String::from_utf8_lossy(&bytes) } }));
+    (status, body)
+}
+
+/// Output and error JSONL files of a running batch.
+struct BatchOutput {
+    store_dir: PathBuf,
+    batch_id: String,
+    owner: Option<String>,
+    output: Option<(String, BufWriter<fs::File>)>,
+    errors: Option<(String, BufWriter<fs::File>)>,
+}
+
+impl BatchOutput {
+    fn write(&mut self, is_error: bool, line: &Value) -> std::io::Result<()> {
+        let slot = if is_error {
+            &mut self.errors
+        } else {
+            &mut self.output
+        };
+        if slot.is_none() {
+            let id = format!("file-{}", Uuid::new_v4().simple());
+            let file = fs::File::create(self.store_dir.join("files").join(&id))?;
+            *slot = Some((id, BufWriter::new(file)));
+        }
+        let (_, writer) = slot.as_mut().unwrap();
+        writeln!(writer, "{line}")
+    }
+
+    /// Registers the written files, returning `(output_file_id, error_file_id)`.
+    fn finish(self, store: &BatchStore) -> (Option<String>, Option<String>) {
+        let batch_id = self.batch_id;
+        let owner = self.owner;
+        let register = |slot: Option<(String, BufWriter<fs::File>)>, kind: &str| {
+            let (id, writer) = slot?;
+            if let Err(e) = writer.into_inner().map_err(|e| e.into_error()) {
+                crate::log_error!("Failed to flush batch {} {} file: {}", batch_id, kind, e);
+            }
+            let bytes = fs::metadata(store.file_path(&id))
+                .map(|m| m.len())
+                .unwrap_or(0);
+            let file = store.register_file(FileObject {
+                id,
+                object: "file".to_string(),
+                bytes,
+                created_at: now_secs(),
+                filename: format!("{batch_id}_{kind}.jsonl"),
+                purpose: "batch_output".to_string(),
+                owner: owner.clone(),
+            });
+            Some(file.id)
+        };
+        (
+            register(self.output, "output"),
+            register(self.errors, "error"),
+        )
+    }
+}
+
+async fn run_batch(data: &Arc<ServerData>, batch_id: &str, key: Option<Arc<ApiKey>>) {
+    let store = &data.batch_store;
+    let Some(batch) = store.batch(batch_id) else {
+        return;
+    };
+    // Cancelled while it was waiting in the queue.
+    if batch.status != BatchStatus::Validating {
+        return;
+    }
+    let content = store
+        .file_content(&batch.input_file_id)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned());
+    let parsed = match content {
+        Some(content) => parse_batch_input(&content, &batch.endpoint, key.as_deref()),
+        None => Err(vec![BatchError::new(
+            "missing_file",
+            format!("Input file {} no longer exists", batch.input_file_id),
+            None,
+        )]),
+    };
+    let lines = match parsed {
+        Ok(lines) => lines,
+        Err(errors) => {
+            crate::log_warn!(
+                "Batch {} failed validation ({} errors)",
+                batch_id,
+                errors.len()
+            );
+            store.update_batch(batch_id, |b| b.fail(errors));
+            return;
+        }
+    };
+
+    let total = lines.len();
+    let started = store.update_batch(batch_id, |b| {
+        if b.status == BatchStatus::Validating {
+            b.status = BatchStatus::InProgress;
+            b.in_progress_at = Some(now_secs());
+            b.request_counts.total = total;
+        }
+    });
+    if started.map(|b| b.status) != Some(BatchStatus::InProgress) {
+        return;
+    }
+    crate::log_info!("Batch {} started with {} requests", batch_id, total);
+
+    let max_inflight = crate::utils::env::batch_max_inflight();
+    let mut pending = lines.into_iter();
+    let mut inflight = JoinSet::new();
+    let mut output = BatchOutput {
+        store_dir: store.dir.clone(),
+        batch_id: batch_id.to_string(),
+        owner: batch.owner.clone(),
+        output: None,
+        errors: None,
+    };
+    let mut stopped = None;
+    loop {
+        if stopped.is_none() {
+            match store.batch(batch_id) {
+                Some(b) if b.status == BatchStatus::Cancelling => {
+                    stopped = Some(BatchStatus::Cancelled)
+                }
+                Some(b) if b.expires_at.is_some_and(|at| now_secs() >= at) => {
+                    stopped = Some(BatchStatus::Expired)
+                }
+                None => stopped = Some(BatchStatus::Cancelled),
+                _ => {}
+            }
+        }
+        while stopped.is_none()
+            && pending.len() > 0
+            && inflight.len() < max_inflight
+            && has_idle_capacity(data, inflight.len())
+            && key.as_ref().map_or(true, |key| key.try_admit())
+        {
+            let Some(line) = pending.next() else {
+                break;
+            };
+            let data = data.clone();
+            let endpoint = batch.endpoint.clone();
+            let key = key.clone();
+            inflight.spawn(async move {
+                let (status, body) = auth::with_key(key, execute(data, &endpoint, line.body)).await;
+                (line.custom_id, status, body)
+            });
+        }
+        if inflight.is_empty() {
+            if stopped.is_some() || pending.len() == 0 {
+                break;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+        let Ok(Some(joined)) = tokio::time::timeout(POLL_INTERVAL, inflight.join_next()).await
+        else {
+            continue;
+        };
+        let request_id = format!("batch_req_{}", Uuid::new_v4().simple());
+        let (is_error, line) = match joined {
+            Ok((custom_id, status, body)) => (
+                status != 200,
+                json!({
+                    "id": request_id,
+                    "custom_id": custom_id,
+                    "response": { "status_code": status, "request_id": request_id, "body": body },
+                    "error": null,
+                }),
+            ),
+            Err(e) => (
+                true,
+                json!({
+                    "id": request_id,
+                    "custom_id": null,
+                    "response": null,
+                    "error": { "code": "internal_error", "message": e.to_string() },
+                }),
+            ),
+        };
+        if let Err(e) = output.write(is_error, &line) {
+            crate::log_error!("Failed to write batch {} result: {}", batch_id, e);
+        }
+        store.update_batch(batch_id, |b| {
+            if is_error {
+                b.request_counts.failed += 1;
+            } else {
+                b.request_counts.completed += 1;
+            }
+        });
+    }
+
+    store.update_batch(batch_id, |b| {
+        if b.status == BatchStatus::InProgress {
+            b.status = BatchStatus::Finalizing;
+            b.finalizing_at = Some(now_secs());
+        }
+    });
+    let (output_file_id, error_file_id) = output.finish(store);
+    let finished = store.update_batch(batch_id, |b| {
+        b.output_file_id = output_file_id;
+        b.error_file_id = error_file_id;
+        let now = Some(now_secs());
+        b.status = match (b.status, stopped) {
+            (BatchStatus::Cancelling, _) | (_, Some(BatchStatus::Cancelled)) => {
+                b.cancelled_at = now;
+                BatchStatus::Cancelled
+            }
+            (_, Some(BatchStatus::Expired)) => {
+                b.expired_at = now;
+                BatchStatus::Expired
+            }
+            _ => {
+                b.completed_at = now;
+                BatchStatus::Completed
+            }
+        };
+    });
+    if let Some(b) = finished {
+        crate::log_info!(
+            "Batch {} {:?}: {} completed, {} failed of {}",
+            batch_id,
+            b.status,
+            b.request_counts.completed,
+            b.request_counts.failed,
+            b.request_counts.total
+        );
+    }
+}
+
+fn not_found(kind: &str, id: &str) -> ChatResponder {
+    ChatResponder::NotFound(format!("No {kind} found with id '{id}'"))
+}
+
+/// Whether the API key of the current request created an item owned by `owner`.
+fn is_visible(owner: &Option<String>) -> bool {
+    *owner == auth::current_owner()
+}
+
+fn visible_file(data: &ServerData, id: &str) -> std::result::Result<FileObject, ChatResponder> {
+    data.batch_store
+        .file(id)
+        .filter(|file| is_visible(&file.owner))
+        .ok_or_else(|| not_found("file", id))
+}
+
+fn visible_batch(data: &ServerData, id: &str) -> std::result::Result<BatchObject, ChatResponder> {
+    data.batch_store
+        .batch(id)
+        .filter(|batch| is_visible(&batch.owner))
+        .ok_or_else(|| not_found("batch", id))
+}
+
+/// POST /v1/files (multipart: `file`, `purpose`)
+pub async fn upload_file(
+    State(data): State<Arc<ServerData>>,
+    mut multipart: Multipart,
+) -> std::result::Result<Json<FileObject>, ChatResponder> {
+    let invalid = |e: axum::extract::multipart::MultipartError| {
+        ChatResponder::ValidationError(format!("Invalid multipart body: {e}"))
+    };
+    let mut purpose = None;
+    let mut upload: Option<(String, Bytes)> = None;
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        let name = field.name().unwrap_or_default().to_string();
+        match name.as_str() {
+            "purpose" => purpose = Some(field.text().await.map_err(invalid)?),
+            "file" => {
+                let filename = field.file_name().unwrap_or("upload.jsonl").to_string();
+                upload = Some((filename, field.bytes().await.map_err(invalid)?));
+            }
+            _ => {}
+        }
+    }
+    let Some(purpose) = purpose else {
+        return Err(ChatResponder::ValidationError("purpose is required".into()));
+    };
+    let Some((filename, content)) = upload else {
+        return Err(ChatResponder::ValidationError("file is required".into()));
+    };
+    if purpose == "batch" && std::str::from_utf8(&content).is_err() {
+        return Err(ChatResponder::ValidationError(
+            "batch input files must be UTF-8 JSONL".into(),
+        ));
+    }
+    data.batch_store
+        .create_file(&filename, &purpose, &content, auth::current_owner())
+        .map(Json)
+        .map_err(|e| ChatResponder::InternalError(format!("Failed to store file: {e}")))
+}
+
+/// GET /v1/files
+pub async fn list_files(
+    State(data): State<Arc<ServerData>>,
+    Query(query): Query<ListQuery>,
+) -> Json<ListResponse<FileObject>> {
+    let files = data.batch_store.files.lock().clone();
+    let owner = auth::current_owner();
+    Json(BatchStore::list(
+        &files,
+        &query,
+        |file| &file.id,
+        |file| file.owner == owner && query.purpose.as_ref().map_or(true, |p| *p == file.purpose),
+    ))
+}
+
+/// GET /v1/files/:file_id
+pub async fn get_file(
+    State(data): State<Arc<ServerData>>,
+    Path(file_id): Path<String>,
+) -> std::result::Result<Json<FileObject>, ChatResponder> {
+    visible_file(&data, &file_id).map(Json)
+}
+
+/// GET /v1/files/:file_id/content
+pub async fn file_content(
+    State(data): State<Arc<ServerData>>,
+    Path(file_id): Path<String>,
+) -> std::result::Result<Response, ChatResponder> {
+    visible_file(&data, &file_id)?;
+    let content = data
+        .batch_store
+        .file_content(&file_id)
+        .ok_or_else(|| not_found("file", &file_id))?;
+    Ok((
+        [(header::CONTENT_TYPE, "application/octet-stream")],
+        content,
+    )
+        .into_response())
+}
+
+/// DELETE /v1/files/:file_id
+pub async fn delete_file(
+    State(data): State<Arc<ServerData>>,
+    Path(file_id): Path<String>,
+) -> std::result::Result<Json<FileDeleted>, ChatResponder> {
+    visible_file(&data, &file_id)?;
+    if !data.batch_store.delete_file(&file_id) {
+        return Err(not_found("file", &file_id));
+    }
+    Ok(Json(FileDeleted {
+        id: file_id,
+        object: "file",
+        deleted: true,
+    }))
+}
+
+/// POST /v1/batches
+pub async fn create_batch(
+    State(data): State<Arc<ServerData>>,
+    Json(request): Json<CreateBatchRequest>,
+) -> std::result::Result<Json<BatchObject>, ChatResponder> {
+    if !SUPPORTED_ENDPOINTS.contains(&request.endpoint.as_str()) {
+        return Err(ChatResponder::ValidationError(format!(
+            "Unsupported endpoint {}, expected one of {}",
+            request.endpoint,
+            SUPPORTED_ENDPOINTS.join(", ")
+        )));
+    }
+    let Some(window) = completion_window_secs(&request.completion_window) else {
+        return Err(ChatResponder::ValidationError(format!(
+            "Invalid completion_window {}, expected hours such as 24h",
+            request.completion_window
+        )));
+    };
+    let key = auth::current_key();
+    if let Some(key) = key.as_ref().filter(|key| !key.allows(&request.endpoint)) {
+        return Err(ChatResponder::ValidationError(format!(
+            "This API key is not allowed to call {}",
+            request.endpoint
+        )));
+    }
+    let file = visible_file(&data, &request.input_file_id)?;
+    if file.purpose != "batch" {
+        return Err(ChatResponder::ValidationError(format!(
+            "File {} has purpose '{}', batches need purpose 'batch'",
+            file.id, file.purpose
+        )));
+    }
+    let created_at = now_secs();
+    let batch = BatchObject {
+        id: format!("batch_{}", Uuid::new_v4().simple()),
+        object: "batch".to_string(),
+        endpoint: request.endpoint,
+        errors: None,
+        input_file_id: request.input_file_id,
+        completion_window: request.completion_window,
+        status: BatchStatus::Validating,
+        output_file_id: None,
+        error_file_id: None,
+        created_at,
+        in_progress_at: None,
+        expires_at: Some(created_at + window),
+        finalizing_at: None,
+        completed_at: None,
+        failed_at: None,
+        expired_at: None,
+        cancelling_at: None,
+        cancelled_at: None,
+        request_counts: RequestCounts::default(),
+        metadata: request.metadata,
+        owner: auth::current_owner(),
+    };
+    crate::log_info!("Batch {} queued for {}", batch.id, batch.endpoint);
+    Ok(Json(data.batch_store.submit(batch, key)))
+}
+
+/// GET /v1/batches
+pub async fn list_batches(
+    State(data): State<Arc<ServerData>>,
+    Query(query): Query<ListQuery>,
+) -> Json<ListResponse<BatchObject>> {
+    let batches = data.batch_store.batches.lock().clone();
+    let owner = auth::current_owner();
+    Json(BatchStore::list(
+        &batches,
+        &query,
+        |batch| &batch.id,
+        |batch| batch.owner == owner,
+    ))
+}
+
+/// GET /v1/batches/:batch_id
+pub async fn get_batch(
+    State(data): State<Arc<ServerData>>,
+    Path(batch_id): Path<String>,
+) -> std::result::Result<Json<BatchObject>, ChatResponder> {
+    visible_batch(&data, &batch_id).map(Json)
+}
+
+/// POST /v1/batches/:batch_id/cancel
+///
+/// Queued batches are cancelled at once. Running ones stop starting new requests and
+/// become `cancelled` when their in-flight requests finish.
+pub async fn cancel_batch(
+    State(data): State<Arc<ServerData>>,
+    Path(batch_id): Path<String>,
+) -> std::result::Result<Json<BatchObject>, ChatResponder> {
+    visible_batch(&data, &batch_id)?;
+    let mut rejected = None;
+    let batch = data.batch_store.update_batch(&batch_id, |b| {
+        let now = Some(now_secs());
+        match b.status {
+            BatchStatus::Validating => {
+                b.status = BatchStatus::Cancelled;
+                b.cancelling_at = now;
+                b.cancelled_at = now;
+            }
+            BatchStatus::InProgress | BatchStatus::Finalizing => {
+                b.status = BatchStatus::Cancelling;
+                b.cancelling_at = now;
+            }
+            BatchStatus::Cancelling => {}
+            status => rejected = Some(status),
+        }
+    });
+    let Some(batch) = batch else {
+        return Err(not_found("batch", &batch_id));
+    };
+    if let Some(status) = rejected {
+        return Err(ChatResponder::ValidationError(format!(
+            "Cannot cancel a batch with status {status:?}"
+        )));
+    }
+    Ok(Json(batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_input_reports_every_invalid_line() {
+        let content = concat!(
+            r#"{"custom_id":"a","method":"POST","url":"/v1/chat/completions","body":{}}"#,
+            "\n\n",
+            r#"{"custom_id":"b","url":"/v1/embeddings","body":{}}"#,
+            "\n",
+            r#"{"custom_id":"a","url":"/v1/chat/completions","body":{}}"#,
+            "\n",
+            "not json\n",
+        );
+        let errors = parse_batch_input(content, "/v1/chat/completions", None).unwrap_err();
+        let codes: Vec<_> = errors.iter().map(|e| (e.code.as_str(), e.line)).collect();
+        assert_eq!(
+            codes,
+            [
+                ("mismatched_url", Some(3)),
+                ("duplicate_custom_id", Some(4)),
+                ("invalid_json_line", Some(5)),
+            ]
+        );
+
+        let lines = parse_batch_input(
+            r#"{"custom_id":"a","url":"/v1/embeddings","body":{"input":"hi"}}"#,
+            "/v1/embeddings",
+            None,
+        )
+        .unwrap();
+        assert_eq!(lines[0].body["input"], "hi");
+        assert_eq!(
+            parse_batch_input("\n", "/v1/embeddings", None).unwrap_err()[0].code,
+            "empty_file"
+        );
+    }
+
+    #[test]
+    fn store_lists_newest_first_and_fails_interrupted_batches() {
+        let dir = std::env::temp_dir().join(format!("vllm-rs-batch-test-{}", Uuid::new_v4()));
+        let store = BatchStore::open(dir.clone());
+        let first = store
+            .create_file("a.jsonl", "batch", b"{}", Some("key-a".to_string()))
+            .unwrap();
+        let second = store.create_file("b.jsonl", "batch", b"{}", None).unwrap();
+        let page = BatchStore::list(
+            &store.files.lock().clone(),
+            &ListQuery {
+                limit: Some(1),
+                ..Default::default()
+            },
+            |file| &file.id,
+            |_| true,
+        );
+        assert_eq!(page.data[0].id, second.id);
+        assert!(page.has_more);
+
+        let batch = store.submit(
+            BatchObject {
+                id: "batch_test".to_string(),
+                object: "batch".to_string(),
+                endpoint: "/v1/embeddings".to_string(),
+                errors: None,
+                input_file_id: first.id.clone(),
+                completion_window: "24h".to_string(),
+                status: BatchStatus::InProgress,
+                output_file_id: None,
+                error_file_id: None,
+                created_at: now_secs(),
+                in_progress_at: None,
+                expires_at: None,
+                finalizing_at: None,
+                completed_at: None,
+                failed_at: None,
+                expired_at: None,
+                cancelling_at: None,
+                cancelled_at: None,
+                request_counts: RequestCounts::default(),
+                metadata: None,
+                owner: None,
+            },
+            None,
+        );
+        drop(store);
+
+        let reopened = BatchStore::open(dir.clone());
+        let reloaded = reopened.file(&first.id).unwrap();
+        assert_eq!(reloaded.filename, "a.jsonl");
+        assert_eq!(reloaded.owner.as_deref(), Some("key-a"));
+        assert_eq!(
+            reopened.batch(&batch.id).unwrap().status,
+            BatchStatus::Failed
+        );
+        let _ = fs::remove_dir_all(dir);
+    }
+}
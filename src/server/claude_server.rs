@@ -1,6 +1,6 @@
 use super::{
-    build_messages_and_images, ChatMessage, ImageUrlContent, MessageContent, MessageContentType,
-    ServerData,
+    build_messages_and_images, validate_truncation_params, ChatMessage, ImageUrlContent,
+    MessageContent, MessageContentType, ServerData,
 };
 use crate::core::engine::{LLMEngine, StreamItem};
 use crate::server::logger::ChatCompletionLogger;
@@ -175,6 +175,20 @@ pub struct ClaudeMessageRequest {
     #[serde(default)]
     pub top_k: Option<i64>,
     #[serde(default)]
+    pub min_p: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub epsilon_cutoff: Option<f32>,
+    #[serde(default)]
+    pub eta_cutoff: Option<f32>,
+    /// Seed for reproducible sampling (extension, not part of the Anthropic API)
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Scheduling priority, lower is scheduled first (extension, not part of the Anthropic API)
+    #[serde(default)]
+    pub priority: Option<i32>,
+    #[serde(default)]
     pub stream: Option<bool>,
     #[serde(default)]
     pub stop_sequences: Option<Vec<String>>,
@@ -2045,6 +2059,29 @@ pub async fn messages(
     params.temperature = request.temperature;
     params.top_k = request.top_k.map(|v| v as isize);
     params.top_p = request.top_p;
+    if let Err(err) = validate_truncation_params(
+        request.min_p,
+        request.typical_p,
+        request.epsilon_cutoff,
+        request.eta_cutoff,
+    ) {
+        return ClaudeResponder::Error(
+            ClaudeErrorResponse {
+                response_type: "error",
+                error: ClaudeErrorBody {
+                    error_type: "invalid_request_error".to_string(),
+                    message: err,
+                },
+            },
+            StatusCode::UNPROCESSABLE_ENTITY,
+        );
+    }
+    params.min_p = request.min_p;
+    params.typical_p = request.typical_p;
+    params.epsilon_cutoff = request.epsilon_cutoff;
+    params.eta_cutoff = request.eta_cutoff;
+    params.seed = request.seed;
+    params.priority = request.priority;
     params.thinking = anthropic_thinking;
     if let Some(stop_sequences) = &request.stop_sequences {
         if !stop_sequences.is_empty() {
@@ -3312,6 +3349,12 @@ pub async fn count_tokens(
         temperature: None,
         top_p: None,
         top_k: None,
+        min_p: None,
+        typical_p: None,
+        epsilon_cutoff: None,
+        eta_cutoff: None,
+        seed: None,
+        priority: None,
         stream: None,
         stop_sequences: None,
         tools: request.tools.clone(),
@@ -3423,6 +3466,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: None,
@@ -3469,6 +3518,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: None,
@@ -3507,6 +3562,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: None,
@@ -3542,6 +3603,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: None,
@@ -3614,6 +3681,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: None,
@@ -3770,6 +3843,12 @@ mod tests {
             temperature: None,
             top_p: None,
             top_k: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            seed: None,
+            priority: None,
             stream: None,
             stop_sequences: None,
             tools: Some(vec![ClaudeTool {
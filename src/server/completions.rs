@@ -0,0 +1,669 @@
+// src/server/completions.rs
+//! OpenAI legacy text completions (`/v1/completions`): raw prompts without a chat template.
+//! A `suffix` turns the request into fill-in-the-middle, also served as `/infill`.
+use super::{
+    decode_logprob_token, finite_logprob, parse_logit_bias,
+    streaming::{ChatResponse, Streamer, StreamingStatus},
+    validate_logprobs_request, validate_truncation_params, ChatResponder, ServerData,
+    StreamOptions, Usage,
+};
+use crate::core::engine::{LLMEngine, StreamItem};
+use crate::core::PromptScore;
+use crate::utils::config::SamplingParams;
+use crate::utils::logits_processor::{TokenLogprobs, TopLogprob};
+use crate::utils::special_tokens::FimTemplate;
+use axum::extract::{Json, State};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+use tokio::sync::watch;
+use tokio::task;
+use uuid::Uuid;
+
+/// Prompt(s) of a completion request: text or token ids, single or batched.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum CompletionPrompt {
+    Text(String),
+    Texts(Vec<String>),
+    Tokens(Vec<u32>),
+    TokenBatch(Vec<Vec<u32>>),
+}
+
+/// A single prompt, tokenized, with the text echoed back for it.
+pub struct TokenizedPrompt {
+    pub text: String,
+    pub token_ids: Vec<u32>,
+}
+
+impl CompletionPrompt {
+    /// Tokenize text prompts (with special tokens, e.g. BOS) and decode token prompts.
+    pub fn tokenize(
+        self,
+        tokenizer: &Tokenizer,
+    ) -> std::result::Result<Vec<TokenizedPrompt>, String> {
+        let from_text = |text: String| {
+            let encoding = tokenizer
+                .encode_fast(text.as_str(), true)
+                .map_err(|e| format!("Failed to tokenize prompt: {e}"))?;
+            Ok(TokenizedPrompt {
+                token_ids: encoding.get_ids().to_vec(),
+                text,
+            })
+        };
+        let from_tokens = |token_ids: Vec<u32>| {
+            let text = tokenizer
+                .decode(&token_ids, false)
+                .map_err(|e| format!("Invalid prompt token ids: {e}"))?;
+            Ok(TokenizedPrompt { text, token_ids })
+        };
+        let prompts: std::result::Result<Vec<_>, String> = match self {
+            CompletionPrompt::Text(text) => vec![text].into_iter().map(from_text).collect(),
+            CompletionPrompt::Texts(texts) => texts.into_iter().map(from_text).collect(),
+            CompletionPrompt::Tokens(ids) => vec![ids].into_iter().map(from_tokens).collect(),
+            CompletionPrompt::TokenBatch(batch) => batch.into_iter().map(from_tokens).collect(),
+        };
+        let prompts = prompts?;
+        if prompts.is_empty() {
+            return Err("prompt cannot be empty".to_string());
+        }
+        if prompts.iter().any(|p| p.token_ids.is_empty()) {
+            return Err("prompt cannot be empty".to_string());
+        }
+        Ok(prompts)
+    }
+
+    /// Wrap text prompts (the code before the cursor) into the model's FIM prompt.
+    pub fn into_fim(
+        self,
+        template: &FimTemplate,
+        suffix: &str,
+    ) -> std::result::Result<Self, String> {
+        match self {
+            CompletionPrompt::Text(prefix) => Ok(CompletionPrompt::Text(
+                template.build_prompt(&prefix, suffix),
+            )),
+            CompletionPrompt::Texts(prefixes) => Ok(CompletionPrompt::Texts(
+                prefixes
+                    .iter()
+                    .map(|prefix| template.build_prompt(prefix, suffix))
+                    .collect(),
+            )),
+            _ => Err("suffix requires a text prompt".to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CompletionRequest {
+    pub model: Option<String>,
+    pub prompt: CompletionPrompt,
+    /// Code after the insertion point; the completion fills the gap (fill-in-the-middle)
+    #[serde(default)]
+    pub suffix: Option<String>,
+    pub max_tokens: Option<usize>,
+    pub temperature: Option<f32>,
+    pub top_k: Option<isize>,
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    /// HF-style multiplicative penalty for tokens in the prompt or output (1.0 disables)
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    #[serde(default, deserialize_with = "super::deserialize_stop_sequences")]
+    pub stop: Option<Vec<String>>,
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub stream_options: Option<StreamOptions>,
+    /// Return the prompt in front of the completion
+    #[serde(default)]
+    pub echo: Option<bool>,
+    /// Return the logprobs of the sampled tokens and this many (0-20) alternatives per token
+    #[serde(default)]
+    pub logprobs: Option<usize>,
+    /// Number of completions per prompt (non-streaming only)
+    #[serde(default)]
+    pub n: Option<usize>,
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Scheduling priority under `--scheduling-policy priority`, lower is scheduled first
+    #[serde(default)]
+    pub priority: Option<i32>,
+    /// Bias (-100 to 100) added to the logits of the given token ids, keyed by token id string
+    #[serde(default)]
+    pub logit_bias: Option<HashMap<String, f32>>,
+    #[serde(default)]
+    pub min_tokens: Option<usize>,
+    #[serde(default)]
+    pub ignore_eos: Option<bool>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CompletionResponse {
+    pub id: String,
+    pub object: &'static str,
+    pub created: u64,
+    pub model: String,
+    pub choices: Vec<CompletionChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CompletionChoice {
+    pub index: usize,
+    pub text: String,
+    pub logprobs: Option<CompletionLogprobs>,
+    pub finish_reason: Option<String>,
+}
+
+/// Legacy completions logprobs: parallel lists over the returned tokens. `text_offset` is
+/// the character offset of each token in the returned `text`.
+#[derive(Serialize, Debug, Default)]
+pub struct CompletionLogprobs {
+    pub tokens: Vec<String>,
+    pub token_logprobs: Vec<Option<f32>>,
+    pub top_logprobs: Vec<Option<HashMap<String, f32>>>,
+    pub text_offset: Vec<usize>,
+    #[serde(skip)]
+    next_offset: usize,
+}
+
+impl CompletionLogprobs {
+    fn push(
+        &mut self,
+        tokenizer: &Tokenizer,
+        token_id: u32,
+        logprob: Option<f32>,
+        top_logprobs: Option<&[TopLogprob]>,
+    ) {
+        let token = decode_logprob_token(tokenizer, token_id);
+        self.text_offset.push(self.next_offset);
+        self.next_offset += token.chars().count();
+        self.tokens.push(token);
+        self.token_logprobs.push(logprob.map(finite_logprob));
+        self.top_logprobs.push(top_logprobs.map(|top| {
+            top.iter()
+                .map(|t| {
+                    (
+                        decode_logprob_token(tokenizer, t.token_id),
+                        finite_logprob(t.logprob),
+                    )
+                })
+                .collect()
+        }));
+    }
+
+    fn push_sampled(&mut self, tokenizer: &Tokenizer, logprobs: &TokenLogprobs) {
+        self.push(
+            tokenizer,
+            logprobs.token_id,
+            Some(logprobs.logprob),
+            Some(&logprobs.top_logprobs),
+        );
+    }
+
+    /// Echoed prompt tokens; the first one has no logprob.
+    fn push_prompt(&mut self, tokenizer: &Tokenizer, token_ids: &[u32], score: &PromptScore) {
+        for (i, &token_id) in token_ids.iter().enumerate() {
+            match i.checked_sub(1).and_then(|j| score.logprobs.get(j)) {
+                Some(logprobs) => self.push_sampled(tokenizer, logprobs),
+                None => self.push(tokenizer, token_id, None, None),
+            }
+        }
+    }
+}
+
+fn build_sampling_params(
+    request: &CompletionRequest,
+    max_tokens: usize,
+) -> std::result::Result<SamplingParams, String> {
+    let mut params = SamplingParams::new_with_max_tokens(max_tokens);
+    params.temperature = request.temperature;
+    params.top_k = request.top_k;
+    params.top_p = request.top_p;
+    validate_truncation_params(request.min_p, None, None, None)?;
+    params.min_p = request.min_p;
+    params.frequency_penalty = request.frequency_penalty;
+    params.presence_penalty = request.presence_penalty;
+    if let Some(penalty) = request.repetition_penalty {
+        if penalty.is_nan() || penalty <= 0.0 {
+            return Err(format!(
+                "repetition_penalty must be positive, got {}",
+                penalty
+            ));
+        }
+    }
+    params.repetition_penalty = request.repetition_penalty;
+    params.stop_sequences = request.stop.clone();
+    if let Some(top_n) = request.logprobs {
+        validate_logprobs_request(Some(true), Some(top_n))
+            .map_err(|e| e.replace("top_logprobs", "logprobs"))?;
+        params.logprobs = Some(true);
+        params.top_logprobs = Some(top_n);
+    }
+    match request.n {
+        Some(0) => return Err("n must be at least 1".to_string()),
+        Some(n) if n > 1 && request.stream.unwrap_or(false) => {
+            return Err("n > 1 is not supported with stream=true".to_string());
+        }
+        _ => {}
+    }
+    params.n = request.n;
+    if let Some(logit_bias) = &request.logit_bias {
+        params.logit_bias = Some(parse_logit_bias(logit_bias)?);
+    }
+    params.seed = request.seed;
+    params.priority = request.priority;
+    if let Some(min_tokens) = request.min_tokens {
+        if min_tokens > max_tokens {
+            return Err(format!(
+                "min_tokens ({}) must not exceed max_tokens ({})",
+                min_tokens, max_tokens
+            ));
+        }
+    }
+    params.min_tokens = request.min_tokens;
+    params.ignore_eos = request.ignore_eos.unwrap_or(false);
+    Ok(params)
+}
+
+/// Map a llama.cpp-style `/infill` body (`input_prefix`, `input_suffix`, `n_predict`)
+/// onto a completion request; OpenAI field names are accepted as well.
+fn infill_to_completion(
+    mut body: serde_json::Value,
+) -> std::result::Result<CompletionRequest, String> {
+    let Some(fields) = body.as_object_mut() else {
+        return Err("request body must be a JSON object".to_string());
+    };
+    for (from, to) in [
+        ("input_prefix", "prompt"),
+        ("input_suffix", "suffix"),
+        ("n_predict", "max_tokens"),
+    ] {
+        if let Some(value) = fields.remove(from) {
+            fields.insert(to.to_string(), value);
+        }
+    }
+    if !fields.contains_key("prompt") {
+        return Err("input_prefix is required".to_string());
+    }
+    fields
+        .entry("suffix")
+        .or_insert_with(|| serde_json::Value::String(String::new()));
+    serde_json::from_value(body).map_err(|e| format!("Invalid infill request: {e}"))
+}
+
+fn finish_reason(decoded_length: usize, max_tokens: usize) -> String {
+    if decoded_length >= max_tokens {
+        "length".to_string()
+    } else {
+        "stop".to_string()
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "vllm-rs",
+    path = "/v1/completions",
+    request_body = CompletionRequest,
+    responses((status = 200, description = "Text completions"))
+)]
+pub async fn completions(
+    State(data): State<Arc<ServerData>>,
+    request: Json<CompletionRequest>,
+) -> ChatResponder {
+    let request = request.0;
+    let use_stream = request.stream.unwrap_or(false);
+    let echo = request.echo.unwrap_or(false);
+    let include_usage = request
+        .stream_options
+        .as_ref()
+        .map(|options| options.include_usage)
+        .unwrap_or(true);
+    let model_id = request.model.clone().unwrap_or("default".to_string());
+    let max_tokens = request
+        .max_tokens
+        .unwrap_or(data.econfig.max_tokens.unwrap_or(16384));
+    let params = match build_sampling_params(&request, max_tokens) {
+        Ok(params) => params,
+        Err(err) => return ChatResponder::ValidationError(err),
+    };
+    let (tokenizer, fim_template) = {
+        let e = data.engine.read();
+        (Arc::new(e.tokenizer.clone()), e.fim_template.clone())
+    };
+    let prompt = match (&request.suffix, fim_template) {
+        (None, _) => Ok(request.prompt.clone()),
+        (Some(_), _) if echo => Err("echo is not supported together with suffix".to_string()),
+        (Some(suffix), Some(template)) => request.prompt.clone().into_fim(&template, suffix),
+        (Some(_), None) => {
+            Err("suffix is not supported: the model has no fill-in-the-middle tokens".to_string())
+        }
+    };
+    let prompts = match prompt.and_then(|prompt| prompt.tokenize(&tokenizer)) {
+        Ok(prompts) => prompts,
+        Err(err) => return ChatResponder::ValidationError(err),
+    };
+    if use_stream && prompts.len() > 1 {
+        return ChatResponder::ValidationError("stream=true supports a single prompt".to_string());
+    }
+    let created = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    let id = "cmpl-".to_string() + &Uuid::new_v4().to_string()[..8];
+
+    // Echoed prompts with logprobs report the logprobs of the prompt tokens as well
+    let prompt_scores = match (echo, request.logprobs) {
+        (true, Some(top_n)) => {
+            let token_ids = prompts.iter().map(|p| p.token_ids.clone()).collect();
+            match data.engine.write().score_tokens(token_ids, top_n) {
+                Ok((scores, _)) => Some(scores),
+                Err(e) => {
+                    return ChatResponder::ModelError(format!("Prompt scoring failed: {e:?}"))
+                }
+            }
+        }
+        _ => None,
+    };
+    let prompt_logprobs = |index: usize, prompt: &TokenizedPrompt| {
+        let mut logprobs = CompletionLogprobs::default();
+        if let Some(scores) = &prompt_scores {
+            logprobs.push_prompt(&tokenizer, &prompt.token_ids, &scores[index]);
+        }
+        logprobs
+    };
+
+    if use_stream {
+        let prompt = &prompts[0];
+        let (seq_id, prompt_length, mut stream) = if max_tokens == 0 {
+            (0, prompt.token_ids.len(), None)
+        } else {
+            let mut e = data.engine.write();
+            match e.generate_completion_stream(&params, prompt.token_ids.clone()) {
+                Ok((seq_id, prompt_length, stream)) => (seq_id, prompt_length, Some(stream)),
+                Err(e) => {
+                    crate::log_error!("Stream generation failed: {:?}", e);
+                    return ChatResponder::ValidationError(format!(
+                        "Stream generation failed: {:?}",
+                        e
+                    ));
+                }
+            }
+        };
+        let (response_tx, client_rx) = flume::unbounded();
+        let (disconnect_tx, mut disconnect_rx) = watch::channel(false);
+        let engine = data.engine.clone();
+        let want_logprobs = request.logprobs.is_some();
+        let echoed = echo.then(|| {
+            (
+                prompt.text.clone(),
+                want_logprobs.then(|| prompt_logprobs(0, prompt)),
+            )
+        });
+        let chunk = move |text: String,
+                          logprobs: Option<CompletionLogprobs>,
+                          finish_reason: Option<String>,
+                          usage: Option<Usage>| {
+            ChatResponse::CompletionChunk(CompletionResponse {
+                id: id.clone(),
+                object: "text_completion",
+                created,
+                model: model_id.clone(),
+                choices: vec![CompletionChoice {
+                    index: 0,
+                    text,
+                    logprobs,
+                    finish_reason,
+                }],
+                usage,
+            })
+        };
+
+        task::spawn(async move {
+            let usage = |completion_tokens: usize| {
+                include_usage.then_some(Usage {
+                    prompt_tokens: prompt_length,
+                    completion_tokens,
+                    total_tokens: prompt_length + completion_tokens,
+                    completion_tokens_details: None,
+                })
+            };
+            if let Some((text, logprobs)) = echoed {
+                if response_tx
+                    .try_send(chunk(text, logprobs, None, None))
+                    .is_err()
+                {
+                    if stream.is_some() {
+                        engine.write().cancel(seq_id);
+                    }
+                    return;
+                }
+            }
+            let Some(stream) = stream.as_mut() else {
+                let _ = response_tx.try_send(chunk(
+                    String::new(),
+                    None,
+                    Some("length".to_string()),
+                    usage(0),
+                ));
+                let _ = response_tx.try_send(ChatResponse::Done);
+                return;
+            };
+            let mut pending_logprobs = Vec::new();
+            loop {
+                let item = tokio::select! {
+                    item = stream.recv() => item,
+                    res = disconnect_rx.changed() => {
+                        if res.is_err() || *disconnect_rx.borrow() {
+                            crate::log_warn!("[Seq {}] Completion client disconnected", seq_id);
+                            engine.write().cancel(seq_id);
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let Some(item) = item else {
+                    break;
+                };
+                match item {
+                    StreamItem::Logprobs(logprobs) => pending_logprobs.push(logprobs),
+                    StreamItem::Token(text, _) => {
+                        let logprobs = want_logprobs.then(|| {
+                            let mut logprobs = CompletionLogprobs::default();
+                            for l in pending_logprobs.drain(..) {
+                                logprobs.push_sampled(&tokenizer, &l);
+                            }
+                            logprobs
+                        });
+                        if response_tx
+                            .try_send(chunk(text, logprobs, None, None))
+                            .is_err()
+                        {
+                            engine.write().cancel(seq_id);
+                            break;
+                        }
+                    }
+                    StreamItem::Done((_, _, _, decoded_length, _)) => {
+                        let _ = response_tx.try_send(chunk(
+                            String::new(),
+                            None,
+                            Some(finish_reason(decoded_length, max_tokens)),
+                            usage(decoded_length),
+                        ));
+                        break;
+                    }
+                    StreamItem::Error(e) => {
+                        crate::log_error!("[Seq {}] Stream error: {}", seq_id, e);
+                        let _ = response_tx.try_send(ChatResponse::ModelError(e));
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+            let _ = response_tx.try_send(ChatResponse::Done);
+        });
+
+        return ChatResponder::Streamer(Streamer {
+            rx: client_rx,
+            status: StreamingStatus::Uninitialized,
+            disconnect_tx: Some(disconnect_tx),
+        });
+    }
+
+    let num_choices = params.n.unwrap_or(1);
+    let prompt_tokens: usize = prompts.iter().map(|p| p.token_ids.len()).sum();
+    let outputs = if max_tokens == 0 {
+        Vec::new()
+    } else {
+        let receivers = {
+            let mut e = data.engine.write();
+            let token_ids = prompts.iter().map(|p| p.token_ids.clone()).collect();
+            match e.generate_completion_sync(&params, token_ids) {
+                Ok(receivers) => receivers,
+                Err(e) => {
+                    crate::log_error!("Completion generation failed: {:?}", e);
+                    return ChatResponder::ValidationError(format!(
+                        "Completion generation failed: {:?}",
+                        e
+                    ));
+                }
+            }
+        };
+        match LLMEngine::collect_sync_results(receivers, tokenizer.clone(), None).await {
+            Ok(outputs) if outputs.len() == prompts.len() * num_choices => outputs,
+            Ok(_) => {
+                return ChatResponder::InternalError("Some completions did not finish".to_string())
+            }
+            Err(e) => {
+                crate::log_error!("Failed to collect completion results: {:?}", e);
+                return ChatResponder::InternalError(format!("Internal server error {:?}", e));
+            }
+        }
+    };
+
+    let mut choices = Vec::with_capacity(prompts.len() * num_choices);
+    let mut completion_tokens = 0;
+    for (index, prompt) in prompts.iter().enumerate() {
+        for k in 0..num_choices {
+            let output = outputs.get(index * num_choices + k);
+            let mut text = if echo {
+                prompt.text.clone()
+            } else {
+                String::new()
+            };
+            let logprobs = request.logprobs.map(|_| {
+                let mut logprobs = if echo {
+                    prompt_logprobs(index, prompt)
+                } else {
+                    CompletionLogprobs::default()
+                };
+                // Offsets of the completion tokens follow the echoed prompt text
+                logprobs.next_offset = text.chars().count();
+                for l in output.map(|o| o.logprobs.as_slice()).unwrap_or_default() {
+                    logprobs.push_sampled(&tokenizer, l);
+                }
+                logprobs
+            });
+            let decoded_length = output.map_or(0, |o| o.decoded_length);
+            if let Some(output) = output {
+                text.push_str(&output.decode_output);
+            }
+            completion_tokens += decoded_length;
+            choices.push(CompletionChoice {
+                index: choices.len(),
+                text,
+                logprobs,
+                finish_reason: Some(finish_reason(decoded_length, max_tokens)),
+            });
+        }
+    }
+
+    ChatResponder::TextCompletion(CompletionResponse {
+        id,
+        object: "text_completion",
+        created,
+        model: model_id,
+        choices,
+        usage: Some(Usage {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            completion_tokens_details: None,
+        }),
+    })
+}
+
+#[utoipa::path(
+    post,
+    tag = "vllm-rs",
+    path = "/infill",
+    request_body = CompletionRequest,
+    responses((status = 200, description = "Fill-in-the-middle completions"))
+)]
+pub async fn infill(state: State<Arc<ServerData>>, body: Json<serde_json::Value>) -> ChatResponder {
+    match infill_to_completion(body.0) {
+        Ok(request) => completions(state, Json(request)).await,
+        Err(err) => ChatResponder::ValidationError(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_forms_parse() {
+        let parse = |json: &str| serde_json::from_str::<CompletionPrompt>(json).unwrap();
+        assert!(matches!(parse(r#""hello""#), CompletionPrompt::Text(_)));
+        assert!(matches!(parse(r#"["a", "b"]"#), CompletionPrompt::Texts(v) if v.len() == 2));
+        assert!(matches!(parse("[1, 2, 3]"), CompletionPrompt::Tokens(v) if v == [1, 2, 3]));
+        assert!(matches!(parse("[[1], [2, 3]]"), CompletionPrompt::TokenBatch(v) if v.len() == 2));
+    }
+
+    #[test]
+    fn request_validation() {
+        let request = |json: &str| serde_json::from_str::<CompletionRequest>(json).unwrap();
+        let params =
+            build_sampling_params(&request(r#"{"prompt":"a","logprobs":2,"stop":"\n"}"#), 16)
+                .unwrap();
+        assert_eq!(params.top_logprobs, Some(2));
+        assert_eq!(params.stop_sequences, Some(vec!["\n".to_string()]));
+        assert!(build_sampling_params(&request(r#"{"prompt":"a","logprobs":50}"#), 16).is_err());
+        assert!(
+            build_sampling_params(&request(r#"{"prompt":"a","n":2,"stream":true}"#), 16).is_err()
+        );
+    }
+
+    #[test]
+    fn infill_maps_llama_cpp_fields() {
+        let request = infill_to_completion(serde_json::json!({
+            "input_prefix": "def add(a, b):\n    ",
+            "input_suffix": "\n\nprint(add(1, 2))",
+            "n_predict": 32,
+        }))
+        .unwrap();
+        assert!(matches!(&request.prompt, CompletionPrompt::Text(p) if p.ends_with("    ")));
+        assert_eq!(request.suffix.as_deref(), Some("\n\nprint(add(1, 2))"));
+        assert_eq!(request.max_tokens, Some(32));
+
+        let request = infill_to_completion(serde_json::json!({"prompt": "x = "})).unwrap();
+        assert_eq!(request.suffix.as_deref(), Some(""));
+        assert!(infill_to_completion(serde_json::json!({"input_suffix": "y"})).is_err());
+        assert!(CompletionPrompt::Tokens(vec![1])
+            .into_fim(
+                &FimTemplate {
+                    prefix: "<PRE>".to_string(),
+                    suffix: "<SUF>".to_string(),
+                    middle: "<MID>".to_string(),
+                    layout: crate::utils::special_tokens::FimLayout::SpacedPrefixSuffixMiddle,
+                },
+                "",
+            )
+            .is_err());
+    }
+}
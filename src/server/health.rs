@@ -0,0 +1,230 @@
+// src/server/health.rs
+//! Liveness, readiness and graceful drain.
+//!
+//! `/health` only reports that the process serves HTTP. `/ready` also requires the
+//! runner subprocesses to answer heartbeats, the PD peer to be connected (PD mode) and
+//! the server not to be draining, so load balancers stop routing to a draining replica.
+//! Draining starts with `POST /admin/drain` or SIGTERM: new generation requests are
+//! rejected with 503 while running sequences finish. After SIGTERM the server exits
+//! once the engine is idle. `/admin` routes only accept requests from localhost or
+//! with an API key that has `"admin": true`.
+use super::{auth, ChatResponder, ServerData};
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Routes that submit new work to the engine.
+const GENERATION_ROUTES: &[&str] = &[
+    "/v1/chat/completions",
+    "/v1/completions",
+    "/infill",
+    "/v1/responses",
+    "/v1/messages",
+    "/v1/embeddings",
+    "/v1/loglikelihood",
+    "/v1/batches",
+    "/api/chat",
+    "/api/generate",
+    "/api/embed",
+];
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Default)]
+pub struct DrainState {
+    draining: AtomicBool,
+}
+
+impl DrainState {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Starts draining, returns false if it was already started.
+    pub fn start(&self) -> bool {
+        !self.draining.swap(true, Ordering::SeqCst)
+    }
+}
+
+pub fn is_generation_request(method: &Method, path: &str) -> bool {
+    method == Method::POST && GENERATION_ROUTES.contains(&path)
+}
+
+/// Admin routes are open to localhost (e.g. a Kubernetes `preStop` hook) and to admin
+/// API keys.
+fn is_admin_caller(peer: Option<IpAddr>) -> bool {
+    peer.is_some_and(|ip| ip.is_loopback()) || auth::current_key_is_admin()
+}
+
+/// Middleware rejecting new generation requests while the server drains.
+pub async fn admission(
+    State(data): State<Arc<ServerData>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if data.drain.is_draining() && is_generation_request(request.method(), request.uri().path()) {
+        return ChatResponder::ServiceUnavailable(
+            "Server is draining and does not accept new requests".to_string(),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+#[utoipa::path(
+    get,
+    tag = "vllm-rs",
+    path = "/health",
+    responses((status = 200, description = "Process Alive"))
+)]
+pub async fn health() -> impl IntoResponse {
+    Json(json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    get,
+    tag = "vllm-rs",
+    path = "/ready",
+    responses(
+        (status = 200, description = "Ready To Serve"),
+        (status = 503, description = "Not Ready Or Draining")
+    )
+)]
+pub async fn ready(State(data): State<Arc<ServerData>>) -> impl IntoResponse {
+    let (runners_alive, pd_connected) = {
+        let engine = data.engine.read();
+        let pd_connected = engine.is_pd_mode().then(|| engine.is_pd_connected());
+        (engine.runners_alive(), pd_connected)
+    };
+    let draining = data.drain.is_draining();
+    let ready = runners_alive && pd_connected.unwrap_or(true) && !draining;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (
+        status,
+        Json(json!({
+            "status": if ready { "ready" } else { "not_ready" },
+            // The HTTP server only starts after the model is loaded
+            "model_loaded": true,
+            "runners_alive": runners_alive,
+            "pd_connected": pd_connected,
+            "draining": draining,
+        })),
+    )
+}
+
+#[utoipa::path(
+    post,
+    tag = "vllm-rs",
+    path = "/admin/drain",
+    responses(
+        (status = 202, description = "Draining Started"),
+        (status = 403, description = "Not called from localhost or with an admin API key")
+    )
+)]
+pub async fn drain(
+    State(data): State<Arc<ServerData>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+) -> Response {
+    if !is_admin_caller(peer.map(|ConnectInfo(addr)| addr.ip())) {
+        return auth::error_response(
+            StatusCode::FORBIDDEN,
+            false,
+            ("invalid_request_error", "admin_only", "permission_error"),
+            "Admin routes are only available from localhost or with an admin API key.",
+            None,
+        );
+    }
+    let active_requests = data.engine.read().num_active_requests();
+    if data.drain.start() {
+        crate::log_warn!(
+            "Draining: rejecting new requests, {} request(s) still running",
+            active_requests
+        );
+    }
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "draining": true, "active_requests": active_requests })),
+    )
+        .into_response()
+}
+
+/// Resolves after SIGTERM once running requests have finished, for
+/// `axum::serve(..).with_graceful_shutdown`. Exits the process if they do not finish
+/// within `VLLM_RS_DRAIN_TIMEOUT_SECS`.
+pub async fn shutdown_signal(data: Arc<ServerData>) {
+    terminate().await;
+    data.drain.start();
+    let timeout = Duration::from_secs(crate::utils::env::drain_timeout_secs());
+    crate::log_warn!(
+        "SIGTERM received, waiting up to {}s for {} running request(s) to finish",
+        timeout.as_secs(),
+        data.engine.read().num_active_requests()
+    );
+    let started = Instant::now();
+    while !data.engine.read().is_idle() {
+        if started.elapsed() >= timeout {
+            crate::log_error!(
+                "Drain timed out with {} request(s) still running, exiting",
+                data.engine.read().num_active_requests()
+            );
+            std::process::exit(1);
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    crate::log_info!("All requests finished, shutting down");
+}
+
+#[cfg(unix)]
+async fn terminate() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut sigterm) => {
+            sigterm.recv().await;
+        }
+        Err(e) => {
+            crate::log_error!("Failed to install the SIGTERM handler: {}", e);
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn terminate() {
+    std::future::pending::<()>().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drain_rejects_only_new_generation_requests() {
+        let drain = DrainState::default();
+        assert!(!drain.is_draining());
+        assert!(drain.start());
+        assert!(!drain.start());
+        assert!(drain.is_draining());
+
+        assert!(is_generation_request(&Method::POST, "/v1/chat/completions"));
+        assert!(is_generation_request(&Method::POST, "/api/generate"));
+        assert!(!is_generation_request(&Method::GET, "/v1/batches"));
+        assert!(!is_generation_request(&Method::POST, "/tokenize"));
+        assert!(!is_generation_request(&Method::POST, "/admin/drain"));
+
+        assert!(is_admin_caller(Some("127.0.0.1".parse().unwrap())));
+        assert!(is_admin_caller(Some("::1".parse().unwrap())));
+        assert!(!is_admin_caller(Some("10.0.0.2".parse().unwrap())));
+        assert!(!is_admin_caller(None));
+    }
+}
@@ -2,9 +2,17 @@
 use clap::Parser;
 use llguidance::api::TopLevelGrammar;
 use serde::{Deserialize, Serialize};
+pub mod auth;
+pub mod backpressure;
+pub mod batches;
 pub mod claude_server;
+pub mod completions;
+pub mod health;
 pub mod logger;
+pub mod offline;
+pub mod ollama;
 pub mod parser;
+pub mod responses;
 pub mod server;
 pub mod streaming;
 use crate::core::engine::LLMEngine;
@@ -20,7 +28,7 @@ use crate::utils::image::{
 };
 use crate::utils::reasoning::ReasoningEffort;
 use axum::http::{self, StatusCode};
-use axum::response::{IntoResponse, Sse};
+use axum::response::{sse::KeepAlive, IntoResponse, Sse};
 use axum::routing::{get, post};
 use axum::Json;
 use axum::Router;
@@ -61,7 +69,7 @@ where
     }))
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ChatCompletionRequest {
     pub messages: Vec<ChatMessage>,
     pub model: Option<String>,
@@ -109,6 +117,114 @@ pub struct ChatCompletionRequest {
     /// Values: "none", "low", "medium", "high"
     #[serde(default, alias = "reasoning")]
     pub reasoning_effort: Option<String>,
+    /// Return log-probabilities of the output tokens
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    /// Number of most likely tokens (0-20) to return at each position, requires `logprobs`
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+    /// Number of chat completion choices to generate for the prompt (non-streaming only)
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// Decode with beam search of this width (at most 10) instead of sampling (non-streaming only)
+    #[serde(default)]
+    pub beam_width: Option<usize>,
+    /// Exponent of the output length used to normalize beam scores (default 1.0)
+    #[serde(default)]
+    pub length_penalty: Option<f32>,
+    /// Stop beam search once `beam_width` hypotheses have finished (default false)
+    #[serde(default)]
+    pub early_stopping: Option<bool>,
+    /// Drop tokens below `min_p` times the probability of the most likely token
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    /// Locally typical sampling mass
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    /// Drop tokens below this probability
+    #[serde(default)]
+    pub epsilon_cutoff: Option<f32>,
+    /// Eta sampling cutoff
+    #[serde(default)]
+    pub eta_cutoff: Option<f32>,
+    /// HF-style multiplicative penalty for tokens in the prompt or output (1.0 disables)
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    /// Ban tokens that would repeat an n-gram of this size
+    #[serde(default)]
+    pub no_repeat_ngram_size: Option<usize>,
+    /// Bias (-100 to 100) added to the logits of the given token ids, keyed by token id string
+    #[serde(default)]
+    pub logit_bias: Option<std::collections::HashMap<String, f32>>,
+    /// Strings that must never be generated
+    #[serde(default)]
+    pub bad_words: Option<Vec<String>>,
+    /// Seed for reproducible sampling, independent of how requests are batched
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Scheduling priority under `--scheduling-policy priority`, lower is scheduled first (default 0)
+    #[serde(default)]
+    pub priority: Option<i32>,
+    /// Minimum number of tokens to generate before EOS or a stop sequence may end the output
+    #[serde(default)]
+    pub min_tokens: Option<usize>,
+    /// Mirostat mode (llama.cpp semantics), only 2 (Mirostat v2) is supported
+    #[serde(default)]
+    pub mirostat: Option<usize>,
+    /// Mirostat target surprise (default 5.0)
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    /// Mirostat learning rate (default 0.1)
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    /// DRY repetition penalty multiplier, 0 disables DRY
+    #[serde(default)]
+    pub dry_multiplier: Option<f32>,
+    /// DRY penalty base (default 1.75)
+    #[serde(default)]
+    pub dry_base: Option<f32>,
+    /// Longest repetition DRY tolerates (default 2)
+    #[serde(default)]
+    pub dry_allowed_length: Option<usize>,
+    /// Trailing tokens searched by DRY (default: whole context)
+    #[serde(default)]
+    pub dry_penalty_last_n: Option<usize>,
+    /// Strings that interrupt a DRY repetition
+    #[serde(default)]
+    pub dry_sequence_breakers: Option<Vec<String>>,
+    /// Predicted output, verified as speculative draft tokens
+    #[serde(default)]
+    pub prediction: Option<PredictionContent>,
+}
+
+/// OpenAI predicted output: `{"type": "content", "content": ...}`, text the response is
+/// expected to largely repeat (e.g. a file being edited).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct PredictionContent {
+    #[serde(rename = "type")]
+    pub prediction_type: String,
+    pub content: MessageContentType,
+}
+
+impl PredictionContent {
+    /// The predicted text, with array content joined from its text parts.
+    pub fn text(&self) -> std::result::Result<String, String> {
+        if self.prediction_type != "content" {
+            return Err(format!(
+                "prediction type must be \"content\", got {:?}",
+                self.prediction_type
+            ));
+        }
+        let text_of = |part: &MessageContent| match part {
+            MessageContent::Text { text } => Ok(text.clone()),
+            _ => Err("prediction content must only contain text parts".to_string()),
+        };
+        match &self.content {
+            MessageContentType::PureText(text) => Ok(text.clone()),
+            MessageContentType::Single(part) => text_of(part),
+            MessageContentType::Multi(parts) => parts.iter().map(text_of).collect(),
+        }
+    }
 }
 
 pub fn resolve_engine_model_id(econfig: &EngineConfig) -> Option<String> {
@@ -499,11 +615,11 @@ impl ImageUrlContent {
 #[serde(tag = "type")]
 pub enum MessageContent {
     // pure text (classic chat format)
-    #[serde(alias = "input_text", alias = "text")]
+    #[serde(alias = "input_text", alias = "output_text", alias = "text")]
     Text { text: String },
 
     // URL image: "image_url": "https://..."
-    #[serde(alias = "image_url")]
+    #[serde(alias = "image_url", alias = "input_image")]
     ImageUrl { image_url: ImageUrlContent },
 
     // Base64 format: "data:image/jpeg;base64,xxxxx"
@@ -585,6 +701,173 @@ pub struct ChatChoice {
     pub index: usize,
     pub message: ChatResponseMessage,
     pub finish_reason: Option<String>,
+    pub logprobs: Option<ChatLogprobs>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChatTopLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Option<Vec<u8>>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChatTokenLogprob {
+    pub token: String,
+    pub logprob: f32,
+    pub bytes: Option<Vec<u8>>,
+    pub top_logprobs: Vec<ChatTopLogprob>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ChatLogprobs {
+    pub content: Vec<ChatTokenLogprob>,
+}
+
+/// Validate OpenAI `logprobs`/`top_logprobs` request fields.
+pub fn validate_logprobs_request(
+    logprobs: Option<bool>,
+    top_logprobs: Option<usize>,
+) -> std::result::Result<(), String> {
+    if let Some(n) = top_logprobs {
+        if n > crate::utils::logits_processor::MAX_TOP_LOGPROBS {
+            return Err(format!(
+                "top_logprobs must be between 0 and {}, got {}",
+                crate::utils::logits_processor::MAX_TOP_LOGPROBS,
+                n
+            ));
+        }
+        if !logprobs.unwrap_or(false) {
+            return Err("top_logprobs requires logprobs to be true".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Validate the min_p / typical_p / epsilon / eta truncation parameters, all probabilities.
+pub fn validate_truncation_params(
+    min_p: Option<f32>,
+    typical_p: Option<f32>,
+    epsilon_cutoff: Option<f32>,
+    eta_cutoff: Option<f32>,
+) -> std::result::Result<(), String> {
+    for (name, value) in [
+        ("min_p", min_p),
+        ("typical_p", typical_p),
+        ("epsilon_cutoff", epsilon_cutoff),
+        ("eta_cutoff", eta_cutoff),
+    ] {
+        if let Some(v) = value {
+            if !(0.0..=1.0).contains(&v) {
+                return Err(format!("{} must be between 0 and 1, got {}", name, v));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Validate the Mirostat and DRY parameters.
+pub fn validate_mirostat_dry_params(
+    mirostat: Option<usize>,
+    mirostat_tau: Option<f32>,
+    mirostat_eta: Option<f32>,
+    dry_multiplier: Option<f32>,
+    dry_base: Option<f32>,
+) -> std::result::Result<(), String> {
+    if let Some(mode) = mirostat {
+        if mode != 0 && mode != 2 {
+            return Err(format!(
+                "mirostat must be 0 (disabled) or 2 (Mirostat v2), got {}",
+                mode
+            ));
+        }
+    }
+    if let Some(tau) = mirostat_tau {
+        if tau.is_nan() || tau <= 0.0 {
+            return Err(format!("mirostat_tau must be positive, got {}", tau));
+        }
+    }
+    if let Some(eta) = mirostat_eta {
+        if eta.is_nan() || eta <= 0.0 || eta > 1.0 {
+            return Err(format!("mirostat_eta must be in (0, 1], got {}", eta));
+        }
+    }
+    if let Some(multiplier) = dry_multiplier {
+        if multiplier.is_nan() || multiplier < 0.0 {
+            return Err(format!(
+                "dry_multiplier must not be negative, got {}",
+                multiplier
+            ));
+        }
+    }
+    if let Some(base) = dry_base {
+        if base.is_nan() || base < 1.0 {
+            return Err(format!("dry_base must be at least 1, got {}", base));
+        }
+    }
+    Ok(())
+}
+
+/// Parse OpenAI `logit_bias` (token id strings to a bias between -100 and 100).
+pub fn parse_logit_bias(
+    logit_bias: &std::collections::HashMap<String, f32>,
+) -> std::result::Result<std::collections::HashMap<u32, f32>, String> {
+    logit_bias
+        .iter()
+        .map(|(token, bias)| {
+            let token_id = token
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| format!("logit_bias keys must be token ids, got {:?}", token))?;
+            if !(-100.0..=100.0).contains(bias) {
+                return Err(format!(
+                    "logit_bias values must be between -100 and 100, got {}",
+                    bias
+                ));
+            }
+            Ok((token_id, *bias))
+        })
+        .collect()
+}
+
+// Non-finite logprobs (masked tokens) are not representable in JSON
+fn finite_logprob(logprob: f32) -> f32 {
+    if logprob.is_finite() {
+        logprob
+    } else {
+        -9999.0
+    }
+}
+
+fn decode_logprob_token(tokenizer: &tokenizers::Tokenizer, token_id: u32) -> String {
+    tokenizer
+        .decode(&[token_id], false)
+        .unwrap_or_else(|_| String::new())
+}
+
+/// Convert engine token logprobs into the OpenAI chat `logprobs.content` entry.
+pub fn build_chat_token_logprob(
+    tokenizer: &tokenizers::Tokenizer,
+    logprobs: &crate::utils::logits_processor::TokenLogprobs,
+) -> ChatTokenLogprob {
+    let token = decode_logprob_token(tokenizer, logprobs.token_id);
+    ChatTokenLogprob {
+        bytes: Some(token.as_bytes().to_vec()),
+        token,
+        logprob: finite_logprob(logprobs.logprob),
+        top_logprobs: logprobs
+            .top_logprobs
+            .iter()
+            .map(|top| {
+                let token = decode_logprob_token(tokenizer, top.token_id);
+                ChatTopLogprob {
+                    bytes: Some(token.as_bytes().to_vec()),
+                    token,
+                    logprob: finite_logprob(top.logprob),
+                }
+            })
+            .collect(),
+    }
 }
 
 /// Public tool call structure with correct serialization fields
@@ -615,6 +898,15 @@ pub struct Usage {
     pub prompt_tokens: usize,
     pub completion_tokens: usize,
     pub total_tokens: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+/// Breakdown of the completion tokens, reported for requests with a predicted output.
+#[derive(Serialize, Debug, Default)]
+pub struct CompletionTokensDetails {
+    pub accepted_prediction_tokens: usize,
+    pub rejected_prediction_tokens: usize,
 }
 
 #[derive(Serialize, Debug)]
@@ -638,6 +930,8 @@ pub struct ChatChoiceChunk {
     pub delta: Delta,
     pub finish_reason: Option<String>,
     pub error: Option<Vec<ErrorMsg>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<ChatLogprobs>,
 }
 
 #[derive(Serialize, Debug)]
@@ -706,6 +1000,38 @@ pub struct EmbeddingRequest {
     pub embedding_type: EmbeddingStrategy,
 }
 
+// === Loglikelihood API ===
+
+/// Request body for /v1/loglikelihood: score prompt tokens without generating.
+#[derive(Deserialize)]
+pub struct LoglikelihoodRequest {
+    pub model: Option<String>,
+    pub input: EmbeddingInput,
+    /// Number of most likely alternatives to return per prompt token (default: 0)
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct LoglikelihoodData {
+    pub object: &'static str,
+    pub index: usize,
+    /// Prompt tokens scored (every token after the first)
+    pub num_tokens: usize,
+    pub total_logprob: f64,
+    pub perplexity: f64,
+    /// Logprob of each prompt token given the tokens before it
+    pub tokens: Vec<ChatTokenLogprob>,
+}
+
+#[derive(Serialize)]
+pub struct LoglikelihoodResponse {
+    pub object: &'static str,
+    pub data: Vec<LoglikelihoodData>,
+    pub model: String,
+    pub usage: EmbeddingUsage,
+}
+
 // === Tokenize API ===
 
 /// Input for tokenize request - either plain text or chat messages
@@ -783,6 +1109,9 @@ pub struct ServerData {
     pub engine: Arc<RwLock<LLMEngine>>,
     pub econfig: EngineConfig,
     pub mcp_manager: Option<Arc<crate::mcp::McpClientManager>>,
+    pub response_store: responses::ResponseStore,
+    pub batch_store: batches::BatchStore,
+    pub drain: health::DrainState,
 }
 
 trait ErrorToResponse: Serialize {
@@ -806,24 +1135,44 @@ impl JsonError {
 impl ErrorToResponse for JsonError {}
 
 pub enum ChatResponder {
-    Streamer(Sse<Streamer>),
+    Streamer(Streamer),
     Completion(ChatCompletionResponse),
+    TextCompletion(completions::CompletionResponse),
+    Response(responses::ResponseObject),
+    ResponseDeleted(responses::ResponseDeleted),
     Usage(UsageResponse),
     Embedding(EmbeddingResponse),
+    Loglikelihood(LoglikelihoodResponse),
     Tokenize(TokenizeResponse),
     Detokenize(DetokenizeResponse),
     ModelError(String),
     InternalError(String),
     ValidationError(String),
+    NotFound(String),
+    ServiceUnavailable(String),
 }
 
 impl IntoResponse for ChatResponder {
     fn into_response(self) -> axum::response::Response {
         match self {
-            ChatResponder::Streamer(s) => s.into_response(),
+            ChatResponder::Streamer(s) => Sse::new(s)
+                .keep_alive(
+                    KeepAlive::new()
+                        .interval(std::time::Duration::from_millis(
+                            std::env::var("KEEP_ALIVE_INTERVAL")
+                                .map(|val| val.parse::<u64>().unwrap_or(100))
+                                .unwrap_or(100),
+                        ))
+                        .text("keep-alive-text"),
+                )
+                .into_response(),
             ChatResponder::Completion(s) => Json(s).into_response(),
+            ChatResponder::TextCompletion(s) => Json(s).into_response(),
+            ChatResponder::Response(s) => Json(s).into_response(),
+            ChatResponder::ResponseDeleted(s) => Json(s).into_response(),
             ChatResponder::Usage(s) => Json(s).into_response(),
             ChatResponder::Embedding(s) => Json(s).into_response(),
+            ChatResponder::Loglikelihood(s) => Json(s).into_response(),
             ChatResponder::Tokenize(s) => Json(s).into_response(),
             ChatResponder::Detokenize(s) => Json(s).into_response(),
             ChatResponder::InternalError(e) => {
@@ -835,6 +1184,12 @@ impl IntoResponse for ChatResponder {
             ChatResponder::ModelError(msg) => {
                 JsonError::new(msg).to_response(http::StatusCode::INTERNAL_SERVER_ERROR)
             }
+            ChatResponder::NotFound(msg) => {
+                JsonError::new(msg).to_response(http::StatusCode::NOT_FOUND)
+            }
+            ChatResponder::ServiceUnavailable(msg) => {
+                JsonError::new(msg).to_response(http::StatusCode::SERVICE_UNAVAILABLE)
+            }
         }
     }
 }
@@ -903,6 +1258,21 @@ pub struct Args {
     #[arg(long, default_value = None)]
     pub batch: Option<usize>,
 
+    /// Run chat completion requests from a JSONL file (`-` for stdin) offline,
+    /// without the HTTP server
+    #[arg(long, default_value = None)]
+    pub batch_input: Option<String>,
+
+    /// JSONL file for `--batch-input` results (stdout if not set). Requests already
+    /// in the file are skipped, so an interrupted run can be resumed
+    #[arg(long, default_value = None)]
+    pub batch_output: Option<String>,
+
+    /// Compute perplexity over a text file, or a JSONL file with one
+    /// `{"text": ...}` object (or JSON string) per line, instead of generating
+    #[arg(long, default_value = None)]
+    pub perplexity: Option<String>,
+
     #[arg(long, default_value = None)]
     pub temperature: Option<f32>,
 
@@ -975,6 +1345,26 @@ pub struct Args {
     #[arg(long, value_delimiter = ',', default_value = None)]
     pub mcp_args: Option<Vec<String>>,
 
+    /// API keys file (JSON) with per-key rate limits; all routes except /health and /ready then require a key
+    #[arg(long, default_value = None)]
+    pub api_keys: Option<String>,
+
+    /// Scheduling policy: fcfs (default), priority (lower `priority` first, preempting lower priorities), shortest-prompt or fair-share (round robin over `session_id`)
+    #[arg(long, default_value = None)]
+    pub scheduling_policy: Option<String>,
+
+    /// Reject new generation requests with 429 once this many requests wait for scheduling
+    #[arg(long, default_value = None)]
+    pub max_waiting_requests: Option<usize>,
+
+    /// Reject new generation requests with 429 once this many prompt tokens wait for scheduling
+    #[arg(long, default_value = None)]
+    pub max_waiting_tokens: Option<usize>,
+
+    /// Reject new generation requests with 429 once the estimated queue wait exceeds this
+    #[arg(long, default_value = None)]
+    pub max_queue_wait_secs: Option<u64>,
+
     /// YARN RoPE scaling factor (explicit override, no auto-calculation)
     #[arg(long, default_value = None)]
     pub yarn_scaling_factor: Option<f64>,
@@ -983,6 +1373,28 @@ pub struct Args {
     /// `thinking` / `enable_thinking`.
     #[arg(long, default_value_t = false)]
     pub disable_reasoning: bool,
+
+    /// Number of draft tokens verified per decode step (enables speculative decoding)
+    #[arg(long, default_value = None)]
+    pub num_speculative_tokens: Option<usize>,
+
+    /// Longest n-gram matched by prompt-lookup speculation
+    #[arg(long, default_value_t = 4)]
+    pub prompt_lookup_max: usize,
+
+    /// Shortest n-gram matched by prompt-lookup speculation
+    #[arg(long, default_value_t = 1)]
+    pub prompt_lookup_min: usize,
+
+    /// Model id or local path of a smaller draft model proposing the speculative tokens
+    /// (same tokenizer as the target; 4 tokens per step unless `--num-speculative-tokens`)
+    #[arg(long, default_value = None)]
+    pub draft_model: Option<String>,
+
+    /// Draft with the multi-token prediction layers of the checkpoint (DeepSeek-V3, GLM-4.5
+    /// or Qwen3.5; 1 token per step unless `--num-speculative-tokens`)
+    #[arg(long, default_value_t = false)]
+    pub mtp: bool,
 }
 
 /// Result of executing tool calls via MCP
@@ -1391,14 +1803,40 @@ pub async fn run_server(
         engine,
         econfig,
         mcp_manager,
+        response_store: responses::ResponseStore::new(crate::utils::env::responses_store_capacity()),
+        batch_store: batches::BatchStore::open(crate::utils::env::batch_dir()),
+        drain: health::DrainState::default(),
     };
+    let server_data = Arc::new(server_data);
+    batches::spawn_worker(server_data.clone());
+
+    let api_keys = match &econfig.api_keys {
+        Some(path) => {
+            let keys = auth::ApiKeys::from_file(path)?;
+            crate::log_info!("API key authentication enabled ({} keys)", keys.num_keys());
+            Some(Arc::new(keys))
+        }
+        None => {
+            if !is_pd_server {
+                crate::log_warn!(
+                    "No --api-keys file given, the API is open to anyone who can reach this port"
+                );
+            }
+            None
+        }
+    };
+
+    let queue_limits = backpressure::QueueLimits::new(&server_data.econfig);
+    if queue_limits.is_enabled() {
+        crate::log_info!("Queue admission limits: {:?}", queue_limits);
+    }
 
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
         .allow_headers(Any);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route(
             "/v1/models",
             get(move || async move {
@@ -1427,18 +1865,68 @@ pub async fn run_server(
             }),
         )
         .route("/v1/chat/completions", post(server::chat_completion))
+        .route("/v1/completions", post(completions::completions))
+        .route("/infill", post(completions::infill))
+        .route("/v1/responses", post(responses::create_response))
+        .route(
+            "/v1/responses/:response_id",
+            get(responses::get_response).delete(responses::delete_response),
+        )
         .route("/v1/messages", post(claude_server::messages))
         .route(
             "/v1/messages/count_tokens",
             post(claude_server::count_tokens),
         )
         .route("/v1/embeddings", post(server::create_embeddings))
+        .route("/v1/loglikelihood", post(server::loglikelihood))
         .route("/v1/usage", get(server::get_usage))
+        .route("/metrics", get(server::metrics))
+        .route("/health", get(health::health))
+        .route("/ready", get(health::ready))
+        .route("/admin/drain", post(health::drain))
         .route("/tokenize", post(server::tokenize))
         .route("/detokenize", post(server::detokenize))
+        .route(
+            "/v1/files",
+            post(batches::upload_file).get(batches::list_files),
+        )
+        .route(
+            "/v1/files/:file_id",
+            get(batches::get_file).delete(batches::delete_file),
+        )
+        .route("/v1/files/:file_id/content", get(batches::file_content))
+        .route(
+            "/v1/batches",
+            post(batches::create_batch).get(batches::list_batches),
+        )
+        .route("/v1/batches/:batch_id", get(batches::get_batch))
+        .route("/v1/batches/:batch_id/cancel", post(batches::cancel_batch))
+        .route("/api/chat", post(ollama::chat))
+        .route("/api/generate", post(ollama::generate))
+        .route("/api/embed", post(ollama::embed))
+        .route("/api/tags", get(ollama::tags))
+        .route("/api/show", post(ollama::show))
+        .route("/api/version", get(ollama::version));
+    if queue_limits.is_enabled() {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            (server_data.clone(), queue_limits),
+            backpressure::limit_queue,
+        ));
+    }
+    app = app.layer(axum::middleware::from_fn_with_state(
+        server_data.clone(),
+        health::admission,
+    ));
+    if let Some(keys) = api_keys {
+        app = app.layer(axum::middleware::from_fn_with_state(
+            keys,
+            auth::authenticate,
+        ));
+    }
+    let app = app
         .layer(DefaultBodyLimit::max(100 * 1024 * 1024)) // 100MB body size limit
         .layer(cors)
-        .with_state(Arc::new(server_data));
+        .with_state(server_data.clone());
 
     let addr = if is_pd_server {
         crate::log_warn!("🚀 PD server started, waiting for prefill request(s)...",);
@@ -1459,16 +1947,32 @@ pub async fn run_server(
             format!("📡 Supported endpoints (OpenAI/Claude):").yellow()
         );
         println!("{}", format!("   - POST /v1/chat/completions").yellow());
+        println!("{}", format!("   - POST /v1/completions").yellow());
+        println!("{}", format!("   - POST /infill").yellow());
+        println!("{}", format!("   - POST /v1/responses").yellow());
+        println!("{}", format!("   - POST /v1/files, /v1/batches").yellow());
         println!("{}", format!("   - POST /v1/messages").yellow());
         println!(
             "{}",
             format!("   - POST /v1/messages/count_tokens").yellow()
         );
         println!("{}", format!("   - POST /v1/embeddings").yellow());
+        println!("{}", format!("   - POST /v1/loglikelihood").yellow());
         println!("{}", format!("   - GET  /v1/models").yellow());
         println!("{}", format!("   - GET  /v1/usage").yellow());
+        println!("{}", format!("   - GET  /metrics").yellow());
+        println!("{}", format!("   - GET  /health, /ready").yellow());
+        println!("{}", format!("   - POST /admin/drain").yellow());
         println!("{}", format!("   - POST /tokenize").yellow());
         println!("{}", format!("   - POST /detokenize").yellow());
+        println!(
+            "{}",
+            format!("   - POST /api/chat, /api/generate, /api/embed, /api/show (Ollama)").yellow()
+        );
+        println!(
+            "{}",
+            format!("   - GET  /api/tags, /api/version (Ollama)").yellow()
+        );
         println!("");
         println!(
             "🛑 {}",
@@ -1481,24 +1985,26 @@ pub async fn run_server(
     };
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    let mut tasks = Vec::new();
-    tasks.push(tokio::spawn(async move {
-        if let Err(e) = axum::serve(listener, app).await {
+    let api_server = tokio::spawn(async move {
+        let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+        if let Err(e) = axum::serve(listener, app)
+            .with_graceful_shutdown(health::shutdown_signal(server_data))
+            .await
+        {
             eprintln!("API server error: {e:?}");
         }
-    }));
+    });
 
     if with_ui_server {
-        tasks.push(tokio::spawn(async move {
+        tokio::spawn(async move {
             start_ui_server((port + 1) as u16, Some(port as u16), None, None)
                 .await
                 .unwrap();
-        }));
+        });
     }
 
-    futures::future::try_join_all(tasks)
-        .await
-        .map_err(candle_core::Error::wrap)?;
+    // The API server returns after a graceful drain; the UI server is dropped with the process
+    api_server.await.map_err(candle_core::Error::wrap)?;
 
     Ok(())
 }
@@ -1665,6 +2171,73 @@ mod tests {
         assert_eq!(request.stop, Some(vec!["END".to_string()]));
     }
 
+    #[test]
+    fn test_chat_completion_n_parsing() {
+        let json =
+            r#"{"messages":[{"role":"user","content":"hi"}],"n":3,"seed":1234,"min_tokens":16}"#;
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.n, Some(3));
+        assert_eq!(request.seed, Some(1234));
+        assert_eq!(request.min_tokens, Some(16));
+    }
+
+    #[test]
+    fn test_chat_completion_prediction_parsing() {
+        let json = r#"{"messages":[{"role":"user","content":"hi"}],
+            "prediction":{"type":"content","content":[{"type":"text","text":"fn main"},{"type":"text","text":"() {}"}]}}"#;
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.prediction.unwrap().text().unwrap(), "fn main() {}");
+
+        let json = r#"{"type":"content","content":"hello"}"#;
+        let prediction: PredictionContent = serde_json::from_str(json).unwrap();
+        assert_eq!(prediction.text().unwrap(), "hello");
+        let json = r#"{"type":"diff","content":"hello"}"#;
+        let prediction: PredictionContent = serde_json::from_str(json).unwrap();
+        assert!(prediction.text().is_err());
+    }
+
+    #[test]
+    fn test_validate_truncation_params() {
+        assert!(validate_truncation_params(Some(0.05), Some(0.9), None, Some(3e-4)).is_ok());
+        assert!(validate_truncation_params(Some(1.5), None, None, None).is_err());
+        assert!(validate_truncation_params(None, None, Some(-0.1), None).is_err());
+    }
+
+    #[test]
+    fn test_validate_mirostat_dry_params() {
+        assert!(
+            validate_mirostat_dry_params(Some(2), Some(5.0), Some(0.1), Some(0.8), None).is_ok()
+        );
+        assert!(validate_mirostat_dry_params(Some(1), None, None, None, None).is_err());
+        assert!(validate_mirostat_dry_params(None, None, Some(0.0), None, None).is_err());
+        assert!(validate_mirostat_dry_params(None, None, None, Some(-1.0), None).is_err());
+        assert!(validate_mirostat_dry_params(None, None, None, None, Some(0.5)).is_err());
+    }
+
+    #[test]
+    fn test_parse_logit_bias() {
+        let json = r#"{"messages":[{"role":"user","content":"hi"}],"logit_bias":{"50256":-100,"7":2.5},"bad_words":["foo bar"]}"#;
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        let bias = parse_logit_bias(request.logit_bias.as_ref().unwrap()).unwrap();
+        assert_eq!(bias.get(&50256), Some(&-100.0));
+        assert_eq!(bias.get(&7), Some(&2.5));
+        assert_eq!(request.bad_words, Some(vec!["foo bar".to_string()]));
+
+        let invalid = [("x".to_string(), 1.0)].into_iter().collect();
+        assert!(parse_logit_bias(&invalid).is_err());
+        let out_of_range = [("1".to_string(), 150.0)].into_iter().collect();
+        assert!(parse_logit_bias(&out_of_range).is_err());
+    }
+
+    #[test]
+    fn test_chat_completion_beam_search_parsing() {
+        let json = r#"{"messages":[{"role":"user","content":"hi"}],"beam_width":4,"length_penalty":0.6,"early_stopping":true}"#;
+        let request: ChatCompletionRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.beam_width, Some(4));
+        assert_eq!(request.length_penalty, Some(0.6));
+        assert_eq!(request.early_stopping, Some(true));
+    }
+
     #[test]
     fn test_chat_completion_stream_options_parsing() {
         let json = r#"{
@@ -1968,4 +2541,46 @@ mod tests {
         assert_eq!(params.thinking, Some(false));
         assert_eq!(params.reasoning_effort, None);
     }
+
+    #[test]
+    fn test_validate_logprobs_request() {
+        assert!(validate_logprobs_request(None, None).is_ok());
+        assert!(validate_logprobs_request(Some(true), Some(5)).is_ok());
+        assert!(validate_logprobs_request(Some(false), Some(5)).is_err());
+        assert!(validate_logprobs_request(Some(true), Some(21)).is_err());
+    }
+
+    #[test]
+    fn test_chat_logprobs_serialize_openai_shape() {
+        let chunk = ChatChoiceChunk {
+            index: 0,
+            delta: Delta {
+                role: None,
+                content: Some("Hi".to_string()),
+                reasoning_content: None,
+                tool_calls: None,
+            },
+            finish_reason: None,
+            error: None,
+            logprobs: Some(ChatLogprobs {
+                content: vec![ChatTokenLogprob {
+                    token: "Hi".to_string(),
+                    logprob: -0.5,
+                    bytes: Some(vec![72, 105]),
+                    top_logprobs: vec![ChatTopLogprob {
+                        token: "Hi".to_string(),
+                        logprob: -0.5,
+                        bytes: Some(vec![72, 105]),
+                    }],
+                }],
+            }),
+        };
+        let value = serde_json::to_value(&chunk).unwrap();
+        assert_eq!(value["logprobs"]["content"][0]["token"], "Hi");
+        assert_eq!(value["logprobs"]["content"][0]["bytes"][1], 105);
+        assert_eq!(
+            value["logprobs"]["content"][0]["top_logprobs"][0]["logprob"],
+            -0.5
+        );
+    }
 }
@@ -0,0 +1,269 @@
+// src/server/offline.rs
+//! Offline batch inference: runs a JSONL file of chat completion requests through the
+//! chat handler without starting the HTTP server, writing one JSONL result per request.
+//! Requests are submitted concurrently (up to `max_num_seqs`), so the engine batches
+//! them continuously.
+//!
+//! Requests whose results are already in the output file are skipped, so an interrupted
+//! run is resumed by running the same command again.
+use super::batches::{self, BatchStore};
+use super::health::DrainState;
+use super::responses::ResponseStore;
+use super::ServerData;
+use crate::core::engine::LLMEngine;
+use candle_core::Result;
+use parking_lot::RwLock;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+/// One request of the input file.
+#[derive(Debug)]
+pub struct OfflineRequest {
+    pub custom_id: String,
+    /// 1-based line in the input file.
+    pub line: usize,
+    pub body: Value,
+}
+
+/// Parses the input. Each line is a chat completion request body, or an object with
+/// `custom_id` and `body` as in `/v1/batches` input files. Requests without a
+/// `custom_id` are identified by their line number.
+pub fn parse_requests(content: &str) -> Result<Vec<OfflineRequest>> {
+    let mut requests = Vec::new();
+    let mut seen = HashSet::new();
+    for (idx, raw) in content.lines().enumerate() {
+        let line = idx + 1;
+        if raw.trim().is_empty() {
+            continue;
+        }
+        let value: Value = serde_json::from_str(raw)
+            .map_err(|e| candle_core::Error::msg(format!("line {line}: invalid JSON: {e}")))?;
+        let Value::Object(mut object) = value else {
+            candle_core::bail!("line {line}: expected a JSON object");
+        };
+        let custom_id = match object.remove("custom_id") {
+            Some(Value::String(id)) => id,
+            Some(other) => other.to_string(),
+            None => format!("line-{line}"),
+        };
+        if !seen.insert(custom_id.clone()) {
+            candle_core::bail!("line {line}: duplicate custom_id {custom_id}");
+        }
+        let body = object.remove("body").unwrap_or(Value::Object(object));
+        requests.push(OfflineRequest {
+            custom_id,
+            line,
+            body,
+        });
+    }
+    Ok(requests)
+}
+
+/// Ids of the requests already written to `path`. A trailing partial line left by an
+/// interrupted write is cut from the file.
+pub fn completed_ids(path: &Path) -> Result<HashSet<String>> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(HashSet::new());
+    };
+    let complete = content.rfind('\n').map_or(0, |idx| idx + 1);
+    if complete < content.len() {
+        crate::log_warn!("Dropping an incomplete last line from {:?}", path);
+        OpenOptions::new()
+            .write(true)
+            .open(path)?
+            .set_len(complete as u64)?;
+    }
+    Ok(content[..complete]
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter_map(|record| Some(record.get("custom_id")?.as_str()?.to_string()))
+        .collect())
+}
+
+fn result_line(custom_id: &str, line: usize, status: u16, body: Value, elapsed: Duration) -> Value {
+    if status != 200 {
+        let error = body.get("error").cloned().unwrap_or(body);
+        return json!({
+            "custom_id": custom_id,
+            "line": line,
+            "status_code": status,
+            "error": error,
+        });
+    }
+    let usage = body.get("usage").cloned().unwrap_or(Value::Null);
+    let completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+    json!({
+        "custom_id": custom_id,
+        "line": line,
+        "status_code": status,
+        "response": body,
+        "usage": usage,
+        "timing": {
+            "latency_ms": elapsed.as_millis() as u64,
+            "completion_tokens_per_s": completion_tokens as f64 / elapsed.as_secs_f64().max(1e-6),
+        },
+    })
+}
+
+/// Runs every request of `input` (a file path, or `-` for stdin) and writes results to
+/// `output` (stdout when `None`; resuming needs an output file).
+pub async fn run(engine: Arc<RwLock<LLMEngine>>, input: &str, output: Option<&str>) -> Result<()> {
+    let content = if input == "-" {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content)?;
+        content
+    } else {
+        fs::read_to_string(input)?
+    };
+    let requests = parse_requests(&content)?;
+    let total = requests.len();
+
+    let (mut writer, done): (Box<dyn Write + Send>, HashSet<String>) = match output {
+        Some(path) => {
+            let done = completed_ids(Path::new(path))?;
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            (Box::new(BufWriter::new(file)), done)
+        }
+        None => (Box::new(std::io::stdout()), HashSet::new()),
+    };
+    let pending: Vec<OfflineRequest> = requests
+        .into_iter()
+        .filter(|request| !done.contains(&request.custom_id))
+        .collect();
+    if total > pending.len() {
+        crate::log_info!(
+            "Resuming: {} of {} requests already have results",
+            total - pending.len(),
+            total
+        );
+    }
+
+    let econfig = engine.read().econfig.clone();
+    let max_inflight = econfig.max_num_seqs.max(1);
+    if max_inflight == 1 && pending.len() > 1 {
+        crate::log_warn!("max_num_seqs is 1, requests will run one at a time");
+    }
+    let data = Arc::new(ServerData {
+        engine,
+        econfig,
+        mcp_manager: None,
+        response_store: ResponseStore::new(0),
+        batch_store: BatchStore::detached(),
+        drain: DrainState::default(),
+    });
+
+    let started = Instant::now();
+    let remaining = pending.len();
+    let mut pending = pending.into_iter();
+    let mut inflight = JoinSet::new();
+    let (mut succeeded, mut failed) = (0usize, 0usize);
+    let (mut prompt_tokens, mut completion_tokens) = (0u64, 0u64);
+    loop {
+        while inflight.len() < max_inflight {
+            let Some(request) = pending.next() else {
+                break;
+            };
+            let data = data.clone();
+            inflight.spawn(async move {
+                let start = Instant::now();
+                let (status, body) =
+                    batches::execute(data, "/v1/chat/completions", request.body).await;
+                (
+                    request.custom_id,
+                    request.line,
+                    status,
+                    body,
+                    start.elapsed(),
+                )
+            });
+        }
+        let Some(joined) = inflight.join_next().await else {
+            break;
+        };
+        let (custom_id, line, status, body, elapsed) = joined.map_err(candle_core::Error::wrap)?;
+        let record = result_line(&custom_id, line, status, body, elapsed);
+        if status == 200 {
+            succeeded += 1;
+            prompt_tokens += record["usage"]["prompt_tokens"].as_u64().unwrap_or(0);
+            completion_tokens += record["usage"]["completion_tokens"].as_u64().unwrap_or(0);
+        } else {
+            failed += 1;
+            crate::log_warn!(
+                "Request {} (line {}) failed with status {}",
+                custom_id,
+                line,
+                status
+            );
+        }
+        writeln!(writer, "{record}")?;
+        writer.flush()?;
+        if (succeeded + failed) % 100 == 0 {
+            crate::log_info!("{}/{} requests finished", succeeded + failed, remaining);
+        }
+    }
+
+    let elapsed = started.elapsed().as_secs_f64().max(1e-6);
+    crate::log_info!(
+        "Offline batch finished: {} succeeded, {} failed in {:.2}s ({} prompt tokens, {} completion tokens, {:.2} tokens/s)",
+        succeeded,
+        failed,
+        elapsed,
+        prompt_tokens,
+        completion_tokens,
+        completion_tokens as f64 / elapsed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_accept_bodies_and_batch_lines() {
+        let content = concat!(
+            r#"{"messages":[{"role":"user","content":"hi"}],"max_tokens":8}"#,
+            "\n\n",
+            r#"{"custom_id":"q2","body":{"messages":[]}}"#,
+            "\n",
+        );
+        let requests = parse_requests(content).unwrap();
+        assert_eq!(requests[0].custom_id, "line-1");
+        assert_eq!(requests[0].body["max_tokens"], 8);
+        assert_eq!(
+            (requests[1].custom_id.as_str(), requests[1].line),
+            ("q2", 3)
+        );
+        assert!(requests[1].body.get("custom_id").is_none());
+
+        let duplicate = r#"{"custom_id":"a","body":{}}
+{"custom_id":"a","body":{}}"#;
+        assert!(parse_requests(duplicate).is_err());
+    }
+
+    #[test]
+    fn resume_skips_written_results_and_cuts_partial_lines() {
+        let path = std::env::temp_dir().join(format!(
+            "vllm-rs-offline-{}.jsonl",
+            uuid::Uuid::new_v4().simple()
+        ));
+        fs::write(
+            &path,
+            "{\"custom_id\":\"a\"}\n{\"custom_id\":\"b\"}\n{\"custom_id\":\"c",
+        )
+        .unwrap();
+        let done = completed_ids(&path).unwrap();
+        assert_eq!(done, HashSet::from(["a".to_string(), "b".to_string()]));
+        assert!(fs::read_to_string(&path).unwrap().ends_with("\"b\"}\n"));
+        let _ = fs::remove_file(path);
+        assert!(completed_ids(Path::new("/nonexistent/vllm-rs.jsonl"))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,1069 @@
+// src/server/ollama.rs
+//! Ollama-compatible API (`/api/chat`, `/api/generate`, `/api/embed`, ...), so clients
+//! written against Ollama (Open WebUI, IDE plugins) can talk to the engine directly.
+//!
+//! Streaming responses are newline-delimited JSON objects; the last one has
+//! `"done": true` and carries the token counts and timings (in nanoseconds).
+use super::auth;
+use super::responses::ReasoningSplitter;
+use super::{
+    build_guided_decoding_grammar, build_messages_and_images,
+    grammar_fragment_from_response_format, normalize_reasoning_controls,
+    parse_template_tool_arguments, resolve_engine_model_id, validate_mirostat_dry_params,
+    validate_truncation_params, ChatMessage, EmbeddingInput, EmbeddingStrategy, MessageContent,
+    MessageContentType, ResponseFormat, ResponseFormatJsonSchema, ServerData,
+};
+use crate::core::engine::{LLMEngine, StreamItem};
+use crate::server::parser::{BufferedFinalizeResult, StreamResult, StreamToolParser};
+use crate::tools::helpers::{
+    build_tool_schema_map, filter_tool_calls, log_tool_calls, strict_tool_call_validation_enabled,
+};
+use crate::tools::{generate_tool_call_id, new_tool_call, Tool, ToolCall};
+use crate::utils::config::SamplingParams;
+use axum::body::Body;
+use axum::extract::{Json, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::StreamExt;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::task;
+
+/// Ollama's `options` object. Unknown options (`num_gpu`, `num_thread`, ...) are ignored.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct OllamaOptions {
+    /// Maximum tokens to generate; `-1` (or any value <= 0) means the server default.
+    #[serde(default)]
+    pub num_predict: Option<i64>,
+    /// Context window requested by the client. The server's `max_model_len` applies.
+    #[serde(default)]
+    pub num_ctx: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_k: Option<isize>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    #[serde(default)]
+    pub repeat_penalty: Option<f32>,
+    #[serde(default)]
+    pub presence_penalty: Option<f32>,
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+    /// Negative seeds ask for a random seed, as in Ollama.
+    #[serde(default)]
+    pub seed: Option<i64>,
+    #[serde(default, deserialize_with = "super::deserialize_stop_sequences")]
+    pub stop: Option<Vec<String>>,
+    /// 0 disables Mirostat; only Mirostat 2.0 is supported.
+    #[serde(default)]
+    pub mirostat: Option<usize>,
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    #[serde(flatten)]
+    pub other: HashMap<String, Value>,
+}
+
+impl OllamaOptions {
+    fn max_tokens(&self, default: usize) -> usize {
+        match self.num_predict {
+            Some(n) if n > 0 => n as usize,
+            _ => default,
+        }
+    }
+
+    fn sampling_params(&self, max_tokens: usize) -> std::result::Result<SamplingParams, String> {
+        validate_truncation_params(self.min_p, self.typical_p, None, None)?;
+        validate_mirostat_dry_params(
+            self.mirostat,
+            self.mirostat_tau,
+            self.mirostat_eta,
+            None,
+            None,
+        )?;
+        if let Some(penalty) = self.repeat_penalty {
+            if !penalty.is_finite() || penalty <= 0.0 {
+                return Err(format!("repeat_penalty must be positive, got {penalty}"));
+            }
+        }
+
+        let mut params = SamplingParams::new_with_max_tokens(max_tokens);
+        params.temperature = self.temperature;
+        params.top_k = self.top_k;
+        params.top_p = self.top_p;
+        params.min_p = self.min_p;
+        params.typical_p = self.typical_p;
+        params.repetition_penalty = self.repeat_penalty;
+        params.presence_penalty = self.presence_penalty;
+        params.frequency_penalty = self.frequency_penalty;
+        params.seed = self.seed.and_then(|seed| u64::try_from(seed).ok());
+        params.stop_sequences = self.stop.clone();
+        params.mirostat = self.mirostat.filter(|&mode| mode != 0);
+        params.mirostat_tau = self.mirostat_tau;
+        params.mirostat_eta = self.mirostat_eta;
+        Ok(params)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OllamaToolCall {
+    pub function: OllamaFunctionCall,
+}
+
+/// A tool call as Ollama sends it: `arguments` is a JSON object, not a string.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OllamaFunctionCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: Value,
+}
+
+impl From<&ToolCall> for OllamaToolCall {
+    fn from(call: &ToolCall) -> Self {
+        Self {
+            function: OllamaFunctionCall {
+                name: call.function.name.clone(),
+                arguments: parse_template_tool_arguments(call.function.arguments.as_deref()),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct OllamaMessage {
+    pub role: String,
+    #[serde(default)]
+    pub content: String,
+    /// Base64-encoded images attached to the message.
+    #[serde(default)]
+    pub images: Option<Vec<String>>,
+    #[serde(default)]
+    pub thinking: Option<String>,
+    #[serde(default)]
+    pub tool_calls: Option<Vec<OllamaToolCall>>,
+    /// Name of the tool a `tool` message answers.
+    #[serde(default)]
+    pub tool_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaChatRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub messages: Vec<OllamaMessage>,
+    #[serde(default)]
+    pub tools: Option<Vec<Tool>>,
+    /// `"json"` or a JSON schema.
+    #[serde(default)]
+    pub format: Option<Value>,
+    #[serde(default)]
+    pub options: Option<OllamaOptions>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub think: Option<bool>,
+    #[serde(default)]
+    pub keep_alive: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaGenerateRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub prompt: String,
+    /// Text after the insertion point; turns the request into fill-in-the-middle.
+    #[serde(default)]
+    pub suffix: Option<String>,
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Send `prompt` to the model as-is, without the chat template.
+    #[serde(default)]
+    pub raw: Option<bool>,
+    #[serde(default)]
+    pub images: Option<Vec<String>>,
+    #[serde(default)]
+    pub format: Option<Value>,
+    #[serde(default)]
+    pub options: Option<OllamaOptions>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    #[serde(default)]
+    pub think: Option<bool>,
+    #[serde(default)]
+    pub keep_alive: Option<Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaShowRequest {
+    #[serde(default, alias = "name")]
+    pub model: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct OllamaEmbedRequest {
+    #[serde(default)]
+    pub model: Option<String>,
+    pub input: EmbeddingInput,
+    #[serde(default)]
+    pub options: Option<OllamaOptions>,
+    #[serde(default)]
+    pub keep_alive: Option<Value>,
+}
+
+pub enum OllamaResponder {
+    Json(Value),
+    /// NDJSON stream, one object per line.
+    Stream(flume::Receiver<Value>),
+    Error(StatusCode, String),
+}
+
+impl IntoResponse for OllamaResponder {
+    fn into_response(self) -> Response {
+        match self {
+            OllamaResponder::Json(value) => Json(value).into_response(),
+            OllamaResponder::Stream(rx) => {
+                let lines = rx
+                    .into_stream()
+                    .map(|value| Ok::<_, Infallible>(format!("{value}\n")));
+                (
+                    [(header::CONTENT_TYPE, "application/x-ndjson")],
+                    Body::from_stream(lines),
+                )
+                    .into_response()
+            }
+            OllamaResponder::Error(status, message) => {
+                (status, Json(json!({ "error": message }))).into_response()
+            }
+        }
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> OllamaResponder {
+    OllamaResponder::Error(StatusCode::BAD_REQUEST, message.into())
+}
+
+/// RFC 3339 UTC timestamp, as Ollama reports `created_at` and `modified_at`.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Civil date from days since 1970-01-01 (H. Hinnant's days_from_civil inverse).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:09}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since_epoch.subsec_nanos()
+    )
+}
+
+fn now() -> String {
+    rfc3339(SystemTime::now())
+}
+
+fn model_name(data: &ServerData) -> String {
+    resolve_engine_model_id(&data.econfig).unwrap_or_else(|| data.engine.read().get_model_info().1)
+}
+
+fn user_content(content: &str, images: Option<&[String]>) -> MessageContentType {
+    match images.filter(|images| !images.is_empty()) {
+        None => MessageContentType::PureText(content.to_string()),
+        Some(images) => {
+            let mut parts = vec![MessageContent::Text {
+                text: content.to_string(),
+            }];
+            parts.extend(images.iter().map(|image| MessageContent::ImageBase64 {
+                image_base64: image.clone(),
+            }));
+            MessageContentType::Multi(parts)
+        }
+    }
+}
+
+/// Converts Ollama messages to chat messages. Ollama tool calls carry no ids, so ids are
+/// generated for assistant tool calls and `tool` results are matched to them by name,
+/// falling back to call order.
+fn chat_messages(messages: &[OllamaMessage]) -> Vec<ChatMessage> {
+    let mut pending_calls: Vec<(String, String)> = Vec::new();
+    let mut out = Vec::with_capacity(messages.len());
+    for message in messages {
+        let mut chat = ChatMessage::text(message.role.clone(), String::new());
+        chat.content = Some(user_content(&message.content, message.images.as_deref()));
+        match message.role.as_str() {
+            "assistant" => {
+                chat.reasoning_content = message.thinking.clone().filter(|t| !t.is_empty());
+                if let Some(calls) = message.tool_calls.as_ref().filter(|c| !c.is_empty()) {
+                    let calls: Vec<ToolCall> = calls
+                        .iter()
+                        .map(|call| {
+                            let arguments = match &call.function.arguments {
+                                Value::String(raw) => raw.clone(),
+                                Value::Null => "{}".to_string(),
+                                other => other.to_string(),
+                            };
+                            new_tool_call(generate_tool_call_id(), &call.function.name, arguments)
+                        })
+                        .collect();
+                    pending_calls = calls
+                        .iter()
+                        .map(|call| (call.id.clone(), call.function.name.clone()))
+                        .collect();
+                    if message.content.is_empty() {
+                        chat.content = None;
+                    }
+                    chat.tool_calls = Some(calls);
+                }
+            }
+            "tool" => {
+                let position = message
+                    .tool_name
+                    .as_ref()
+                    .and_then(|name| pending_calls.iter().position(|(_, n)| n == name))
+                    .or((!pending_calls.is_empty()).then_some(0));
+                let id = match position {
+                    Some(idx) => pending_calls.remove(idx).0,
+                    None => generate_tool_call_id(),
+                };
+                chat.tool_call_id = Some(id);
+            }
+            _ => {}
+        }
+        out.push(chat);
+    }
+    out
+}
+
+/// Applies `format` (`"json"` or a JSON schema) as a guided decoding grammar.
+fn constrain_format(
+    data: &ServerData,
+    params: &mut SamplingParams,
+    format: Option<&Value>,
+    max_tokens: usize,
+) -> std::result::Result<(), String> {
+    let response_format = match format {
+        None | Some(Value::Null) => return Ok(()),
+        Some(Value::String(kind)) if kind.is_empty() => return Ok(()),
+        Some(Value::String(kind)) if kind == "json" => ResponseFormat {
+            format_type: "json_object".to_string(),
+            json_schema: None,
+        },
+        Some(schema @ Value::Object(_)) => ResponseFormat {
+            format_type: "json_schema".to_string(),
+            json_schema: Some(ResponseFormatJsonSchema {
+                name: None,
+                schema: schema.clone(),
+            }),
+        },
+        Some(other) => return Err(format!("Unsupported format {other}")),
+    };
+    let grammar = grammar_fragment_from_response_format(&response_format)
+        .map_err(|e| format!("Invalid format: {e}"))?;
+    let guidance_tokens = data.engine.read().guidance_tokens.clone();
+    normalize_reasoning_controls(params, &guidance_tokens);
+    params.grammar = build_guided_decoding_grammar(
+        &guidance_tokens,
+        grammar,
+        max_tokens,
+        params.reasoning_effort.clone(),
+    );
+    Ok(())
+}
+
+/// Sampling parameters and limits shared by `/api/chat` and `/api/generate`.
+fn request_params(
+    data: &ServerData,
+    options: Option<&OllamaOptions>,
+    format: Option<&Value>,
+    think: Option<bool>,
+) -> std::result::Result<(SamplingParams, usize), String> {
+    let options = options.cloned().unwrap_or_default();
+    if let (Some(num_ctx), Some(max_model_len)) = (options.num_ctx, data.econfig.max_model_len) {
+        if num_ctx > max_model_len {
+            crate::log_warn!(
+                "Ollama num_ctx {} exceeds max_model_len {}, using the server limit",
+                num_ctx,
+                max_model_len
+            );
+        }
+    }
+    let max_tokens = options.max_tokens(data.econfig.max_tokens.unwrap_or(16384));
+    let mut params = options.sampling_params(max_tokens)?;
+    params.thinking = think;
+    constrain_format(data, &mut params, format, max_tokens)?;
+    Ok((params, max_tokens))
+}
+
+/// Token counts and timings reported in the final (`"done": true`) object.
+#[derive(Default)]
+struct GenerationSummary {
+    prompt_eval_count: usize,
+    prompt_eval_duration: u64,
+    eval_count: usize,
+    eval_duration: u64,
+    done_reason: &'static str,
+    tool_calls: Vec<ToolCall>,
+    error: Option<String>,
+}
+
+/// An accepted sequence and the state needed to turn its tokens into Ollama output.
+struct Generation {
+    seq_id: usize,
+    prompt_length: usize,
+    max_tokens: usize,
+    rx: mpsc::Receiver<StreamItem>,
+    splitter: ReasoningSplitter,
+    tool_parser: Option<StreamToolParser>,
+    tool_schemas: HashMap<String, Value>,
+}
+
+impl Generation {
+    fn chat(
+        data: &ServerData,
+        params: &mut SamplingParams,
+        max_tokens: usize,
+        messages: &[ChatMessage],
+        tools: Vec<Tool>,
+    ) -> std::result::Result<Self, OllamaResponder> {
+        let has_tools = !tools.is_empty();
+        params.mcp_mode = has_tools.then_some(true);
+        let (img_cfg, model_type, tool_config) = {
+            let e = data.engine.read();
+            (
+                e.img_cfg.clone(),
+                e.model_type.clone(),
+                e.tool_config.clone(),
+            )
+        };
+        let (messages, image_data) = build_messages_and_images(messages, img_cfg.as_ref())
+            .map_err(|e| bad_request(format!("Failed to process messages: {e}")))?;
+        let (seq_id, prompt_length, prefilled_reasoning_end, rx) = data
+            .engine
+            .write()
+            .generate_stream(params, &messages, image_data, &tools, &None)
+            .map_err(|e| {
+                crate::log_error!("Ollama generation failed: {:?}", e);
+                bad_request(format!("Generation failed: {e}"))
+            })?;
+
+        let tool_schemas = build_tool_schema_map(&tools);
+        let tool_parser = has_tools.then(|| {
+            let mut parser = StreamToolParser::new_with_config(
+                &model_type,
+                model_name(data),
+                tool_config,
+                tools,
+                data.econfig.enforce_parser.clone(),
+            );
+            parser.set_initial_reasoning_end_marker(prefilled_reasoning_end.clone());
+            parser
+        });
+        Ok(Self {
+            seq_id,
+            prompt_length,
+            max_tokens,
+            rx,
+            splitter: prefilled_reasoning_end
+                .as_deref()
+                .map(ReasoningSplitter::in_reasoning)
+                .unwrap_or_default(),
+            tool_parser,
+            tool_schemas,
+        })
+    }
+
+    fn raw(
+        data: &ServerData,
+        params: &SamplingParams,
+        max_tokens: usize,
+        prompt: &str,
+    ) -> std::result::Result<Self, OllamaResponder> {
+        let mut e = data.engine.write();
+        let token_ids = e
+            .tokenizer
+            .encode_fast(prompt, true)
+            .map_err(|err| bad_request(format!("Failed to tokenize prompt: {err}")))?
+            .get_ids()
+            .to_vec();
+        let (seq_id, prompt_length, rx) =
+            e.generate_completion_stream(params, token_ids)
+                .map_err(|err| {
+                    crate::log_error!("Ollama generation failed: {:?}", err);
+                    bad_request(format!("Generation failed: {err}"))
+                })?;
+        Ok(Self {
+            seq_id,
+            prompt_length,
+            max_tokens,
+            rx,
+            splitter: ReasoningSplitter::default(),
+            tool_parser: None,
+            tool_schemas: HashMap::new(),
+        })
+    }
+
+    /// Drives the sequence to the end, handing `(is_thinking, text)` pieces to `emit`.
+    /// Returns `None` (and cancels the sequence) once `emit` reports the client is gone.
+    async fn run(
+        mut self,
+        engine: &Arc<RwLock<LLMEngine>>,
+        mut emit: impl FnMut(bool, String) -> bool,
+    ) -> Option<GenerationSummary> {
+        let mut summary = GenerationSummary {
+            prompt_eval_count: self.prompt_length,
+            done_reason: "stop",
+            ..Default::default()
+        };
+        let mut tool_calls = Vec::new();
+        while let Some(item) = self.rx.recv().await {
+            match item {
+                StreamItem::Token(token, token_id) => {
+                    let text = match self.tool_parser.as_mut() {
+                        None => token,
+                        Some(parser) => match parser.process_token(token_id, &token).await {
+                            StreamResult::Content(text) => text,
+                            StreamResult::FlushBuffer(text) => {
+                                parser.sanitize_tool_markup_for_display(&text)
+                            }
+                            StreamResult::Buffering => continue,
+                            StreamResult::ToolCalls(calls) => {
+                                tool_calls.extend(calls);
+                                continue;
+                            }
+                        },
+                    };
+                    // Text after a tool call is not part of the answer.
+                    if !tool_calls.is_empty() {
+                        continue;
+                    }
+                    for (is_thinking, piece) in self.splitter.push(&text) {
+                        if !emit(is_thinking, piece) {
+                            engine.write().cancel(self.seq_id);
+                            return None;
+                        }
+                    }
+                }
+                StreamItem::Done((
+                    prompt_start,
+                    decode_start,
+                    decode_finish,
+                    decoded_length,
+                    _,
+                )) => {
+                    summary.eval_count = decoded_length;
+                    summary.prompt_eval_duration =
+                        decode_start.saturating_sub(prompt_start) as u64 * 1_000_000;
+                    summary.eval_duration =
+                        decode_finish.saturating_sub(decode_start) as u64 * 1_000_000;
+                    if decoded_length >= self.max_tokens {
+                        summary.done_reason = "length";
+                    }
+                    break;
+                }
+                StreamItem::Error(e) => {
+                    crate::log_error!("[Seq {}] Stream error: {}", self.seq_id, e);
+                    summary.error = Some(e);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(parser) = self.tool_parser.as_mut() {
+            match parser.finalize_buffered_tool_calls().await {
+                Some(BufferedFinalizeResult::ToolCalls(calls)) => tool_calls.extend(calls),
+                Some(BufferedFinalizeResult::FlushBuffer(buffer)) if tool_calls.is_empty() => {
+                    let text = parser.sanitize_tool_markup_for_display(&buffer);
+                    for (is_thinking, piece) in self.splitter.push(&text) {
+                        if !emit(is_thinking, piece) {
+                            return None;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            if tool_calls.is_empty() {
+                let output = parser.accumulated_output().to_string();
+                tool_calls = parser.parse_complete_with_fallback(&output).await;
+            }
+            let (valid, invalid) = filter_tool_calls(&tool_calls, &self.tool_schemas);
+            if !invalid.is_empty() {
+                log_tool_calls("Invalid", &invalid);
+                if strict_tool_call_validation_enabled() {
+                    tool_calls = valid;
+                }
+            }
+        }
+        for (is_thinking, piece) in self.splitter.finish() {
+            if !emit(is_thinking, piece) {
+                return None;
+            }
+        }
+        summary.tool_calls = tool_calls;
+        Some(summary)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Endpoint {
+    Chat,
+    Generate,
+}
+
+/// A streamed object carrying one piece of answer or thinking text.
+fn partial_reply(endpoint: Endpoint, model: &str, is_thinking: bool, text: String) -> Value {
+    match endpoint {
+        Endpoint::Chat => {
+            let field = if is_thinking { "thinking" } else { "content" };
+            json!({
+            "model": model,
+            "created_at": now(),
+            "message": { "role": "assistant", "content": "", field: text },
+            "done": false,
+            })
+        }
+        Endpoint::Generate => {
+            let field = if is_thinking { "thinking" } else { "response" };
+            json!({ "model": model, "created_at": now(), "response": "", field: text, "done": false })
+        }
+    }
+}
+
+fn final_reply(
+    endpoint: Endpoint,
+    model: &str,
+    content: String,
+    thinking: String,
+    tool_calls: &[ToolCall],
+    summary: &GenerationSummary,
+    started: Instant,
+) -> Value {
+    let mut reply = match endpoint {
+        Endpoint::Chat => {
+            let mut message = json!({ "role": "assistant", "content": content });
+            if !thinking.is_empty() {
+                message["thinking"] = json!(thinking);
+            }
+            if !tool_calls.is_empty() {
+                let calls: Vec<OllamaToolCall> = tool_calls.iter().map(Into::into).collect();
+                message["tool_calls"] = json!(calls);
+            }
+            json!({ "model": model, "created_at": now(), "message": message })
+        }
+        Endpoint::Generate => {
+            let mut reply = json!({ "model": model, "created_at": now(), "response": content });
+            if !thinking.is_empty() {
+                reply["thinking"] = json!(thinking);
+            }
+            reply
+        }
+    };
+    let done_reason = if tool_calls.is_empty() {
+        summary.done_reason
+    } else {
+        "stop"
+    };
+    reply["done"] = json!(true);
+    reply["done_reason"] = json!(done_reason);
+    reply["total_duration"] = json!(started.elapsed().as_nanos() as u64);
+    reply["load_duration"] = json!(0);
+    reply["prompt_eval_count"] = json!(summary.prompt_eval_count);
+    reply["prompt_eval_duration"] = json!(summary.prompt_eval_duration);
+    reply["eval_count"] = json!(summary.eval_count);
+    reply["eval_duration"] = json!(summary.eval_duration);
+    reply
+}
+
+/// Reply for a request with nothing to generate; Ollama uses it to preload a model.
+fn load_reply(endpoint: Endpoint, model: &str) -> OllamaResponder {
+    let mut reply =
+        json!({ "model": model, "created_at": now(), "done": true, "done_reason": "load" });
+    match endpoint {
+        Endpoint::Chat => reply["message"] = json!({ "role": "assistant", "content": "" }),
+        Endpoint::Generate => reply["response"] = json!(""),
+    }
+    OllamaResponder::Json(reply)
+}
+
+async fn respond(
+    engine: Arc<RwLock<LLMEngine>>,
+    generation: Generation,
+    endpoint: Endpoint,
+    model: String,
+    stream: bool,
+    started: Instant,
+) -> OllamaResponder {
+    if !stream {
+        let mut content = String::new();
+        let mut thinking = String::new();
+        let summary = generation
+            .run(&engine, |is_thinking, text| {
+                if is_thinking {
+                    thinking.push_str(&text);
+                } else {
+                    content.push_str(&text);
+                }
+                true
+            })
+            .await;
+        let Some(summary) = summary else {
+            return OllamaResponder::Error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Generation was cancelled".to_string(),
+            );
+        };
+        if let Some(e) = summary.error {
+            return OllamaResponder::Error(StatusCode::INTERNAL_SERVER_ERROR, e);
+        }
+        let reply = final_reply(
+            endpoint,
+            &model,
+            content,
+            thinking,
+            &summary.tool_calls,
+            &summary,
+            started,
+        );
+        return OllamaResponder::Json(reply);
+    }
+
+    let (tx, rx) = flume::unbounded();
+    let key = auth::current_key();
+    task::spawn(async move {
+        let summary = auth::with_key(
+            key,
+            generation.run(&engine, |is_thinking, text| {
+                tx.send(partial_reply(endpoint, &model, is_thinking, text))
+                    .is_ok()
+            }),
+        )
+        .await;
+        let Some(summary) = summary else {
+            return;
+        };
+        if let Some(e) = summary.error {
+            let _ = tx.send(json!({ "error": e }));
+            return;
+        }
+        // Like Ollama, tool calls arrive in their own message before the final object.
+        if endpoint == Endpoint::Chat && !summary.tool_calls.is_empty() {
+            let calls: Vec<OllamaToolCall> = summary.tool_calls.iter().map(Into::into).collect();
+            let _ = tx.send(json!({
+                "model": model,
+                "created_at": now(),
+                "message": { "role": "assistant", "content": "", "tool_calls": calls },
+                "done": false,
+            }));
+        }
+        let _ = tx.send(final_reply(
+            endpoint,
+            &model,
+            String::new(),
+            String::new(),
+            &[],
+            &summary,
+            started,
+        ));
+    });
+    OllamaResponder::Stream(rx)
+}
+
+/// POST /api/chat
+pub async fn chat(
+    State(data): State<Arc<ServerData>>,
+    Json(request): Json<OllamaChatRequest>,
+) -> OllamaResponder {
+    let started = Instant::now();
+    let model = model_name(&data);
+    if request.messages.is_empty() {
+        return load_reply(Endpoint::Chat, &model);
+    }
+    let (mut params, max_tokens) = match request_params(
+        &data,
+        request.options.as_ref(),
+        request.format.as_ref(),
+        request.think,
+    ) {
+        Ok(output) => output,
+        Err(e) => return bad_request(e),
+    };
+    let messages = chat_messages(&request.messages);
+    let tools = request.tools.unwrap_or_default();
+    let generation = match Generation::chat(&data, &mut params, max_tokens, &messages, tools) {
+        Ok(generation) => generation,
+        Err(e) => return e,
+    };
+    respond(
+        data.engine.clone(),
+        generation,
+        Endpoint::Chat,
+        model,
+        request.stream.unwrap_or(true),
+        started,
+    )
+    .await
+}
+
+/// POST /api/generate
+pub async fn generate(
+    State(data): State<Arc<ServerData>>,
+    Json(request): Json<OllamaGenerateRequest>,
+) -> OllamaResponder {
+    let started = Instant::now();
+    let model = model_name(&data);
+    if request.prompt.is_empty() && request.suffix.is_none() {
+        return load_reply(Endpoint::Generate, &model);
+    }
+    let (mut params, max_tokens) = match request_params(
+        &data,
+        request.options.as_ref(),
+        request.format.as_ref(),
+        request.think,
+    ) {
+        Ok(output) => output,
+        Err(e) => return bad_request(e),
+    };
+
+    let generation = if let Some(suffix) = &request.suffix {
+        let Some(template) = data.engine.read().fim_template.clone() else {
+            return bad_request(
+                "suffix is not supported: the model has no fill-in-the-middle tokens",
+            );
+        };
+        Generation::raw(
+            &data,
+            &params,
+            max_tokens,
+            &template.build_prompt(&request.prompt, suffix),
+        )
+    } else if request.raw.unwrap_or(false) {
+        Generation::raw(&data, &params, max_tokens, &request.prompt)
+    } else {
+        let mut messages = Vec::new();
+        if let Some(system) = request.system.as_ref().filter(|s| !s.is_empty()) {
+            messages.push(ChatMessage::text("system", system.clone()));
+        }
+        let mut user = ChatMessage::text("user", String::new());
+        user.content = Some(user_content(&request.prompt, request.images.as_deref()));
+        messages.push(user);
+        Generation::chat(&data, &mut params, max_tokens, &messages, Vec::new())
+    };
+    let generation = match generation {
+        Ok(generation) => generation,
+        Err(e) => return e,
+    };
+    respond(
+        data.engine.clone(),
+        generation,
+        Endpoint::Generate,
+        model,
+        request.stream.unwrap_or(true),
+        started,
+    )
+    .await
+}
+
+fn model_details(data: &ServerData) -> Value {
+    let family = format!("{:?}", data.engine.read().model_type).to_lowercase();
+    let is_gguf = data
+        .econfig
+        .weight_file
+        .as_ref()
+        .is_some_and(|file| file.to_lowercase().ends_with(".gguf"));
+    json!({
+        "parent_model": "",
+        "format": if is_gguf { "gguf" } else { "safetensors" },
+        "family": family,
+        "families": [family],
+        "parameter_size": "",
+        "quantization_level": data.econfig.isq.clone().unwrap_or_default(),
+    })
+}
+
+/// GET /api/tags
+pub async fn tags(State(data): State<Arc<ServerData>>) -> OllamaResponder {
+    let model = model_name(&data);
+    OllamaResponder::Json(json!({
+        "models": [{
+            "name": model,
+            "model": model,
+            "modified_at": now(),
+            "size": 0,
+            "digest": "",
+            "details": model_details(&data),
+        }]
+    }))
+}
+
+/// POST /api/show
+pub async fn show(
+    State(data): State<Arc<ServerData>>,
+    Json(_request): Json<OllamaShowRequest>,
+) -> OllamaResponder {
+    let (has_vision, has_fim, has_thinking, max_model_len) = {
+        let e = data.engine.read();
+        let (has_vision, _, max_model_len) = e.get_model_info();
+        (
+            has_vision,
+            e.fim_template.is_some(),
+            !e.guidance_tokens.reasoning_start_ids.is_empty(),
+            max_model_len,
+        )
+    };
+    let mut capabilities = vec!["completion", "tools"];
+    if has_vision {
+        capabilities.push("vision");
+    }
+    if has_fim {
+        capabilities.push("insert");
+    }
+    if has_thinking {
+        capabilities.push("thinking");
+    }
+    let details = model_details(&data);
+    let family = details["family"].as_str().unwrap_or_default().to_string();
+    OllamaResponder::Json(json!({
+        "modelfile": "",
+        "parameters": "",
+        "template": "",
+        "details": details,
+        "model_info": {
+            "general.architecture": family,
+            format!("{family}.context_length"): max_model_len,
+        },
+        "capabilities": capabilities,
+        "modified_at": now(),
+    }))
+}
+
+/// POST /api/embed
+pub async fn embed(
+    State(data): State<Arc<ServerData>>,
+    Json(request): Json<OllamaEmbedRequest>,
+) -> OllamaResponder {
+    let started = Instant::now();
+    let inputs = request.input.into_vec();
+    if inputs.is_empty() {
+        return bad_request("input must not be empty");
+    }
+    let result = data
+        .engine
+        .write()
+        .embed(&inputs, EmbeddingStrategy::default());
+    let (vectors, prompt_tokens) = match result {
+        Ok(output) => output,
+        Err(e) => {
+            crate::log_error!("Ollama embedding failed: {:?}", e);
+            return OllamaResponder::Error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+        }
+    };
+    // Ollama returns L2-normalized embeddings.
+    let embeddings: Vec<Vec<f32>> = vectors
+        .into_iter()
+        .map(|mut vector| {
+            let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                vector.iter_mut().for_each(|v| *v /= norm);
+            }
+            vector
+        })
+        .collect();
+    OllamaResponder::Json(json!({
+        "model": model_name(&data),
+        "embeddings": embeddings,
+        "total_duration": started.elapsed().as_nanos() as u64,
+        "load_duration": 0,
+        "prompt_eval_count": prompt_tokens,
+    }))
+}
+
+/// GET /api/version
+pub async fn version() -> OllamaResponder {
+    OllamaResponder::Json(json!({ "version": env!("CARGO_PKG_VERSION") }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn options_map_to_sampling_params() {
+        let options: OllamaOptions = serde_json::from_value(json!({
+            "num_predict": -1,
+            "num_ctx": 8192,
+            "num_gpu": 99,
+            "repeat_penalty": 1.1,
+            "top_k": 20,
+            "seed": -1,
+            "stop": "</s>",
+            "mirostat": 0
+        }))
+        .unwrap();
+        assert_eq!(options.max_tokens(512), 512);
+        let params = options.sampling_params(512).unwrap();
+        assert_eq!(params.repetition_penalty, Some(1.1));
+        assert_eq!(params.top_k, Some(20));
+        assert_eq!(params.seed, None);
+        assert_eq!(params.stop_sequences, Some(vec!["</s>".to_string()]));
+        assert_eq!(params.mirostat, None);
+
+        let options: OllamaOptions =
+            serde_json::from_value(json!({ "num_predict": 64, "mirostat": 1 })).unwrap();
+        assert_eq!(options.max_tokens(512), 64);
+        assert!(options.sampling_params(64).is_err());
+    }
+
+    #[test]
+    fn tool_results_pair_with_generated_call_ids() {
+        let messages: Vec<OllamaMessage> = serde_json::from_value(json!([
+            { "role": "user", "content": "Weather in Paris and Rome?" },
+            { "role": "assistant", "content": "", "tool_calls": [
+                { "function": { "name": "weather", "arguments": { "city": "Paris" } } },
+                { "function": { "name": "time", "arguments": { "city": "Rome" } } }
+            ]},
+            { "role": "tool", "content": "12:00", "tool_name": "time" },
+            { "role": "tool", "content": "sunny" }
+        ]))
+        .unwrap();
+        let chat = chat_messages(&messages);
+        let calls = chat[1].tool_calls.as_ref().unwrap();
+        assert!(chat[1].content.is_none());
+        assert_eq!(
+            calls[0].function.arguments.as_deref(),
+            Some(r#"{"city":"Paris"}"#)
+        );
+        assert_eq!(chat[2].tool_call_id.as_ref(), Some(&calls[1].id));
+        assert_eq!(chat[3].tool_call_id.as_ref(), Some(&calls[0].id));
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339() {
+        let at = |secs| rfc3339(UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "1970-01-01T00:00:00.000000000Z");
+        assert_eq!(at(951_782_400), "2000-02-29T00:00:00.000000000Z");
+        assert_eq!(at(1_700_000_000), "2023-11-14T22:13:20.000000000Z");
+    }
+}
@@ -2405,6 +2405,7 @@ impl StreamToolParser {
                 },
                 finish_reason: None,
                 error: None,
+                logprobs: None,
             }],
             usage: None,
         }
@@ -2441,6 +2442,7 @@ impl StreamToolParser {
                 },
                 finish_reason: None,
                 error: None,
+                logprobs: None,
             }],
             usage: None,
         }
@@ -0,0 +1,1311 @@
+// src/server/responses.rs
+//! OpenAI Responses API (`/v1/responses`), served by the chat completion pipeline.
+//!
+//! Finished responses are kept in an in-process store so a request can continue a
+//! conversation with `previous_response_id`. A chain of responses shares one session id
+//! and re-sends the same conversation prefix, so earlier turns are served from the prefix
+//! cache instead of being prefilled again.
+use super::{
+    auth,
+    server::chat_completion,
+    streaming::{ChatResponse, Streamer, StreamingStatus},
+    ChatCompletionRequest, ChatMessage, ChatResponder, MessageContentType, PublicToolCall,
+    ResponseFormat, ResponseFormatJsonSchema, ServerData, StreamOptions,
+};
+use crate::tools::{new_tool_call, Function, Tool, ToolCall, ToolChoice, ToolChoiceMode};
+use crate::utils::chat_template::extract_reasoning_content;
+use axum::extract::{Json, Path, State};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::task;
+use uuid::Uuid;
+
+/// `input` of a request: a user message or a list of input items.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ResponseInput {
+    Text(String),
+    Items(Vec<Value>),
+}
+
+/// One input item. Messages may omit `"type": "message"`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseInputItem {
+    Message {
+        role: String,
+        content: MessageContentType,
+    },
+    FunctionCall {
+        call_id: String,
+        name: String,
+        arguments: String,
+    },
+    FunctionCallOutput {
+        call_id: String,
+        output: Value,
+    },
+    Reasoning {
+        #[serde(default)]
+        summary: Vec<ReasoningPart>,
+        #[serde(default)]
+        content: Vec<ReasoningPart>,
+    },
+}
+
+impl ResponseInputItem {
+    fn parse(mut value: Value) -> Result<Self, String> {
+        if let Some(fields) = value.as_object_mut() {
+            if !fields.contains_key("type") && fields.contains_key("role") {
+                fields.insert("type".to_string(), Value::String("message".to_string()));
+            }
+        }
+        serde_json::from_value(value).map_err(|e| format!("Invalid input item: {e}"))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ReasoningPart {
+    #[serde(rename = "type")]
+    pub part_type: String,
+    pub text: String,
+}
+
+impl ReasoningPart {
+    fn reasoning_text(text: String) -> Self {
+        Self {
+            part_type: "reasoning_text".to_string(),
+            text,
+        }
+    }
+}
+
+/// Responses tools are flat: `{"type": "function", "name": ..., "parameters": ...}`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ResponseTool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl ResponseTool {
+    fn to_tool(&self) -> Result<Tool, String> {
+        if self.tool_type != "function" {
+            return Err(format!(
+                "Unsupported tool type '{}', only function tools are supported",
+                self.tool_type
+            ));
+        }
+        let Some(name) = self.name.clone() else {
+            return Err("function tools require a name".to_string());
+        };
+        Ok(Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name,
+                description: self.description.clone(),
+                parameters: self
+                    .parameters
+                    .clone()
+                    .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                strict: self.strict,
+            },
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ResponseToolChoice {
+    Mode(ToolChoiceMode),
+    Function {
+        #[serde(rename = "type")]
+        choice_type: String,
+        name: String,
+    },
+}
+
+impl ResponseToolChoice {
+    fn to_tool_choice(&self) -> ToolChoice {
+        match self {
+            ResponseToolChoice::Mode(mode) => ToolChoice::Mode(mode.clone()),
+            ResponseToolChoice::Function { name, .. } => ToolChoice::function(name.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ReasoningConfig {
+    #[serde(default)]
+    pub effort: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TextConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<TextFormat>,
+}
+
+/// `text.format`: `text`, `json_object` or `json_schema` (with the schema inlined).
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TextFormat {
+    #[serde(rename = "type")]
+    pub format_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+impl TextFormat {
+    fn to_response_format(&self) -> Option<ResponseFormat> {
+        match self.format_type.as_str() {
+            "text" => None,
+            "json_schema" => Some(ResponseFormat {
+                format_type: self.format_type.clone(),
+                json_schema: Some(ResponseFormatJsonSchema {
+                    name: self.name.clone(),
+                    schema: self.schema.clone().unwrap_or_else(|| json!({})),
+                }),
+            }),
+            _ => Some(ResponseFormat {
+                format_type: self.format_type.clone(),
+                json_schema: None,
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponsesRequest {
+    pub model: Option<String>,
+    pub input: ResponseInput,
+    /// System message for this turn; not carried over to responses chained after it
+    #[serde(default)]
+    pub instructions: Option<String>,
+    /// Continue the conversation of a stored response
+    #[serde(default)]
+    pub previous_response_id: Option<String>,
+    #[serde(default)]
+    pub tools: Option<Vec<ResponseTool>>,
+    #[serde(default)]
+    pub tool_choice: Option<ResponseToolChoice>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    #[serde(default)]
+    pub max_output_tokens: Option<usize>,
+    #[serde(default)]
+    pub stream: Option<bool>,
+    /// Keep the response for `previous_response_id` and `GET /v1/responses/{id}` (default true)
+    #[serde(default)]
+    pub store: Option<bool>,
+    #[serde(default)]
+    pub reasoning: Option<ReasoningConfig>,
+    #[serde(default)]
+    pub text: Option<TextConfig>,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+    /// Scheduling priority under `--scheduling-policy priority`, lower is scheduled first
+    #[serde(default)]
+    pub priority: Option<i32>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct OutputText {
+    #[serde(rename = "type")]
+    pub part_type: &'static str,
+    pub text: String,
+    pub annotations: Vec<Value>,
+}
+
+impl OutputText {
+    fn new(text: String) -> Self {
+        Self {
+            part_type: "output_text",
+            text,
+            annotations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseOutputItem {
+    Reasoning {
+        id: String,
+        summary: Vec<ReasoningPart>,
+        content: Vec<ReasoningPart>,
+        status: &'static str,
+    },
+    Message {
+        id: String,
+        role: &'static str,
+        status: &'static str,
+        content: Vec<OutputText>,
+    },
+    FunctionCall {
+        id: String,
+        call_id: String,
+        name: String,
+        arguments: String,
+        status: &'static str,
+    },
+}
+
+impl ResponseOutputItem {
+    fn reasoning(id: String, text: String, status: &'static str) -> Self {
+        ResponseOutputItem::Reasoning {
+            id,
+            summary: Vec::new(),
+            content: if text.is_empty() {
+                Vec::new()
+            } else {
+                vec![ReasoningPart::reasoning_text(text)]
+            },
+            status,
+        }
+    }
+
+    fn message(id: String, text: String, status: &'static str) -> Self {
+        ResponseOutputItem::Message {
+            id,
+            role: "assistant",
+            status,
+            content: if text.is_empty() && status != "completed" {
+                Vec::new()
+            } else {
+                vec![OutputText::new(text)]
+            },
+        }
+    }
+
+    fn function_call(call: &PublicToolCall, arguments: String) -> Self {
+        ResponseOutputItem::FunctionCall {
+            id: format!("fc_{}", item_suffix()),
+            call_id: call.id.clone(),
+            name: call.function.name.clone(),
+            arguments,
+            status: "completed",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct InputTokensDetails {
+    pub cached_tokens: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct OutputTokensDetails {
+    pub reasoning_tokens: usize,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ResponseUsage {
+    pub input_tokens: usize,
+    pub input_tokens_details: InputTokensDetails,
+    pub output_tokens: usize,
+    pub output_tokens_details: OutputTokensDetails,
+    pub total_tokens: usize,
+}
+
+impl ResponseUsage {
+    fn new(input_tokens: usize, output_tokens: usize) -> Self {
+        Self {
+            input_tokens,
+            output_tokens,
+            total_tokens: input_tokens + output_tokens,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ResponseObject {
+    pub id: String,
+    pub object: &'static str,
+    pub created_at: u64,
+    pub status: &'static str,
+    pub incomplete_details: Option<Value>,
+    pub error: Option<Value>,
+    pub model: String,
+    pub instructions: Option<String>,
+    pub previous_response_id: Option<String>,
+    pub output: Vec<ResponseOutputItem>,
+    pub tools: Vec<ResponseTool>,
+    pub tool_choice: Value,
+    pub parallel_tool_calls: bool,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub max_output_tokens: Option<usize>,
+    pub reasoning: Option<ReasoningConfig>,
+    pub text: Option<TextConfig>,
+    pub store: bool,
+    pub metadata: HashMap<String, String>,
+    pub usage: Option<ResponseUsage>,
+}
+
+impl ResponseObject {
+    /// Mark the response finished; hitting the output limit makes it `incomplete`.
+    fn finish(&mut self, output: Vec<ResponseOutputItem>, usage: ResponseUsage, truncated: bool) {
+        self.output = output;
+        self.usage = Some(usage);
+        if truncated {
+            self.status = "incomplete";
+            self.incomplete_details = Some(json!({"reason": "max_output_tokens"}));
+        } else {
+            self.status = "completed";
+        }
+    }
+
+    fn fail(&mut self, message: String) {
+        self.status = "failed";
+        self.error = Some(json!({"code": "server_error", "message": message}));
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResponseDeleted {
+    pub id: String,
+    pub object: &'static str,
+    pub deleted: bool,
+}
+
+/// A stored response with the conversation it concluded.
+struct StoredResponse {
+    response: ResponseObject,
+    /// All turns up to and including this response, without `instructions`
+    messages: Vec<ChatMessage>,
+    session_id: String,
+    /// API key that created the response (see `auth::current_owner`).
+    owner: Option<String>,
+}
+
+#[derive(Default)]
+struct ResponseStoreInner {
+    responses: HashMap<String, Arc<StoredResponse>>,
+    order: VecDeque<String>,
+}
+
+/// In-process store of finished responses, evicting the oldest beyond `capacity`.
+pub struct ResponseStore {
+    capacity: usize,
+    inner: parking_lot::Mutex<ResponseStoreInner>,
+}
+
+impl ResponseStore {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: parking_lot::Mutex::new(ResponseStoreInner::default()),
+        }
+    }
+
+    /// Looks up a response created by `owner`; other keys' responses look missing.
+    fn get(&self, id: &str, owner: Option<&str>) -> Option<Arc<StoredResponse>> {
+        self.inner
+            .lock()
+            .responses
+            .get(id)
+            .filter(|stored| stored.owner.as_deref() == owner)
+            .cloned()
+    }
+
+    fn insert(&self, stored: StoredResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        let id = stored.response.id.clone();
+        if inner
+            .responses
+            .insert(id.clone(), Arc::new(stored))
+            .is_none()
+        {
+            inner.order.push_back(id);
+        }
+        while inner.order.len() > self.capacity {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.responses.remove(&oldest);
+            }
+        }
+    }
+
+    fn remove(&self, id: &str, owner: Option<&str>) -> bool {
+        let mut inner = self.inner.lock();
+        if !inner
+            .responses
+            .get(id)
+            .is_some_and(|stored| stored.owner.as_deref() == owner)
+        {
+            return false;
+        }
+        inner.responses.remove(id);
+        inner.order.retain(|stored| stored != id);
+        true
+    }
+}
+
+fn item_suffix() -> String {
+    Uuid::new_v4().simple().to_string()[..24].to_string()
+}
+
+/// Convert input items into chat messages. Function calls join the preceding assistant
+/// message and reasoning items attach to the next one.
+fn input_messages(input: ResponseInput) -> Result<Vec<ChatMessage>, String> {
+    let items = match input {
+        ResponseInput::Text(text) => return Ok(vec![ChatMessage::text("user", text)]),
+        ResponseInput::Items(items) => items,
+    };
+    let mut messages: Vec<ChatMessage> = Vec::new();
+    let mut reasoning: Option<String> = None;
+    for item in items {
+        match ResponseInputItem::parse(item)? {
+            ResponseInputItem::Message { role, content } => {
+                let role = match role.as_str() {
+                    "developer" => "system".to_string(),
+                    "user" | "assistant" | "system" => role,
+                    other => return Err(format!("Unsupported message role '{other}'")),
+                };
+                let reasoning_content = if role == "assistant" {
+                    reasoning.take()
+                } else {
+                    None
+                };
+                messages.push(ChatMessage {
+                    role,
+                    content: Some(content),
+                    tool_calls: None,
+                    tool_call_id: None,
+                    reasoning_content,
+                });
+            }
+            ResponseInputItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+            } => {
+                let call = new_tool_call(call_id, name, arguments);
+                if let Some(last) = messages.last_mut().filter(|m| m.role == "assistant") {
+                    last.tool_calls.get_or_insert_with(Vec::new).push(call);
+                } else {
+                    let mut message = ChatMessage::with_tool_calls(vec![call]);
+                    message.reasoning_content = reasoning.take();
+                    messages.push(message);
+                }
+            }
+            ResponseInputItem::FunctionCallOutput { call_id, output } => {
+                let output = match output {
+                    Value::String(text) => text,
+                    Value::Array(parts) => parts
+                        .iter()
+                        .filter_map(|part| part.get("text").and_then(Value::as_str))
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                    other => other.to_string(),
+                };
+                messages.push(ChatMessage::tool_result(call_id, output));
+            }
+            ResponseInputItem::Reasoning { summary, content } => {
+                let parts = if content.is_empty() { summary } else { content };
+                let text = parts
+                    .into_iter()
+                    .map(|part| part.text)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if !text.is_empty() {
+                    reasoning = Some(text);
+                }
+            }
+        }
+    }
+    if messages.is_empty() {
+        return Err("input cannot be empty".to_string());
+    }
+    Ok(messages)
+}
+
+/// Separate reasoning from the answer when the chat pipeline left the markers inline.
+fn split_reasoning(
+    content: Option<String>,
+    reasoning: Option<String>,
+) -> (Option<String>, Option<String>) {
+    let (reasoning, content) = match (reasoning, content) {
+        (None, Some(text)) => match extract_reasoning_content(&text) {
+            Some((reasoning, rest)) => (Some(reasoning), Some(rest)),
+            None => (None, Some(text)),
+        },
+        other => other,
+    };
+    (
+        reasoning.filter(|text| !text.trim().is_empty()),
+        content.filter(|text| !text.is_empty()),
+    )
+}
+
+/// The assistant turn of a finished response, as it is replayed in later turns.
+fn assistant_message(output: &[ResponseOutputItem]) -> ChatMessage {
+    let mut reasoning = Vec::new();
+    let mut text = String::new();
+    let mut tool_calls: Vec<ToolCall> = Vec::new();
+    for item in output {
+        match item {
+            ResponseOutputItem::Reasoning { content, .. } => {
+                reasoning.extend(content.iter().map(|part| part.text.clone()))
+            }
+            ResponseOutputItem::Message { content, .. } => {
+                content.iter().for_each(|part| text.push_str(&part.text))
+            }
+            ResponseOutputItem::FunctionCall {
+                call_id,
+                name,
+                arguments,
+                ..
+            } => tool_calls.push(new_tool_call(call_id, name, arguments)),
+        }
+    }
+    ChatMessage {
+        role: "assistant".to_string(),
+        content: (!text.is_empty() || tool_calls.is_empty())
+            .then(|| MessageContentType::PureText(text)),
+        tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+        tool_call_id: None,
+        reasoning_content: (!reasoning.is_empty()).then(|| reasoning.join("\n")),
+    }
+}
+
+/// Splits streamed content into reasoning and answer text on inline reasoning markers.
+/// Marker fragments are held back until it is clear whether they complete a marker.
+#[derive(Default)]
+pub(super) struct ReasoningSplitter {
+    pending: String,
+    state: SplitState,
+}
+
+#[derive(Default, PartialEq)]
+enum SplitState {
+    #[default]
+    Start,
+    Reasoning(&'static str),
+    Content,
+}
+
+impl ReasoningSplitter {
+    /// A splitter for output that starts inside reasoning (the prompt already opened it).
+    pub(super) fn in_reasoning(close: &str) -> Self {
+        let state = crate::server::parser::reasoning_markers()
+            .iter()
+            .find(|(_, known)| *known == close)
+            .map_or(SplitState::Start, |&(_, known)| {
+                SplitState::Reasoning(known)
+            });
+        Self {
+            pending: String::new(),
+            state,
+        }
+    }
+
+    /// Returns `(is_reasoning, text)` segments ready to be emitted.
+    pub(super) fn push(&mut self, text: &str) -> Vec<(bool, String)> {
+        let mut out = Vec::new();
+        self.pending.push_str(text);
+        loop {
+            match self.state {
+                SplitState::Start => {
+                    let trimmed = self.pending.trim_start();
+                    if trimmed.is_empty() {
+                        return out;
+                    }
+                    let markers = crate::server::parser::reasoning_markers();
+                    if let Some(&(open, close)) =
+                        markers.iter().find(|(open, _)| trimmed.starts_with(open))
+                    {
+                        self.pending = trimmed[open.len()..].trim_start().to_string();
+                        self.state = SplitState::Reasoning(close);
+                        continue;
+                    }
+                    if markers.iter().any(|(open, _)| open.starts_with(trimmed)) {
+                        return out;
+                    }
+                    self.state = SplitState::Content;
+                }
+                SplitState::Reasoning(close) => {
+                    if let Some(idx) = self.pending.find(close) {
+                        let rest = self.pending[idx + close.len()..]
+                            .trim_start_matches('\n')
+                            .to_string();
+                        push_segment(&mut out, true, self.pending[..idx].trim_end());
+                        self.pending = rest;
+                        self.state = SplitState::Content;
+                        continue;
+                    }
+                    let keep = (1..close.len())
+                        .rev()
+                        .find(|&n| close.is_char_boundary(n) && self.pending.ends_with(&close[..n]))
+                        .unwrap_or(0);
+                    let emit = self.pending.len() - keep;
+                    push_segment(&mut out, true, &self.pending[..emit]);
+                    self.pending.drain(..emit);
+                    return out;
+                }
+                SplitState::Content => {
+                    push_segment(&mut out, false, &self.pending);
+                    self.pending.clear();
+                    return out;
+                }
+            }
+        }
+    }
+
+    pub(super) fn finish(&mut self) -> Vec<(bool, String)> {
+        let mut out = Vec::new();
+        let is_reasoning = matches!(self.state, SplitState::Reasoning(_));
+        push_segment(&mut out, is_reasoning, &std::mem::take(&mut self.pending));
+        out
+    }
+}
+
+fn push_segment(out: &mut Vec<(bool, String)>, is_reasoning: bool, text: &str) {
+    if !text.is_empty() {
+        out.push((is_reasoning, text.to_string()));
+    }
+}
+
+/// Emits Responses SSE events and tracks the output items they describe.
+struct ResponseEventWriter {
+    tx: flume::Sender<ChatResponse>,
+    sequence_number: usize,
+    output: Vec<ResponseOutputItem>,
+    /// The reasoning or message item receiving deltas: (is_reasoning, id, text)
+    open: Option<(bool, String, String)>,
+}
+
+impl ResponseEventWriter {
+    fn new(tx: flume::Sender<ChatResponse>) -> Self {
+        Self {
+            tx,
+            sequence_number: 0,
+            output: Vec::new(),
+            open: None,
+        }
+    }
+
+    /// Returns false if the client disconnected.
+    fn send(&mut self, event_type: &'static str, mut body: Value) -> bool {
+        body["type"] = json!(event_type);
+        body["sequence_number"] = json!(self.sequence_number);
+        self.sequence_number += 1;
+        self.tx
+            .try_send(ChatResponse::ResponseEvent(event_type, body))
+            .is_ok()
+    }
+
+    fn send_response(&mut self, event_type: &'static str, response: &ResponseObject) -> bool {
+        self.send(event_type, json!({ "response": response }))
+    }
+
+    fn delta(&mut self, is_reasoning: bool, text: &str) -> bool {
+        if self
+            .open
+            .as_ref()
+            .is_some_and(|(open_reasoning, _, _)| *open_reasoning != is_reasoning)
+        {
+            self.close();
+        }
+        let output_index = self.output.len();
+        if self.open.is_none() {
+            let id = if is_reasoning {
+                format!("rs_{}", item_suffix())
+            } else {
+                format!("msg_{}", item_suffix())
+            };
+            let item = if is_reasoning {
+                ResponseOutputItem::reasoning(id.clone(), String::new(), "in_progress")
+            } else {
+                ResponseOutputItem::message(id.clone(), String::new(), "in_progress")
+            };
+            self.send(
+                "response.output_item.added",
+                json!({ "output_index": output_index, "item": item }),
+            );
+            if !is_reasoning {
+                self.send(
+                    "response.content_part.added",
+                    json!({
+                        "item_id": id,
+                        "output_index": output_index,
+                        "content_index": 0,
+                        "part": OutputText::new(String::new()),
+                    }),
+                );
+            }
+            self.open = Some((is_reasoning, id, String::new()));
+        }
+        let Some((_, id, buffer)) = self.open.as_mut() else {
+            return true;
+        };
+        buffer.push_str(text);
+        let id = id.clone();
+        if is_reasoning {
+            self.send(
+                "response.reasoning_text.delta",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "delta": text,
+                }),
+            )
+        } else {
+            self.send(
+                "response.output_text.delta",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "delta": text,
+                    "logprobs": [],
+                }),
+            )
+        }
+    }
+
+    fn close(&mut self) {
+        let Some((is_reasoning, id, text)) = self.open.take() else {
+            return;
+        };
+        let output_index = self.output.len();
+        let item = if is_reasoning {
+            self.send(
+                "response.reasoning_text.done",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "text": text,
+                }),
+            );
+            ResponseOutputItem::reasoning(id, text, "completed")
+        } else {
+            self.send(
+                "response.output_text.done",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "text": text,
+                    "logprobs": [],
+                }),
+            );
+            self.send(
+                "response.content_part.done",
+                json!({
+                    "item_id": id,
+                    "output_index": output_index,
+                    "content_index": 0,
+                    "part": OutputText::new(text.clone()),
+                }),
+            );
+            ResponseOutputItem::message(id, text, "completed")
+        };
+        self.send(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+        self.output.push(item);
+    }
+
+    fn function_call(&mut self, call: &PublicToolCall) {
+        self.close();
+        let output_index = self.output.len();
+        let arguments = call.function.arguments.clone().unwrap_or_default();
+        let item = ResponseOutputItem::function_call(call, arguments.clone());
+        let ResponseOutputItem::FunctionCall { id, call_id, .. } = &item else {
+            return;
+        };
+        self.send(
+            "response.output_item.added",
+            json!({
+                "output_index": output_index,
+                "item": {
+                    "type": "function_call",
+                    "id": id,
+                    "call_id": call_id,
+                    "name": call.function.name,
+                    "arguments": "",
+                    "status": "in_progress",
+                },
+            }),
+        );
+        self.send(
+            "response.function_call_arguments.delta",
+            json!({ "item_id": id, "output_index": output_index, "delta": arguments }),
+        );
+        self.send(
+            "response.function_call_arguments.done",
+            json!({
+                "item_id": id,
+                "output_index": output_index,
+                "name": call.function.name,
+                "arguments": arguments,
+            }),
+        );
+        self.send(
+            "response.output_item.done",
+            json!({ "output_index": output_index, "item": item }),
+        );
+        self.output.push(item);
+    }
+}
+
+#[utoipa::path(
+    post,
+    tag = "vllm-rs",
+    path = "/v1/responses",
+    request_body = ResponsesRequest,
+    responses((status = 200, description = "Responses"))
+)]
+pub async fn create_response(
+    State(data): State<Arc<ServerData>>,
+    request: Json<ResponsesRequest>,
+) -> ChatResponder {
+    let request = request.0;
+    let use_stream = request.stream.unwrap_or(false);
+    let store = request.store.unwrap_or(true);
+    let max_tokens = request
+        .max_output_tokens
+        .unwrap_or(data.econfig.max_tokens.unwrap_or(16384));
+
+    let owner = auth::current_owner();
+    let previous = match &request.previous_response_id {
+        Some(id) => match data.response_store.get(id, owner.as_deref()) {
+            Some(previous) => Some(previous),
+            None => return ChatResponder::NotFound(format!("Previous response '{id}' not found")),
+        },
+        None => None,
+    };
+    let tools = match request
+        .tools
+        .iter()
+        .flatten()
+        .map(ResponseTool::to_tool)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(tools) => tools,
+        Err(err) => return ChatResponder::ValidationError(err),
+    };
+    let new_messages = match input_messages(request.input.clone()) {
+        Ok(messages) => messages,
+        Err(err) => return ChatResponder::ValidationError(err),
+    };
+    let mut conversation = previous
+        .as_ref()
+        .map(|previous| previous.messages.clone())
+        .unwrap_or_default();
+    conversation.extend(new_messages);
+    let session_id = previous
+        .as_ref()
+        .map(|previous| previous.session_id.clone())
+        .unwrap_or_else(|| format!("resp-session-{}", item_suffix()));
+
+    let mut messages = Vec::with_capacity(conversation.len() + 1);
+    if let Some(instructions) = &request.instructions {
+        messages.push(ChatMessage::text("system", instructions.clone()));
+    }
+    messages.extend(conversation.iter().cloned());
+    let chat_request = ChatCompletionRequest {
+        messages,
+        model: request.model.clone(),
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_tokens: Some(max_tokens),
+        stream: Some(use_stream),
+        stream_options: Some(StreamOptions {
+            include_usage: true,
+        }),
+        session_id: Some(session_id.clone()),
+        tools: (!tools.is_empty()).then_some(tools),
+        tool_choice: request
+            .tool_choice
+            .as_ref()
+            .map(ResponseToolChoice::to_tool_choice),
+        response_format: request
+            .text
+            .as_ref()
+            .and_then(|text| text.format.as_ref())
+            .and_then(TextFormat::to_response_format),
+        reasoning_effort: request
+            .reasoning
+            .as_ref()
+            .and_then(|reasoning| reasoning.effort.clone()),
+        priority: request.priority,
+        ..Default::default()
+    };
+
+    let mut response = ResponseObject {
+        id: format!("resp_{}", item_suffix()),
+        object: "response",
+        created_at: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as u64,
+        status: "in_progress",
+        incomplete_details: None,
+        error: None,
+        model: request.model.clone().unwrap_or("default".to_string()),
+        instructions: request.instructions.clone(),
+        previous_response_id: request.previous_response_id.clone(),
+        output: Vec::new(),
+        tools: request.tools.clone().unwrap_or_default(),
+        tool_choice: request
+            .tool_choice
+            .as_ref()
+            .map(|choice| json!(choice))
+            .unwrap_or(json!("auto")),
+        parallel_tool_calls: true,
+        temperature: request.temperature,
+        top_p: request.top_p,
+        max_output_tokens: request.max_output_tokens,
+        reasoning: request.reasoning.clone(),
+        text: request.text.clone(),
+        store,
+        metadata: request.metadata.clone().unwrap_or_default(),
+        usage: None,
+    };
+    let save = move |data: &ServerData, response: &ResponseObject, conversation| {
+        if store {
+            data.response_store.insert(StoredResponse {
+                response: response.clone(),
+                messages: conversation,
+                session_id,
+                owner,
+            });
+        }
+    };
+
+    if !use_stream {
+        let completion = match chat_completion(State(data.clone()), Json(chat_request)).await {
+            ChatResponder::Completion(completion) => completion,
+            other => return other,
+        };
+        let Some(choice) = completion.choices.into_iter().next() else {
+            return ChatResponder::InternalError("Chat completion returned no choices".into());
+        };
+        let (reasoning, content) =
+            split_reasoning(choice.message.content, choice.message.reasoning_content);
+        let mut output = Vec::new();
+        if let Some(reasoning) = reasoning {
+            output.push(ResponseOutputItem::reasoning(
+                format!("rs_{}", item_suffix()),
+                reasoning,
+                "completed",
+            ));
+        }
+        if let Some(content) = content {
+            output.push(ResponseOutputItem::message(
+                format!("msg_{}", item_suffix()),
+                content,
+                "completed",
+            ));
+        }
+        for call in choice.message.tool_calls.iter().flatten() {
+            output.push(ResponseOutputItem::function_call(
+                call,
+                call.function.arguments.clone().unwrap_or_default(),
+            ));
+        }
+        let usage = ResponseUsage::new(
+            completion.usage.prompt_tokens,
+            completion.usage.completion_tokens,
+        );
+        let truncated = completion.usage.completion_tokens >= max_tokens;
+        response.finish(output, usage, truncated);
+        conversation.push(assistant_message(&response.output));
+        save(data.as_ref(), &response, conversation);
+        return ChatResponder::Response(response);
+    }
+
+    let mut inner = match chat_completion(State(data.clone()), Json(chat_request)).await {
+        ChatResponder::Streamer(streamer) => streamer,
+        other => return other,
+    };
+    let (response_tx, client_rx) = flume::unbounded();
+    let (disconnect_tx, mut disconnect_rx) = watch::channel(false);
+
+    task::spawn(async move {
+        let mut events = ResponseEventWriter::new(response_tx.clone());
+        let mut splitter = ReasoningSplitter::default();
+        let mut tool_calls: Vec<PublicToolCall> = Vec::new();
+        let mut usage = ResponseUsage::default();
+        let mut finish_reason = None;
+        let mut error = None;
+
+        let mut connected = events.send_response("response.created", &response)
+            && events.send_response("response.in_progress", &response);
+        while connected {
+            let item = tokio::select! {
+                item = inner.rx.recv_async() => item,
+                res = disconnect_rx.changed() => {
+                    if res.is_err() || *disconnect_rx.borrow() {
+                        // Dropping the chat stream cancels the sequence
+                        connected = false;
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let Ok(item) = item else {
+                break;
+            };
+            let chunk = match item {
+                ChatResponse::Chunk(chunk) => chunk,
+                ChatResponse::Done => {
+                    inner.status = StreamingStatus::Stopped;
+                    break;
+                }
+                ChatResponse::InternalError(e)
+                | ChatResponse::ValidationError(e)
+                | ChatResponse::ModelError(e) => {
+                    error = Some(e);
+                    continue;
+                }
+                ChatResponse::CompletionChunk(_) | ChatResponse::ResponseEvent(..) => continue,
+            };
+            if let Some(chunk_usage) = &chunk.usage {
+                usage =
+                    ResponseUsage::new(chunk_usage.prompt_tokens, chunk_usage.completion_tokens);
+            }
+            let Some(choice) = chunk.choices.into_iter().next() else {
+                continue;
+            };
+            if let Some(message) = choice
+                .error
+                .and_then(|errors| errors.into_iter().find_map(|e| e.message))
+            {
+                error = Some(message);
+            }
+            if let Some(reasoning) = choice.delta.reasoning_content {
+                connected &= events.delta(true, &reasoning);
+            }
+            if let Some(content) = choice.delta.content {
+                for (is_reasoning, text) in splitter.push(&content) {
+                    connected &= events.delta(is_reasoning, &text);
+                }
+            }
+            tool_calls.extend(choice.delta.tool_calls.into_iter().flatten());
+            if choice.finish_reason.is_some() {
+                finish_reason = choice.finish_reason;
+            }
+        }
+        if !connected {
+            return;
+        }
+
+        for (is_reasoning, text) in splitter.finish() {
+            events.delta(is_reasoning, &text);
+        }
+        events.close();
+        for call in &tool_calls {
+            events.function_call(call);
+        }
+        let output = std::mem::take(&mut events.output);
+        match error {
+            Some(message) => {
+                crate::log_error!("[{}] Response failed: {}", response.id, message);
+                response.output = output;
+                response.fail(message);
+                events.send_response("response.failed", &response);
+            }
+            None => {
+                let truncated = finish_reason.as_deref() == Some("length");
+                response.finish(output, usage, truncated);
+                if truncated {
+                    events.send_response("response.incomplete", &response);
+                } else {
+                    events.send_response("response.completed", &response);
+                }
+                conversation.push(assistant_message(&response.output));
+                save(data.as_ref(), &response, conversation);
+            }
+        }
+        let _ = response_tx.try_send(ChatResponse::Done);
+    });
+
+    ChatResponder::Streamer(Streamer {
+        rx: client_rx,
+        status: StreamingStatus::Uninitialized,
+        disconnect_tx: Some(disconnect_tx),
+    })
+}
+
+#[utoipa::path(
+    get,
+    tag = "vllm-rs",
+    path = "/v1/responses/{response_id}",
+    params(("response_id" = String, Path, description = "Response id")),
+    responses((status = 200, description = "Stored response"))
+)]
+pub async fn get_response(
+    State(data): State<Arc<ServerData>>,
+    Path(response_id): Path<String>,
+) -> ChatResponder {
+    match data
+        .response_store
+        .get(&response_id, auth::current_owner().as_deref())
+    {
+        Some(stored) => ChatResponder::Response(stored.response.clone()),
+        None => ChatResponder::NotFound(format!("Response '{response_id}' not found")),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    tag = "vllm-rs",
+    path = "/v1/responses/{response_id}",
+    params(("response_id" = String, Path, description = "Response id")),
+    responses((status = 200, description = "Deleted response"))
+)]
+pub async fn delete_response(
+    State(data): State<Arc<ServerData>>,
+    Path(response_id): Path<String>,
+) -> ChatResponder {
+    if data
+        .response_store
+        .remove(&response_id, auth::current_owner().as_deref())
+    {
+        ChatResponder::ResponseDeleted(ResponseDeleted {
+            id: response_id,
+            object: "response.deleted",
+            deleted: true,
+        })
+    } else {
+        ChatResponder::NotFound(format!("Response '{response_id}' not found"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(json: Value) -> ResponseInput {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn input_items_become_chat_messages() {
+        let messages = input_messages(items(json!([
+            {"role": "developer", "content": "Be brief."},
+            {"type": "message", "role": "user", "content": [{"type": "input_text", "text": "Weather?"}]},
+            {"type": "reasoning", "id": "rs_1", "summary": [{"type": "summary_text", "text": "Need a tool."}]},
+            {"type": "function_call", "call_id": "call_1", "name": "weather", "arguments": "{}"},
+            {"type": "function_call", "call_id": "call_2", "name": "time", "arguments": "{}"},
+            {"type": "function_call_output", "call_id": "call_1", "output": "sunny"},
+        ])))
+        .unwrap();
+        let roles: Vec<_> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "tool"]);
+        assert_eq!(messages[2].tool_calls.as_ref().unwrap().len(), 2);
+        assert_eq!(
+            messages[2].reasoning_content.as_deref(),
+            Some("Need a tool.")
+        );
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_1"));
+
+        assert!(input_messages(items(json!([{"type": "web_search_call"}]))).is_err());
+        assert!(input_messages(items(json!([]))).is_err());
+    }
+
+    #[test]
+    fn streamed_reasoning_is_split_on_markers() {
+        let mut splitter = ReasoningSplitter::default();
+        let mut segments = Vec::new();
+        for piece in ["<th", "ink>plan", " it</th", "ink>\n\nAnswer", "."] {
+            segments.extend(splitter.push(piece));
+        }
+        segments.extend(splitter.finish());
+        let reasoning: String = segments
+            .iter()
+            .filter(|s| s.0)
+            .map(|s| s.1.as_str())
+            .collect();
+        let content: String = segments
+            .iter()
+            .filter(|s| !s.0)
+            .map(|s| s.1.as_str())
+            .collect();
+        assert_eq!(reasoning, "plan it");
+        assert_eq!(content, "Answer.");
+
+        let mut splitter = ReasoningSplitter::default();
+        assert_eq!(splitter.push("Hi"), vec![(false, "Hi".to_string())]);
+    }
+
+    #[test]
+    fn store_evicts_oldest_and_replays_assistant_turns() {
+        let store = ResponseStore::new(1);
+        let response = |id: &str| ResponseObject {
+            id: id.to_string(),
+            object: "response",
+            created_at: 0,
+            status: "completed",
+            incomplete_details: None,
+            error: None,
+            model: "default".to_string(),
+            instructions: None,
+            previous_response_id: None,
+            output: vec![ResponseOutputItem::message(
+                "msg_1".to_string(),
+                "hello".to_string(),
+                "completed",
+            )],
+            tools: Vec::new(),
+            tool_choice: json!("auto"),
+            parallel_tool_calls: true,
+            temperature: None,
+            top_p: None,
+            max_output_tokens: None,
+            reasoning: None,
+            text: None,
+            store: true,
+            metadata: HashMap::new(),
+            usage: None,
+        };
+        for id in ["resp_a", "resp_b"] {
+            let response = response(id);
+            let messages = vec![assistant_message(&response.output)];
+            store.insert(StoredResponse {
+                response,
+                messages,
+                session_id: "s".to_string(),
+                owner: Some("key-a".to_string()),
+            });
+        }
+        assert!(store.get("resp_a", Some("key-a")).is_none());
+        assert!(store.get("resp_b", None).is_none());
+        assert!(store.get("resp_b", Some("key-b")).is_none());
+        assert!(!store.remove("resp_b", Some("key-b")));
+        let stored = store.get("resp_b", Some("key-a")).unwrap();
+        assert!(matches!(
+            &stored.messages[0].content,
+            Some(MessageContentType::PureText(text)) if text == "hello"
+        ));
+        assert!(store.remove("resp_b", Some("key-a")));
+        assert!(!store.remove("resp_b", Some("key-a")));
+    }
+}
@@ -1,18 +1,22 @@
 // src/server/server.rs
 use super::logger::ChatCompletionLogger;
+use super::{
+    build_chat_token_logprob, parse_logit_bias, validate_logprobs_request,
+    validate_mirostat_dry_params, validate_truncation_params, ChatChoice, ChatChoiceChunk,
+    ChatCompletionChunk, ChatCompletionRequest, ChatCompletionResponse, ChatLogprobs, ChatMessage,
+    ChatResponseMessage, ChatTokenLogprob, CompletionTokensDetails, Delta, EmbeddingData,
+    EmbeddingOutput, EmbeddingUsage, ErrorMsg, ServerData, Usage, UsageQuery, UsageResponse,
+};
 use super::{
     build_guided_decoding_grammar, build_messages_and_images, collect_openai_constraint_grammar,
     normalize_reasoning_controls,
     streaming::{ChatResponse, Streamer, StreamingStatus},
     ChatResponder, DetokenizeRequest, DetokenizeResponse, EmbeddingRequest, EmbeddingResponse,
-    EncodingFormat, TokenizeInput, TokenizeRequest, TokenizeResponse,
+    EncodingFormat, LoglikelihoodData, LoglikelihoodRequest, LoglikelihoodResponse, TokenizeInput,
+    TokenizeRequest, TokenizeResponse,
 };
-use super::{
-    ChatChoice, ChatChoiceChunk, ChatCompletionChunk, ChatCompletionRequest,
-    ChatCompletionResponse, ChatMessage, ChatResponseMessage, Delta, EmbeddingData,
-    EmbeddingOutput, EmbeddingUsage, ErrorMsg, ServerData, Usage, UsageQuery, UsageResponse,
-};
-use crate::core::engine::{LLMEngine, StreamItem};
+use crate::core::beam_search::MAX_BEAM_WIDTH;
+use crate::core::engine::{LLMEngine, StreamItem, BAD_WORDS_UNSUPPORTED};
 use crate::server::parser::{BufferedFinalizeResult, StreamResult, StreamToolParser};
 use crate::tools::helpers::{
     build_invalid_tool_call_feedback, build_tool_schema_map, filter_tool_calls, log_tool_calls,
@@ -21,10 +25,7 @@ use crate::tools::helpers::{
 use crate::tools::{ToolChoice, ToolChoiceMode};
 use crate::utils::config::SamplingParams;
 use crate::utils::guidance::ReasoningEffort;
-use axum::{
-    extract::{Json, Query, State},
-    response::{sse::KeepAlive, Sse},
-};
+use axum::extract::{Json, Query, State};
 use base64::Engine;
 use std::collections::HashSet;
 use std::env;
@@ -41,6 +42,8 @@ struct StreamingContext {
     model_id: String,
     created: u64,
     response_tx: flume::Sender<ChatResponse>,
+    /// Token logprobs not yet attached to an emitted chunk (e.g. while the tool parser buffers)
+    pending_logprobs: parking_lot::Mutex<Vec<ChatTokenLogprob>>,
 }
 
 /// Routes streaming tokens to either `content` or `reasoning_content` in SSE
@@ -261,6 +264,23 @@ impl StreamingContext {
             model_id,
             created,
             response_tx,
+            pending_logprobs: parking_lot::Mutex::new(Vec::new()),
+        }
+    }
+
+    fn push_logprobs(&self, logprobs: ChatTokenLogprob) {
+        self.pending_logprobs.lock().push(logprobs);
+    }
+
+    /// Drain pending logprobs so they are attached to the next chunk sent.
+    fn take_logprobs(&self) -> Option<ChatLogprobs> {
+        let mut pending = self.pending_logprobs.lock();
+        if pending.is_empty() {
+            None
+        } else {
+            Some(ChatLogprobs {
+                content: std::mem::take(&mut *pending),
+            })
         }
     }
 
@@ -281,6 +301,7 @@ impl StreamingContext {
                 },
                 finish_reason: None,
                 error: None,
+                logprobs: self.take_logprobs(),
             }],
             usage: None,
         };
@@ -306,6 +327,7 @@ impl StreamingContext {
                 },
                 finish_reason: None,
                 error: None,
+                logprobs: self.take_logprobs(),
             }],
             usage: None,
         };
@@ -331,6 +353,7 @@ impl StreamingContext {
                 },
                 finish_reason: None,
                 error: None,
+                logprobs: None,
             }],
             usage: None,
         };
@@ -384,6 +407,114 @@ pub async fn chat_completion(
     params.session_id = request.session_id.clone();
     params.thinking = request.thinking.clone();
     params.stop_sequences = request.stop.clone();
+    if let Err(err) = validate_logprobs_request(request.logprobs, request.top_logprobs) {
+        return ChatResponder::ValidationError(err);
+    }
+    params.logprobs = request.logprobs;
+    params.top_logprobs = request.top_logprobs;
+    match request.n {
+        Some(0) => {
+            return ChatResponder::ValidationError("n must be at least 1".to_string());
+        }
+        Some(n) if n > 1 && use_stream => {
+            return ChatResponder::ValidationError(
+                "n > 1 is not supported with stream=true".to_string(),
+            );
+        }
+        _ => {}
+    }
+    params.n = request.n;
+    if let Err(err) = validate_truncation_params(
+        request.min_p,
+        request.typical_p,
+        request.epsilon_cutoff,
+        request.eta_cutoff,
+    ) {
+        return ChatResponder::ValidationError(err);
+    }
+    params.min_p = request.min_p;
+    params.typical_p = request.typical_p;
+    params.epsilon_cutoff = request.epsilon_cutoff;
+    params.eta_cutoff = request.eta_cutoff;
+    if let Some(penalty) = request.repetition_penalty {
+        if penalty.is_nan() || penalty <= 0.0 {
+            return ChatResponder::ValidationError(format!(
+                "repetition_penalty must be positive, got {}",
+                penalty
+            ));
+        }
+    }
+    params.repetition_penalty = request.repetition_penalty;
+    params.no_repeat_ngram_size = request.no_repeat_ngram_size;
+    if let Some(logit_bias) = &request.logit_bias {
+        match parse_logit_bias(logit_bias) {
+            Ok(bias) => params.logit_bias = Some(bias),
+            Err(err) => return ChatResponder::ValidationError(err),
+        }
+    }
+    params.bad_words = request.bad_words.clone();
+    if params.uses_output_history() && !data.engine.read().supports_bad_words() {
+        return ChatResponder::ValidationError(BAD_WORDS_UNSUPPORTED.to_string());
+    }
+    params.seed = request.seed;
+    params.priority = request.priority;
+    if let Some(min_tokens) = request.min_tokens {
+        if min_tokens > max_tokens {
+            return ChatResponder::ValidationError(format!(
+                "min_tokens ({}) must not exceed max_tokens ({})",
+                min_tokens, max_tokens
+            ));
+        }
+    }
+    params.min_tokens = request.min_tokens;
+    if let Err(err) = validate_mirostat_dry_params(
+        request.mirostat,
+        request.mirostat_tau,
+        request.mirostat_eta,
+        request.dry_multiplier,
+        request.dry_base,
+    ) {
+        return ChatResponder::ValidationError(err);
+    }
+    params.mirostat = request.mirostat;
+    params.mirostat_tau = request.mirostat_tau;
+    params.mirostat_eta = request.mirostat_eta;
+    params.dry_multiplier = request.dry_multiplier;
+    params.dry_base = request.dry_base;
+    params.dry_allowed_length = request.dry_allowed_length;
+    params.dry_penalty_last_n = request.dry_penalty_last_n;
+    params.dry_sequence_breakers = request.dry_sequence_breakers.clone();
+    if let Some(prediction) = &request.prediction {
+        match prediction.text() {
+            Ok(text) => params.prediction = Some(text),
+            Err(err) => return ChatResponder::ValidationError(err),
+        }
+    }
+    match request.beam_width {
+        Some(0) => {
+            return ChatResponder::ValidationError("beam_width must be at least 1".to_string());
+        }
+        Some(width) if width > MAX_BEAM_WIDTH => {
+            return ChatResponder::ValidationError(format!(
+                "beam_width must be at most {}",
+                MAX_BEAM_WIDTH
+            ));
+        }
+        Some(_) if use_stream => {
+            return ChatResponder::ValidationError(
+                "beam search is not supported with stream=true".to_string(),
+            );
+        }
+        Some(_) if request.logprobs.unwrap_or(false) => {
+            return ChatResponder::ValidationError(
+                "beam search does not support logprobs".to_string(),
+            );
+        }
+        _ => {}
+    }
+    params.beam_width = request.beam_width;
+    params.length_penalty = request.length_penalty;
+    params.early_stopping = request.early_stopping;
     params.reasoning_effort = request
         .reasoning_effort
         .clone()
@@ -541,11 +672,18 @@ pub async fn chat_completion(
             l.log_start_response();
         }
         let stream_logger = logger.clone();
+        let logprobs_tokenizer = params
+            .logprobs
+            .unwrap_or(false)
+            .then(|| Arc::new(data.engine.read().tokenizer.clone()));
+        let has_prediction = params.prediction.is_some();
 
         task::spawn(async move {
             #[allow(unused_assignments)]
             let mut decode_start_time = 0u64;
             let mut total_decoded_tokens = 0usize;
+            let mut prediction_details: Option<CompletionTokensDetails> =
+                has_prediction.then(Default::default);
             let mut pending_tool_calls: Vec<crate::tools::ToolCall> = Vec::new();
             let mut suppressed_tool_markup: String = String::new();
             let mut buffering_since: Option<Instant> = None;
@@ -957,6 +1095,7 @@ pub async fn chat_completion(
                                     },
                                     finish_reason: None,
                                     error: None,
+                                    logprobs: None,
                                 }],
                                 usage: None,
                             };
@@ -1006,11 +1145,13 @@ pub async fn chat_completion(
                                     Some("stop".to_string())
                                 },
                                 error: None,
+                                logprobs: stream_ctx.take_logprobs(),
                             }],
                             usage: include_usage.then_some(Usage {
                                 prompt_tokens: prompt_length,
                                 completion_tokens: total_decoded_tokens,
                                 total_tokens: prompt_length + total_decoded_tokens,
+                                completion_tokens_details: prediction_details.take(),
                             }),
                         };
 
@@ -1063,6 +1204,18 @@ pub async fn chat_completion(
 
                         break;
                     }
+                    StreamItem::Logprobs(logprobs) => {
+                        if let Some(ref tokenizer) = logprobs_tokenizer {
+                            stream_ctx
+                                .push_logprobs(build_chat_token_logprob(tokenizer, &logprobs));
+                        }
+                    }
+                    StreamItem::Prediction((accepted, rejected)) => {
+                        prediction_details = Some(CompletionTokensDetails {
+                            accepted_prediction_tokens: accepted,
+                            rejected_prediction_tokens: rejected,
+                        });
+                    }
                     StreamItem::Error(e) => {
                         crate::log_error!("[Seq {}] Stream error: {}", current_seq_id, e);
                         let error_chunk = ChatCompletionChunk {
@@ -1080,6 +1233,7 @@ pub async fn chat_completion(
                                 },
                                 finish_reason: None,
                                 error: Some(vec![ErrorMsg { message: Some(e) }]),
+                                logprobs: None,
                             }],
                             usage: None,
                         };
@@ -1094,27 +1248,17 @@ pub async fn chat_completion(
             let _ = response_tx.try_send(ChatResponse::Done);
         });
 
-        ChatResponder::Streamer(
-            Sse::new(Streamer {
-                rx: client_rx,
-                status: StreamingStatus::Uninitialized,
-                disconnect_tx: Some(disconnect_tx),
-            })
-            .keep_alive(
-                KeepAlive::new()
-                    .interval(Duration::from_millis(
-                        env::var("KEEP_ALIVE_INTERVAL")
-                            .map(|val| val.parse::<u64>().unwrap_or(100))
-                            .unwrap_or(100),
-                    ))
-                    .text("keep-alive-text"),
-            ),
-        )
+        ChatResponder::Streamer(Streamer {
+            rx: client_rx,
+            status: StreamingStatus::Uninitialized,
+            disconnect_tx: Some(disconnect_tx),
+        })
     } else {
         // Non-streaming
         let current_params = params.clone();
         let mut total_prompt_tokens = 0;
         let mut total_decoded_tokens = 0;
+        let mut prediction_details: Option<CompletionTokensDetails> = None;
         let mut total_prompt_time_taken = 0f32;
         let mut total_decoded_time_taken = 0f32;
         let mut choices = Vec::new();
@@ -1157,14 +1301,29 @@ pub async fn chat_completion(
                 }
             };
 
-        for output in results {
-            total_prompt_tokens += output.prompt_length;
+        let want_logprobs = current_params.logprobs.unwrap_or(false);
+        for (index, output) in results.into_iter().enumerate() {
+            let logprobs = want_logprobs.then(|| ChatLogprobs {
+                content: output
+                    .logprobs
+                    .iter()
+                    .map(|l| build_chat_token_logprob(&tokenizer, l))
+                    .collect(),
+            });
             total_decoded_tokens += output.decoded_length;
-            let prompt_time_taken =
-                (output.decode_start_time - output.prompt_start_time) as f32 / 1000.0;
+            if current_params.prediction.is_some() {
+                let details = prediction_details.get_or_insert_with(Default::default);
+                details.accepted_prediction_tokens += output.accepted_prediction_tokens;
+                details.rejected_prediction_tokens += output.rejected_prediction_tokens;
+            }
             let decode_time_taken =
                 (output.decode_finish_time - output.decode_start_time) as f32 / 1000.0;
-            total_prompt_time_taken += prompt_time_taken;
+            // All choices share a single prefill of the prompt
+            if index == 0 {
+                total_prompt_tokens = output.prompt_length;
+                total_prompt_time_taken =
+                    (output.decode_start_time - output.prompt_start_time) as f32 / 1000.0;
+            }
             total_decoded_time_taken += decode_time_taken;
 
             // Parse tool calls from the model output if tools were provided
@@ -1269,7 +1428,7 @@ pub async fn chat_completion(
                     (content, None)
                 };
             choices.push(ChatChoice {
-                index: 0,
+                index,
                 message: ChatResponseMessage {
                     role: "assistant".to_string(),
                     content,
@@ -1281,6 +1440,7 @@ pub async fn chat_completion(
                 } else {
                     Some("stop".to_string())
                 },
+                logprobs,
             });
         }
 
@@ -1310,6 +1470,7 @@ pub async fn chat_completion(
                 prompt_tokens: total_prompt_tokens,
                 completion_tokens: total_decoded_tokens,
                 total_tokens: total_prompt_tokens + total_decoded_tokens,
+                completion_tokens_details: prediction_details,
             },
         };
 
@@ -1385,6 +1546,69 @@ pub async fn create_embeddings(
     })
 }
 
+#[utoipa::path(
+    post,
+    tag = "vllm-rs",
+    path = "/v1/loglikelihood",
+    request_body = LoglikelihoodRequest,
+    responses((status = 200, description = "Prompt token logprobs and perplexity"))
+)]
+pub async fn loglikelihood(
+    State(data): State<Arc<ServerData>>,
+    request: Json<LoglikelihoodRequest>,
+) -> ChatResponder {
+    let LoglikelihoodRequest {
+        model,
+        input,
+        top_logprobs,
+    } = request.0;
+    let inputs = input.into_vec();
+    if inputs.is_empty() {
+        return ChatResponder::ValidationError("input cannot be empty".to_string());
+    }
+    let top_n = top_logprobs.unwrap_or(0);
+    if let Err(e) = validate_logprobs_request(Some(true), Some(top_n)) {
+        return ChatResponder::ValidationError(e);
+    }
+
+    let model_name = model.unwrap_or_else(|| "default".to_string());
+
+    let (scores, prompt_tokens, tokenizer) = {
+        let mut engine = data.engine.write();
+        match engine.score(&inputs, top_n) {
+            Ok((scores, prompt_tokens)) => (scores, prompt_tokens, engine.tokenizer.clone()),
+            Err(e) => return ChatResponder::ModelError(format!("Prompt scoring failed: {e:?}")),
+        }
+    };
+
+    let data: Vec<LoglikelihoodData> = scores
+        .iter()
+        .enumerate()
+        .map(|(idx, score)| LoglikelihoodData {
+            object: "loglikelihood",
+            index: idx,
+            num_tokens: score.logprobs.len(),
+            total_logprob: score.total_logprob(),
+            perplexity: score.perplexity(),
+            tokens: score
+                .logprobs
+                .iter()
+                .map(|lp| build_chat_token_logprob(&tokenizer, lp))
+                .collect(),
+        })
+        .collect();
+
+    ChatResponder::Loglikelihood(LoglikelihoodResponse {
+        object: "list",
+        data,
+        model: model_name,
+        usage: EmbeddingUsage {
+            prompt_tokens,
+            total_tokens: prompt_tokens,
+        },
+    })
+}
+
 #[utoipa::path(
     get,
     tag = "vllm-rs",
@@ -1415,6 +1639,23 @@ pub async fn get_usage(
     })
 }
 
+#[utoipa::path(
+    get,
+    tag = "vllm-rs",
+    path = "/metrics",
+    responses((status = 200, description = "Prometheus Metrics"))
+)]
+pub async fn metrics(State(state): State<Arc<ServerData>>) -> impl axum::response::IntoResponse {
+    let gauges = state.engine.read().engine_gauges();
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4; charset=utf-8",
+        )],
+        crate::utils::metrics::metrics().render(&gauges),
+    )
+}
+
 #[utoipa::path(
     post,
     tag = "vllm-rs",
@@ -1559,6 +1800,29 @@ mod tests {
         deltas
     }
 
+    #[test]
+    fn streaming_context_attaches_pending_logprobs_to_next_chunk() {
+        let (ctx, rx) = make_test_ctx();
+        let entry = |token: &str| ChatTokenLogprob {
+            token: token.to_string(),
+            logprob: -1.0,
+            bytes: Some(token.as_bytes().to_vec()),
+            top_logprobs: Vec::new(),
+        };
+        ctx.push_logprobs(entry("Hel"));
+        ctx.push_logprobs(entry("lo"));
+        assert!(ctx.send_token("Hello"));
+        assert!(ctx.send_token("!"));
+
+        let mut attached = Vec::new();
+        while let Ok(ChatResponse::Chunk(chunk)) = rx.try_recv() {
+            attached.push(chunk.choices[0].logprobs.clone());
+        }
+        assert_eq!(attached.len(), 2);
+        assert_eq!(attached[0].as_ref().map(|l| l.content.len()), Some(2));
+        assert!(attached[1].is_none());
+    }
+
     #[test]
     fn reasoning_router_disabled_sends_all_as_content() {
         let (ctx, rx) = make_test_ctx();
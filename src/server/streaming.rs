@@ -1,3 +1,4 @@
+use super::completions::CompletionResponse;
 use super::ChatCompletionChunk;
 use axum::response::sse::Event;
 use flume::Receiver;
@@ -20,6 +21,9 @@ pub enum ChatResponse {
     ValidationError(String),
     ModelError(String),
     Chunk(ChatCompletionChunk),
+    CompletionChunk(CompletionResponse),
+    /// Responses API event, sent with its type as the SSE event name
+    ResponseEvent(&'static str, serde_json::Value),
     Done, //finish flag
 }
 
@@ -57,6 +61,18 @@ impl Stream for Streamer {
                     }
                     Poll::Ready(Some(Event::default().json_data(response)))
                 }
+                ChatResponse::CompletionChunk(response) => {
+                    if self.status != StreamingStatus::Started {
+                        self.status = StreamingStatus::Started;
+                    }
+                    Poll::Ready(Some(Event::default().json_data(response)))
+                }
+                ChatResponse::ResponseEvent(event_type, body) => {
+                    if self.status != StreamingStatus::Started {
+                        self.status = StreamingStatus::Started;
+                    }
+                    Poll::Ready(Some(Event::default().event(event_type).json_data(body)))
+                }
                 ChatResponse::Done => {
                     self.status = StreamingStatus::Stopped;
                     Poll::Ready(Some(Ok(Event::default().data("[DONE]"))))
@@ -91,6 +91,11 @@ impl Communicator {
         }
     }
 
+    /// Whether the connection to the PD peer is currently established.
+    pub fn is_connected(&self) -> bool {
+        self.writer.lock().is_some()
+    }
+
     /// Internal method to receive a message.
     /// This is called from the *listener thread* in a loop.
     fn receive(&self) -> Result<TransferMessage> {
@@ -189,6 +189,10 @@ impl Transfer {
         matches!(self.config.role, PdRole::Server)
     }
 
+    pub fn is_connected(&self) -> bool {
+        self.communicator.is_connected()
+    }
+
     // --- Client-side API ---
 
     /// (Client) Sends a sequence to the PDServer for prefill.
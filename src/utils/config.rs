@@ -1,4 +1,5 @@
 // src/utils/config.rs
+use crate::core::spec_decode::SpeculativeConfig;
 use crate::transfer::PdConfig;
 use crate::utils::reasoning::ReasoningEffort;
 use llguidance::api::TopLevelGrammar;
@@ -279,6 +280,21 @@ impl Config {
                 .as_ref()
                 .is_some_and(|cfg| matches!(cfg.quant_method.as_str(), "mxfp4" | "nvfp4"))
     }
+
+    /// Multi-token prediction layers stored after the decoder layers (`num_nextn_predict_layers`,
+    /// or `mtp_num_hidden_layers` for Qwen3.5, which may nest it in `text_config`)
+    pub fn num_nextn_predict_layers(&self) -> usize {
+        self.extra_config_json
+            .as_ref()
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+            .and_then(|extra| {
+                let text = extra.get("text_config").unwrap_or(&extra);
+                ["num_nextn_predict_layers", "mtp_num_hidden_layers"]
+                    .iter()
+                    .find_map(|key| text.get(*key).or_else(|| extra.get(*key))?.as_u64())
+            })
+            .unwrap_or(0) as usize
+    }
 }
 #[cfg(not(feature = "python"))]
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -319,12 +335,28 @@ pub struct EngineConfig {
     pub mcp_command: Option<String>,
     pub mcp_config: Option<String>,
     pub mcp_args: Option<Vec<String>>,
+    /// JSON file of API keys; authentication is off when unset
+    #[serde(default)]
+    pub api_keys: Option<String>,
+    /// Name of the scheduling policy (see `scheduling_policy::SCHEDULING_POLICIES`), FCFS when unset
+    #[serde(default)]
+    pub scheduling_policy: Option<String>,
+    /// Server queue admission limits (see `server::backpressure`); the
+    /// `VLLM_RS_MAX_WAITING_*` / `VLLM_RS_MAX_QUEUE_WAIT_SECS` variables apply when unset
+    #[serde(default)]
+    pub max_waiting_requests: Option<usize>,
+    #[serde(default)]
+    pub max_waiting_tokens: Option<usize>,
+    #[serde(default)]
+    pub max_queue_wait_secs: Option<u64>,
     pub tool_prompt_template: Option<String>,
     pub pd_server_prefix_cache_ratio: Option<f32>,
     pub pd_client_prefix_cache_ratio: Option<f32>,
     pub yarn_scaling_factor: Option<f64>,
     #[serde(default)]
     pub disable_reasoning: bool,
+    #[serde(default)]
+    pub speculative_config: Option<SpeculativeConfig>,
 }
 
 #[cfg(feature = "python")]
@@ -397,6 +429,21 @@ pub struct EngineConfig {
     #[pyo3(get, set)]
     pub mcp_args: Option<Vec<String>>,
     #[pyo3(get, set)]
+    #[serde(default)]
+    pub api_keys: Option<String>,
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub scheduling_policy: Option<String>,
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub max_waiting_requests: Option<usize>,
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub max_waiting_tokens: Option<usize>,
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub max_queue_wait_secs: Option<u64>,
+    #[pyo3(get, set)]
     pub tool_prompt_template: Option<String>,
     #[pyo3(get, set)]
     pub pd_server_prefix_cache_ratio: Option<f32>,
@@ -406,6 +453,8 @@ pub struct EngineConfig {
     pub yarn_scaling_factor: Option<f64>,
     #[pyo3(get, set)]
     pub disable_reasoning: bool,
+    #[pyo3(get, set)]
+    pub speculative_config: Option<SpeculativeConfig>,
 }
 
 #[cfg(not(feature = "python"))]
@@ -442,6 +491,12 @@ impl EngineConfig {
         pd_client_prefix_cache_ratio: Option<f32>,
         yarn_scaling_factor: Option<f64>,
         disable_reasoning: bool,
+        speculative_config: Option<SpeculativeConfig>,
+        api_keys: Option<String>,
+        scheduling_policy: Option<String>,
+        max_waiting_requests: Option<usize>,
+        max_waiting_tokens: Option<usize>,
+        max_queue_wait_secs: Option<u64>,
     ) -> Self {
         let mut device_ids = device_ids.unwrap_or_default();
         if device_ids.is_empty() {
@@ -489,11 +544,17 @@ impl EngineConfig {
             mcp_command,
             mcp_config,
             mcp_args,
+            api_keys,
+            scheduling_policy,
+            max_waiting_requests,
+            max_waiting_tokens,
+            max_queue_wait_secs,
             tool_prompt_template,
             pd_server_prefix_cache_ratio,
             pd_client_prefix_cache_ratio,
             yarn_scaling_factor,
             disable_reasoning,
+            speculative_config,
         }
     }
 }
@@ -521,7 +582,8 @@ pub struct SamplingParams {
     pub presence_penalty: Option<f32>,
     #[serde(default)]
     pub stop_sequences: Option<Vec<String>>,
-    #[serde(skip)]
+    /// Tokenized `stop_sequences`, resolved by the engine
+    #[serde(default)]
     pub stop_token_ids: Option<Vec<Vec<u32>>>,
     #[serde(alias = "enable_thinking")]
     pub thinking: Option<bool>, // enable reasoning
@@ -541,6 +603,87 @@ pub struct SamplingParams {
     /// Reasoning effort level for OpenAI-compatible reasoning API
     #[serde(default)]
     pub reasoning_effort: Option<ReasoningEffort>,
+    /// Return the log-probability of each sampled token
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives returned per position (requires `logprobs`)
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+    /// Number of completions to sample for the prompt (default 1)
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// Beam width; enables deterministic beam search instead of sampling when set
+    #[serde(default)]
+    pub beam_width: Option<usize>,
+    /// Exponent applied to the generated length when ranking beam hypotheses (default 1.0)
+    #[serde(default)]
+    pub length_penalty: Option<f32>,
+    /// Stop beam search as soon as `beam_width` hypotheses are finished (default false)
+    #[serde(default)]
+    pub early_stopping: Option<bool>,
+    /// Drop tokens whose probability is below `min_p` times that of the most likely token
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    /// Locally typical sampling: probability mass of the most typical tokens to keep
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    /// Drop tokens whose probability is below this cutoff
+    #[serde(default)]
+    pub epsilon_cutoff: Option<f32>,
+    /// Eta sampling: drop tokens below `min(eta, sqrt(eta) * exp(-entropy))`
+    #[serde(default)]
+    pub eta_cutoff: Option<f32>,
+    /// HF-style multiplicative penalty for tokens already in the prompt or output (1.0 disables)
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    /// Ban tokens that would repeat an n-gram of this size from the prompt or output
+    #[serde(default)]
+    pub no_repeat_ngram_size: Option<usize>,
+    /// Additive bias per token id applied to the logits before sampling (OpenAI `logit_bias`)
+    #[serde(default)]
+    pub logit_bias: Option<HashMap<u32, f32>>,
+    /// Strings that must never appear in the generated text, also across token boundaries
+    #[serde(default)]
+    pub bad_words: Option<Vec<String>>,
+    /// Seed of this sequence's own sampling RNG, for output that does not depend on batching
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Minimum number of output tokens before EOS, stop token ids or stop sequences can end the sequence
+    #[serde(default)]
+    pub min_tokens: Option<usize>,
+    /// Mirostat mode as in llama.cpp; 2 enables Mirostat v2, 0 disables it
+    #[serde(default)]
+    pub mirostat: Option<usize>,
+    /// Mirostat target surprise in bits (default 5.0)
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    /// Mirostat learning rate (default 0.1)
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    /// DRY penalty multiplier, 0 disables the penalty
+    #[serde(default)]
+    pub dry_multiplier: Option<f32>,
+    /// DRY penalty growth per token beyond `dry_allowed_length` (default 1.75)
+    #[serde(default)]
+    pub dry_base: Option<f32>,
+    /// Longest repeated sequence that is not penalized by DRY (default 2)
+    #[serde(default)]
+    pub dry_allowed_length: Option<usize>,
+    /// Number of trailing tokens searched for DRY repeats (default: whole context)
+    #[serde(default)]
+    pub dry_penalty_last_n: Option<usize>,
+    /// Strings that end a repeated sequence for DRY (default newline, colon, quote and asterisk)
+    #[serde(default)]
+    pub dry_sequence_breakers: Option<Vec<String>>,
+    /// Tokenized `dry_sequence_breakers`, resolved by the engine
+    #[serde(default)]
+    pub dry_sequence_breaker_ids: Option<Vec<u32>>,
+    /// Expected output text (OpenAI predicted outputs), verified as a speculative draft
+    #[serde(default)]
+    pub prediction: Option<String>,
+    /// Scheduling priority, lower values are scheduled first (default 0)
+    #[serde(default)]
+    pub priority: Option<i32>,
 }
 
 #[cfg(feature = "python")]
@@ -566,7 +709,8 @@ pub struct SamplingParams {
     #[pyo3(get, set)]
     #[serde(default)]
     pub stop_sequences: Option<Vec<String>>,
-    #[serde(skip)]
+    /// Tokenized `stop_sequences`, resolved by the engine
+    #[serde(default)]
     pub stop_token_ids: Option<Vec<Vec<u32>>>,
     /// Tool mode for tool call handling.
     /// If Some(true), external tools are enabled and stream finishes at </tool_call>.
@@ -587,6 +731,113 @@ pub struct SamplingParams {
     pub grammar_json: Option<String>,
     /// Reasoning effort level for OpenAI-compatible reasoning API
     pub reasoning_effort: Option<ReasoningEffort>,
+    /// Return the log-probability of each sampled token
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    /// Number of most likely alternatives returned per position (requires `logprobs`)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+    /// Number of completions to sample for the prompt (default 1)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// Beam width; enables deterministic beam search instead of sampling when set
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub beam_width: Option<usize>,
+    /// Exponent applied to the generated length when ranking beam hypotheses (default 1.0)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub length_penalty: Option<f32>,
+    /// Stop beam search as soon as `beam_width` hypotheses are finished (default false)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub early_stopping: Option<bool>,
+    /// Drop tokens whose probability is below `min_p` times that of the most likely token
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub min_p: Option<f32>,
+    /// Locally typical sampling: probability mass of the most typical tokens to keep
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub typical_p: Option<f32>,
+    /// Drop tokens whose probability is below this cutoff
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub epsilon_cutoff: Option<f32>,
+    /// Eta sampling: drop tokens below `min(eta, sqrt(eta) * exp(-entropy))`
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub eta_cutoff: Option<f32>,
+    /// HF-style multiplicative penalty for tokens already in the prompt or output (1.0 disables)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    /// Ban tokens that would repeat an n-gram of this size from the prompt or output
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub no_repeat_ngram_size: Option<usize>,
+    /// Additive bias per token id applied to the logits before sampling (OpenAI `logit_bias`)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub logit_bias: Option<HashMap<u32, f32>>,
+    /// Strings that must never appear in the generated text, also across token boundaries
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub bad_words: Option<Vec<String>>,
+    /// Seed of this sequence's own sampling RNG, for output that does not depend on batching
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Minimum number of output tokens before EOS, stop token ids or stop sequences can end the sequence
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub min_tokens: Option<usize>,
+    /// Mirostat mode as in llama.cpp; 2 enables Mirostat v2, 0 disables it
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub mirostat: Option<usize>,
+    /// Mirostat target surprise in bits (default 5.0)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub mirostat_tau: Option<f32>,
+    /// Mirostat learning rate (default 0.1)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub mirostat_eta: Option<f32>,
+    /// DRY penalty multiplier, 0 disables the penalty
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub dry_multiplier: Option<f32>,
+    /// DRY penalty growth per token beyond `dry_allowed_length` (default 1.75)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub dry_base: Option<f32>,
+    /// Longest repeated sequence that is not penalized by DRY (default 2)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub dry_allowed_length: Option<usize>,
+    /// Number of trailing tokens searched for DRY repeats (default: whole context)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub dry_penalty_last_n: Option<usize>,
+    /// Strings that end a repeated sequence for DRY (default newline, colon, quote and asterisk)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub dry_sequence_breakers: Option<Vec<String>>,
+    /// Tokenized `dry_sequence_breakers`, resolved by the engine
+    #[serde(default)]
+    pub dry_sequence_breaker_ids: Option<Vec<u32>>,
+    /// Expected output text (OpenAI predicted outputs), verified as a speculative draft
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub prediction: Option<String>,
+    /// Scheduling priority, lower values are scheduled first (default 0)
+    #[pyo3(get, set)]
+    #[serde(default)]
+    pub priority: Option<i32>,
 }
 
 #[cfg(not(feature = "python"))]
@@ -619,6 +870,33 @@ impl SamplingParams {
             grammar: None,
             grammar_json: None,
             reasoning_effort,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            beam_width: None,
+            length_penalty: None,
+            early_stopping: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            repetition_penalty: None,
+            no_repeat_ngram_size: None,
+            logit_bias: None,
+            bad_words: None,
+            seed: None,
+            min_tokens: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            dry_multiplier: None,
+            dry_base: None,
+            dry_allowed_length: None,
+            dry_penalty_last_n: None,
+            dry_sequence_breakers: None,
+            dry_sequence_breaker_ids: None,
+            prediction: None,
+            priority: None,
         }
     }
 
@@ -639,6 +917,33 @@ impl SamplingParams {
             grammar: None,
             grammar_json: None,
             reasoning_effort: None,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            beam_width: None,
+            length_penalty: None,
+            early_stopping: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            repetition_penalty: None,
+            no_repeat_ngram_size: None,
+            logit_bias: None,
+            bad_words: None,
+            seed: None,
+            min_tokens: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            dry_multiplier: None,
+            dry_base: None,
+            dry_allowed_length: None,
+            dry_penalty_last_n: None,
+            dry_sequence_breakers: None,
+            dry_sequence_breaker_ids: None,
+            prediction: None,
+            priority: None,
         }
     }
 }
@@ -661,6 +966,77 @@ impl Default for SamplingParams {
             grammar: None,
             grammar_json: None,
             reasoning_effort: None,
+            logprobs: None,
+            top_logprobs: None,
+            n: None,
+            beam_width: None,
+            length_penalty: None,
+            early_stopping: None,
+            min_p: None,
+            typical_p: None,
+            epsilon_cutoff: None,
+            eta_cutoff: None,
+            repetition_penalty: None,
+            no_repeat_ngram_size: None,
+            logit_bias: None,
+            bad_words: None,
+            seed: None,
+            min_tokens: None,
+            mirostat: None,
+            mirostat_tau: None,
+            mirostat_eta: None,
+            dry_multiplier: None,
+            dry_base: None,
+            dry_allowed_length: None,
+            dry_penalty_last_n: None,
+            dry_sequence_breakers: None,
+            dry_sequence_breaker_ids: None,
+            prediction: None,
+            priority: None,
+        }
+    }
+}
+
+impl SamplingParams {
+    /// Whether sampling needs the full prompt and output tokens of the sequence.
+    pub fn uses_token_history(&self) -> bool {
+        self.repetition_penalty.is_some_and(|p| p != 1.0)
+            || self.no_repeat_ngram_size.unwrap_or(0) > 0
+            || self.dry_multiplier.is_some_and(|m| m > 0.0)
+    }
+
+    /// Whether a sequence with `output_len` tokens must not stop yet.
+    pub fn below_min_tokens(&self, output_len: usize) -> bool {
+        output_len < self.min_tokens.unwrap_or(0)
+    }
+
+    /// Whether sampling needs the output tokens of the sequence (`bad_words`).
+    pub fn uses_output_history(&self) -> bool {
+        self.bad_words
+            .as_ref()
+            .is_some_and(|words| words.iter().any(|w| !w.is_empty()))
+    }
+
+    /// Whether draft tokens of this request can be verified in one pass: each position must
+    /// be sampled independently of grammar state, the token history and Mirostat feedback.
+    pub fn allows_speculation(&self) -> bool {
+        self.grammar.is_none()
+            && !self.uses_token_history()
+            && !self.uses_output_history()
+            && self.mirostat.unwrap_or(0) == 0
+            && self.beam_width.unwrap_or(0) == 0
+            && !self.frequency_penalty.is_some_and(|p| p != 0.0)
+            && !self.presence_penalty.is_some_and(|p| p != 0.0)
+            && self.requested_top_logprobs().is_none()
+    }
+
+    /// Number of top alternatives to report per sampled token,
+    /// or `None` when logprobs were not requested.
+    pub fn requested_top_logprobs(&self) -> Option<usize> {
+        if self.logprobs.unwrap_or(false) {
+            Some(self.top_logprobs.unwrap_or(0))
+        } else {
+            None
         }
     }
 }
@@ -706,6 +1082,14 @@ pub struct GenerationConfig {
     pub frequency_penalty: Option<f32>,
     pub presence_penalty: Option<f32>,
 
+    pub min_p: Option<f32>,
+    pub typical_p: Option<f32>,
+    pub epsilon_cutoff: Option<f32>,
+    pub eta_cutoff: Option<f32>,
+
+    pub repetition_penalty: Option<f32>,
+    pub no_repeat_ngram_size: Option<usize>,
+
     pub bos_token_id: Option<usize>,
     pub eos_token_id: Option<EosTokenId>,
 }
@@ -957,6 +1341,33 @@ impl fmt::Debug for QuantConfig {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_min_tokens_params_survive_runner_transfer() {
+        let mut params = SamplingParams::new_with_max_tokens(64);
+        params.min_tokens = Some(4);
+        params.stop_token_ids = Some(vec![vec![13], vec![7, 8]]);
+        let params: SamplingParams =
+            serde_json::from_str(&serde_json::to_string(&params).unwrap()).unwrap();
+        assert_eq!(params.stop_token_ids, Some(vec![vec![13], vec![7, 8]]));
+        assert!(params.below_min_tokens(3));
+        assert!(!params.below_min_tokens(4));
+        assert!(!SamplingParams::new_with_max_tokens(64).below_min_tokens(0));
+    }
+
+    #[test]
+    fn test_zero_penalties_allow_speculation() {
+        let mut params = SamplingParams::new_with_max_tokens(64);
+        params.frequency_penalty = Some(0.0);
+        params.presence_penalty = Some(0.0);
+        params.repetition_penalty = Some(1.0);
+        assert!(params.allows_speculation());
+        params.presence_penalty = Some(0.5);
+        assert!(!params.allows_speculation());
+        params.presence_penalty = None;
+        params.frequency_penalty = Some(-0.1);
+        assert!(!params.allows_speculation());
+    }
+
     #[test]
     fn test_match_ignore_literal_exact() {
         assert!(match_ignore_pattern("lm_head", "lm_head"));
@@ -6,6 +6,20 @@ pub const DEFAULT_MAMBA_SNAPSHOT_BLOCK_STRIDE: usize = 1;
 
 pub const STREAM_AS_REASONING_CONTENT_ENV: &str = "VLLM_RS_STREAM_AS_REASONING_CONTENT";
 
+pub const RESPONSES_STORE_CAPACITY_ENV: &str = "VLLM_RS_RESPONSES_STORE_CAPACITY";
+pub const DEFAULT_RESPONSES_STORE_CAPACITY: usize = 1024;
+
+pub const BATCH_DIR_ENV: &str = "VLLM_RS_BATCH_DIR";
+pub const BATCH_MAX_INFLIGHT_ENV: &str = "VLLM_RS_BATCH_MAX_INFLIGHT";
+pub const DEFAULT_BATCH_MAX_INFLIGHT: usize = 8;
+
+pub const DRAIN_TIMEOUT_ENV: &str = "VLLM_RS_DRAIN_TIMEOUT_SECS";
+pub const DEFAULT_DRAIN_TIMEOUT_SECS: u64 = 300;
+
+pub const MAX_WAITING_REQUESTS_ENV: &str = "VLLM_RS_MAX_WAITING_REQUESTS";
+pub const MAX_WAITING_TOKENS_ENV: &str = "VLLM_RS_MAX_WAITING_TOKENS";
+pub const MAX_QUEUE_WAIT_ENV: &str = "VLLM_RS_MAX_QUEUE_WAIT_SECS";
+
 static STREAM_AS_REASONING_CONTENT: OnceLock<bool> = OnceLock::new();
 
 pub fn stream_as_reasoning_content() -> bool {
@@ -42,3 +56,100 @@ pub fn mamba_snapshot_block_stride_blocks() -> usize {
         }
     }
 }
+
+/// Number of `/v1/responses` results kept for `previous_response_id`, 0 disables the store.
+pub fn responses_store_capacity() -> usize {
+    let default = DEFAULT_RESPONSES_STORE_CAPACITY;
+    let Ok(raw) = env::var(RESPONSES_STORE_CAPACITY_ENV) else {
+        return default;
+    };
+    raw.trim().parse::<usize>().unwrap_or_else(|_| {
+        crate::log_warn!(
+            "Invalid {}='{}'. Falling back to default {}.",
+            RESPONSES_STORE_CAPACITY_ENV,
+            raw,
+            default
+        );
+        default
+    })
+}
+
+/// Directory holding `/v1/files` uploads and `/v1/batches` state.
+pub fn batch_dir() -> std::path::PathBuf {
+    if let Ok(raw) = env::var(BATCH_DIR_ENV) {
+        if !raw.trim().is_empty() {
+            return std::path::PathBuf::from(raw.trim());
+        }
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".cache").join("vllm-rs").join("batches"))
+        .unwrap_or_else(|| env::temp_dir().join("vllm-rs-batches"))
+}
+
+/// Maximum number of batch requests running at once.
+pub fn batch_max_inflight() -> usize {
+    let default = DEFAULT_BATCH_MAX_INFLIGHT;
+    let Ok(raw) = env::var(BATCH_MAX_INFLIGHT_ENV) else {
+        return default;
+    };
+    match raw.trim().parse::<usize>() {
+        Ok(v) if v >= 1 => v,
+        _ => {
+            crate::log_warn!(
+                "Invalid {}='{}'. Falling back to default {}.",
+                BATCH_MAX_INFLIGHT_ENV,
+                raw,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// How long a SIGTERM waits for running requests to finish before the server exits anyway.
+pub fn drain_timeout_secs() -> u64 {
+    let default = DEFAULT_DRAIN_TIMEOUT_SECS;
+    let Ok(raw) = env::var(DRAIN_TIMEOUT_ENV) else {
+        return default;
+    };
+    match raw.trim().parse::<u64>() {
+        Ok(v) => v,
+        _ => {
+            crate::log_warn!(
+                "Invalid {}='{}'. Falling back to default {}.",
+                DRAIN_TIMEOUT_ENV,
+                raw,
+                default
+            );
+            default
+        }
+    }
+}
+
+/// An optional limit, unset or 0 meaning unlimited.
+fn optional_limit(name: &str) -> Option<u64> {
+    let raw = env::var(name).ok()?;
+    match raw.trim().parse::<u64>() {
+        Ok(0) => None,
+        Ok(v) => Some(v),
+        Err(_) => {
+            crate::log_warn!("Invalid {}='{}'. Ignoring the limit.", name, raw);
+            None
+        }
+    }
+}
+
+/// Most requests allowed to wait for scheduling before new ones are rejected.
+pub fn max_waiting_requests() -> Option<usize> {
+    optional_limit(MAX_WAITING_REQUESTS_ENV).map(|v| v as usize)
+}
+
+/// Most prompt tokens allowed to wait for scheduling before new requests are rejected.
+pub fn max_waiting_tokens() -> Option<usize> {
+    optional_limit(MAX_WAITING_TOKENS_ENV).map(|v| v as usize)
+}
+
+/// Longest estimated queue wait at which new requests are still accepted.
+pub fn max_queue_wait() -> Option<std::time::Duration> {
+    optional_limit(MAX_QUEUE_WAIT_ENV).map(std::time::Duration::from_secs)
+}
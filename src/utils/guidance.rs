@@ -1056,6 +1056,125 @@ impl GuidanceState {
     }
 }
 
+/// Token bytes of the vocabulary, sorted for prefix lookups.
+pub struct TokenByteIndex {
+    tokens: Vec<Vec<u8>>,
+    /// Token ids ordered by their bytes
+    sorted: Vec<u32>,
+}
+
+impl TokenByteIndex {
+    pub fn new(trie: &TokTrie) -> Self {
+        let tokens = (0..trie.vocab_size() as u32)
+            .map(|id| trie.token(id).to_vec())
+            .collect();
+        Self::from_tokens(tokens)
+    }
+
+    fn from_tokens(tokens: Vec<Vec<u8>>) -> Self {
+        let mut sorted: Vec<u32> = (0..tokens.len() as u32).collect();
+        sorted.sort_by(|a, b| tokens[*a as usize].cmp(&tokens[*b as usize]));
+        Self { tokens, sorted }
+    }
+
+    pub fn vocab_size(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn token_bytes(&self, token: u32) -> &[u8] {
+        self.tokens
+            .get(token as usize)
+            .map_or(&[], |b| b.as_slice())
+    }
+
+    /// Tokens whose bytes start with `prefix`.
+    pub fn with_prefix(&self, prefix: &[u8]) -> &[u32] {
+        let start = self
+            .sorted
+            .partition_point(|&id| self.tokens[id as usize].as_slice() < prefix);
+        let len = self.sorted[start..]
+            .partition_point(|&id| self.tokens[id as usize].starts_with(prefix));
+        &self.sorted[start..start + len]
+    }
+}
+
+/// Keeps `bad_words` out of the generated text.
+///
+/// Matching is done on bytes rather than token ids, so a word is also banned when the
+/// model tries to spell it with a different tokenization or across several tokens.
+pub struct BadWordsFilter {
+    words: Vec<Vec<u8>>,
+    /// Tokens that contain a bad word on their own
+    always_banned: Vec<u32>,
+    /// Last generated bytes, as many as can hold the start of a bad word
+    tail: Vec<u8>,
+    /// Output tokens already folded into `tail`
+    num_tokens: usize,
+}
+
+impl BadWordsFilter {
+    pub fn new(words: &[String], index: &TokenByteIndex) -> Self {
+        let words: Vec<Vec<u8>> = words
+            .iter()
+            .filter(|w| !w.is_empty())
+            .map(|w| w.as_bytes().to_vec())
+            .collect();
+        let always_banned = (0..index.vocab_size() as u32)
+            .filter(|&id| {
+                let bytes = index.token_bytes(id);
+                words
+                    .iter()
+                    .any(|w| bytes.windows(w.len()).any(|window| window == w.as_slice()))
+            })
+            .collect();
+        Self {
+            words,
+            always_banned,
+            tail: Vec::new(),
+            num_tokens: 0,
+        }
+    }
+
+    pub fn num_tokens(&self) -> usize {
+        self.num_tokens
+    }
+
+    /// Append a newly generated token.
+    pub fn push(&mut self, token: u32, index: &TokenByteIndex) {
+        // Only the last `max_tail - 1` generated bytes can hold the start of a bad word
+        let max_tail = self.words.iter().map(|w| w.len()).max().unwrap_or(0);
+        self.tail.extend_from_slice(index.token_bytes(token));
+        let excess = self.tail.len().saturating_sub(max_tail.saturating_sub(1));
+        self.tail.drain(..excess);
+        self.num_tokens += 1;
+    }
+
+    /// Catch up with `output_ids`, appending the tokens not seen yet. Starts over if the
+    /// history is shorter than what was already folded in.
+    pub fn sync(&mut self, output_ids: &[u32], index: &TokenByteIndex) {
+        if output_ids.len() < self.num_tokens {
+            self.tail.clear();
+            self.num_tokens = 0;
+        }
+        for &token in &output_ids[self.num_tokens..] {
+            self.push(token, index);
+        }
+    }
+
+    /// Tokens that would complete a bad word if sampled next.
+    pub fn banned_tokens(&self, index: &TokenByteIndex) -> Vec<u32> {
+        let mut banned = self.always_banned.clone();
+        for word in &self.words {
+            for split in 1..word.len() {
+                if self.tail.ends_with(&word[..split]) {
+                    banned.extend_from_slice(index.with_prefix(&word[split..]));
+                }
+            }
+        }
+        banned
+    }
+}
+
 /// Apply sparse mask bias to logits
 /// Uses iter_set_entries to only iterate allowed tokens
 pub fn _batch_mask_bias(
@@ -1166,3 +1285,41 @@ pub fn _early_exit_validate(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(tokens: &[&str]) -> TokenByteIndex {
+        TokenByteIndex::from_tokens(tokens.iter().map(|t| t.as_bytes().to_vec()).collect())
+    }
+
+    #[test]
+    fn test_token_byte_index_prefix_lookup() {
+        let index = index(&["b", "ab", "abc", "a", "bc"]);
+        let mut ids = index.with_prefix(b"ab").to_vec();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2]);
+        assert!(index.with_prefix(b"z").is_empty());
+    }
+
+    #[test]
+    fn test_bad_words_banned_across_token_boundaries() {
+        let index = index(&["fo", "o", "obar", "foo", " food", "x"]);
+        let mut filter = BadWordsFilter::new(&["foo".to_string()], &index);
+        // Tokens containing the word are always banned
+        assert_eq!(filter.banned_tokens(&index), vec![3, 4]);
+        // "fo" + "o..." would complete the word
+        filter.sync(&[5, 0], &index);
+        let mut banned = filter.banned_tokens(&index);
+        banned.sort();
+        assert_eq!(banned, vec![1, 2, 3, 4]);
+        filter.push(5, &index);
+        assert_eq!(filter.banned_tokens(&index), vec![3, 4]);
+        assert_eq!(filter.num_tokens(), 3);
+        // A shorter history starts over
+        filter.sync(&[0], &index);
+        assert_eq!(filter.num_tokens(), 1);
+        assert_eq!(filter.banned_tokens(&index).len(), 4);
+    }
+}
@@ -1,10 +1,29 @@
 use super::command::CommandManager;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
+    atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{process, thread, time};
 
+/// Heartbeats are exchanged every second; the peer is considered gone after this long
+/// without a successful one.
+const HEARTBEAT_TIMEOUT_MS: u64 = 5000;
+static LAST_HEARTBEAT_MS: AtomicU64 = AtomicU64::new(0);
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+/// Whether a heartbeat with the runner subprocesses (or, in a runner, with the main
+/// process) succeeded recently.
+pub fn peers_alive() -> bool {
+    now_ms().saturating_sub(LAST_HEARTBEAT_MS.load(Ordering::Relaxed)) < HEARTBEAT_TIMEOUT_MS
+}
+
 pub fn heartbeat_worker(
     num_subprocess: Option<usize>,
     is_daemon: bool,
@@ -45,6 +64,9 @@ pub fn heartbeat_worker(
             crate::log_info!("enter heartbeat processing loop ({:?})", manager);
             while !flag_clone.load(Ordering::Relaxed) {
                 let alive_result = manager.heartbeat(is_daemon);
+                if alive_result.is_ok() {
+                    LAST_HEARTBEAT_MS.store(now_ms(), Ordering::Relaxed);
+                }
                 if let Err(e) = alive_result {
                     if !flag_clone.load(Ordering::Relaxed) {
                         crate::log_warn!("{:?}", e);
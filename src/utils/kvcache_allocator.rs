@@ -119,6 +119,8 @@ pub struct KVCacheAllocator {
     /// Per-layer KV cache config: (num_kv_heads, head_dim) per KV layer.
     /// When set, overrides uniform num_kv_heads/head_dim for cache allocation.
     per_layer_cache_config: Option<Vec<(usize, usize)>>,
+    /// Bytes of a draft model's KV cache that share each block (speculative decoding)
+    draft_block_bytes: usize,
 }
 
 impl KVCacheAllocator {
@@ -321,9 +323,22 @@ impl KVCacheAllocator {
             mla_kv_lora_rank,
             mla_qk_rope_head_dim,
             per_layer_cache_config,
+            draft_block_bytes: 0,
         }
     }
 
+    /// Plan for a draft model (or MTP layer) whose KV cache has one block per target block,
+    /// so a block costs the KV bytes of both.
+    pub fn with_draft_model(
+        mut self,
+        econfig: &EngineConfig,
+        draft: &Config,
+        dtype: DType,
+    ) -> Self {
+        self.draft_block_bytes = KVCacheAllocator::new(econfig, draft, dtype).per_block_bytes();
+        self
+    }
+
     /// Set per-layer KV cache configuration for models with heterogeneous head dims
     /// (e.g., Gemma4 with SWA head_dim=256 and full-attention head_dim=512).
     /// Each entry is (num_kv_heads, head_dim) for one KV layer.
@@ -430,6 +445,10 @@ impl KVCacheAllocator {
     }
     /// Calculate per-block memory size in bytes
     pub fn per_block_bytes(&self) -> usize {
+        self.kv_block_bytes() + self.draft_block_bytes
+    }
+
+    fn kv_block_bytes(&self) -> usize {
         if self.is_mla {
             self.block_size
                 * (self.mla_kv_lora_rank + self.mla_qk_rope_head_dim)
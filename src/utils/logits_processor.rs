@@ -7,14 +7,439 @@ use parking_lot::Mutex;
 use rand::{distr::Distribution, SeedableRng};
 use rayon::iter::IntoParallelIterator;
 use rayon::iter::ParallelIterator;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
+
+/// Maximum number of alternatives returned per position (OpenAI limit).
+pub const MAX_TOP_LOGPROBS: usize = 20;
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TopLogprob {
+    pub token_id: u32,
+    pub logprob: f32,
+}
+
+/// Log-probability of a sampled token and its most likely alternatives.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TokenLogprobs {
+    pub token_id: u32,
+    pub logprob: f32,
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum Sampling {
     ArgMax,
-    All { temperature: f32 },
-    TopK { k: usize, temperature: f32 },
-    TopP { p: f32, temperature: f32 },
-    TopKThenTopP { k: usize, p: f32, temperature: f32 },
+    All {
+        temperature: f32,
+    },
+    TopK {
+        k: usize,
+        temperature: f32,
+    },
+    TopP {
+        p: f32,
+        temperature: f32,
+    },
+    TopKThenTopP {
+        k: usize,
+        p: f32,
+        temperature: f32,
+    },
+    /// `sampling` applied to the distribution left after `truncation`
+    Truncated {
+        sampling: Box<Sampling>,
+        truncation: Truncation,
+    },
+}
+
+impl Sampling {
+    pub fn temperature(&self) -> Option<f32> {
+        match self {
+            Sampling::ArgMax => None,
+            Sampling::All { temperature }
+            | Sampling::TopK { temperature, .. }
+            | Sampling::TopP { temperature, .. }
+            | Sampling::TopKThenTopP { temperature, .. } => Some(*temperature),
+            Sampling::Truncated { sampling, .. } => sampling.temperature(),
+        }
+    }
+
+    /// The distribution this strategy samples one row of `logits` from.
+    pub fn probs(&self, logits: &[f32]) -> Vec<f32> {
+        let softmax = |temperature: f32| -> Vec<f32> {
+            let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            let mut probs: Vec<f32> = logits
+                .iter()
+                .map(|&l| ((l - max) / temperature).exp())
+                .collect();
+            let sum: f32 = probs.iter().sum();
+            probs.iter_mut().for_each(|p| *p /= sum);
+            probs
+        };
+        let descending = |probs: &[f32]| -> Vec<usize> {
+            let mut order: Vec<usize> = (0..probs.len()).collect();
+            order.sort_by(|&a, &b| probs[b].total_cmp(&probs[a]));
+            order
+        };
+        let keep_top_k = |probs: &mut Vec<f32>, k: usize| {
+            for i in descending(probs.as_slice()).into_iter().skip(k) {
+                probs[i] = 0.0;
+            }
+        };
+        // Same cut as the samplers: tokens are kept until the kept mass reaches `top_p`
+        let keep_top_p = |probs: &mut Vec<f32>, top_p: f32| {
+            let mut cumsum = 0.0;
+            for i in descending(probs.as_slice()) {
+                if cumsum >= top_p {
+                    probs[i] = 0.0;
+                } else {
+                    cumsum += probs[i];
+                }
+            }
+        };
+
+        let mut probs = match self {
+            Sampling::ArgMax => {
+                let mut probs = vec![0.0; logits.len()];
+                let top = logits
+                    .iter()
+                    .enumerate()
+                    .fold((0, f32::NEG_INFINITY), |best, (i, &l)| {
+                        if l > best.1 {
+                            (i, l)
+                        } else {
+                            best
+                        }
+                    })
+                    .0;
+                if let Some(p) = probs.get_mut(top) {
+                    *p = 1.0;
+                }
+                return probs;
+            }
+            Sampling::All { temperature } => softmax(*temperature),
+            Sampling::TopK { k, temperature } => {
+                let mut probs = softmax(*temperature);
+                keep_top_k(&mut probs, *k);
+                probs
+            }
+            Sampling::TopP { p, temperature } => {
+                let mut probs = softmax(*temperature);
+                if *p > 0.0 && *p < 1.0 {
+                    keep_top_p(&mut probs, *p);
+                }
+                probs
+            }
+            Sampling::TopKThenTopP { k, p, temperature } => {
+                let mut probs = softmax(*temperature);
+                keep_top_k(&mut probs, *k);
+                let kept: f32 = probs.iter().sum();
+                if *p > 0.0 && *p < kept {
+                    keep_top_p(&mut probs, *p);
+                }
+                probs
+            }
+            Sampling::Truncated {
+                sampling,
+                truncation,
+            } => {
+                let mut logits = logits.to_vec();
+                truncation.mask_row(&mut logits, sampling.temperature().unwrap_or(1.0));
+                return sampling.probs(&logits);
+            }
+        };
+        let sum: f32 = probs.iter().sum();
+        probs.iter_mut().for_each(|p| *p /= sum);
+        probs
+    }
+
+    /// Apply `truncation` before this strategy; greedy decoding is left unchanged.
+    pub fn with_truncation(self, truncation: Truncation) -> Sampling {
+        if self == Sampling::ArgMax || truncation.is_empty() {
+            return self;
+        }
+        match self {
+            Sampling::Truncated { sampling, .. } => Sampling::Truncated {
+                sampling,
+                truncation,
+            },
+            sampling => Sampling::Truncated {
+                sampling: Box::new(sampling),
+                truncation,
+            },
+        }
+    }
+}
+
+/// Truncation of the temperature-scaled distribution, applied before top-k/top-p.
+/// All thresholds are relative to that full distribution.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Truncation {
+    /// Drop tokens below `min_p` times the probability of the most likely token
+    pub min_p: Option<f32>,
+    /// Keep the tokens closest to the expected information content until their mass
+    /// reaches `typical_p` (locally typical sampling)
+    pub typical_p: Option<f32>,
+    /// Drop tokens below this probability
+    pub epsilon_cutoff: Option<f32>,
+    /// Drop tokens below `min(eta, sqrt(eta) * exp(-entropy))`
+    pub eta_cutoff: Option<f32>,
+}
+
+impl Truncation {
+    pub fn from_sampling_params(params: &SamplingParams) -> Self {
+        Self {
+            min_p: params.min_p.filter(|&p| p > 0.0),
+            typical_p: params.typical_p.filter(|&p| p > 0.0 && p < 1.0),
+            epsilon_cutoff: params.epsilon_cutoff.filter(|&e| e > 0.0),
+            eta_cutoff: params.eta_cutoff.filter(|&e| e > 0.0),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min_p.is_none()
+            && self.typical_p.is_none()
+            && self.epsilon_cutoff.is_none()
+            && self.eta_cutoff.is_none()
+    }
+
+    /// Set the logits of truncated tokens to `-inf`; the most likely token is always kept.
+    /// Runs on the device of `logits`, matching [`Self::mask_row`] up to ties.
+    pub fn mask_logits(&self, logits: &Tensor, temperature: f32) -> Result<Tensor> {
+        if self.is_empty() {
+            return Ok(logits.clone());
+        }
+        let logits_f32 = logits.to_dtype(DType::F32)?;
+        let temperature = temperature.max(1e-7) as f64;
+        let probs = candle_nn::ops::softmax_last_dim(&(&logits_f32 / temperature)?)?;
+        let top = probs.max_keepdim(D::Minus1)?;
+        // `0 * ln(0)` counts as 0
+        let entropy = |probs: &Tensor| -> Result<Tensor> {
+            (probs * probs.clamp(f32::MIN_POSITIVE, 1f32)?.log()?)?
+                .sum_keepdim(D::Minus1)?
+                .neg()
+        };
+
+        let mut threshold = top.zeros_like()?;
+        if let Some(min_p) = self.min_p {
+            threshold = threshold.maximum(&(&top * min_p as f64)?)?;
+        }
+        if let Some(epsilon) = self.epsilon_cutoff {
+            threshold = threshold.maximum(epsilon)?;
+        }
+        if let Some(eta) = self.eta_cutoff {
+            let cutoff = (entropy(&probs)?.neg()?.exp()? * (eta as f64).sqrt())?.minimum(eta)?;
+            threshold = threshold.maximum(&cutoff)?;
+        }
+        let mut keep = (probs.broadcast_ge(&threshold)?.to_dtype(DType::F32)?
+            * probs.gt(0f32)?.to_dtype(DType::F32)?)?;
+
+        if let Some(mass) = self.typical_p {
+            // Tokens closest to the expected information content are kept until their mass
+            // reaches `typical_p`. Instead of sorting the vocabulary, bisect the largest
+            // kept distance from the entropy.
+            let kept = (&probs * &keep)?;
+            let kept = kept.broadcast_div(&kept.sum_keepdim(D::Minus1)?)?;
+            let distance = (kept
+                .clamp(f32::MIN_POSITIVE, 1f32)?
+                .log()?
+                .neg()?
+                .broadcast_sub(&entropy(&kept)?)?
+                .abs()?
+                * &keep)?;
+            let mut lo = top.zeros_like()?;
+            let mut hi = distance.max_keepdim(D::Minus1)?;
+            for _ in 0..32 {
+                let mid = ((&lo + &hi)? * 0.5)?;
+                let within = distance.broadcast_le(&mid)?.to_dtype(DType::F32)?;
+                let reached = (&kept * within)?.sum_keepdim(D::Minus1)?.ge(mass)?;
+                lo = reached.where_cond(&lo, &mid)?;
+                hi = reached.where_cond(&mid, &hi)?;
+            }
+            keep = (keep * distance.broadcast_le(&hi)?.to_dtype(DType::F32)?)?;
+        }
+
+        let keep = keep.maximum(&probs.broadcast_ge(&top)?.to_dtype(DType::F32)?)?;
+        let masked = Tensor::full(f32::NEG_INFINITY, logits_f32.shape(), logits_f32.device())?;
+        keep.to_dtype(DType::U8)?
+            .where_cond(&logits_f32, &masked)?
+            .to_dtype(logits.dtype())
+    }
+
+    fn mask_row(&self, logits: &mut [f32], temperature: f32) {
+        let Some((top, &max)) = logits
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        else {
+            return;
+        };
+        if !max.is_finite() {
+            return;
+        }
+        let temperature = temperature.max(1e-7);
+        let mut probs: Vec<f32> = logits
+            .iter()
+            .map(|&l| ((l - max) / temperature).exp())
+            .collect();
+        let sum: f32 = probs.iter().sum();
+        probs.iter_mut().for_each(|p| *p /= sum);
+        let entropy = |probs: &[f32], norm: f32| -> f32 {
+            probs
+                .iter()
+                .filter(|&&p| p > 0.0)
+                .map(|&p| -(p / norm) * (p / norm).ln())
+                .sum()
+        };
+
+        let mut threshold = 0f32;
+        if let Some(min_p) = self.min_p {
+            threshold = threshold.max(min_p * probs[top]);
+        }
+        if let Some(epsilon) = self.epsilon_cutoff {
+            threshold = threshold.max(epsilon);
+        }
+        if let Some(eta) = self.eta_cutoff {
+            threshold = threshold.max(eta.min(eta.sqrt() * (-entropy(&probs, 1.0)).exp()));
+        }
+        let mut keep: Vec<bool> = probs.iter().map(|&p| p > 0.0 && p >= threshold).collect();
+
+        if let Some(mass) = self.typical_p {
+            let kept: Vec<f32> = probs
+                .iter()
+                .zip(&keep)
+                .map(|(&p, &k)| if k { p } else { 0.0 })
+                .collect();
+            let norm: f32 = kept.iter().sum();
+            let expected = entropy(&kept, norm);
+            let mut order: Vec<usize> = (0..kept.len()).filter(|&i| keep[i]).collect();
+            order.sort_by(|&a, &b| {
+                let da = (-(kept[a] / norm).ln() - expected).abs();
+                let db = (-(kept[b] / norm).ln() - expected).abs();
+                da.total_cmp(&db)
+            });
+            let mut cumsum = 0f32;
+            let mut cut = order.len();
+            for (i, &idx) in order.iter().enumerate() {
+                cumsum += kept[idx] / norm;
+                if cumsum >= mass {
+                    cut = i + 1;
+                    break;
+                }
+            }
+            for &idx in &order[cut..] {
+                keep[idx] = false;
+            }
+        }
+
+        keep[top] = true;
+        for (logit, keep) in logits.iter_mut().zip(keep) {
+            if !keep {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+    }
+}
+
+/// Mirostat v2: sample among the tokens whose surprise is below `mu`, then move `mu`
+/// so that the observed surprise approaches `tau`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Mirostat {
+    pub tau: f32,
+    pub eta: f32,
+}
+
+impl Mirostat {
+    pub fn from_sampling_params(params: &SamplingParams) -> Option<Self> {
+        (params.mirostat == Some(2)).then(|| Self {
+            tau: params.mirostat_tau.unwrap_or(5.0),
+            eta: params.mirostat_eta.unwrap_or(0.1),
+        })
+    }
+
+    /// `mu` of a sequence that has not sampled with Mirostat yet.
+    pub fn initial_mu(&self) -> f32 {
+        2.0 * self.tau
+    }
+}
+
+/// DRY ("don't repeat yourself"): penalize tokens that would extend a sequence
+/// already seen in the context, growing exponentially with the repeated length.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DryParams {
+    pub multiplier: f32,
+    pub base: f32,
+    pub allowed_length: usize,
+    /// Only the last `penalty_last_n` tokens are searched for repeats
+    pub penalty_last_n: Option<usize>,
+    /// Tokens that never take part in a repeated sequence
+    pub sequence_breakers: Vec<u32>,
+}
+
+impl DryParams {
+    /// Repeats longer than this are penalized as if they had this length.
+    const MAX_MATCH_LENGTH: usize = 64;
+
+    pub fn from_sampling_params(params: &SamplingParams) -> Option<Self> {
+        params
+            .dry_multiplier
+            .filter(|&m| m > 0.0)
+            .map(|multiplier| Self {
+                multiplier,
+                base: params.dry_base.unwrap_or(1.75),
+                allowed_length: params.dry_allowed_length.unwrap_or(2),
+                penalty_last_n: params.dry_penalty_last_n.filter(|&n| n > 0),
+                sequence_breakers: params.dry_sequence_breaker_ids.clone().unwrap_or_default(),
+            })
+    }
+
+    /// Length of the longest repeated sequence each token would extend.
+    pub fn match_lengths(&self, context: &[u32]) -> HashMap<u32, usize> {
+        let context = match self.penalty_last_n {
+            Some(n) if n < context.len() => &context[context.len() - n..],
+            _ => context,
+        };
+        let mut lengths = HashMap::new();
+        let n = context.len();
+        if n < 2 {
+            return lengths;
+        }
+        let is_breaker = |token: u32| self.sequence_breakers.contains(&token);
+        // The suffix ending at `i` is compared with the suffix of the whole context,
+        // `context[i + 1]` is the token that continued it
+        for i in 0..n - 1 {
+            let next = context[i + 1];
+            if is_breaker(next) {
+                continue;
+            }
+            let mut len = 0;
+            while len <= i
+                && len < Self::MAX_MATCH_LENGTH
+                && context[i - len] == context[n - 1 - len]
+                && !is_breaker(context[i - len])
+            {
+                len += 1;
+            }
+            if len > 0 {
+                let entry = lengths.entry(next).or_insert(0);
+                *entry = (*entry).max(len);
+            }
+        }
+        lengths
+    }
+
+    fn apply(&self, logits: &mut [f32], context: &[u32]) {
+        for (token, len) in self.match_lengths(context) {
+            if len < self.allowed_length {
+                continue;
+            }
+            if let Some(logit) = logits.get_mut(token as usize) {
+                *logit -= self.multiplier * self.base.powi((len - self.allowed_length) as i32);
+            }
+        }
+    }
 }
 
 pub struct LogitsProcessor {
@@ -35,6 +460,72 @@ impl LogitsProcessor {
         }
     }
 
+    /// A processor that shares this one's samplers but draws from its own RNG stream.
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self {
+            rng: Arc::new(Mutex::new(rand::rngs::StdRng::seed_from_u64(seed))),
+            sampling: self.sampling.clone(),
+            #[cfg(feature = "cuda")]
+            fast_sampler: self.fast_sampler.clone(),
+        }
+    }
+
+    /// Draw a token from the (not necessarily normalized) distribution `probs`.
+    pub fn sample_probs(&self, probs: &[f32]) -> Result<u32> {
+        let distr = rand::distr::weighted::WeightedIndex::new(probs).map_err(Error::wrap)?;
+        let mut rng = self.rng.lock();
+        Ok(distr.sample(&mut *rng) as u32)
+    }
+
+    /// A uniform draw from `[0, 1)`.
+    pub fn uniform(&self) -> f32 {
+        use rand::Rng;
+        self.rng.lock().random::<f32>()
+    }
+
+    /// Sample one row of logits with Mirostat v2, returns the token and the updated `mu`.
+    pub fn sample_mirostat(
+        &self,
+        logits: &[f32],
+        temperature: f32,
+        mirostat: &Mirostat,
+        mu: f32,
+    ) -> Result<(u32, f32)> {
+        let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let mut probs: Vec<f32> = logits
+            .iter()
+            .map(|&l| ((l - max_logit) / temperature).exp())
+            .collect();
+        let total: f32 = probs.iter().sum();
+        let top = probs
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map_or(0, |(i, _)| i);
+        // Keep the tokens with surprise -log2(p) <= mu, and always the most likely one
+        for (i, p) in probs.iter_mut().enumerate() {
+            *p /= total;
+            if i != top && -p.log2() > mu {
+                *p = 0.0;
+            }
+        }
+        let token = self.sample_multinomial(&probs)?;
+        let kept: f32 = probs.iter().sum();
+        let surprise = -(probs[token as usize] / kept).log2();
+        Ok((token, mu - mirostat.eta * (surprise - mirostat.tau)))
+    }
+
+    /// RNG seed for sampling the token at `position` of a sequence seeded with `seed`.
+    /// Depends only on the two inputs, so a seeded sequence samples the same tokens
+    /// regardless of the batch it runs in.
+    pub fn step_seed(seed: u64, position: usize) -> u64 {
+        // splitmix64 finalizer
+        let mut z = seed ^ (position as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
     pub fn new(
         seed: u64,
         temperature: Option<f32>,
@@ -197,6 +688,16 @@ impl LogitsProcessor {
     /// Sample tokens using a pre-computed sampling strategy.
     /// This is more efficient than `sample()` when the strategy is already computed and cached.
     pub fn sample_with_strategy(&self, logits: &Tensor, sampling: &Sampling) -> Result<Vec<u32>> {
+        if let Sampling::Truncated {
+            sampling,
+            truncation,
+        } = sampling
+        {
+            let temperature = sampling.temperature().unwrap_or(1.0);
+            let logits = truncation.mask_logits(logits, temperature)?;
+            return self.sample_with_strategy(&logits, sampling);
+        }
+
         #[cfg(feature = "cuda")]
         {
             // Extract k, p, and temperature based on the sampling strategy.
@@ -267,6 +768,7 @@ impl LogitsProcessor {
                 let prs = prs(*temperature as f64)?;
                 self.sample_topk_topp(&prs, *k, *p as f32)?
             }
+            Sampling::Truncated { sampling, .. } => self.sample_with_strategy(&logits, sampling)?,
         };
         Ok(next_tokens)
     }
@@ -278,11 +780,73 @@ impl LogitsProcessor {
     ) -> Result<Vec<u32>> {
         let sampling = sampling_params.as_ref().map_or_else(
             || self.sampling.to_owned(),
-            |param| LogitsProcessor::get_strategy(param.temperature, param.top_k, param.top_p),
+            |param| {
+                LogitsProcessor::get_strategy(param.temperature, param.top_k, param.top_p)
+                    .with_truncation(Truncation::from_sampling_params(param))
+            },
         );
         self.sample_with_strategy(logits, &sampling)
     }
 
+    /// Compute log-probabilities of the sampled `tokens` from the processed logits
+    /// (after penalties and masking, before temperature scaling).
+    /// `top_n[b]` is `None` for rows that did not request logprobs.
+    pub fn compute_logprobs(
+        logits: &Tensor,
+        tokens: &[u32],
+        top_n: &[Option<usize>],
+    ) -> Result<Vec<Option<TokenLogprobs>>> {
+        if top_n.iter().all(|n| n.is_none()) {
+            return Ok(vec![None; tokens.len()]);
+        }
+        let logprobs: Vec<Vec<f32>> =
+            candle_nn::ops::log_softmax(&logits.to_dtype(DType::F32)?, D::Minus1)?
+                .to_device(&candle_core::Device::Cpu)?
+                .to_vec2()?;
+        let ret = tokens
+            .iter()
+            .zip(top_n.iter())
+            .enumerate()
+            .map(|(b, (&token_id, n))| {
+                n.map(|n| {
+                    let row = &logprobs[b];
+                    TokenLogprobs {
+                        token_id,
+                        logprob: row
+                            .get(token_id as usize)
+                            .copied()
+                            .unwrap_or(f32::NEG_INFINITY),
+                        top_logprobs: Self::top_n_logprobs(row, n),
+                    }
+                })
+            })
+            .collect();
+        Ok(ret)
+    }
+
+    fn top_n_logprobs(row: &[f32], n: usize) -> Vec<TopLogprob> {
+        let n = n.min(MAX_TOP_LOGPROBS).min(row.len());
+        if n == 0 {
+            return Vec::new();
+        }
+        let cmp = |a: &usize, b: &usize| {
+            row[*b]
+                .partial_cmp(&row[*a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        };
+        let mut indices: Vec<usize> = (0..row.len()).collect();
+        indices.select_nth_unstable_by(n - 1, cmp);
+        indices.truncate(n);
+        indices.sort_by(cmp);
+        indices
+            .into_iter()
+            .map(|i| TopLogprob {
+                token_id: i as u32,
+                logprob: row[i],
+            })
+            .collect()
+    }
+
     fn apply_penalties(
         &self,
         logits: &mut [f32],
@@ -342,4 +906,299 @@ impl LogitsProcessor {
         let logits = vec_ret.into_iter().flatten().collect();
         Tensor::from_vec(logits, (batch, logits_len), device)
     }
+
+    /// HF-style repetition penalty: logits of tokens in `context` are divided by `penalty`
+    /// when positive and multiplied by it otherwise.
+    fn apply_repetition_penalty(logits: &mut [f32], context: &[u32], penalty: f32) {
+        let mut seen = vec![false; logits.len()];
+        for &token in context {
+            let token = token as usize;
+            if token >= logits.len() || seen[token] {
+                continue;
+            }
+            seen[token] = true;
+            let logit = &mut logits[token];
+            *logit = if *logit > 0.0 {
+                *logit / penalty
+            } else {
+                *logit * penalty
+            };
+        }
+    }
+
+    /// Tokens that would complete an n-gram of size `n` already present in `context`.
+    pub fn banned_ngram_tokens(context: &[u32], n: usize) -> Vec<u32> {
+        if n == 0 || context.len() + 1 < n {
+            return Vec::new();
+        }
+        let prefix = &context[context.len() + 1 - n..];
+        context
+            .windows(n)
+            .filter(|window| &window[..n - 1] == prefix)
+            .map(|window| window[n - 1])
+            .collect()
+    }
+
+    /// Apply `repetition_penalty`, `no_repeat_ngram_size` and DRY against the full prompt
+    /// and output tokens of each row.
+    pub fn apply_batch_history_penalties(
+        &self,
+        logits: &Tensor,
+        repetition_penalties: Vec<Option<f32>>,
+        ngram_sizes: Vec<Option<usize>>,
+        dry_params: Vec<Option<DryParams>>,
+        context: Vec<&[u32]>,
+    ) -> Result<Tensor> {
+        let device = logits.device();
+        let batch = logits.layout().dims()[0];
+        let logits_len = logits.layout().dims()[1];
+        let logits: Vec<Vec<f32>> = logits.to_dtype(DType::F32)?.to_vec2::<f32>()?;
+        let vec_ret: Vec<Vec<f32>> = (0..batch)
+            .into_par_iter()
+            .map(|b| {
+                let mut logits = logits[b].to_vec();
+                if let Some(penalty) = repetition_penalties[b].filter(|&p| p > 0.0 && p != 1.0) {
+                    Self::apply_repetition_penalty(&mut logits, context[b], penalty);
+                }
+                if let Some(n) = ngram_sizes[b] {
+                    for token in Self::banned_ngram_tokens(context[b], n) {
+                        if let Some(logit) = logits.get_mut(token as usize) {
+                            *logit = f32::NEG_INFINITY;
+                        }
+                    }
+                }
+                if let Some(dry) = &dry_params[b] {
+                    dry.apply(&mut logits, context[b]);
+                }
+                logits
+            })
+            .collect();
+
+        let logits = vec_ret.into_iter().flatten().collect();
+        Tensor::from_vec(logits, (batch, logits_len), device)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    #[test]
+    fn test_dry_penalizes_continuation_of_repeats() {
+        let dry = DryParams {
+            multiplier: 1.0,
+            base: 2.0,
+            allowed_length: 2,
+            penalty_last_n: None,
+            sequence_breakers: vec![9],
+        };
+        // "1 2 3 ... 1 2" would repeat with 3
+        let lengths = dry.match_lengths(&[1, 2, 3, 4, 1, 2]);
+        assert_eq!(lengths.get(&3), Some(&2));
+        let mut logits = vec![0.0f32; 5];
+        dry.apply(&mut logits, &[5, 1, 2, 3, 4, 5, 1, 2]);
+        assert_eq!(logits[3], -2.0);
+        assert_eq!(logits[4], 0.0);
+        // Breakers interrupt the repeated sequence
+        assert!(dry.match_lengths(&[1, 9, 3, 1, 9]).is_empty());
+    }
+
+    #[test]
+    fn test_mirostat_adapts_mu_towards_tau() {
+        let processor = LogitsProcessor::new(0, Some(1.0), None, None);
+        let mirostat = Mirostat { tau: 3.0, eta: 0.5 };
+        let logits = vec![4.0f32, 3.0, 2.0, 1.0, 0.0, -1.0, -2.0, -3.0];
+        // A low mu only keeps the most likely token, whose low surprise raises mu
+        let (token, mu) = processor
+            .sample_mirostat(&logits, 1.0, &mirostat, 0.1)
+            .unwrap();
+        assert_eq!(token, 0);
+        assert!((mu - (0.1 + 0.5 * 3.0)).abs() < 1e-5);
+        let mut mu = mirostat.initial_mu();
+        for _ in 0..32 {
+            mu = processor
+                .sample_mirostat(&logits, 1.0, &mirostat, mu)
+                .unwrap()
+                .1;
+        }
+        assert!(mu.is_finite());
+    }
+
+    #[test]
+    fn test_seeded_sampling_is_reproducible() {
+        let logits = Tensor::new(&[[0.0f32; 64]], &Device::Cpu).unwrap();
+        let processor = LogitsProcessor::new(0, Some(1.0), None, None);
+        let sampling = Sampling::All { temperature: 1.0 };
+        let draw = |seed: u64| -> Vec<u32> {
+            (0..8)
+                .flat_map(|pos| {
+                    processor
+                        .with_seed(LogitsProcessor::step_seed(seed, pos))
+                        .sample_with_strategy(&logits, &sampling)
+                        .unwrap()
+                })
+                .collect()
+        };
+        // Draws from the shared RNG in between must not affect seeded streams
+        let first = draw(42);
+        processor.sample_with_strategy(&logits, &sampling).unwrap();
+        assert_eq!(first, draw(42));
+        assert_ne!(first, draw(43));
+    }
+
+    #[test]
+    fn test_compute_logprobs_returns_sampled_and_top_alternatives() {
+        let logits = Tensor::new(
+            &[[1.0f32, 3.0, 2.0, 0.0], [0.0, 0.0, 0.0, 0.0]],
+            &Device::Cpu,
+        )
+        .unwrap();
+        let out = LogitsProcessor::compute_logprobs(&logits, &[2, 0], &[Some(2), None]).unwrap();
+
+        assert!(out[1].is_none());
+        let first = out[0].as_ref().unwrap();
+        let norm = [1.0f32, 3.0, 2.0, 0.0]
+            .iter()
+            .map(|x| x.exp())
+            .sum::<f32>()
+            .ln();
+        assert_eq!(first.token_id, 2);
+        assert!((first.logprob - (2.0 - norm)).abs() < 1e-5);
+        let top_ids: Vec<u32> = first.top_logprobs.iter().map(|t| t.token_id).collect();
+        assert_eq!(top_ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_banned_ngram_tokens() {
+        let context = [1u32, 2, 3, 1, 2];
+        assert_eq!(LogitsProcessor::banned_ngram_tokens(&context, 3), vec![3]);
+        assert_eq!(LogitsProcessor::banned_ngram_tokens(&context, 2), vec![3]);
+        assert_eq!(
+            LogitsProcessor::banned_ngram_tokens(&context, 1),
+            context.to_vec()
+        );
+        assert!(LogitsProcessor::banned_ngram_tokens(&context, 7).is_empty());
+    }
+
+    #[test]
+    fn test_repetition_penalty_scales_towards_zero() {
+        let mut logits = vec![2.0f32, -2.0, 1.0];
+        LogitsProcessor::apply_repetition_penalty(&mut logits, &[0, 1, 1], 2.0);
+        assert_eq!(logits, vec![1.0, -4.0, 1.0]);
+    }
+
+    fn masked(truncation: Truncation, probs: &[f32]) -> Vec<bool> {
+        let mut logits: Vec<f32> = probs.iter().map(|p| p.ln()).collect();
+        truncation.mask_row(&mut logits, 1.0);
+        logits.iter().map(|l| l.is_finite()).collect()
+    }
+
+    #[test]
+    fn test_truncation_thresholds() {
+        let probs = [0.5f32, 0.3, 0.15, 0.05];
+        let min_p = Truncation {
+            min_p: Some(0.4),
+            ..Default::default()
+        };
+        assert_eq!(masked(min_p, &probs), vec![true, true, false, false]);
+        let epsilon = Truncation {
+            epsilon_cutoff: Some(0.1),
+            ..Default::default()
+        };
+        assert_eq!(masked(epsilon, &probs), vec![true, true, true, false]);
+        // Typical order is 1, 0, 2, 3 (closest to the entropy first)
+        let typical = Truncation {
+            typical_p: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(masked(typical, &probs), vec![true, true, false, false]);
+        // The most likely token survives any cutoff
+        let strict = Truncation {
+            epsilon_cutoff: Some(0.9),
+            ..Default::default()
+        };
+        assert_eq!(masked(strict, &probs), vec![true, false, false, false]);
+    }
+
+    #[test]
+    fn test_mask_logits_matches_row_masking() -> Result<()> {
+        let rows = [[0.5f32, 0.3, 0.15, 0.05], [0.05, 0.1, 0.25, 0.6]];
+        let truncations = [
+            Truncation {
+                min_p: Some(0.4),
+                ..Default::default()
+            },
+            Truncation {
+                typical_p: Some(0.5),
+                ..Default::default()
+            },
+            Truncation {
+                eta_cutoff: Some(0.2),
+                ..Default::default()
+            },
+            Truncation {
+                min_p: Some(0.1),
+                typical_p: Some(0.9),
+                epsilon_cutoff: Some(0.9),
+                eta_cutoff: None,
+            },
+        ];
+        let logits: Vec<f32> = rows.iter().flatten().map(|p| p.ln()).collect();
+        let logits = Tensor::from_vec(logits, (2, 4), &candle_core::Device::Cpu)?;
+        for truncation in truncations {
+            let masked_rows = truncation.mask_logits(&logits, 1.0)?.to_vec2::<f32>()?;
+            for (row, probs) in masked_rows.iter().zip(&rows) {
+                let kept: Vec<bool> = row.iter().map(|l| l.is_finite()).collect();
+                assert_eq!(kept, masked(truncation.clone(), probs), "{:?}", truncation);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_truncation_keeps_greedy_decoding() {
+        let truncation = Truncation {
+            min_p: Some(0.1),
+            ..Default::default()
+        };
+        assert_eq!(
+            Sampling::ArgMax.with_truncation(truncation.clone()),
+            Sampling::ArgMax
+        );
+        assert!(matches!(
+            Sampling::All { temperature: 0.7 }.with_truncation(truncation),
+            Sampling::Truncated { .. }
+        ));
+    }
+
+    #[test]
+    fn test_sampling_probs_follow_strategy() {
+        let logits = [2.0f32.ln(), 1.0f32.ln(), 1.0f32.ln(), f32::NEG_INFINITY];
+        assert_eq!(Sampling::ArgMax.probs(&logits), vec![1.0, 0.0, 0.0, 0.0]);
+        let all = Sampling::All { temperature: 1.0 }.probs(&logits);
+        assert!((all[0] - 0.5).abs() < 1e-6 && (all[1] - 0.25).abs() < 1e-6);
+        assert_eq!(all[3], 0.0);
+        let top_k = Sampling::TopK {
+            k: 1,
+            temperature: 1.0,
+        }
+        .probs(&logits);
+        assert_eq!(top_k, vec![1.0, 0.0, 0.0, 0.0]);
+        // 0.5 alone does not reach 0.6, so one 0.25 token joins it
+        let top_p = Sampling::TopP {
+            p: 0.6,
+            temperature: 1.0,
+        }
+        .probs(&logits);
+        assert!((top_p[0] - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(top_p.iter().filter(|&&p| p > 0.0).count(), 2);
+    }
+
+    #[test]
+    fn test_compute_logprobs_skips_work_when_not_requested() {
+        let logits = Tensor::new(&[[1.0f32, 2.0]], &Device::Cpu).unwrap();
+        let out = LogitsProcessor::compute_logprobs(&logits, &[1], &[None]).unwrap();
+        assert_eq!(out, vec![None]);
+    }
 }
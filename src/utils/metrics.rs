@@ -0,0 +1,375 @@
+// src/utils/metrics.rs
+//! Process-wide engine counters and latency histograms, exported in the Prometheus
+//! text format by the server's `/metrics` route.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+const REQUEST_LATENCY_BUCKETS: &[f64] = &[
+    0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 40.0, 80.0, 160.0, 320.0,
+];
+const TOKEN_LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.015, 0.02, 0.025, 0.03, 0.04, 0.05, 0.075, 0.1, 0.15, 0.2, 0.3, 0.5, 1.0, 2.5,
+];
+
+/// Why a request left the engine, the `finished_reason` label of
+/// `vllm_rs_request_success_total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinishReason {
+    Stop,
+    Length,
+    ToolCalls,
+    Abort,
+    Error,
+}
+
+impl FinishReason {
+    const ALL: [FinishReason; 5] = [
+        FinishReason::Stop,
+        FinishReason::Length,
+        FinishReason::ToolCalls,
+        FinishReason::Abort,
+        FinishReason::Error,
+    ];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            FinishReason::Stop => "stop",
+            FinishReason::Length => "length",
+            FinishReason::ToolCalls => "tool_calls",
+            FinishReason::Abort => "abort",
+            FinishReason::Error => "error",
+        }
+    }
+}
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// One count per bound plus the `+Inf` bucket, not cumulative.
+    counts: Vec<AtomicU64>,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, seconds: f64) {
+        let seconds = seconds.max(0.0);
+        let idx = self
+            .bounds
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add((seconds * 1e6) as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} histogram");
+        let mut cumulative = 0u64;
+        for (idx, count) in self.counts.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = match self.bounds.get(idx) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+        }
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+        let _ = writeln!(out, "{name}_sum {sum}");
+        let _ = writeln!(out, "{name}_count {cumulative}");
+    }
+}
+
+/// Point-in-time engine state, sampled when `/metrics` is scraped.
+#[derive(Debug, Clone, Default)]
+pub struct EngineGauges {
+    pub num_waiting: usize,
+    pub num_running: usize,
+    pub num_swapped: usize,
+    /// Fraction of GPU KV cache blocks in use (0-1).
+    pub kv_cache_usage: f32,
+    pub cpu_swap_used_gb: f32,
+    pub cpu_swap_total_gb: f32,
+}
+
+pub struct Metrics {
+    prompt_tokens: AtomicU64,
+    generation_tokens: AtomicU64,
+    prefix_cache_queried_tokens: AtomicU64,
+    prefix_cache_hit_tokens: AtomicU64,
+    preemptions: AtomicU64,
+    swap_outs: AtomicU64,
+    swap_ins: AtomicU64,
+    rejected: AtomicU64,
+    finished: [AtomicU64; 5],
+    time_to_first_token: Histogram,
+    time_per_output_token: Histogram,
+    e2e_request_latency: Histogram,
+}
+
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        Self {
+            prompt_tokens: AtomicU64::new(0),
+            generation_tokens: AtomicU64::new(0),
+            prefix_cache_queried_tokens: AtomicU64::new(0),
+            prefix_cache_hit_tokens: AtomicU64::new(0),
+            preemptions: AtomicU64::new(0),
+            swap_outs: AtomicU64::new(0),
+            swap_ins: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            finished: Default::default(),
+            time_to_first_token: Histogram::new(REQUEST_LATENCY_BUCKETS),
+            time_per_output_token: Histogram::new(TOKEN_LATENCY_BUCKETS),
+            e2e_request_latency: Histogram::new(REQUEST_LATENCY_BUCKETS),
+        }
+    }
+
+    /// Records a request that produced output. Times are unix milliseconds, as carried
+    /// by `StreamItem::Done`.
+    pub fn record_finished(
+        &self,
+        reason: FinishReason,
+        prompt_tokens: usize,
+        output_tokens: usize,
+        created_ms: usize,
+        decode_start_ms: usize,
+        finish_ms: usize,
+    ) {
+        self.record_outcome(reason);
+        self.prompt_tokens
+            .fetch_add(prompt_tokens as u64, Ordering::Relaxed);
+        self.generation_tokens
+            .fetch_add(output_tokens as u64, Ordering::Relaxed);
+        let seconds = |from: usize, to: usize| to.saturating_sub(from) as f64 / 1000.0;
+        self.time_to_first_token
+            .observe(seconds(created_ms, decode_start_ms));
+        self.e2e_request_latency
+            .observe(seconds(created_ms, finish_ms));
+        if output_tokens > 1 {
+            self.time_per_output_token
+                .observe(seconds(decode_start_ms, finish_ms) / (output_tokens - 1) as f64);
+        }
+    }
+
+    /// Records a request that left the engine without finishing (cancelled or failed).
+    pub fn record_outcome(&self, reason: FinishReason) {
+        let idx = FinishReason::ALL
+            .iter()
+            .position(|r| *r == reason)
+            .unwrap_or(0);
+        self.finished[idx].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_prefix_lookup(&self, queried_tokens: usize, hit_tokens: usize) {
+        self.prefix_cache_queried_tokens
+            .fetch_add(queried_tokens as u64, Ordering::Relaxed);
+        self.prefix_cache_hit_tokens
+            .fetch_add(hit_tokens as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_swap_out(&self, preempted: bool) {
+        self.swap_outs.fetch_add(1, Ordering::Relaxed);
+        if preempted {
+            self.preemptions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_swap_in(&self) {
+        self.swap_ins.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a request turned away by admission control before reaching the engine.
+    pub fn record_rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format (version 0.0.4).
+    pub fn render(&self, gauges: &EngineGauges) -> String {
+        let mut out = String::new();
+        let gauge = |out: &mut String, name: &str, help: &str, value: f64| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name} {value}");
+        };
+        let counter = |out: &mut String, name: &str, help: &str, value: &AtomicU64| {
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", value.load(Ordering::Relaxed));
+        };
+
+        gauge(
+            &mut out,
+            "vllm_rs_num_requests_waiting",
+            "Sequences waiting to be scheduled.",
+            gauges.num_waiting as f64,
+        );
+        gauge(
+            &mut out,
+            "vllm_rs_num_requests_running",
+            "Sequences currently scheduled on the GPU.",
+            gauges.num_running as f64,
+        );
+        gauge(
+            &mut out,
+            "vllm_rs_num_requests_swapped",
+            "Sequences swapped out to CPU memory.",
+            gauges.num_swapped as f64,
+        );
+        gauge(
+            &mut out,
+            "vllm_rs_kv_cache_usage_perc",
+            "Fraction of GPU KV cache blocks in use (1 means 100 percent).",
+            gauges.kv_cache_usage as f64,
+        );
+        gauge(
+            &mut out,
+            "vllm_rs_cpu_swap_used_gigabytes",
+            "CPU memory used by swapped-out KV cache.",
+            gauges.cpu_swap_used_gb as f64,
+        );
+        gauge(
+            &mut out,
+            "vllm_rs_cpu_swap_total_gigabytes",
+            "CPU memory reserved for swapped-out KV cache.",
+            gauges.cpu_swap_total_gb as f64,
+        );
+        let queried = self.prefix_cache_queried_tokens.load(Ordering::Relaxed);
+        let hits = self.prefix_cache_hit_tokens.load(Ordering::Relaxed);
+        gauge(
+            &mut out,
+            "vllm_rs_prefix_cache_hit_rate",
+            "Fraction of prompt tokens served from the prefix cache since startup.",
+            if queried > 0 {
+                hits as f64 / queried as f64
+            } else {
+                0.0
+            },
+        );
+        counter(
+            &mut out,
+            "vllm_rs_prefix_cache_queries_total",
+            "Prompt tokens looked up in the prefix cache.",
+            &self.prefix_cache_queried_tokens,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_prefix_cache_hits_total",
+            "Prompt tokens served from the prefix cache.",
+            &self.prefix_cache_hit_tokens,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_prompt_tokens_total",
+            "Prompt tokens of finished requests.",
+            &self.prompt_tokens,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_generation_tokens_total",
+            "Generated tokens of finished requests.",
+            &self.generation_tokens,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_num_preemptions_total",
+            "Running sequences swapped out because the KV cache was full.",
+            &self.preemptions,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_swap_out_total",
+            "Sequences swapped out to CPU memory.",
+            &self.swap_outs,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_swap_in_total",
+            "Sequences swapped back in from CPU memory.",
+            &self.swap_ins,
+        );
+        counter(
+            &mut out,
+            "vllm_rs_request_rejected_total",
+            "Requests rejected because the waiting queue was over its limits.",
+            &self.rejected,
+        );
+
+        let name = "vllm_rs_request_success_total";
+        let _ = writeln!(
+            out,
+            "# HELP {name} Requests that left the engine, by finish reason."
+        );
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (reason, count) in FinishReason::ALL.iter().zip(&self.finished) {
+            let _ = writeln!(
+                out,
+                "{name}{{finished_reason=\"{}\"}} {}",
+                reason.as_str(),
+                count.load(Ordering::Relaxed)
+            );
+        }
+
+        self.time_to_first_token.render(
+            &mut out,
+            "vllm_rs_time_to_first_token_seconds",
+            "Time from request arrival to the first generated token.",
+        );
+        self.time_per_output_token.render(
+            &mut out,
+            "vllm_rs_time_per_output_token_seconds",
+            "Average time between generated tokens of a request.",
+        );
+        self.e2e_request_latency.render(
+            &mut out,
+            "vllm_rs_e2e_request_latency_seconds",
+            "Time from request arrival to its last generated token.",
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_cumulative_histograms_and_labelled_outcomes() {
+        let metrics = Metrics::new();
+        metrics.record_finished(FinishReason::Length, 10, 5, 1_000, 1_200, 1_600);
+        metrics.record_outcome(FinishReason::Abort);
+        metrics.record_prefix_lookup(64, 16);
+        metrics.record_swap_out(true);
+
+        let text = metrics.render(&EngineGauges {
+            num_waiting: 2,
+            ..Default::default()
+        });
+        assert!(text.contains("vllm_rs_num_requests_waiting 2\n"));
+        assert!(text.contains("vllm_rs_prefix_cache_hit_rate 0.25\n"));
+        assert!(text.contains("vllm_rs_prompt_tokens_total 10\n"));
+        assert!(text.contains("vllm_rs_num_preemptions_total 1\n"));
+        assert!(text.contains("vllm_rs_request_success_total{finished_reason=\"length\"} 1\n"));
+        assert!(text.contains("vllm_rs_request_success_total{finished_reason=\"abort\"} 1\n"));
+        // TTFT 0.2s lands in the 0.25 bucket and every larger one
+        assert!(text.contains("vllm_rs_time_to_first_token_seconds_bucket{le=\"0.1\"} 0\n"));
+        assert!(text.contains("vllm_rs_time_to_first_token_seconds_bucket{le=\"0.25\"} 1\n"));
+        assert!(text.contains("vllm_rs_time_to_first_token_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("vllm_rs_time_per_output_token_seconds_sum 0.1\n"));
+        assert!(text.contains("vllm_rs_e2e_request_latency_seconds_count 1\n"));
+    }
+}
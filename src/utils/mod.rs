@@ -13,6 +13,7 @@ pub mod heartbeat;
 pub mod image;
 pub mod kvcache_allocator;
 pub mod logits_processor;
+pub mod metrics;
 pub mod progress;
 pub mod reasoning;
 pub mod special_tokens;
@@ -1404,6 +1405,12 @@ pub fn init_config_tokenizer(
                     top_k: None,
                     frequency_penalty: Some(1.2),
                     presence_penalty: Some(1.2),
+                    min_p: None,
+                    typical_p: None,
+                    epsilon_cutoff: None,
+                    eta_cutoff: None,
+                    repetition_penalty: None,
+                    no_repeat_ngram_size: None,
                     bos_token_id: None,
                     eos_token_id: None,
                 })
@@ -1529,6 +1536,12 @@ pub fn init_config_tokenizer(
                 top_k: None,
                 frequency_penalty: Some(1.2),
                 presence_penalty: Some(1.2),
+                min_p: None,
+                typical_p: None,
+                epsilon_cutoff: None,
+                eta_cutoff: None,
+                repetition_penalty: None,
+                no_repeat_ngram_size: None,
                 bos_token_id: None,
                 eos_token_id: None,
             })
@@ -2002,6 +2015,15 @@ pub fn prepare_engine_config(
             if egen_cfg.presence_penalty.is_none() {
                 egen_cfg.presence_penalty = gen_cfg.presence_penalty;
             }
+            egen_cfg.min_p = egen_cfg.min_p.or(gen_cfg.min_p);
+            egen_cfg.typical_p = egen_cfg.typical_p.or(gen_cfg.typical_p);
+            egen_cfg.epsilon_cutoff = egen_cfg.epsilon_cutoff.or(gen_cfg.epsilon_cutoff);
+            egen_cfg.eta_cutoff = egen_cfg.eta_cutoff.or(gen_cfg.eta_cutoff);
+            egen_cfg.repetition_penalty =
+                egen_cfg.repetition_penalty.or(gen_cfg.repetition_penalty);
+            egen_cfg.no_repeat_ngram_size = egen_cfg
+                .no_repeat_ngram_size
+                .or(gen_cfg.no_repeat_ngram_size);
             if egen_cfg.temperature.is_none() {
                 egen_cfg.temperature = gen_cfg.temperature;
                 egen_cfg.top_p = gen_cfg.top_p;
@@ -1,4 +1,5 @@
 // src/utils/special_tokens.rs
+use crate::utils::config::ModelType;
 use tokenizers::Tokenizer;
 
 const REASONING_START_TOKENS: &[&str] = &[
@@ -23,6 +24,102 @@ const REASONING_END_TOKENS: &[&str] = &[
     "<channel|>",
 ];
 
+/// How the fill-in-the-middle sentinels are arranged around the code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FimLayout {
+    /// `{prefix_tok}{prefix}{suffix_tok}{suffix}{middle_tok}` (Qwen-Coder, StarCoder, DeepSeek-Coder, CodeGemma).
+    PrefixSuffixMiddle,
+    /// `{suffix_tok}{suffix}{prefix_tok}{prefix}` (Codestral), generation starts right after the prefix.
+    SuffixPrefix,
+    /// `{prefix_tok} {prefix} {suffix_tok}{suffix} {middle_tok}` (CodeLlama).
+    SpacedPrefixSuffixMiddle,
+}
+
+/// (prefix, suffix, middle, layout) sentinel sets, most specific first.
+const FIM_TEMPLATES: &[(&str, &str, &str, FimLayout)] = &[
+    (
+        "<|fim_prefix|>",
+        "<|fim_suffix|>",
+        "<|fim_middle|>",
+        FimLayout::PrefixSuffixMiddle,
+    ),
+    (
+        "<fim_prefix>",
+        "<fim_suffix>",
+        "<fim_middle>",
+        FimLayout::PrefixSuffixMiddle,
+    ),
+    (
+        "<｜fim▁begin｜>",
+        "<｜fim▁hole｜>",
+        "<｜fim▁end｜>",
+        FimLayout::PrefixSuffixMiddle,
+    ),
+    ("[PREFIX]", "[SUFFIX]", "", FimLayout::SuffixPrefix),
+    (
+        "<PRE>",
+        "<SUF>",
+        "<MID>",
+        FimLayout::SpacedPrefixSuffixMiddle,
+    ),
+];
+
+/// Fill-in-the-middle prompt format supported by the loaded tokenizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FimTemplate {
+    pub prefix: String,
+    pub suffix: String,
+    pub middle: String,
+    pub layout: FimLayout,
+}
+
+impl FimTemplate {
+    /// Picks the FIM format whose sentinels are all single tokens in `tokenizer`.
+    /// The model family is consulted first so that e.g. a DeepSeek checkpoint
+    /// shipping several sentinel sets still gets its native format.
+    pub fn detect(tokenizer: &Tokenizer, model_type: &ModelType) -> Option<Self> {
+        let preferred = match model_type {
+            ModelType::DeepSeek => Some(2),
+            ModelType::Mistral => Some(3),
+            ModelType::LLaMa => Some(4),
+            _ => None,
+        };
+        preferred
+            .into_iter()
+            .chain(0..FIM_TEMPLATES.len())
+            .find_map(|idx| {
+                let (prefix, suffix, middle, layout) = FIM_TEMPLATES[idx];
+                let present = [prefix, suffix, middle]
+                    .iter()
+                    .filter(|token| !token.is_empty())
+                    .all(|token| candidate_token_id(tokenizer, token).is_some());
+                present.then(|| Self {
+                    prefix: prefix.to_string(),
+                    suffix: suffix.to_string(),
+                    middle: middle.to_string(),
+                    layout,
+                })
+            })
+    }
+
+    /// Builds the raw prompt; it must be encoded with special-token parsing enabled.
+    pub fn build_prompt(&self, prefix: &str, suffix: &str) -> String {
+        match self.layout {
+            FimLayout::PrefixSuffixMiddle => format!(
+                "{}{}{}{}{}",
+                self.prefix, prefix, self.suffix, suffix, self.middle
+            ),
+            FimLayout::SuffixPrefix => {
+                format!("{}{}{}{}", self.suffix, suffix, self.prefix, prefix)
+            }
+            FimLayout::SpacedPrefixSuffixMiddle => format!(
+                "{} {} {}{} {}",
+                self.prefix, prefix, self.suffix, suffix, self.middle
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct SpecialTokens {
     reasoning_start_ids: Vec<u32>,
@@ -79,7 +176,8 @@ fn sort_and_dedup(ids: &mut Vec<u32>) {
 
 #[cfg(test)]
 mod tests {
-    use super::SpecialTokens;
+    use super::{FimLayout, FimTemplate, SpecialTokens};
+    use crate::utils::config::ModelType;
     use tokenizers::{models::bpe::BPE, AddedToken, Tokenizer};
 
     #[test]
@@ -96,4 +194,29 @@ mod tests {
         assert_eq!(special_tokens.reasoning_start_ids().len(), 1);
         assert_eq!(special_tokens.reasoning_end_ids().len(), 1);
     }
+
+    #[test]
+    fn fim_template_follows_tokenizer_sentinels() {
+        let mut tokenizer = Tokenizer::new(BPE::default());
+        assert!(FimTemplate::detect(&tokenizer, &ModelType::Qwen3).is_none());
+
+        tokenizer.add_special_tokens(&[
+            AddedToken::from("<|fim_prefix|>", true),
+            AddedToken::from("<|fim_suffix|>", true),
+            AddedToken::from("<|fim_middle|>", true),
+            AddedToken::from("[PREFIX]", true),
+            AddedToken::from("[SUFFIX]", true),
+        ]);
+
+        let qwen = FimTemplate::detect(&tokenizer, &ModelType::Qwen3).unwrap();
+        assert_eq!(qwen.layout, FimLayout::PrefixSuffixMiddle);
+        assert_eq!(
+            qwen.build_prompt("def f(", "):"),
+            "<|fim_prefix|>def f(<|fim_suffix|>):<|fim_middle|>"
+        );
+
+        let codestral = FimTemplate::detect(&tokenizer, &ModelType::Mistral).unwrap();
+        assert_eq!(codestral.layout, FimLayout::SuffixPrefix);
+        assert_eq!(codestral.build_prompt("a", "b"), "[SUFFIX]b[PREFIX]a");
+    }
 }
@@ -26,6 +26,20 @@ class PdConfig:
     method = PdMethod
     url: Optional[str]
 
+@dataclass
+class SpeculativeMethod(Enum):
+    Ngram = 1
+    Draft = 2
+    Mtp = 3
+
+@dataclass
+class SpeculativeConfig:
+    method: SpeculativeMethod
+    num_speculative_tokens: int
+    prompt_lookup_max: int
+    prompt_lookup_min: int
+    draft_model: Optional[str]
+
 
 @dataclass
 class GenerationOutput:
@@ -36,6 +50,8 @@ class GenerationOutput:
     decode_finish_time: int
     decoded_length: int
     decode_output: str
+    accepted_prediction_tokens: int
+    rejected_prediction_tokens: int
 
 @dataclass
 class GenerationConfig:
@@ -44,6 +60,12 @@ class GenerationConfig:
     top_k: Optional[int]
     frequency_penalty: Optional[float]
     presence_penalty: Optional[float]
+    min_p: Optional[float]
+    typical_p: Optional[float]
+    epsilon_cutoff: Optional[float]
+    eta_cutoff: Optional[float]
+    repetition_penalty: Optional[float]
+    no_repeat_ngram_size: Optional[int]
 
 @dataclass
 class EngineConfig:
@@ -75,6 +97,12 @@ class EngineConfig:
     mcp_config: Optional[str]
     mcp_command: Optional[str]
     mcp_args: Optional[str]
+    speculative_config: Optional[SpeculativeConfig]
+    api_keys: Optional[str]
+    scheduling_policy: Optional[str]
+    max_waiting_requests: Optional[int]
+    max_waiting_tokens: Optional[int]
+    max_queue_wait_secs: Optional[int]
 
 @dataclass
 class SamplingParams:
@@ -86,6 +114,32 @@ class SamplingParams:
     session_id: Optional[str]
     frequency_penalty: Optional[float]
     presence_penalty: Optional[float]
+    logprobs: Optional[bool]
+    top_logprobs: Optional[int]
+    n: Optional[int]
+    beam_width: Optional[int]
+    length_penalty: Optional[float]
+    early_stopping: Optional[bool]
+    min_p: Optional[float]
+    typical_p: Optional[float]
+    epsilon_cutoff: Optional[float]
+    eta_cutoff: Optional[float]
+    repetition_penalty: Optional[float]
+    no_repeat_ngram_size: Optional[int]
+    logit_bias: Optional[Mapping[int, float]]
+    bad_words: Optional[List[str]]
+    seed: Optional[int]
+    min_tokens: Optional[int]
+    mirostat: Optional[int]
+    mirostat_tau: Optional[float]
+    mirostat_eta: Optional[float]
+    dry_multiplier: Optional[float]
+    dry_base: Optional[float]
+    dry_allowed_length: Optional[int]
+    dry_penalty_last_n: Optional[int]
+    dry_sequence_breakers: Optional[List[str]]
+    prediction: Optional[str]
+    priority: Optional[int]
 
 @dataclass
 class Message:
@@ -105,7 +159,7 @@ class StreamItem:
     Check the `type` attribute to determine how to interpret the `data`.
     """
     @property
-    def datatype(self) -> Literal["TOKEN", "TOKEN_ID", "COMPLETION", "DONE", "ERROR"]:
+    def datatype(self) -> Literal["TOKEN", "TOKEN_ID", "COMPLETION", "DONE", "LOGPROBS", "ERROR"]:
         """The type of the stream item."""
         ...
 
@@ -114,7 +168,8 @@ class StreamItem:
         str,                         # For TOKEN or ERROR
         int,                         # For TOKEN_ID
         Tuple[int, int, int, int],   # For DONE
-        Tuple[int, int, int, List[int]] # For COMPLETION
+        Tuple[int, int, int, List[int]], # For COMPLETION
+        Tuple[int, float, List[Tuple[int, float]]] # For LOGPROBS (token_id, logprob, top_logprobs)
     ]:
         """The data payload of the stream item."""
         ...